@@ -0,0 +1,410 @@
+#[macro_use]
+extern crate log;
+extern crate libllama;
+
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::panic;
+use std::process::exit;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libllama::{cpu, dbgcore, hwcore, ldr, mem, msgs};
+use libllama::utils::from_hex;
+
+const EXIT_OK: i32 = 0;
+const EXIT_USAGE: i32 = 1;
+const EXIT_PANIC: i32 = 2;
+const EXIT_TIMEOUT: i32 = 3;
+const EXIT_DUMP: i32 = 4;
+
+// Instructions to run between timeout checks
+const CHUNK_INSTRS: u64 = 100_000;
+
+const USAGE: &'static str = "\
+Usage: llama-headless <package.ctr9 | program.elf | image.firm> [options]
+       llama-headless --boot9 <boot9.bin> [options]
+
+Options:
+    --boot9 <file>                 Power on with this ARM9 bootrom dump, which boots from NAND
+    --instrs <n>                   Stop after n ARM9 instructions (default 100000000), or
+                                   once the ARM9 is left waiting for an IRQ
+    --timeout <seconds>            Fail if emulation runs longer than this
+    --break <addr hex>             Stop at this address; may be repeated. If any are given,
+                                   reaching the instruction limit first counts as a timeout
+    --dump-regs <file>             Write ARM9 registers to a text file on exit
+    --dump-mem <addr>:<len>:<file> Write a memory range (address and length in hex) to a file;
+                                   may be repeated
+    --dump-fbs <prefix>            Write raw framebuffers to <prefix>top.bin and <prefix>bot.bin
+    --exec-trace <file>            Record an execution trace of the ARM9 to a file
+    --unmapped <abort|zero|break>  What accesses to unmapped memory do: raise a bus error
+                                   (default), read as zero, or stop like a breakpoint
+    --verbose                      Print info level logs
+
+Exit codes: 0 on success, 1 on bad arguments, 2 on emulator panic, 3 on timeout, 4 if
+writing a dump failed. Dumps are still written after a panic or timeout.";
+
+struct StderrLogger(log::LogLevelFilter);
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::LogMetadata) -> bool {
+        metadata.level() <= self.0
+    }
+
+    fn log(&self, record: &log::LogRecord) {
+        if !self.enabled(record.metadata()) { return }
+        let _ = writeln!(&mut std::io::stderr(), "{}: {}", record.level(), record.args());
+    }
+}
+
+struct MemDump {
+    addr: u32,
+    len: u32,
+    filename: String
+}
+
+struct Options {
+    package: String,
+    boot9: Option<String>,
+    instrs: u64,
+    timeout: Option<Duration>,
+    breakpoints: Vec<u32>,
+    regs_file: Option<String>,
+    mem_dumps: Vec<MemDump>,
+    fbs_prefix: Option<String>,
+    exec_trace: Option<String>,
+    unmapped: mem::UnmappedPolicy,
+    verbose: bool,
+}
+
+fn parse_mem_dump(arg: &str) -> Option<MemDump> {
+    let mut parts = arg.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(addr), Some(len), Some(filename)) => Some(MemDump {
+            addr: from_hex(addr).ok()?,
+            len: from_hex(len).ok()?,
+            filename: filename.to_owned()
+        }),
+        _ => None
+    }
+}
+
+fn parse_args<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+    let mut opts = Options {
+        package: String::new(),
+        boot9: None,
+        instrs: 100_000_000,
+        timeout: None,
+        breakpoints: Vec::new(),
+        regs_file: None,
+        mem_dumps: Vec::new(),
+        fbs_prefix: None,
+        exec_trace: None,
+        unmapped: mem::UnmappedPolicy::Abort,
+        verbose: false,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("Missing value for `{}`", name));
+        match arg.as_str() {
+            "--instrs" => {
+                let val = value("--instrs")?;
+                opts.instrs = val.parse().map_err(|_| format!("Invalid instruction count `{}`", val))?;
+            }
+            "--timeout" => {
+                let val = value("--timeout")?;
+                let secs = val.parse().map_err(|_| format!("Invalid timeout `{}`", val))?;
+                opts.timeout = Some(Duration::from_secs(secs));
+            }
+            "--break" => {
+                let val = value("--break")?;
+                opts.breakpoints.push(from_hex(&val).map_err(|_| format!("Invalid address `{}`", val))?);
+            }
+            "--boot9" => opts.boot9 = Some(value("--boot9")?),
+            "--dump-regs" => opts.regs_file = Some(value("--dump-regs")?),
+            "--dump-mem" => {
+                let val = value("--dump-mem")?;
+                opts.mem_dumps.push(parse_mem_dump(&val).ok_or(format!("Invalid memory dump `{}`", val))?);
+            }
+            "--dump-fbs" => opts.fbs_prefix = Some(value("--dump-fbs")?),
+            "--exec-trace" => opts.exec_trace = Some(value("--exec-trace")?),
+            "--unmapped" => {
+                let val = value("--unmapped")?;
+                opts.unmapped = mem::UnmappedPolicy::from_name(&val)
+                    .ok_or(format!("Invalid unmapped memory policy `{}`", val))?;
+            }
+            "--verbose" | "-v" => opts.verbose = true,
+            "--help" | "-h" => return Err(String::new()),
+            _ if arg.starts_with("-") => return Err(format!("Unknown option `{}`", arg)),
+            _ if opts.package.is_empty() => opts.package = arg.clone(),
+            _ => return Err(format!("Unexpected argument `{}`", arg)),
+        }
+    }
+
+    if opts.package.is_empty() && opts.boot9.is_none() {
+        return Err("No ctr9 package, ELF executable or FIRM image specified".to_owned());
+    }
+    Ok(opts)
+}
+
+fn write_file(filename: &str, data: &[u8]) -> bool {
+    match File::create(filename).and_then(|mut file| file.write_all(data)) {
+        Ok(_) => true,
+        Err(x) => { error!("Failed to write `{}`; {:?}", filename, x); false }
+    }
+}
+
+/// Writes everything asked for with the `--dump-*` options, returning whether it all worked.
+/// This also works after the emulator panicked, with the machine as the panic left it.
+fn dump_state(debugger: &mut dbgcore::DbgCore, opts: &Options) -> bool {
+    let mut ok = true;
+    let mut ctx = debugger.ctx();
+
+    if let Some(ref prefix) = opts.fbs_prefix {
+        let mut fbs = hwcore::Framebuffers {
+            top_screen: Vec::new(), bot_screen: Vec::new(),
+            top_screen_size: (240, 400, 3), bot_screen_size: (240, 320, 3),
+        };
+        ctx.hwcore_mut().copy_framebuffers(&mut fbs);
+        ok &= write_file(&format!("{}top.bin", prefix), &fbs.top_screen);
+        ok &= write_file(&format!("{}bot.bin", prefix), &fbs.bot_screen);
+    }
+
+    let hw = ctx.hw();
+
+    if let Some(ref filename) = opts.regs_file {
+        let mut text = String::new();
+        for i in 0..16 {
+            text.push_str(&format!("r{} = 0x{:08X}\n", i, hw.read_reg(i)));
+        }
+        text.push_str(&format!("cpsr = 0x{:08X}\n", hw.read_cpsr()));
+        ok &= write_file(filename, text.as_bytes());
+    }
+
+    for dump in opts.mem_dumps.iter() {
+        let mut buf = vec![0u8; dump.len as usize];
+        hw.read_mem(dump.addr, &mut buf);
+        ok &= write_file(&dump.filename, &buf);
+    }
+    ok
+}
+
+/// Returns whether a breakpoint or, with the `break` policy, an unmapped access stopped the
+/// core, or None if the time limit was exceeded
+fn run(debugger: &mut dbgcore::DbgCore, opts: &Options) -> Option<bool> {
+    let start = Instant::now();
+    let mut remaining = opts.instrs;
+
+    while remaining > 0 {
+        let chunk = if remaining < CHUNK_INSTRS { remaining } else { CHUNK_INSTRS };
+        match debugger.ctx().hwcore_mut().run_sync(chunk) {
+            cpu::BreakReason::Breakpoint | cpu::BreakReason::BusError(..) => return Some(true),
+            cpu::BreakReason::WFI => {
+                warn!("ARM9 stopped waiting for an IRQ that never came");
+                return Some(false)
+            }
+            _ => {}
+        }
+        remaining -= chunk;
+
+        if let Some(timeout) = opts.timeout {
+            if start.elapsed() > timeout {
+                return None
+            }
+        }
+    }
+    Some(false)
+}
+
+/// Picks the exit code for the result of `run`, and whether writing the dumps worked
+fn exit_code(res: &thread::Result<Option<bool>>, opts: &Options, dumped: bool) -> i32 {
+    let code = match *res {
+        Err(_) => {
+            error!("Emulator panicked!");
+            EXIT_PANIC
+        }
+        Ok(None) => {
+            error!("Timed out after {} seconds", opts.timeout.map_or(0, |t| t.as_secs()));
+            EXIT_TIMEOUT
+        }
+        Ok(Some(false)) if !opts.breakpoints.is_empty() => {
+            error!("Reached the instruction limit before any breakpoint");
+            EXIT_TIMEOUT
+        }
+        Ok(Some(hit_breakpoint)) => {
+            if hit_breakpoint {
+                info!("Breakpoint hit");
+            }
+            EXIT_OK
+        }
+    };
+
+    // Failing to dump only counts if nothing else went wrong first
+    if !dumped && code == EXIT_OK {
+        EXIT_DUMP
+    } else {
+        code
+    }
+}
+
+fn main() {
+    let opts = match parse_args(env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            if !msg.is_empty() {
+                let _ = writeln!(&mut std::io::stderr(), "{}\n", msg);
+            }
+            let _ = writeln!(&mut std::io::stderr(), "{}", USAGE);
+            exit(EXIT_USAGE);
+        }
+    };
+
+    let level = if opts.verbose { log::LogLevelFilter::Info } else { log::LogLevelFilter::Warn };
+    log::set_logger(|max| {
+        max.set(level);
+        Box::new(StderrLogger(level))
+    }).unwrap();
+
+    let (path, loader) = match opts.boot9 {
+        Some(ref path) => {
+            let loader = ldr::Boot9Loader::from_file(path)
+                .map(|loader| Box::new(loader) as Box<ldr::Loader>)
+                .map_err(ldr::Error::from);
+            (path, loader)
+        }
+        None => (&opts.package, ldr::from_path(&opts.package))
+    };
+    let loader = match loader {
+        Ok(loader) => loader,
+        Err(x) => { error!("Failed to load `{}`; {}", path, x); exit(EXIT_USAGE) }
+    };
+
+    let pump = msgs::Pump::new();
+    let mut hwcore = hwcore::HwCore::new(pump, &*loader);
+    hwcore.set_unmapped_policy(opts.unmapped);
+    let mut debugger = dbgcore::DbgCore::bind(hwcore);
+
+    {
+        let mut ctx = debugger.ctx();
+        let mut hw = ctx.hw();
+        for addr in opts.breakpoints.iter() {
+            hw.set_breakpoint(*addr);
+        }
+
+        if let Some(ref filename) = opts.exec_trace {
+            let res = File::create(filename)
+                .map_err(cpu::trace::Error::from)
+                .and_then(|file| hw.start_trace(Box::new(BufWriter::new(file))));
+            if let Err(x) = res {
+                error!("Failed to start execution trace `{}`; {}", filename, x);
+                exit(EXIT_USAGE)
+            }
+        }
+    }
+
+    let res = {
+        let debugger = &mut debugger;
+        let opts = &opts;
+        panic::catch_unwind(panic::AssertUnwindSafe(move || run(debugger, opts)))
+    };
+
+    // Exiting skips destructors, so the trace has to be flushed here
+    if opts.exec_trace.is_some() {
+        if let Err(x) = debugger.ctx().hw().stop_trace() {
+            error!("Failed to finish execution trace; {}", x);
+        }
+    }
+
+    let dumped = dump_state(&mut debugger, &opts);
+    exit(exit_code(&res, &opts, dumped));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::any::Any;
+    use std::io::Read;
+
+    struct IdleLoader;
+
+    impl ldr::Loader for IdleLoader {
+        fn entrypoint(&self) -> u32 {
+            0x08000000
+        }
+
+        fn load(&self, _controller: &mut mem::MemController) {}
+
+        fn arm11_state(&self) -> hwcore::Arm11State {
+            hwcore::Arm11State::None
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn read_file(path: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn parse_dump_options() {
+        let opts = parse_args(args(&["test.elf", "--dump-mem", "8000000:10:mem.bin",
+                                     "--dump-regs", "regs.txt"]).into_iter()).unwrap();
+        assert_eq!(opts.package, "test.elf");
+        assert_eq!(opts.regs_file, Some("regs.txt".to_owned()));
+        assert_eq!((opts.mem_dumps[0].addr, opts.mem_dumps[0].len), (0x08000000, 0x10));
+        assert_eq!(opts.mem_dumps[0].filename, "mem.bin");
+
+        assert!(parse_args(args(&["test.elf", "--dump-mem", "8000000:zz:mem.bin"]).into_iter())
+            .is_err());
+    }
+
+    #[test]
+    fn dump_after_panic() {
+        let dir = env::temp_dir();
+        let regs_path = dir.join("llama-headless-test-regs.txt").to_string_lossy().into_owned();
+        let mem_path = dir.join("llama-headless-test-mem.bin").to_string_lossy().into_owned();
+        let opts = parse_args(args(&["test.elf", "--dump-regs", &regs_path,
+                                     "--dump-mem", &format!("8000000:4:{}", mem_path)])
+                              .into_iter()).unwrap();
+
+        let hwcore = hwcore::HwCore::new(msgs::Pump::new(), &IdleLoader);
+        let mut debugger = dbgcore::DbgCore::bind(hwcore);
+        let res: thread::Result<Option<bool>> = {
+            let debugger = &mut debugger;
+            panic::catch_unwind(panic::AssertUnwindSafe(move || {
+                let mut ctx = debugger.ctx();
+                let mut hw = ctx.hw();
+                hw.write_reg(1, 0x1234);
+                hw.write_mem(0x08000000, &[1, 2, 3, 4]);
+                panic!("Emulator bug")
+            }))
+        };
+        assert!(res.is_err());
+
+        // The panic poisoned the locks it was holding, which doesn't stop the dump
+        assert!(dump_state(&mut debugger, &opts));
+        let regs = String::from_utf8(read_file(&regs_path)).unwrap();
+        assert!(regs.contains("r1 = 0x00001234"));
+        assert_eq!(read_file(&mem_path), [1, 2, 3, 4]);
+        assert_eq!(exit_code(&res, &opts, true), EXIT_PANIC);
+    }
+
+    #[test]
+    fn dump_failures() {
+        let mut opts = parse_args(args(&["test.elf"]).into_iter()).unwrap();
+        let finished: thread::Result<Option<bool>> = Ok(Some(false));
+        assert_eq!(exit_code(&finished, &opts, true), EXIT_OK);
+        assert_eq!(exit_code(&finished, &opts, false), EXIT_DUMP);
+
+        let panicked: thread::Result<Option<bool>> = Err(Box::new(()) as Box<Any + Send>);
+        assert_eq!(exit_code(&panicked, &opts, false), EXIT_PANIC);
+
+        opts.breakpoints.push(0x08000000);
+        assert_eq!(exit_code(&finished, &opts, false), EXIT_TIMEOUT);
+    }
+}
@@ -1,20 +1,43 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use parking_lot::Mutex;
+
 use cpu::irq::IrqRequests;
-use io::timer;
+use io::{ndma, timer};
+use snapshot;
 
 #[derive(Clone)]
 pub struct SysClock {
     counter: Arc<AtomicUsize>,
     pub timer_states: timer::TimerStates,
+    pub dma_triggers: ndma::DmaTriggers,
     pub irq_tx: IrqRequests,
+    // Services the startup requests raised as time passes, on the clock driving the ARM9 timers
+    ndma: Option<Arc<Mutex<ndma::NdmaDevice>>>,
 }
 
 impl SysClock {
     pub fn increment(&mut self, by: usize) {
         self.counter.fetch_add(by, Ordering::Relaxed);
-        timer::handle_clock_update(&self.timer_states, by, &mut self.irq_tx);
+        timer::handle_clock_update(&self.timer_states, by, &mut self.irq_tx, &self.dma_triggers);
+
+        if !self.dma_triggers.any() {
+            return
+        }
+        // Fails when the clock is advanced from inside an IO access that holds the NDMA device;
+        // the access services the requests itself once it's done
+        if let Some(ref ndma) = self.ndma {
+            if let Some(mut ndma) = ndma.try_lock() {
+                ndma::handle_triggers(&mut ndma);
+            }
+        }
+    }
+
+    /// Lets the clock start NDMA transfers for requests raised by timers and other devices,
+    /// without waiting for the next ARM9 IO access
+    pub fn connect_ndma(&mut self, ndma: Arc<Mutex<ndma::NdmaDevice>>) {
+        self.ndma = Some(ndma);
     }
 
     pub fn get(&self) -> usize {
@@ -22,12 +45,26 @@ impl SysClock {
     }
 }
 
+// Timer states are shared with the timer device, which saves them itself
+impl snapshot::Snapshot for SysClock {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.get() as u64);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.counter.store(r.get::<u64>()? as usize, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
 pub fn make_channel(irq_tx: IrqRequests) -> SysClock {
     let counter = Arc::new(AtomicUsize::new(0));
     let timer_states = timer::TimerStates::new();
     SysClock {
         counter: counter.clone(),
         timer_states: timer_states,
-        irq_tx: irq_tx
+        dma_triggers: ndma::DmaTriggers::new(),
+        irq_tx: irq_tx,
+        ndma: None,
     }
 }
\ No newline at end of file
@@ -0,0 +1,651 @@
+use capstone::Capstone;
+use capstone::arch::BuildsCapstone;
+use capstone::arch::arm::ArchMode;
+
+use dbgcore;
+use io;
+use utils::from_hex;
+
+/// Where debugger commands print their results, e.g. the log or a GDB client
+pub trait Output {
+    fn info(&mut self, msg: &str);
+    fn error(&mut self, msg: &str);
+}
+
+/// Prints command output to the log
+pub struct LogOutput;
+
+impl Output for LogOutput {
+    fn info(&mut self, msg: &str) {
+        info!("{}", msg);
+    }
+
+    fn error(&mut self, msg: &str) {
+        error!("{}", msg);
+    }
+}
+
+macro_rules! out_info {
+    ($out:expr, $($arg:tt)*) => ($out.info(&format!($($arg)*)))
+}
+
+macro_rules! out_error {
+    ($out:expr, $($arg:tt)*) => ($out.error(&format!($($arg)*)))
+}
+
+/// Prints disassembly for the next instruction
+/// Command format: "asm [address hex | symbol]"
+///
+/// `args`: Iterator over &str items
+fn cmd_asm<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let symbols = ctx.symbols();
+    let hw = ctx.hw();
+
+    let pause_addr = match args.next().map(|arg| symbols.resolve(arg)) {
+        Some(Some(x)) => x,
+        Some(None) => { out_error!(out, "Could not parse hex value or symbol!"); return }
+        None => hw.pause_addr(),
+    };
+
+    let cpu_mode = if hw.is_thumb() {
+        ArchMode::Thumb
+    } else {
+        ArchMode::Arm
+    };
+
+    let cs = Capstone::new()
+        .arm()
+        .mode(cpu_mode)
+        .build();
+
+    if let Ok(cs) = cs {
+        let mut inst_bytes = [0u8; 4];
+        hw.read_mem(pause_addr, &mut inst_bytes);
+
+        match cs.disasm_count(&inst_bytes, pause_addr as u64, 1) {
+            Ok(insts) => {
+                let inst = insts.iter().next().unwrap();
+                out_info!(out, "{}: {} {}", symbols.describe(pause_addr),
+                                            inst.mnemonic().unwrap(),
+                                            inst.op_str().unwrap())
+            }
+            Err(_) => out_error!(out, "Failed to disassemble instruction at 0x{:X}", pause_addr),
+        }
+    } else {
+        out_error!(out, "Could not initialize capstone!");
+    }
+}
+
+/// Toggles a CPU breakpoint at an instruction address, or sets a conditional breakpoint if a
+/// condition is given. Lists breakpoints if no address is given.
+/// Command format: "brk [address hex | symbol] [condition]"
+///
+/// `args`: Iterator over &str items
+fn cmd_brk<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+    use cpu::BrkCondition;
+
+    let symbols = ctx.symbols();
+    let mut hw = ctx.hw();
+
+    let addr = match args.next() {
+        Some(arg) => match symbols.resolve(arg) {
+            Some(x) => x,
+            None => { out_error!(out, "Could not parse hex value or symbol!"); return }
+        },
+        None => {
+            for (addr, brk) in hw.breakpoints() {
+                let addr = symbols.describe(addr);
+                match brk.condition {
+                    Some(cond) => {
+                        out_info!(out, "Breakpoint at {} if {}, hit {} times",
+                                  addr, cond, brk.hits)
+                    }
+                    None => out_info!(out, "Breakpoint at {}, hit {} times", addr, brk.hits),
+                }
+            }
+            return
+        }
+    };
+
+    let cond_str = args.collect::<Vec<_>>().join(" ");
+    if !cond_str.is_empty() {
+        match cond_str.parse::<BrkCondition>() {
+            Ok(cond) => {
+                out_info!(out, "Setting breakpoint at {} if {}", symbols.describe(addr), cond_str);
+                hw.set_conditional_breakpoint(addr, cond);
+            }
+            Err(x) => out_error!(out, "Invalid breakpoint condition; {}", x),
+        }
+        return
+    }
+
+    out_info!(out, "Toggling breakpoint at {}", symbols.describe(addr));
+
+    if !hw.has_breakpoint(addr) {
+        hw.set_breakpoint(addr);
+    } else {
+        hw.del_breakpoint(addr);
+    }
+}
+
+/// Toggles a data watchpoint on a range of memory, or lists watchpoints if no address is given
+/// Command format: "watch [address hex] [# bytes hex] [r | w | rw]"
+///
+/// `args`: Iterator over &str items
+fn cmd_watch<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+    use cpu::{Watchpoint, WatchKind};
+
+    let mut hw = ctx.hw();
+
+    let addr = match args.next().map(from_hex) {
+        Some(Ok(x)) => x,
+        Some(Err(_)) => { out_error!(out, "Could not parse hex value!"); return }
+        None => {
+            for wp in hw.watchpoints() {
+                out_info!(out, "{:?} watchpoint at 0x{:X}, {} bytes", wp.kind, wp.addr, wp.len);
+            }
+            return
+        }
+    };
+
+    let len = match args.next().map(from_hex) {
+        Some(Ok(x)) if x > 0 => x,
+        Some(_) => { out_error!(out, "Could not parse hex value!"); return }
+        None => 4,
+    };
+
+    let kind = match args.next() {
+        Some("r") => WatchKind::Read,
+        Some("w") | None => WatchKind::Write,
+        Some("rw") => WatchKind::Access,
+        Some(_) => { out_info!(out, "Usage: `watch [addr] [num] [r | w | rw]`"); return }
+    };
+
+    let wp = Watchpoint { addr: addr, len: len, kind: kind };
+    out_info!(out, "Toggling {:?} watchpoint at 0x{:X}, {} bytes", kind, addr, len);
+
+    if !hw.has_watchpoint(wp) {
+        hw.set_watchpoint(wp);
+    } else {
+        hw.del_watchpoint(wp);
+    }
+}
+
+/// Records an execution trace of the ARM9 to a file, in the format read by
+/// `cpu::trace::TraceReader`
+/// Command format: "exectrace <file | off>"
+///
+/// `args`: Iterator over &str items
+fn cmd_exectrace<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let arg = match args.next() {
+        Some(arg) => arg,
+        None => { out_info!(out, "Usage: `exectrace <file | off>`"); return }
+    };
+
+    ctx.pause();
+    let mut hw = ctx.hw();
+
+    if arg == "off" {
+        match hw.stop_trace() {
+            Ok(_) => out_info!(out, "Stopped execution trace"),
+            Err(x) => out_error!(out, "Failed to finish execution trace; {}", x)
+        }
+        return
+    }
+
+    use std::fs::File;
+    use std::io::BufWriter;
+    use cpu::trace;
+    let res = File::create(arg)
+        .map_err(trace::Error::from)
+        .and_then(|file| hw.start_trace(Box::new(BufWriter::new(file))));
+    match res {
+        Ok(_) => out_info!(out, "Recording execution trace to `{}`", arg),
+        Err(x) => out_error!(out, "Failed to start execution trace `{}`; {}", arg, x)
+    }
+}
+
+/// Records and inspects IO register accesses
+/// Command format: "iotrace <on [devices...] | off | clear | show [device] | save <file>>"
+///
+/// `args`: Iterator over &str items
+fn cmd_iotrace<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    // How many of the latest accesses `show` prints
+    const SHOW_ENTRIES: usize = 64;
+
+    let tracer = ctx.hwcore().io_tracer();
+    let known = io::device_names();
+    match args.next() {
+        Some("on") => {
+            let devices: Vec<String> = args.map(|s| s.to_owned()).collect();
+            if let Some(unk) = devices.iter().find(|name| !known.contains(&name.as_str())) {
+                out_error!(out, "Unknown IO device `{}`; expected one of {}", unk,
+                           known.join(", "));
+                return
+            }
+            if devices.is_empty() {
+                out_info!(out, "Tracing accesses to all IO devices");
+            } else {
+                out_info!(out, "Tracing accesses to {}", devices.join(", "));
+            }
+            tracer.start(devices);
+        }
+        Some("off") => {
+            tracer.stop();
+            out_info!(out, "Stopped IO tracing");
+        }
+        Some("clear") => tracer.clear(),
+        Some("show") => {
+            let device = args.next();
+            match device {
+                Some(name) if !known.contains(&name) => {
+                    out_error!(out, "Unknown IO device `{}`; expected one of {}", name,
+                               known.join(", "));
+                    return
+                }
+                _ => {}
+            }
+            let entries = tracer.entries(device);
+            let skip = entries.len().saturating_sub(SHOW_ENTRIES);
+            for access in entries[skip..].iter() {
+                out_info!(out, "{}", access);
+            }
+            out_info!(out, "{} accesses recorded", entries.len());
+        }
+        Some("save") => {
+            let filename = match args.next() {
+                Some(arg) => arg,
+                None => { out_info!(out, "Usage: `iotrace save <file>`"); return }
+            };
+
+            use std::fs::File;
+            match File::create(filename).and_then(|mut file| tracer.export(&mut file)) {
+                Ok(_) => out_info!(out, "Saved IO trace to `{}`", filename),
+                Err(x) => out_error!(out, "Failed to write IO trace file `{}`; {:?}", filename, x)
+            }
+        }
+        _ => out_info!(out, "Usage: `iotrace <on [devices...] | off | clear | show [device] | \
+                             save <file>>`")
+    }
+}
+
+/// Sets AES key-dumping state
+/// Command format: "keydmp"
+///
+/// `args`: Unused
+fn cmd_keydmp<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, _: It)
+    where It: Iterator<Item=&'a str> {
+
+    use io::aes;
+
+    let key_slots = {
+        let mut aes_dev = ctx.hwcore_mut().hardware_io.0.aes.lock();
+        aes::dump_keys(&mut aes_dev)
+    };
+
+    let filename = aes::keydb_path();
+    out_info!(out, "Dumping AES keys to disk at `{}`...", filename);
+
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    let mut file = match OpenOptions::new().create(true).read(true).write(true)
+                                            .truncate(true).open(&filename) {
+        Ok(file) => file,
+        Err(x) => {
+            out_error!(out, "Failed to open aeskeydb file `{}`; {:?}", filename, x);
+            return
+        }
+    };
+    for k in key_slots.iter() {
+        if let Err(x) = file.write_all(&k.data) {
+            out_error!(out, "Failed to write to aeskeydb file `{}`; {:?}", filename, x);
+            return
+        }
+    }
+}
+
+/// Triggers the specified IRQ
+/// Command format: "irq <type>"
+///
+/// `args`: Iterator over &str items
+fn cmd_irq<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let irq_ty = match args.next() {
+        Some(arg) => arg.to_lowercase(),
+        None => { out_info!(out, "Usage: `irq <type>`"); return }
+    };
+
+    let irq = match irq_ty.as_str() {
+        "timer0" => dbgcore::IrqType::Timer0,
+        "timer1" => dbgcore::IrqType::Timer1,
+        "timer2" => dbgcore::IrqType::Timer2,
+        "timer3" => dbgcore::IrqType::Timer3,
+        _ => { out_error!(out, "Unimplemented/unknown IRQ type `{}`", irq_ty); return }
+    };
+
+    out_info!(out, "Triggering IRQ {}", irq_ty);
+
+    ctx.trigger_irq(irq);
+}
+
+/// Prints memory to the screen based on provided address, number of bytes
+/// Command format: "mem <start address hex> [# bytes hex]"
+///
+/// `args`: Iterator over &str items
+fn cmd_mem<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    // Tuple: (u32: start, u32: num)
+    let arg_res = match (args.next(), args.next()) {
+        (Some(ss), Some(ns)) => from_hex(ss).and_then(|s| Ok((s, from_hex(ns)?))),
+        (Some(ss), None) => from_hex(ss).and_then(|s| Ok((s, 1))),
+        (None, _) => { out_info!(out, "Usage: `mem <start> [num]`"); return }
+    };
+
+    // Check for from_hex errors, validate `num` input
+    let (start, num) = match arg_res {
+        Ok((s, n)) if n > 0 => (s, n),
+        Ok((s, _)) => (s, 1),
+        _ => { out_error!(out, "Could not parse hex value!"); return }
+    };
+
+    trace!("Printing {} bytes of RAM starting at 0x{:08X}", num, start);
+
+    let hw = ctx.hw();
+
+    let mut mem_bytes = vec![0u8; num as usize];
+    hw.read_mem(start, &mut mem_bytes);
+
+    let mut strbuf = String::new();
+    strbuf.push_str(&format!("{:02X}", mem_bytes[0]));
+    for i in 1 .. num as usize {
+        strbuf.push_str(&format!(" {:02X}", mem_bytes[i]));
+    }
+    out_info!(out, "{}", &strbuf);
+}
+
+/// Prints registers to the screen based on provided register name
+/// Command format: "reg [register name]"
+///
+/// `args`: Iterator over &str items
+fn cmd_reg<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+    let hw = ctx.hw();
+
+    let reg_str = match args.next() {
+        Some(arg) => arg.to_owned().to_lowercase(),
+        None => {
+            for i in 0..16 {
+                out_info!(out, "R{} = 0x{:08X}", i, hw.read_reg(i));
+            }
+            return;
+        }
+    };
+
+    let reg_num = match reg_str.as_str() {
+        "r0" => 0,
+        "r1" => 1,
+        "r2" => 2,
+        "r3" => 3,
+        "r4" => 4,
+        "r5" => 5,
+        "r6" => 6,
+        "r7" => 7,
+        "r8" => 8,
+        "r9" => 9,
+        "r10" => 10,
+        "r11" => 11,
+        "r12" => 12,
+        "sp" | "r13" => 13,
+        "lr" | "r14" => 14,
+        "pc" | "r15" => 15,
+        _ => { out_error!(out, "Unrecognized register!"); return }
+    };
+    out_info!(out, "R{} = 0x{:08X}", reg_num, hw.read_reg(reg_num));
+}
+
+/// Saves the state of the emulated machine to a file
+/// Command format: "save <file path>"
+///
+/// `args`: Iterator over &str items
+fn cmd_save<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let filename = match args.next() {
+        Some(arg) => arg,
+        None => { out_info!(out, "Usage: `save <file>`"); return }
+    };
+
+    ctx.pause();
+    let data = ctx.hwcore_mut().save_snapshot();
+
+    use std::fs::File;
+    use std::io::Write;
+    let res = File::create(filename).and_then(|mut file| file.write_all(&data));
+    match res {
+        Ok(_) => out_info!(out, "Saved {} byte snapshot to `{}`", data.len(), filename),
+        Err(x) => out_error!(out, "Failed to write snapshot file `{}`; {:?}", filename, x)
+    }
+}
+
+/// Restores the state of the emulated machine from a file
+/// Command format: "load <file path>"
+///
+/// `args`: Iterator over &str items
+fn cmd_load<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let filename = match args.next() {
+        Some(arg) => arg,
+        None => { out_info!(out, "Usage: `load <file>`"); return }
+    };
+
+    use std::fs::File;
+    use std::io::Read;
+    let mut data = Vec::new();
+    if let Err(x) = File::open(filename).and_then(|mut file| file.read_to_end(&mut data)) {
+        out_error!(out, "Failed to read snapshot file `{}`; {:?}", filename, x);
+        return
+    }
+
+    ctx.pause();
+    match ctx.hwcore_mut().load_snapshot(&data) {
+        Ok(_) => out_info!(out, "Loaded snapshot from `{}`", filename),
+        Err(x) => out_error!(out, "Failed to load snapshot `{}`; {}", filename, x)
+    }
+}
+
+/// Sets what happens to accesses to unmapped memory
+/// Command format: "unmapped <abort | zero | break>"
+///
+/// `args`: Iterator over &str items
+fn cmd_unmapped<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+    use mem::UnmappedPolicy;
+
+    let policy = match args.next().map(UnmappedPolicy::from_name) {
+        Some(Some(policy)) => policy,
+        _ => { out_info!(out, "Usage: `unmapped <abort | zero | break>`"); return }
+    };
+
+    ctx.pause();
+    ctx.hwcore_mut().set_unmapped_policy(policy);
+    out_info!(out, "Unmapped memory accesses now use the {:?} policy", policy);
+}
+
+/// Runs one instruction on the CPU, or enters the IRQ handler if an IRQ comes first
+/// Command format: "step"
+///
+/// `args`: Unused
+fn cmd_step<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, args: It)
+    where It: Iterator<Item=&'a str> {
+    use cpu::BreakReason;
+    let _ = args;
+
+    let over_irqs = ctx.hwcore().step_over_irqs();
+    let symbols = ctx.symbols();
+    let mut hw = ctx.hw();
+
+    match hw.step(over_irqs) {
+        BreakReason::IrqEntry => {
+            out_info!(out, "Entered IRQ handler @ {}", symbols.describe(hw.pause_addr()))
+        }
+        BreakReason::Breakpoint => {
+            out_info!(out, "Breakpoint hit @ {}", symbols.describe(hw.pause_addr()))
+        }
+        BreakReason::Watchpoint(kind, addr) => {
+            out_info!(out, "{:?} watchpoint hit by access to {}", kind, symbols.describe(addr))
+        }
+        _ => {}
+    }
+}
+
+/// Sets whether stepping stops on entering IRQ handlers or runs through them
+/// Command format: "irqstep [enter | over]"
+///
+/// `args`: Iterator over &str items
+fn cmd_irqstep<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    match args.next() {
+        Some("enter") => ctx.hwcore_mut().set_step_over_irqs(false),
+        Some("over") => ctx.hwcore_mut().set_step_over_irqs(true),
+        None => {}
+        Some(_) => { out_info!(out, "Usage: `irqstep [enter | over]`"); return }
+    }
+
+    if ctx.hwcore().step_over_irqs() {
+        out_info!(out, "Stepping runs through IRQ handlers");
+    } else {
+        out_info!(out, "Stepping stops on entering IRQ handlers");
+    }
+}
+
+/// Starts or stops recording ARM9 execution for reverse-step and reverse-continue
+/// Command format: "history <on [interval [checkpoints [max MB]]] | off>"
+///
+/// `args`: Iterator over &str items
+fn cmd_history<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+    use history;
+
+    let usage = "Usage: `history <on [interval [checkpoints [max MB]]] | off>`";
+    match args.next() {
+        Some("on") => {
+            let parse = |arg: Option<&str>, default: usize| arg.map_or(Ok(default), str::parse);
+            let parsed = (parse(args.next(), history::DEFAULT_INTERVAL as usize),
+                          parse(args.next(), history::DEFAULT_CHECKPOINTS),
+                          parse(args.next(), history::DEFAULT_MEMORY_MB));
+            let (interval, checkpoints, memory_mb) = match parsed {
+                (Ok(i), Ok(c), Ok(m)) => (i, c, m),
+                _ => { out_info!(out, "{}", usage); return }
+            };
+            let config = history::Config {
+                interval: interval as u64,
+                max_checkpoints: checkpoints,
+                max_bytes: memory_mb.saturating_mul(1 << 20),
+            };
+
+            ctx.pause();
+            ctx.hwcore_mut().enable_history(config);
+            out_info!(out, "Recording ARM9 execution, checkpointing every {} instructions and \
+                            keeping up to {} checkpoints in {}MB",
+                      config.interval, config.max_checkpoints, memory_mb);
+        }
+        Some("off") => {
+            ctx.pause();
+            ctx.hwcore_mut().disable_history();
+            out_info!(out, "Stopped recording ARM9 execution");
+        }
+        _ => out_info!(out, "{}", usage)
+    }
+}
+
+/// Runs the ARM9 backwards by some number of instructions, or 1 if unspecified
+/// Command format: "reverse-step [count]"
+///
+/// `args`: Iterator over &str items
+fn cmd_reverse_step<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let count = match args.next().map(str::parse::<u64>) {
+        Some(Ok(count)) => count,
+        Some(Err(_)) => { out_info!(out, "Usage: `reverse-step [count]`"); return }
+        None => 1,
+    };
+
+    ctx.pause();
+    let symbols = ctx.symbols();
+    let res = ctx.hwcore_mut().reverse_step(count);
+    match res {
+        Ok(went) => {
+            let pc = symbols.describe(ctx.hw().pause_addr());
+            out_info!(out, "Went back {} instructions to {}", went, pc);
+        }
+        Err(x) => out_error!(out, "Could not step backwards; {}", x)
+    }
+}
+
+/// Runs the ARM9 backwards until the last breakpoint it hit
+/// Command format: "reverse-continue"
+///
+/// `args`: Unused
+fn cmd_reverse_continue<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, args: It)
+    where It: Iterator<Item=&'a str> {
+    let _ = args;
+
+    ctx.pause();
+    let symbols = ctx.symbols();
+    let res = ctx.hwcore_mut().reverse_continue();
+    match res {
+        Ok(found) => {
+            let pc = symbols.describe(ctx.hw().pause_addr());
+            if found {
+                out_info!(out, "Went back to breakpoint @ {}", pc);
+            } else {
+                out_info!(out, "Went back to the start of the recorded execution @ {}", pc);
+            }
+        }
+        Err(x) => out_error!(out, "Could not continue backwards; {}", x)
+    }
+}
+
+/// Controls debugger behavior based on user-provided commands. Shared by the frontends and
+/// the GDB stub's `monitor` command, so quitting is left to the frontend.
+///
+/// `command`: Iterator over &str items
+pub fn handle<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut Output, mut command: It)
+    where It: Iterator<Item=&'a str> {
+
+    match command.next() {
+        Some("run") => ctx.resume(),
+        Some("brk") => cmd_brk(ctx, out, command),
+        Some("keydmp") => cmd_keydmp(ctx, out, command),
+        Some("exectrace") => cmd_exectrace(ctx, out, command),
+        Some("history") => cmd_history(ctx, out, command),
+        Some("iotrace") => cmd_iotrace(ctx, out, command),
+        Some("irq") => cmd_irq(ctx, out, command),
+        Some("irqstep") => cmd_irqstep(ctx, out, command),
+        Some("asm") => cmd_asm(ctx, out, command),
+        Some("mem") => cmd_mem(ctx, out, command),
+        Some("reg") => cmd_reg(ctx, out, command),
+        Some("reverse-step") | Some("rstep") => cmd_reverse_step(ctx, out, command),
+        Some("reverse-continue") | Some("rcont") => cmd_reverse_continue(ctx, out, command),
+        Some("save") => cmd_save(ctx, out, command),
+        Some("load") => cmd_load(ctx, out, command),
+        Some("step") => cmd_step(ctx, out, command),
+        Some("unmapped") => cmd_unmapped(ctx, out, command),
+        Some("watch") => cmd_watch(ctx, out, command),
+        None => {},
+        Some(unk_cmd @ _) => out_error!(out, "Unrecognized command `{}`", unk_cmd),
+    }
+}
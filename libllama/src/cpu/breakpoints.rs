@@ -0,0 +1,182 @@
+use std::fmt;
+use std::str::FromStr;
+
+use cpu::Cpu;
+use utils::from_hex;
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Reg(usize),
+    Cpsr,
+    Imm(u32),
+    /// Word in memory at the address given by the inner operand plus an offset
+    Mem(Box<Operand>, u32),
+}
+
+impl Operand {
+    fn parse(string: &str) -> Result<Operand, String> {
+        let string = string.trim();
+        if string.starts_with('[') && string.ends_with(']') {
+            let inner = &string[1 .. string.len() - 1];
+            let mut parts = inner.splitn(2, '+');
+            let base = Operand::parse(parts.next().unwrap())?;
+            let offset = match parts.next() {
+                Some(offs) => from_hex(offs.trim())
+                    .map_err(|_| format!("Could not parse offset `{}`", offs.trim()))?,
+                None => 0
+            };
+            return Ok(Operand::Mem(Box::new(base), offset))
+        }
+
+        let reg = match string.to_lowercase().as_str() {
+            "sp" => Some(13),
+            "lr" => Some(14),
+            "pc" => Some(15),
+            "cpsr" => return Ok(Operand::Cpsr),
+            s if s.starts_with('r') => match s[1..].parse::<usize>() {
+                Ok(n) if n < 16 => Some(n),
+                _ => None
+            },
+            _ => None
+        };
+        if let Some(reg) = reg {
+            return Ok(Operand::Reg(reg))
+        }
+
+        from_hex(string)
+            .map(Operand::Imm)
+            .map_err(|_| format!("Could not parse operand `{}`", string))
+    }
+
+    // None if the operand reads memory that isn't RAM
+    fn eval(&self, cpu: &Cpu) -> Option<u32> {
+        match *self {
+            Operand::Reg(15) => Some(cpu.regs[15] - cpu.get_pc_offset()),
+            Operand::Reg(n) => Some(cpu.regs[n]),
+            Operand::Cpsr => Some(cpu.cpsr.raw()),
+            Operand::Imm(val) => Some(val),
+            // Peeks at the bus directly so that checking a condition can't trigger
+            // watchpoints, bus errors or IO side effects
+            Operand::Mem(ref base, offset) => base.eval(cpu).and_then(|base| {
+                cpu.mpu.memory.peek::<u32>(base.wrapping_add(offset))
+            })
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+}
+
+// Two-character operators come first so that `<=` isn't read as `<`
+const CMP_OPS: [(&'static str, CmpOp); 6] = [
+    ("==", CmpOp::Eq),
+    ("!=", CmpOp::Ne),
+    ("<=", CmpOp::Le),
+    (">=", CmpOp::Ge),
+    ("<", CmpOp::Lt),
+    (">", CmpOp::Gt),
+];
+
+/// Condition under which a breakpoint triggers, in the form `<operand> <op> <operand>`.
+/// Operands are registers (`r0`-`r15`, `sp`, `lr`, `pc`, `cpsr`), hex values, or
+/// `[operand]`/`[operand+offset hex]` for the word at that address. Comparisons are unsigned:
+/// `==`, `!=`, `<`, `<=`, `>`, `>=`.
+#[derive(Clone, Debug)]
+pub struct BrkCondition {
+    lhs: Operand,
+    op: CmpOp,
+    rhs: Operand,
+    text: String,
+}
+
+impl BrkCondition {
+    /// Whether the condition holds. Conditions reading memory other than RAM never do.
+    pub fn eval(&self, cpu: &Cpu) -> bool {
+        let (lhs, rhs) = match (self.lhs.eval(cpu), self.rhs.eval(cpu)) {
+            (Some(lhs), Some(rhs)) => (lhs, rhs),
+            _ => {
+                debug!("Could not read memory to check breakpoint condition `{}`", self.text);
+                return false
+            }
+        };
+        match self.op {
+            CmpOp::Eq => lhs == rhs,
+            CmpOp::Ne => lhs != rhs,
+            CmpOp::Le => lhs <= rhs,
+            CmpOp::Ge => lhs >= rhs,
+            CmpOp::Lt => lhs < rhs,
+            CmpOp::Gt => lhs > rhs,
+        }
+    }
+}
+
+impl FromStr for BrkCondition {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<BrkCondition, String> {
+        for &(op_str, op) in CMP_OPS.iter() {
+            if let Some(pos) = string.find(op_str) {
+                return Ok(BrkCondition {
+                    lhs: Operand::parse(&string[..pos])?,
+                    op: op,
+                    rhs: Operand::parse(&string[pos + op_str.len() ..])?,
+                    text: string.trim().to_owned(),
+                })
+            }
+        }
+        Err(format!("No comparison operator in condition `{}`", string))
+    }
+}
+
+impl fmt::Display for BrkCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Execution breakpoint. Breakpoints stay set after triggering; `hits` counts how many times
+/// the breakpoint has triggered.
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoint {
+    pub condition: Option<BrkCondition>,
+    pub hits: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_conditions() {
+        assert!("r0 == 5".parse::<BrkCondition>().is_ok());
+        assert!("[sp+8]<=0x1000".parse::<BrkCondition>().is_ok());
+        assert!("[[r1]] != cpsr".parse::<BrkCondition>().is_ok());
+        assert!("r16 == 0".parse::<BrkCondition>().is_err());
+        assert!("r0".parse::<BrkCondition>().is_err());
+        assert!("[r0+zz] > 1".parse::<BrkCondition>().is_err());
+    }
+
+    #[test]
+    fn memory_conditions() {
+        use cpu::testutil::make_cpu;
+        use cpu::ArmVersion;
+
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[]);
+        cpu.mpu.memory.write::<u32>(0x100, 0x1234);
+        cpu.regs[0] = 0x100;
+        assert!("[r0] == 0x1234".parse::<BrkCondition>().unwrap().eval(&cpu));
+
+        // Nothing is read from outside RAM, not even as a bus error
+        cpu.regs[0] = 0x100000;
+        assert!(!"[r0] == 0".parse::<BrkCondition>().unwrap().eval(&cpu));
+        assert!(!"[r0] != 0".parse::<BrkCondition>().unwrap().eval(&cpu));
+        assert!(cpu.mpu.memory.take_bus_error().is_none());
+    }
+}
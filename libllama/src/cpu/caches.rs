@@ -1,10 +1,64 @@
-use utils::cache::TinyCache;
+use std::cmp;
+use std::mem as std_mem;
+
+use cpu::tcm::{Tcm, TcmConfig};
+use cpu::timing;
+use cpu::trace::MemWrite;
 use mem;
+use snapshot::{self, Snapshot};
+use utils::bytes;
+
+const LINE_WORDS: usize = 8;
+const NUM_WAYS: usize = 4;
+
+#[derive(Copy, Clone)]
+struct CacheLine {
+    base: u32,
+    valid: bool,
+    dirty: bool,
+    data: [u32; LINE_WORDS],
+}
+
+const EMPTY_LINE: CacheLine = CacheLine {
+    base: 0,
+    valid: false,
+    dirty: false,
+    data: [0; LINE_WORDS],
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineOp {
+    Clean,
+    Invalidate,
+    CleanInvalidate,
+}
+
+/// Lines a cache maintenance operation applies to
+#[derive(Copy, Clone, Debug)]
+pub enum LineSel {
+    All,
+    /// The line holding the given address, if it's cached
+    Mva(u32),
+    /// The line at the given way (bits 30-31) and set (bits 5 and up) of the cache
+    SetWay(u32),
+}
+
+/// 4-way set-associative cache with 32-byte lines, as on the ARM946E-S. Lines are only
+/// allocated on reads, and are replaced round-robin.
+pub struct MemCache {
+    lines: Vec<CacheLine>,
+    num_sets: usize,
+    next_victim: usize,
+}
 
-pub struct MemCache(TinyCache<[u32; 8]>);
 impl MemCache {
-    fn new() -> Self {
-        MemCache(TinyCache::new())
+    fn new(size_kb: usize) -> Self {
+        let num_lines = size_kb * 1024 / (LINE_WORDS * 4);
+        MemCache {
+            lines: vec![EMPTY_LINE; num_lines],
+            num_sets: num_lines / NUM_WAYS,
+            next_victim: 0,
+        }
     }
 
     #[inline]
@@ -13,24 +67,139 @@ impl MemCache {
     }
 
     #[inline]
-    fn read<T: Copy>(&mut self, addr: u32, fallback_mem: &mem::MemController) -> T {
-        let (line_base, line_rem) = Self::decompose_addr(addr);
-        let fallback_fn = |k| fallback_mem.read::<[u32; 8]>(k);
+    fn set_index(&self, addr: u32) -> usize {
+        (addr as usize >> 5) & (self.num_sets - 1)
+    }
 
-        let buf = self.0.get_or(line_base, fallback_fn);
-        unsafe { *(((buf.as_ptr() as usize) + line_rem as usize) as *const T) }
+    // Index of the line holding the address, if it's cached
+    #[inline]
+    fn find(&self, addr: u32) -> Option<usize> {
+        let (line_base, _) = Self::decompose_addr(addr);
+        let first = self.set_index(addr) * NUM_WAYS;
+        (first .. first + NUM_WAYS).find(|&i| self.lines[i].valid && self.lines[i].base == line_base)
+    }
+
+    // Writes the line back to memory if it's dirty. Returns whether it was.
+    fn clean_index(&mut self, index: usize, mem: &mut mem::MemController) -> bool {
+        let line = &mut self.lines[index];
+        if !(line.valid && line.dirty) {
+            return false
+        }
+        mem.write::<[u32; LINE_WORDS]>(line.base, line.data);
+        line.dirty = false;
+        true
     }
 
+    // Also returns how many lines had to go over the bus: one for the line fill on a miss,
+    // plus one if a dirty line had to be written back to make room
     #[inline]
-    fn write<T: Copy>(&mut self, addr: u32, val: T) {
+    fn read<T: Copy>(&mut self, addr: u32, mem: &mut mem::MemController) -> (T, usize) {
         let (line_base, line_rem) = Self::decompose_addr(addr);
-        self.0.update_in(line_base, |k, line| {
-            unsafe { *(((line.as_mut_ptr() as usize) + line_rem as usize) as *mut T) = val; }
-        });
+        let (index, transfers) = match self.find(addr) {
+            Some(index) => (index, 0),
+            None => {
+                let index = self.set_index(addr) * NUM_WAYS + self.next_victim;
+                self.next_victim = (self.next_victim + 1) % NUM_WAYS;
+                let written_back = self.clean_index(index, mem);
+                self.lines[index] = CacheLine {
+                    base: line_base,
+                    valid: true,
+                    dirty: false,
+                    data: mem.read::<[u32; LINE_WORDS]>(line_base),
+                };
+                (index, 1 + written_back as usize)
+            }
+        };
+
+        let buf = &self.lines[index].data;
+        let val = unsafe { *(((buf.as_ptr() as usize) + line_rem as usize) as *const T) };
+        (val, transfers)
     }
 
-    pub fn invalidate(&mut self) {
-        self.0.invalidate();
+    // Updates the cached copy of the address, if there is one. Returns whether the cache
+    // absorbed the write, which only write-back lines do.
+    #[inline]
+    fn write<T: Copy>(&mut self, addr: u32, val: T, write_back: bool) -> bool {
+        let index = match self.find(addr) {
+            Some(index) => index,
+            None => return false
+        };
+        let (_, line_rem) = Self::decompose_addr(addr);
+        let line = &mut self.lines[index];
+        unsafe { *(((line.data.as_mut_ptr() as usize) + line_rem as usize) as *mut T) = val; }
+        line.dirty |= write_back;
+        write_back
+    }
+
+    fn apply_op(&mut self, index: usize, op: LineOp, mem: &mut mem::MemController) {
+        if op != LineOp::Invalidate {
+            self.clean_index(index, mem);
+        }
+        if op != LineOp::Clean {
+            self.lines[index].valid = false;
+        }
+    }
+
+    /// Cleans and/or invalidates lines. Invalidating a dirty line without cleaning it loses
+    /// its data, as on hardware.
+    pub fn maintain(&mut self, op: LineOp, sel: LineSel, mem: &mut mem::MemController) {
+        match sel {
+            LineSel::All => {
+                for index in 0..self.lines.len() {
+                    self.apply_op(index, op, mem);
+                }
+            }
+            LineSel::Mva(addr) => {
+                if let Some(index) = self.find(addr) {
+                    self.apply_op(index, op, mem);
+                }
+            }
+            LineSel::SetWay(set_way) => {
+                let way = (set_way >> 30) as usize;
+                let set = (set_way as usize >> 5) & (self.num_sets - 1);
+                self.apply_op(set * NUM_WAYS + way, op, mem);
+            }
+        }
+    }
+
+    // The cached copy of a byte, if there is one
+    fn peek(&self, addr: u32) -> Option<u8> {
+        self.find(addr).map(|index| {
+            let word = self.lines[index].data[(addr as usize & 31) / 4];
+            (word >> (8 * (addr & 3))) as u8
+        })
+    }
+
+    // Updates the cached copy of a byte, if there is one, without dirtying its line
+    fn poke(&mut self, addr: u32, val: u8) {
+        if let Some(index) = self.find(addr) {
+            let shift = 8 * (addr & 3);
+            let word = &mut self.lines[index].data[(addr as usize & 31) / 4];
+            *word = (*word & !(0xFF << shift)) | ((val as u32) << shift);
+        }
+    }
+}
+
+impl Snapshot for MemCache {
+    fn save(&self, w: &mut snapshot::Writer) {
+        for line in self.lines.iter() {
+            w.put(line.base);
+            w.put_bool(line.valid);
+            w.put_bool(line.dirty);
+            w.put(line.data);
+        }
+        w.put(self.next_victim as u64);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        for line in self.lines.iter_mut() {
+            line.base = r.get()?;
+            line.valid = r.get_bool()?;
+            line.dirty = r.get_bool()?;
+            line.data = r.get()?;
+        }
+        self.next_victim = r.get::<u64>()? as usize;
+        Ok(())
     }
 }
 
@@ -42,6 +211,84 @@ pub struct MpuRegion {
     pub enabled: bool,
     pub use_icache: bool,
     pub use_dcache: bool,
+    // Bufferable, which makes cached writes to the region write-back instead of write-through
+    pub write_back: bool,
+    // Extended access permissions, as written to CP15 c5
+    pub data_perms: u8,
+    pub instr_perms: u8,
+}
+
+// Whether the given extended access permissions let an access go ahead
+fn perms_allow(perms: u8, privileged: bool, is_write: bool) -> bool {
+    match (perms, privileged) {
+        (0b0001, true) | (0b0010, true) | (0b0011, _) => true,
+        (0b0010, false) | (0b0101, true) | (0b0110, _) => !is_write,
+        // No access, along with the reserved encodings
+        _ => false
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    /// The address isn't in any enabled region
+    Background,
+    /// The region's permissions don't allow the access in the current mode
+    Permission,
+    /// Nothing is mapped at the address, so the bus reported an error
+    External,
+}
+
+/// An access refused by the MPU
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemFault {
+    pub kind: FaultKind,
+    pub addr: u32,
+    pub is_write: bool,
+}
+
+impl MemFault {
+    /// Fault status register value for the fault. The ARM946E-S doesn't report one, so this
+    /// uses the PMSA encodings of later cores, with bit 11 set for writes.
+    pub fn status(&self) -> u32 {
+        let status = match self.kind {
+            FaultKind::Background => 0b0000,
+            FaultKind::Permission => 0b1101,
+            FaultKind::External => 0b1000,
+        };
+        status | ((self.is_write as u32) << 11)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access
+}
+
+impl WatchKind {
+    fn matches(self, is_write: bool) -> bool {
+        match self {
+            WatchKind::Read => !is_write,
+            WatchKind::Write => is_write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+/// Data watchpoint on the `len` bytes starting at `addr`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Caching {
+    Uncached,
+    WriteThrough,
+    WriteBack,
 }
 
 pub struct Mpu {
@@ -52,7 +299,21 @@ pub struct Mpu {
 
     pub memory: mem::MemController,
     pub icache: MemCache,
-    pub dcache: MemCache
+    pub dcache: MemCache,
+    itcm: Option<Tcm>,
+    dtcm: Option<Tcm>,
+
+    pub watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<(WatchKind, u32)>,
+
+    /// Whether accesses are checked against privileged rather than User mode permissions
+    pub privileged: bool,
+    fault: Option<MemFault>,
+
+    // Cycles spent waiting on memory since the last call to take_stall_cycles
+    stall_cycles: usize,
+    // Data writes made since the last call to take_writes, if they're being logged
+    write_log: Option<Vec<MemWrite>>,
 }
 
 impl Mpu {
@@ -63,48 +324,364 @@ impl Mpu {
             dcache_enabled: false,
             regions: [Default::default(); 8],
             memory: memory,
-            icache: MemCache::new(),
-            dcache: MemCache::new(),
+            icache: MemCache::new(8),
+            dcache: MemCache::new(4),
+            itcm: None,
+            dtcm: None,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            privileged: true,
+            fault: None,
+            stall_cycles: 0,
+            write_log: None,
         }
     }
 
-    fn addr_region(&self, addr: u32) -> &MpuRegion {
-        for region in self.regions.iter().rev() {
-            if region.enabled && ((addr >> region.size_exp as u32) == region.base_sigbits) {
-                return region
+    /// Starts or stops logging data writes, for execution traces
+    pub fn log_writes(&mut self, enable: bool) {
+        self.write_log = if enable { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns the data writes made since the last call, if they're being logged
+    pub fn take_writes(&mut self) -> Vec<MemWrite> {
+        match self.write_log {
+            Some(ref mut log) => std_mem::replace(log, Vec::new()),
+            None => Vec::new()
+        }
+    }
+
+    /// Hands the core its TCMs, which must already be mapped into its memory
+    pub fn attach_tcms(&mut self, itcm: Tcm, dtcm: Tcm) {
+        self.itcm = Some(itcm);
+        self.dtcm = Some(dtcm);
+    }
+
+    /// Moves the TCMs, if the core has any
+    pub fn configure_tcms(&mut self, itcm: TcmConfig, dtcm: TcmConfig) {
+        if let Some(ref mut tcm) = self.itcm {
+            tcm.configure(itcm, &mut self.memory);
+        }
+        if let Some(ref mut tcm) = self.dtcm {
+            tcm.configure(dtcm, &mut self.memory);
+        }
+    }
+
+    fn in_tcm(&self, addr: u32) -> bool {
+        self.itcm.as_ref().map_or(false, |tcm| tcm.config().contains(addr))
+            || self.dtcm.as_ref().map_or(false, |tcm| tcm.config().contains(addr))
+    }
+
+    // Higher-numbered regions take priority where they overlap
+    fn addr_region(&self, addr: u32) -> Option<&MpuRegion> {
+        self.regions.iter().rev()
+            .find(|region| region.enabled && (addr >> region.size_exp as u32) == region.base_sigbits)
+    }
+
+    // Checks an access against the region it falls in, recording a fault if it's refused.
+    // Returns how the region is cached for the access, or None if it was refused.
+    fn check_access(&mut self, addr: u32, is_fetch: bool, is_write: bool) -> Option<Caching> {
+        if !self.enabled {
+            return Some(Caching::Uncached)
+        }
+
+        let kind = match self.addr_region(addr) {
+            None => FaultKind::Background,
+            Some(region) => {
+                let (perms, cacheable) = if is_fetch {
+                    (region.instr_perms, self.icache_enabled && region.use_icache)
+                } else {
+                    (region.data_perms, self.dcache_enabled && region.use_dcache)
+                };
+                if perms_allow(perms, self.privileged, is_write) {
+                    // TCMs are never cached
+                    let cacheable = cacheable && !self.in_tcm(addr);
+                    return Some(match (cacheable, region.write_back) {
+                        (false, _) => Caching::Uncached,
+                        (true, false) => Caching::WriteThrough,
+                        (true, true) => Caching::WriteBack,
+                    })
+                }
+                FaultKind::Permission
+            }
+        };
+
+        trace!("MPU refused {} access to {:#010X}: {:?}",
+               if is_write { "write" } else { "read" }, addr, kind);
+        if self.fault.is_none() {
+            self.fault = Some(MemFault { kind: kind, addr: addr, is_write: is_write });
+        }
+        None
+    }
+
+    /// Returns the first access the MPU refused since the last call. Refused reads return
+    /// zero and refused writes are dropped, so the core must raise an abort for it.
+    pub fn take_fault(&mut self) -> Option<MemFault> {
+        self.fault.take()
+    }
+
+    /// Whether an access was refused since the last `take_fault`. Loads and stores check this
+    /// before writing any registers, so an aborted one leaves them as they were.
+    pub fn faulted(&self) -> bool {
+        self.fault.is_some()
+    }
+
+    // Records a bus error from the last access as a fault, so the core aborts on it too
+    fn check_bus_error(&mut self) {
+        if let Some(err) = self.memory.take_bus_error() {
+            if self.fault.is_none() {
+                self.fault = Some(MemFault {
+                    kind: FaultKind::External,
+                    addr: err.addr,
+                    is_write: err.is_write
+                });
             }
         }
-        panic!("Attempted to read memory from {:#08X} in nonexistent MpuRegion!", addr);
     }
 
-    fn icache_enabled(&self) -> bool {
-        self.enabled & self.icache_enabled
+    fn cached_read_stall(&self, addr: u32, line_transfers: usize) -> usize {
+        line_transfers * (self.memory.wait_states(addr) + timing::LINE_FILL)
+    }
+
+    /// Returns the number of cycles memory accesses have stalled for since the last call
+    pub fn take_stall_cycles(&mut self) -> usize {
+        let cycles = self.stall_cycles;
+        self.stall_cycles = 0;
+        cycles
     }
 
-    fn dcache_enabled(&self) -> bool {
-        self.enabled & self.dcache_enabled
+    /// Applies a maintenance operation to the I-cache
+    pub fn icache_maintain(&mut self, op: LineOp, sel: LineSel) {
+        self.icache.maintain(op, sel, &mut self.memory);
+    }
+
+    /// Applies a maintenance operation to the D-cache
+    pub fn dcache_maintain(&mut self, op: LineOp, sel: LineSel) {
+        self.dcache.maintain(op, sel, &mut self.memory);
+        self.check_bus_error();
+    }
+
+    /// Reads memory as the core would see it, including data still in the D-cache, without
+    /// disturbing the caches
+    pub fn debug_read_buf(&self, addr: u32, buf: &mut [u8]) {
+        self.memory.read_buf(addr, buf);
+        // The debugger poking at unmapped memory is no concern of the core's
+        self.memory.take_bus_error();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let byte_addr = addr.wrapping_add(i as u32);
+            if let Some(cached) = self.dcache.peek(byte_addr) {
+                *byte = cached;
+            }
+        }
+    }
+
+    /// Writes memory for the debugger, updating any cached copies so the core sees the new
+    /// data, e.g. for software breakpoints
+    pub fn debug_write_buf(&mut self, addr: u32, buf: &[u8]) {
+        self.memory.write_buf(addr, buf);
+        self.memory.take_bus_error();
+        for (i, byte) in buf.iter().enumerate() {
+            let byte_addr = addr.wrapping_add(i as u32);
+            self.icache.poke(byte_addr, *byte);
+            self.dcache.poke(byte_addr, *byte);
+        }
     }
 
     pub fn imem_read<T: Copy>(&mut self, addr: u32) -> T {
-        if self.icache_enabled() && self.addr_region(addr).use_icache {
-            self.icache.read(addr, &self.memory)
+        let caching = match self.check_access(addr, true, false) {
+            Some(caching) => caching,
+            None => return unsafe { std_mem::zeroed() }
+        };
+        let val = if caching != Caching::Uncached {
+            let (val, transfers) = self.icache.read(addr, &mut self.memory);
+            let stall = self.cached_read_stall(addr, transfers);
+            self.stall_cycles += stall;
+            val
         } else {
+            let stall = self.memory.wait_states(addr);
+            self.stall_cycles += stall;
             self.memory.read(addr)
+        };
+        self.check_bus_error();
+        val
+    }
+
+    fn check_watchpoints(&mut self, addr: u32, size: usize, is_write: bool) {
+        if self.watch_hit.is_some() {
+            return
+        }
+        let start = addr as u64;
+        let end = start + size as u64;
+        for wp in self.watchpoints.iter() {
+            let wp_start = wp.addr as u64;
+            let wp_end = wp_start + wp.len as u64;
+            if wp.kind.matches(is_write) && start < wp_end && wp_start < end {
+                self.watch_hit = Some((wp.kind, cmp::max(addr, wp.addr)));
+                return
+            }
         }
     }
 
+    /// Returns the kind of the first watchpoint triggered since the last call, along with
+    /// the watched address that was accessed
+    pub fn take_watch_hit(&mut self) -> Option<(WatchKind, u32)> {
+        self.watch_hit.take()
+    }
+
     pub fn dmem_read<T: Copy>(&mut self, addr: u32) -> T {
-        if self.dcache_enabled() && self.addr_region(addr).use_dcache {
-            self.dcache.read(addr, &self.memory)
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, std_mem::size_of::<T>(), false);
+        }
+        let caching = match self.check_access(addr, false, false) {
+            Some(caching) => caching,
+            None => return unsafe { std_mem::zeroed() }
+        };
+        let val = if caching != Caching::Uncached {
+            let (val, transfers) = self.dcache.read(addr, &mut self.memory);
+            let stall = self.cached_read_stall(addr, transfers);
+            self.stall_cycles += stall;
+            val
         } else {
+            let stall = self.memory.wait_states(addr);
+            self.stall_cycles += stall;
             self.memory.read(addr)
-        }
+        };
+        self.check_bus_error();
+        val
     }
 
     pub fn dmem_write<T: Copy>(&mut self, addr: u32, val: T) {
-        self.memory.write(addr, val);
-        if self.dcache_enabled() && self.addr_region(addr).use_dcache {
-            self.dcache.write(addr, val);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, std_mem::size_of::<T>(), true);
+        }
+        let caching = match self.check_access(addr, false, true) {
+            Some(caching) => caching,
+            None => return
+        };
+        if let Some(ref mut log) = self.write_log {
+            let data = unsafe { bytes::from_val(&val) };
+            log.push(MemWrite {
+                addr: addr,
+                size: data.len() as u8,
+                value: data.iter().rev().fold(0u64, |acc, byte| (acc << 8) | *byte as u64),
+            });
+        }
+        // Only writes that hit a write-back line stay in the cache; the rest go out on the bus
+        let absorbed = caching != Caching::Uncached
+            && self.dcache.write(addr, val, caching == Caching::WriteBack);
+        if !absorbed {
+            let stall = self.memory.wait_states(addr);
+            self.stall_cycles += stall;
+            self.memory.write(addr, val);
+        }
+        self.check_bus_error();
+    }
+}
+
+// The memory map is shared between cores and DMA controllers, so whoever owns the bus
+// saves it instead
+impl Snapshot for Mpu {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put_bool(self.enabled);
+        w.put_bool(self.icache_enabled);
+        w.put_bool(self.dcache_enabled);
+        for region in self.regions.iter() {
+            w.put(region.base_sigbits);
+            w.put(region.size_exp);
+            w.put_bool(region.enabled);
+            w.put_bool(region.use_icache);
+            w.put_bool(region.use_dcache);
+            w.put(region.data_perms);
+            w.put(region.instr_perms);
+            w.put_bool(region.write_back);
+        }
+        self.icache.save(w);
+        self.dcache.save(w);
+        for tcm in self.itcm.iter().chain(self.dtcm.iter()) {
+            tcm.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.enabled = r.get_bool()?;
+        self.icache_enabled = r.get_bool()?;
+        self.dcache_enabled = r.get_bool()?;
+        for region in self.regions.iter_mut() {
+            region.base_sigbits = r.get()?;
+            region.size_exp = r.get()?;
+            region.enabled = r.get_bool()?;
+            region.use_icache = r.get_bool()?;
+            region.use_dcache = r.get_bool()?;
+            region.data_perms = r.get()?;
+            region.instr_perms = r.get()?;
+            region.write_back = r.get_bool()?;
+        }
+        self.icache.load(r)?;
+        self.dcache.load(r)?;
+        if let Some(ref mut tcm) = self.itcm {
+            tcm.load(r, &mut self.memory)?;
+        }
+        if let Some(ref mut tcm) = self.dtcm {
+            tcm.load(r, &mut self.memory)?;
         }
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_mpu(write_back: bool) -> Mpu {
+        let mut memory = mem::MemController::new();
+        memory.map_region(0x0, mem::AddressBlock::SharedRam(mem::SharedMemoryBlock::new(4)));
+
+        let mut mpu = Mpu::new(memory);
+        mpu.enabled = true;
+        mpu.dcache_enabled = true;
+        mpu.regions[0] = MpuRegion {
+            base_sigbits: 0,
+            size_exp: 12,
+            enabled: true,
+            use_icache: false,
+            use_dcache: true,
+            write_back: write_back,
+            data_perms: 0b0011,
+            instr_perms: 0b0011,
+        };
+        mpu
+    }
+
+    #[test]
+    fn write_back_dcache() {
+        let mut mpu = make_mpu(true);
+        mpu.dmem_read::<u32>(0x100);
+        mpu.dmem_write::<u32>(0x104, 0xDEADBEEF);
+        assert_eq!(mpu.memory.read::<u32>(0x104), 0);
+
+        let mut buf = [0u8; 4];
+        mpu.debug_read_buf(0x104, &mut buf);
+        assert_eq!(buf, [0xEF, 0xBE, 0xAD, 0xDE]);
+
+        mpu.dcache_maintain(LineOp::Clean, LineSel::Mva(0x104));
+        assert_eq!(mpu.memory.read::<u32>(0x104), 0xDEADBEEF);
+
+        // Writes from other bus masters aren't seen until the stale line is invalidated
+        mpu.memory.write::<u32>(0x100, 0x1234);
+        assert_eq!(mpu.dmem_read::<u32>(0x100), 0);
+        mpu.dcache_maintain(LineOp::Invalidate, LineSel::SetWay(8 << 5));
+        assert_eq!(mpu.dmem_read::<u32>(0x100), 0x1234);
+    }
+
+    #[test]
+    fn write_through_dcache() {
+        let mut mpu = make_mpu(false);
+        mpu.dmem_read::<u32>(0x100);
+        mpu.dmem_write::<u32>(0x104, 0xDEADBEEF);
+        assert_eq!(mpu.memory.read::<u32>(0x104), 0xDEADBEEF);
+        assert_eq!(mpu.dmem_read::<u32>(0x104), 0xDEADBEEF);
+
+        // Invalidating never loses data that was written through
+        mpu.dcache_maintain(LineOp::Invalidate, LineSel::All);
+        assert_eq!(mpu.dmem_read::<u32>(0x104), 0xDEADBEEF);
+    }
+}
@@ -1,11 +1,28 @@
 mod sys_control;
+mod sys_control11;
 
 use cpu;
+use cpu::caches::MemFault;
+use snapshot;
 pub use self::sys_control::*;
+pub use self::sys_control11::*;
 
 pub type CpEffect = Box<Fn(&mut cpu::Cpu)>;
 
-pub trait Coprocessor {
+pub trait Coprocessor: snapshot::Snapshot {
     fn move_in(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize, val: u32) -> CpEffect;
     fn move_out(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize) -> u32;
+
+    /// Records an abort in the fault status and address registers
+    fn record_abort(&mut self, fault: &MemFault, prefetch: bool);
+    /// Returns the data fault status, instruction fault status and fault address registers
+    fn fault_regs(&self) -> (u32, u32, u32);
+
+    /// Names of the registers shown to the debugger, in the order `debug_read` and
+    /// `debug_write` number them
+    fn debug_regs(&self) -> Vec<&'static str>;
+    /// Reads a register for the debugger, without logging or other side effects
+    fn debug_read(&self, index: usize) -> u32;
+    /// Writes a register for the debugger, taking effect like an MCR would
+    fn debug_write(&mut self, index: usize, val: u32) -> CpEffect;
 }
\ No newline at end of file
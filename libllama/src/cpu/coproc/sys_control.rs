@@ -1,5 +1,10 @@
+use std::cmp;
+
 use cpu;
+use cpu::caches::{LineOp, LineSel, MemFault};
 use cpu::coproc::{CpEffect, Coprocessor};
+use cpu::tcm::TcmConfig;
+use snapshot;
 
 bitfield!(RegControl: u32, {
     use_mpu: 0 => 0,
@@ -15,6 +20,10 @@ bitfield!(RegControl: u32, {
     high_vectors: 13 => 13,
     predictable_cache: 14 => 14,
     disable_thumb: 15 => 15,
+    dtcm_enable: 16 => 16,
+    dtcm_load_mode: 17 => 17,
+    itcm_enable: 18 => 18,
+    itcm_load_mode: 19 => 19,
     low_latency_frq: 21 => 21,
     allow_unaligned: 22 => 22,
     disable_subpage_ap: 23 => 23,
@@ -29,6 +38,62 @@ bitfield!(MpuRegion: u32, {
     base_shr_12: 12 => 31
 });
 
+// The standard access permission registers hold the low two bits of each region's extended
+// permissions, packed together
+fn expand_perms(val: u32) -> u32 {
+    (0..8).fold(0, |acc, i| acc | (((val >> (2 * i)) & 0b11) << (4 * i)))
+}
+
+fn compact_perms(val: u32) -> u32 {
+    (0..8).fold(0, |acc, i| acc | (((val >> (4 * i)) & 0b11) << (2 * i)))
+}
+
+fn perms_effect(perms: u32, instr: bool) -> CpEffect {
+    Box::new(move |cpu| {
+        for (i, region) in cpu.mpu.regions.iter_mut().enumerate() {
+            let region_perms = ((perms >> (4 * i)) & 0xF) as u8;
+            if instr {
+                region.instr_perms = region_perms;
+            } else {
+                region.data_perms = region_perms;
+            }
+        }
+    })
+}
+
+fn icache_effect(op: LineOp, sel: LineSel) -> CpEffect {
+    Box::new(move |cpu| cpu.mpu.icache_maintain(op, sel))
+}
+
+fn dcache_effect(op: LineOp, sel: LineSel) -> CpEffect {
+    Box::new(move |cpu| cpu.mpu.dcache_maintain(op, sel))
+}
+
+// Registers shown to the debugger, with the CRn, CRm and opcode_2 an MCR writes them with.
+// The fault registers, which software can't access, follow these.
+const DEBUG_REGS: [(&'static str, usize, usize, usize); 16] = [
+    ("control", 1, 0, 0),
+    ("dcacheability", 2, 0, 0),
+    ("icacheability", 2, 0, 1),
+    ("bufferability", 3, 0, 0),
+    ("daccessperms", 5, 0, 2),
+    ("iaccessperms", 5, 0, 3),
+    ("region0", 6, 0, 0),
+    ("region1", 6, 1, 0),
+    ("region2", 6, 2, 0),
+    ("region3", 6, 3, 0),
+    ("region4", 6, 4, 0),
+    ("region5", 6, 5, 0),
+    ("region6", 6, 6, 0),
+    ("region7", 6, 7, 0),
+    ("dtcm", 9, 1, 0),
+    ("itcm", 9, 1, 1),
+];
+const DEBUG_FAULT_REGS: [&'static str; 3] = ["dfsr", "ifsr", "far"];
+
+// Control register at reset: high vectors, with the MPU, caches and TCMs off
+const POWER_ON_CONTROL: u32 = 0x00002078;
+
 pub struct SysControl {
     r1_control: RegControl,
     r2_dcacheability: u32,
@@ -37,6 +102,11 @@ pub struct SysControl {
     r5_daccessperms: u32,
     r5_iaccessperms: u32,
     r6_memregions: [MpuRegion; 8],
+    // The ARM946E-S has no fault status or address registers for software to read, but aborts
+    // are still recorded here for the debugger
+    dfsr: u32,
+    ifsr: u32,
+    far: u32,
     r9_dcache_lockdown: u32,
     r9_icache_lockdown: u32,
     r9_dtcm_size: u32,
@@ -46,18 +116,108 @@ pub struct SysControl {
 impl SysControl {
     pub fn new() -> SysControl {
         SysControl {
-            r1_control: RegControl::new(0),
+            // The TCMs start out where the bootrom leaves them (tcm::ITCM_BOOT and
+            // tcm::DTCM_BOOT), as that's where loaders place data
+            r1_control: RegControl::new(0x00050000),
             r2_dcacheability: 0,
             r2_icacheability: 0,
             r3_bufferability: 0,
             r5_daccessperms: 0,
             r5_iaccessperms: 0,
             r6_memregions: [MpuRegion::new(0); 8],
+            dfsr: 0,
+            ifsr: 0,
+            far: 0,
             r9_dcache_lockdown: 0,
             r9_icache_lockdown: 0,
+            r9_dtcm_size: 0xFFF0000A,
+            r9_itcm_size: 0x00000024,
+        }
+    }
+
+    /// CP15 as it is when the system is powered on, before the bootrom sets it up
+    pub fn power_on() -> SysControl {
+        SysControl {
+            r1_control: RegControl::new(POWER_ON_CONTROL),
             r9_dtcm_size: 0,
             r9_itcm_size: 0,
+            ..SysControl::new()
+        }
+    }
+
+    /// Applies the control register and TCM settings to the core
+    pub fn control_effect(&self) -> CpEffect {
+        let control = self.r1_control;
+        let (itcm, dtcm) = self.tcm_configs();
+        Box::new(move |cpu| {
+            cpu.mpu.enabled = bf!(control.use_mpu) == 1;
+            cpu.mpu.icache_enabled = bf!(control.use_icache) == 1;
+            cpu.mpu.dcache_enabled = bf!(control.use_dcache) == 1;
+            cpu.mpu.configure_tcms(itcm, dtcm);
+            cpu.set_high_vectors(bf!(control.high_vectors) == 1);
+        })
+    }
+
+    // Where c1 and c9 place the TCMs. The ITCM's base is fixed at 0.
+    fn tcm_configs(&self) -> (TcmConfig, TcmConfig) {
+        let size = |reg: u32| 512u64 << cmp::max(bits!(reg, 1 => 5), 3);
+        let control = self.r1_control;
+
+        let itcm = TcmConfig {
+            base: 0,
+            size: size(self.r9_itcm_size),
+            enabled: bf!(control.itcm_enable) == 1,
+            load_mode: bf!(control.itcm_load_mode) == 1,
+        };
+        let dtcm_size = size(self.r9_dtcm_size);
+        let dtcm = TcmConfig {
+            base: ((self.r9_dtcm_size & !0xFFF) as u64 & !(dtcm_size - 1)) as u32,
+            size: dtcm_size,
+            enabled: bf!(control.dtcm_enable) == 1,
+            load_mode: bf!(control.dtcm_load_mode) == 1,
+        };
+        (itcm, dtcm)
+    }
+}
+
+impl snapshot::Snapshot for SysControl {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.r1_control.raw());
+        w.put(self.r2_dcacheability);
+        w.put(self.r2_icacheability);
+        w.put(self.r3_bufferability);
+        w.put(self.r5_daccessperms);
+        w.put(self.r5_iaccessperms);
+        for region in self.r6_memregions.iter() {
+            w.put(region.raw());
         }
+        w.put(self.dfsr);
+        w.put(self.ifsr);
+        w.put(self.far);
+        w.put(self.r9_dcache_lockdown);
+        w.put(self.r9_icache_lockdown);
+        w.put(self.r9_dtcm_size);
+        w.put(self.r9_itcm_size);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.r1_control.set_raw(r.get()?);
+        self.r2_dcacheability = r.get()?;
+        self.r2_icacheability = r.get()?;
+        self.r3_bufferability = r.get()?;
+        self.r5_daccessperms = r.get()?;
+        self.r5_iaccessperms = r.get()?;
+        for region in self.r6_memregions.iter_mut() {
+            region.set_raw(r.get()?);
+        }
+        self.dfsr = r.get()?;
+        self.ifsr = r.get()?;
+        self.far = r.get()?;
+        self.r9_dcache_lockdown = r.get()?;
+        self.r9_icache_lockdown = r.get()?;
+        self.r9_dtcm_size = r.get()?;
+        self.r9_itcm_size = r.get()?;
+        Ok(())
     }
 }
 
@@ -69,15 +229,9 @@ impl Coprocessor for SysControl {
         match cpreg1 {
             1 => match op2 {
                 0b000 => {
-                    warn!("STUBBED: System control register write");
+                    trace!("System control register write: 0x{:08X}", val);
                     self.r1_control.set_raw(val);
-
-                    let control = self.r1_control;
-                    effect = Box::new(move |cpu| {
-                        cpu.mpu.enabled = bf!(control.use_mpu) == 1;
-                        cpu.mpu.icache_enabled = bf!(control.use_icache) == 1;
-                        cpu.mpu.dcache_enabled = bf!(control.use_dcache) == 1;
-                    });
+                    effect = self.control_effect();
                 }
                 0b001 | 0b010 => unimplemented!(),
                 _ => unreachable!()
@@ -108,18 +262,26 @@ impl Coprocessor for SysControl {
             },
 
             3 => {
-                warn!("STUBBED: Data bufferability register write");
+                trace!("Data bufferability register write");
                 self.r3_bufferability = val;
+                let bufferable = val;
+                effect = Box::new(move |cpu| {
+                    for (i, region) in cpu.mpu.regions.iter_mut().enumerate() {
+                        region.write_back = bit!(bufferable, i) == 1;
+                    }
+                });
             }
 
             5 => match op2 {
-                0 | 2 => { // TODO: verify
-                    warn!("STUBBED: Data access perms register write");
-                    self.r5_daccessperms = val;
+                0 | 2 => {
+                    trace!("Data access perms register write");
+                    self.r5_daccessperms = if op2 == 0 { expand_perms(val) } else { val };
+                    effect = perms_effect(self.r5_daccessperms, false);
                 }
-                1 | 3 => { // TODO: verify
-                    warn!("STUBBED: Instr access perms register write");
-                    self.r5_iaccessperms = val;
+                1 | 3 => {
+                    trace!("Instr access perms register write");
+                    self.r5_iaccessperms = if op2 == 1 { expand_perms(val) } else { val };
+                    effect = perms_effect(self.r5_iaccessperms, true);
                 }
                 _ => unreachable!()
             },
@@ -139,17 +301,22 @@ impl Coprocessor for SysControl {
             }
 
             7 => match (cpreg2, op2) {
-                (5, 0...2) => effect = Box::new(move |cpu| cpu.mpu.icache.invalidate()),
-                (6, 0...2) => effect = Box::new(move |cpu| cpu.mpu.dcache.invalidate()),
+                (5, 0) => effect = icache_effect(LineOp::Invalidate, LineSel::All),
+                (5, 1) => effect = icache_effect(LineOp::Invalidate, LineSel::Mva(val)),
+                (5, 2) => effect = icache_effect(LineOp::Invalidate, LineSel::SetWay(val)),
+                (6, 0) => effect = dcache_effect(LineOp::Invalidate, LineSel::All),
+                (6, 1) => effect = dcache_effect(LineOp::Invalidate, LineSel::Mva(val)),
+                (6, 2) => effect = dcache_effect(LineOp::Invalidate, LineSel::SetWay(val)),
                 (7, 0) => effect = Box::new(move |cpu| {
-                    cpu.mpu.icache.invalidate();
-                    cpu.mpu.dcache.invalidate();
+                    cpu.mpu.icache_maintain(LineOp::Invalidate, LineSel::All);
+                    cpu.mpu.dcache_maintain(LineOp::Invalidate, LineSel::All);
                 }),
-                (7, 1...2) => unimplemented!(),
-                (10, 0...2) => effect = Box::new(move |cpu| cpu.mpu.dcache.invalidate()),
-                (11, 0...2) => unimplemented!(),
-                (14, 0...2) => effect = Box::new(move |cpu| cpu.mpu.dcache.invalidate()),
-                (15, 0...2) => unimplemented!(),
+                (10, 1) => effect = dcache_effect(LineOp::Clean, LineSel::Mva(val)),
+                (10, 2) => effect = dcache_effect(LineOp::Clean, LineSel::SetWay(val)),
+                (10, 4) => {} // Drain write buffer; writes aren't buffered
+                (13, 1) => {} // Prefetch I-cache line; only a hint
+                (14, 1) => effect = dcache_effect(LineOp::CleanInvalidate, LineSel::Mva(val)),
+                (14, 2) => effect = dcache_effect(LineOp::CleanInvalidate, LineSel::SetWay(val)),
                 _ => warn!("STUBBED: Cache control register write; reg2={}, op2={}", cpreg2, op2),
             }
 
@@ -162,13 +329,16 @@ impl Coprocessor for SysControl {
                     warn!("STUBBED: ICache lockdown register write");
                     self.r9_icache_lockdown = val;
                 }
-                (1, 0) => {
-                    warn!("STUBBED: DTCM size register write");
-                    self.r9_dtcm_size = val;
-                }
-                (1, 1) => {
-                    warn!("STUBBED: ITCM size register write");
-                    self.r9_itcm_size = val;
+                (1, 0) | (1, 1) => {
+                    trace!("{} region register write", if op2 == 0 { "DTCM" } else { "ITCM" });
+                    if op2 == 0 {
+                        self.r9_dtcm_size = val;
+                    } else {
+                        self.r9_itcm_size = val;
+                    }
+
+                    let (itcm, dtcm) = self.tcm_configs();
+                    effect = Box::new(move |cpu| cpu.mpu.configure_tcms(itcm, dtcm));
                 }
                 _ => unreachable!()
             },
@@ -211,14 +381,10 @@ impl Coprocessor for SysControl {
             }
 
             5 => match op2 {
-                0 | 2 => { // TODO: verify
-                    warn!("STUBBED: Instr access perms register read");
-                    self.r5_daccessperms
-                }
-                1 | 3 => { // TODO: verify
-                    warn!("STUBBED: Data access perms register read");
-                    self.r5_iaccessperms
-                }
+                0 => compact_perms(self.r5_daccessperms),
+                1 => compact_perms(self.r5_iaccessperms),
+                2 => self.r5_daccessperms,
+                3 => self.r5_iaccessperms,
                 _ => unreachable!()
             },
 
@@ -238,14 +404,8 @@ impl Coprocessor for SysControl {
                     warn!("STUBBED: ICache lockdown register read");
                     self.r9_icache_lockdown
                 }
-                (1, 0) => {
-                    warn!("STUBBED: DTCM size register read");
-                    self.r9_dtcm_size
-                }
-                (1, 1) => {
-                    warn!("STUBBED: ITCM size register read");
-                    self.r9_itcm_size
-                }
+                (1, 0) => self.r9_dtcm_size,
+                (1, 1) => self.r9_itcm_size,
                 _ => unreachable!()
             },
 
@@ -255,4 +415,53 @@ impl Coprocessor for SysControl {
         info!("Read from CP15 reg {}; reg2={}, op2={}", cpreg1, cpreg2, op2);
         res
     }
-}
\ No newline at end of file
+
+    fn record_abort(&mut self, fault: &MemFault, prefetch: bool) {
+        if prefetch {
+            self.ifsr = fault.status();
+        } else {
+            self.dfsr = fault.status();
+        }
+        self.far = fault.addr;
+    }
+
+    fn fault_regs(&self) -> (u32, u32, u32) {
+        (self.dfsr, self.ifsr, self.far)
+    }
+
+    fn debug_regs(&self) -> Vec<&'static str> {
+        DEBUG_REGS.iter().map(|reg| reg.0).chain(DEBUG_FAULT_REGS.iter().cloned()).collect()
+    }
+
+    fn debug_read(&self, index: usize) -> u32 {
+        match DEBUG_REGS.get(index) {
+            Some(&(_, 1, _, _)) => self.r1_control.raw(),
+            Some(&(_, 2, _, 0)) => self.r2_dcacheability,
+            Some(&(_, 2, _, _)) => self.r2_icacheability,
+            Some(&(_, 3, _, _)) => self.r3_bufferability,
+            Some(&(_, 5, _, 2)) => self.r5_daccessperms,
+            Some(&(_, 5, _, _)) => self.r5_iaccessperms,
+            Some(&(_, 6, region, _)) => self.r6_memregions[region].raw(),
+            Some(&(_, 9, _, 0)) => self.r9_dtcm_size,
+            Some(&(_, 9, _, _)) => self.r9_itcm_size,
+            Some(_) => unreachable!(),
+            None => match index - DEBUG_REGS.len() {
+                0 => self.dfsr,
+                1 => self.ifsr,
+                _ => self.far,
+            }
+        }
+    }
+
+    fn debug_write(&mut self, index: usize, val: u32) -> CpEffect {
+        if let Some(&(_, cpreg1, cpreg2, op2)) = DEBUG_REGS.get(index) {
+            return self.move_in(cpreg1, cpreg2, 0, op2, val)
+        }
+        match index - DEBUG_REGS.len() {
+            0 => self.dfsr = val,
+            1 => self.ifsr = val,
+            _ => self.far = val,
+        }
+        Box::new(|_| {})
+    }
+}
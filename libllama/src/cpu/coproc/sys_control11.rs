@@ -0,0 +1,291 @@
+use cpu::caches::MemFault;
+use cpu::coproc::{CpEffect, Coprocessor};
+use snapshot;
+
+bitfield!(RegControl11: u32, {
+    use_mmu: 0 => 0,
+    align_fault: 1 => 1,
+    use_dcache: 2 => 2,
+    big_endian: 7 => 7,
+    sys_protect: 8 => 8,
+    rom_protect: 9 => 9,
+    predict_branches: 11 => 11,
+    use_icache: 12 => 12,
+    high_vectors: 13 => 13,
+    allow_unaligned: 22 => 22,
+    extended_page_table: 23 => 23,
+    exception_endian: 25 => 25,
+    tex_remap: 28 => 28,
+    force_ap: 29 => 29
+});
+
+// Main ID of an ARM11 MPCore r0p5
+const MAIN_ID: u32 = 0x410FB025;
+
+// Registers shown to the debugger, with the CRn, CRm, opcode_1 and opcode_2 that MRC and MCR
+// access them with
+const DEBUG_REGS: [(&'static str, usize, usize, usize, usize); 17] = [
+    ("main_id", 0, 0, 0, 0),
+    ("control", 1, 0, 0, 0),
+    ("aux_control", 1, 0, 0, 1),
+    ("cp_access", 1, 0, 0, 2),
+    ("ttbr0", 2, 0, 0, 0),
+    ("ttbr1", 2, 0, 0, 1),
+    ("ttbcr", 2, 0, 0, 2),
+    ("domain_access", 3, 0, 0, 0),
+    ("dfsr", 5, 0, 0, 0),
+    ("ifsr", 5, 0, 0, 1),
+    ("far", 6, 0, 0, 0),
+    ("wfar", 6, 0, 0, 1),
+    ("fcse_pid", 13, 0, 0, 0),
+    ("context_id", 13, 0, 0, 1),
+    ("thread_id_urw", 13, 0, 0, 2),
+    ("thread_id_uro", 13, 0, 0, 3),
+    ("thread_id_prw", 13, 0, 0, 4),
+];
+
+/// CP15 of the ARM11 MPCore. The MMU and caches are not emulated, so translation table and
+/// cache maintenance state is only stored for the software's benefit.
+pub struct SysControl11 {
+    r1_control: RegControl11,
+    r1_aux_control: u32,
+    r1_cp_access: u32,
+    r2_ttbr0: u32,
+    r2_ttbr1: u32,
+    r2_ttbcr: u32,
+    r3_domain_access: u32,
+    r5_dfsr: u32,
+    r5_ifsr: u32,
+    r6_far: u32,
+    r6_wfar: u32,
+    r13_fcse_pid: u32,
+    r13_context_id: u32,
+    r13_thread_ids: [u32; 3],
+}
+
+impl SysControl11 {
+    pub fn new() -> SysControl11 {
+        SysControl11 {
+            r1_control: RegControl11::new(0x00054078),
+            r1_aux_control: 0x0000000F,
+            r1_cp_access: 0,
+            r2_ttbr0: 0,
+            r2_ttbr1: 0,
+            r2_ttbcr: 0,
+            r3_domain_access: 0,
+            r5_dfsr: 0,
+            r5_ifsr: 0,
+            r6_far: 0,
+            r6_wfar: 0,
+            r13_fcse_pid: 0,
+            r13_context_id: 0,
+            r13_thread_ids: [0; 3],
+        }
+    }
+}
+
+impl snapshot::Snapshot for SysControl11 {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.r1_control.raw());
+        w.put(self.r1_aux_control);
+        w.put(self.r1_cp_access);
+        w.put(self.r2_ttbr0);
+        w.put(self.r2_ttbr1);
+        w.put(self.r2_ttbcr);
+        w.put(self.r3_domain_access);
+        w.put(self.r5_dfsr);
+        w.put(self.r5_ifsr);
+        w.put(self.r6_far);
+        w.put(self.r6_wfar);
+        w.put(self.r13_fcse_pid);
+        w.put(self.r13_context_id);
+        w.put(self.r13_thread_ids);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.r1_control.set_raw(r.get()?);
+        self.r1_aux_control = r.get()?;
+        self.r1_cp_access = r.get()?;
+        self.r2_ttbr0 = r.get()?;
+        self.r2_ttbr1 = r.get()?;
+        self.r2_ttbcr = r.get()?;
+        self.r3_domain_access = r.get()?;
+        self.r5_dfsr = r.get()?;
+        self.r5_ifsr = r.get()?;
+        self.r6_far = r.get()?;
+        self.r6_wfar = r.get()?;
+        self.r13_fcse_pid = r.get()?;
+        self.r13_context_id = r.get()?;
+        self.r13_thread_ids = r.get()?;
+        Ok(())
+    }
+}
+
+impl Coprocessor for SysControl11 {
+    fn move_in(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize, val: u32) -> CpEffect {
+        let mut effect: CpEffect = Box::new(move |cpu| {});
+        match (cpreg1, op1) {
+            (1, 0) => match op2 {
+                0 => {
+                    let control = RegControl11::new(val);
+                    if bf!(control.use_mmu) == 1 && bf!((self.r1_control).use_mmu) == 0 {
+                        warn!("STUBBED: ARM11 MMU enabled; addresses will not be translated");
+                    }
+                    if bf!(control.big_endian) == 1 {
+                        error!("Big-endian data accesses are not supported!");
+                    }
+                    self.r1_control = control;
+                }
+                1 => {
+                    warn!("STUBBED: ARM11 auxiliary control register write");
+                    self.r1_aux_control = val;
+                }
+                2 => self.r1_cp_access = val,
+                _ => warn!("STUBBED: ARM11 CP15 write to reg 1; op2={}", op2),
+            },
+
+            (2, 0) => match op2 {
+                0 => self.r2_ttbr0 = val,
+                1 => self.r2_ttbr1 = val,
+                2 => self.r2_ttbcr = val,
+                _ => warn!("STUBBED: ARM11 CP15 write to reg 2; op2={}", op2),
+            },
+
+            (3, 0) => self.r3_domain_access = val,
+
+            (5, 0) => match op2 {
+                0 => self.r5_dfsr = val,
+                1 => self.r5_ifsr = val,
+                _ => warn!("STUBBED: ARM11 CP15 write to reg 5; op2={}", op2),
+            },
+
+            (6, 0) => match op2 {
+                0 => self.r6_far = val,
+                1 => self.r6_wfar = val,
+                _ => warn!("STUBBED: ARM11 CP15 write to reg 6; op2={}", op2),
+            },
+
+            (7, 0) => match (cpreg2, op2) {
+                (0, 4) => effect = Box::new(move |cpu| cpu.wait_for_irq()),
+                (5, 4) | (10, 4) | (10, 5) => {} // Prefetch flush and barriers
+                _ => trace!("ARM11 cache maintenance operation; reg2={}, op2={}", cpreg2, op2),
+            },
+
+            (8, 0) => trace!("ARM11 TLB maintenance operation; reg2={}, op2={}", cpreg2, op2),
+
+            (13, 0) => match op2 {
+                0 => self.r13_fcse_pid = val,
+                1 => self.r13_context_id = val,
+                2...4 => self.r13_thread_ids[op2 - 2] = val,
+                _ => warn!("STUBBED: ARM11 CP15 write to reg 13; op2={}", op2),
+            },
+
+            _ => warn!("STUBBED: ARM11 CP15 write to reg {}; reg2={}, op1={}, op2={}",
+                       cpreg1, cpreg2, op1, op2)
+        };
+
+        trace!("Write 0x{:08X} to ARM11 CP15 reg {}; reg2={}, op2={}", val, cpreg1, cpreg2, op2);
+        effect
+    }
+
+    fn move_out(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize) -> u32 {
+        let res = match (cpreg1, op1) {
+            (0, 0) => match (cpreg2, op2) {
+                (0, 0) => MAIN_ID,
+                (0, 5) => 0, // We only emulate CPU 0
+                _ => {
+                    warn!("STUBBED: ARM11 ID register read; reg2={}, op2={}", cpreg2, op2);
+                    0
+                }
+            },
+
+            (1, 0) => match op2 {
+                0 => self.r1_control.raw(),
+                1 => self.r1_aux_control,
+                2 => self.r1_cp_access,
+                _ => { warn!("STUBBED: ARM11 CP15 read from reg 1; op2={}", op2); 0 }
+            },
+
+            (2, 0) => match op2 {
+                0 => self.r2_ttbr0,
+                1 => self.r2_ttbr1,
+                2 => self.r2_ttbcr,
+                _ => { warn!("STUBBED: ARM11 CP15 read from reg 2; op2={}", op2); 0 }
+            },
+
+            (3, 0) => self.r3_domain_access,
+
+            (5, 0) => match op2 {
+                0 => self.r5_dfsr,
+                1 => self.r5_ifsr,
+                _ => { warn!("STUBBED: ARM11 CP15 read from reg 5; op2={}", op2); 0 }
+            },
+
+            (6, 0) => match op2 {
+                0 => self.r6_far,
+                1 => self.r6_wfar,
+                _ => { warn!("STUBBED: ARM11 CP15 read from reg 6; op2={}", op2); 0 }
+            },
+
+            (13, 0) => match op2 {
+                0 => self.r13_fcse_pid,
+                1 => self.r13_context_id,
+                2...4 => self.r13_thread_ids[op2 - 2],
+                _ => { warn!("STUBBED: ARM11 CP15 read from reg 13; op2={}", op2); 0 }
+            },
+
+            _ => {
+                warn!("STUBBED: ARM11 CP15 read from reg {}; reg2={}, op1={}, op2={}",
+                      cpreg1, cpreg2, op1, op2);
+                0
+            }
+        };
+
+        trace!("Read from ARM11 CP15 reg {}; reg2={}, op2={}", cpreg1, cpreg2, op2);
+        res
+    }
+
+    fn record_abort(&mut self, fault: &MemFault, prefetch: bool) {
+        if prefetch {
+            self.r5_ifsr = fault.status();
+        } else {
+            self.r5_dfsr = fault.status();
+            self.r6_far = fault.addr;
+        }
+    }
+
+    fn fault_regs(&self) -> (u32, u32, u32) {
+        (self.r5_dfsr, self.r5_ifsr, self.r6_far)
+    }
+
+    fn debug_regs(&self) -> Vec<&'static str> {
+        DEBUG_REGS.iter().map(|reg| reg.0).collect()
+    }
+
+    fn debug_read(&self, index: usize) -> u32 {
+        let (_, cpreg1, _, _, op2) = DEBUG_REGS[index];
+        match (cpreg1, op2) {
+            (0, _) => MAIN_ID,
+            (1, 0) => self.r1_control.raw(),
+            (1, 1) => self.r1_aux_control,
+            (1, _) => self.r1_cp_access,
+            (2, 0) => self.r2_ttbr0,
+            (2, 1) => self.r2_ttbr1,
+            (2, _) => self.r2_ttbcr,
+            (3, _) => self.r3_domain_access,
+            (5, 0) => self.r5_dfsr,
+            (5, _) => self.r5_ifsr,
+            (6, 0) => self.r6_far,
+            (6, _) => self.r6_wfar,
+            (13, 0) => self.r13_fcse_pid,
+            (13, 1) => self.r13_context_id,
+            (13, _) => self.r13_thread_ids[op2 - 2],
+            _ => unreachable!()
+        }
+    }
+
+    fn debug_write(&mut self, index: usize, val: u32) -> CpEffect {
+        let (_, cpreg1, cpreg2, op1, op2) = DEBUG_REGS[index];
+        self.move_in(cpreg1, cpreg2, op1, op2, val)
+    }
+}
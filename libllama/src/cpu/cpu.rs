@@ -1,12 +1,24 @@
 use clock;
 use cpu;
-use cpu::caches;
+use cpu::breakpoints::Breakpoint;
+use cpu::caches::{self, MemFault, Watchpoint, WatchKind};
 use cpu::coproc;
 use cpu::irq;
 use cpu::regs::{GpRegs, Psr};
+use cpu::timing;
+use cpu::trace;
+use io::trace::Accessor;
 use mem;
+use snapshot::{self, Snapshot};
 
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::io::Write;
+
+// How many cycles pass between updates of the system clock and checks for new IRQs
+const CLOCK_SYNC_CYCLES: usize = 256;
+// Instructions an IRQ handler gets to return in when stepping over it, so a handler that
+// never returns (e.g. one that switches threads) can't hang the debugger
+const STEP_OVER_IRQ_LIMIT: u32 = 1000000;
 
 #[derive(Copy, Clone, Debug)]
 pub enum Mode {
@@ -21,7 +33,16 @@ pub enum Mode {
 
 impl Mode {
     pub fn from_num(val: u32) -> Mode {
-        match val {
+        match Mode::try_from_num(val) {
+            Some(mode) => mode,
+            None => unreachable!()
+        }
+    }
+
+    /// Like `from_num`, but for values that don't come from the CPU itself, e.g. snapshots
+    /// or a debugger, which may not name a mode at all
+    pub fn try_from_num(val: u32) -> Option<Mode> {
+        Some(match val {
             n if n == Mode::Usr as u32 => Mode::Usr,
             n if n == Mode::Fiq as u32 => Mode::Fiq,
             n if n == Mode::Irq as u32 => Mode::Irq,
@@ -29,12 +50,21 @@ impl Mode {
             n if n == Mode::Abt as u32 => Mode::Abt,
             n if n == Mode::Und as u32 => Mode::Und,
             n if n == Mode::Sys as u32 => Mode::Sys,
-            _ => unreachable!()
-        }
+            _ => return None
+        })
     }
 }
 
+/// Architecture implemented by a core: the ARM946E-S is an ARMv5TE part, the ARM11 MPCore
+/// implements ARMv6K.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ArmVersion {
+    V5TE,
+    V6K
+}
+
 pub struct Cpu {
+    pub version: ArmVersion,
     pub regs: GpRegs,
     pub cpsr: Psr,
     pub spsr_fiq: Psr,
@@ -43,14 +73,35 @@ pub struct Cpu {
     pub spsr_abt: Psr,
     pub spsr_und: Psr,
 
-    coproc_syscnt: coproc::SysControl,
+    coproc_syscnt: Box<coproc::Coprocessor + Send>,
     pub mpu: caches::Mpu,
+    pub exclusive_addr: Option<u32>,
+    // Base of the architectural vector table while the ARM9 bootrom runs. Otherwise exceptions
+    // go straight to where the bootrom's vectors redirect them.
+    arch_vectors: Option<u32>,
 
     irq_line: irq::IrqLine,
     cycles: usize,
+    // Internal cycles taken by the current instruction, on top of its base cycle and stalls
+    extra_cycles: usize,
     sys_clk: clock::SysClock,
+    waiting_for_irq: bool,
+    // Kept across calls to `run` so when IRQs are noticed only depends on the cycle count,
+    // which lets recorded execution be replayed exactly
+    irq_known_pending: bool,
+    // Instructions executed and IRQs taken since reset
+    instrs: u64,
+    // Addresses of executed instructions, for the debugger's execution history. IRQ entries
+    // are logged with the interrupted address and bit 0 set.
+    pc_log: Option<Vec<u32>>,
+
+    pub breakpoints: HashMap<u32, Breakpoint>,
+    // Address of the breakpoint the core last stopped at, which is skipped when resuming
+    step_over_brk: Option<u32>,
+    // Set by BKPT instructions to stop the core once they complete
+    bkpt_hit: bool,
 
-    pub breakpoints: HashSet<u32> // addr, is_triggered
+    tracer: Option<trace::TraceRecorder>,
 }
 
 #[derive(Clone)]
@@ -58,12 +109,27 @@ pub enum BreakReason {
     LimitReached,
     Breakpoint,
     Trapped,
-    WFI
+    WFI,
+    /// A data access triggered a watchpoint. Holds the watchpoint's kind and the watched
+    /// address that was accessed.
+    Watchpoint(WatchKind, u32),
+    /// An access hit unmapped memory while the policy for those was to break
+    BusError(mem::BusError),
+    /// A single step took an IRQ instead of executing an instruction, leaving the core at the
+    /// start of the IRQ handler
+    IrqEntry,
 }
 
 impl Cpu {
-    pub fn new(memory: mem::MemController, irq_line: irq::IrqLine, clk: clock::SysClock) -> Cpu {
+    pub fn new(version: ArmVersion, memory: mem::MemController,
+               irq_line: irq::IrqLine, clk: clock::SysClock) -> Cpu {
+        let coproc_syscnt: Box<coproc::Coprocessor + Send> = match version {
+            ArmVersion::V5TE => Box::new(coproc::SysControl::new()),
+            ArmVersion::V6K => Box::new(coproc::SysControl11::new()),
+        };
+
         Cpu {
+            version: version,
             regs: GpRegs::new(Mode::Svc),
             cpsr: Psr::new(0),
             spsr_fiq: Psr::new(0),
@@ -72,25 +138,57 @@ impl Cpu {
             spsr_abt: Psr::new(0),
             spsr_und: Psr::new(0),
 
-            coproc_syscnt: coproc::SysControl::new(),
+            coproc_syscnt: coproc_syscnt,
             mpu: caches::Mpu::new(memory),
+            exclusive_addr: None,
+            arch_vectors: None,
 
             irq_line: irq_line,
             cycles: 0usize,
+            extra_cycles: 0,
             sys_clk: clk,
+            waiting_for_irq: false,
+            irq_known_pending: false,
+            instrs: 0,
+            pc_log: None,
+
+            breakpoints: HashMap::new(),
+            step_over_brk: None,
+            bkpt_hit: false,
 
-            breakpoints: HashSet::new()
+            tracer: None,
         }
     }
 
+    /// Resets the core to run from `entry`, in Thumb state if its lowest bit is set
     pub fn reset(&mut self, entry: u32) {
         self.regs.swap(Mode::Svc);
         bf!((self.cpsr).mode = Mode::Svc as u32);
-        bf!((self.cpsr).thumb_bit = 0b0);
+        bf!((self.cpsr).thumb_bit = entry & 1);
         bf!((self.cpsr).disable_fiq_bit = 0b1);
         bf!((self.cpsr).disable_irq_bit = 0b1);
+        self.exclusive_addr = None;
+        self.waiting_for_irq = false;
 
-        self.regs[15] = entry + self.get_pc_offset();
+        self.regs[15] = (entry & !1) + self.get_pc_offset();
+    }
+
+    /// Puts the ARM9's CP15 in its power-on state instead of the one the bootrom leaves it in,
+    /// for running the bootrom itself. Exceptions then go through the bootrom's vector table.
+    pub fn power_on_cp15(&mut self) {
+        assert_eq!(self.version, ArmVersion::V5TE);
+        let cp15 = coproc::SysControl::power_on();
+        let effect = cp15.control_effect();
+        self.coproc_syscnt = Box::new(cp15);
+        self.arch_vectors = Some(0);
+        effect(self);
+    }
+
+    /// Moves the architectural vector table as CP15's V bit is changed, if it's in use
+    pub fn set_high_vectors(&mut self, high: bool) {
+        if let Some(ref mut base) = self.arch_vectors {
+            *base = if high { 0xFFFF0000 } else { 0 };
+        }
     }
 
     pub fn get_pc_offset(&self) -> u32 {
@@ -101,10 +199,14 @@ impl Cpu {
         }
     }
 
-    pub fn get_coprocessor(&mut self, cp_index: usize) -> &mut coproc::Coprocessor {
+    /// Returns the coprocessor with the given number, or None if it isn't attached to the core
+    pub fn get_coprocessor(&mut self, cp_index: usize) -> Option<&mut coproc::Coprocessor> {
         match cp_index {
-            15 => &mut self.coproc_syscnt,
-            _ => panic!("Tried to access unknown CP{}", cp_index),
+            15 => {
+                let coproc: &mut coproc::Coprocessor = &mut *self.coproc_syscnt;
+                Some(coproc)
+            }
+            _ => None,
         }
     }
 
@@ -130,38 +232,210 @@ impl Cpu {
         // TODO: Invalidate pipeline once/if we have one
     }
 
+    /// Halts the core until an interrupt is requested, even a masked one.
+    pub fn wait_for_irq(&mut self) {
+        self.waiting_for_irq = true;
+    }
+
+    /// Stops the core for the debugger after the current instruction, as if it had hit a
+    /// breakpoint.
+    pub fn trap_bkpt(&mut self) {
+        self.bkpt_hit = true;
+    }
+
+    /// Number of instructions executed and IRQs taken so far
+    pub fn instr_count(&self) -> u64 {
+        self.instrs
+    }
+
+    /// Starts or stops logging the address of each executed instruction
+    pub fn log_pcs(&mut self, enable: bool) {
+        self.pc_log = if enable { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns the instruction addresses logged since the last call
+    pub fn take_pc_log(&mut self) -> Vec<u32> {
+        match self.pc_log {
+            Some(ref mut log) => ::std::mem::replace(log, Vec::new()),
+            None => Vec::new()
+        }
+    }
+
+    /// Checks the breakpoint at the current PC as if the core had just run into it
+    pub fn at_breakpoint(&mut self) -> bool {
+        let addr = self.regs[15] - self.get_pc_offset();
+        self.check_breakpoint(addr)
+    }
+
+    /// Makes the core run past any breakpoint at the current PC when it's next resumed
+    pub fn skip_breakpoint_here(&mut self) {
+        self.step_over_brk = Some(self.regs[15] - self.get_pc_offset());
+    }
+
+    // Counts an instruction or IRQ entry at `addr` as executed
+    fn retire(&mut self, addr: u32) {
+        self.instrs += 1;
+        if let Some(ref mut log) = self.pc_log {
+            log.push(addr);
+        }
+    }
+
+    /// Starts recording every instruction the core executes into an execution trace, replacing
+    /// any trace that was already being recorded
+    pub fn start_trace(&mut self, out: Box<Write + Send>) -> trace::Result<()> {
+        self.stop_trace()?;
+        self.tracer = Some(trace::TraceRecorder::new(out, self.version)?);
+        self.mpu.log_writes(true);
+        Ok(())
+    }
+
+    /// Stops recording the execution trace, if one was being recorded
+    pub fn stop_trace(&mut self) -> trace::Result<()> {
+        self.mpu.log_writes(false);
+        match self.tracer.take() {
+            Some(mut tracer) => tracer.flush(),
+            None => Ok(())
+        }
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+
+    // Adds the instruction that just ran to the execution trace. The trace is dropped if it
+    // can't be written.
+    fn trace_instr(&mut self, addr: u32, instr: u32, thumb: bool, mode: u32) {
+        let mut state = trace::RegState { regs: [0; 15], cpsr: self.cpsr.raw() };
+        for (i, reg) in state.regs.iter_mut().enumerate() {
+            *reg = self.regs[i];
+        }
+        let writes = self.mpu.take_writes();
+
+        let res = match self.tracer {
+            Some(ref mut tracer) => tracer.record(addr, instr, thumb, mode, &state, &writes),
+            None => return
+        };
+        if let Err(x) = res {
+            error!("Failed to write execution trace, stopping it; {}", x);
+            let _ = self.stop_trace();
+        }
+    }
+
+    /// Charges internal cycles to the instruction being executed
+    pub fn add_cycles(&mut self, cycles: usize) {
+        self.extra_cycles += cycles;
+    }
+
+    // Adds up the cycles taken by the last instruction, advancing the system clock each time
+    // another CLOCK_SYNC_CYCLES have passed. Returns whether it was advanced.
+    fn charge_cycles(&mut self, cycles: &mut usize) -> bool {
+        let taken = 1 + self.extra_cycles + self.mpu.take_stall_cycles();
+        self.extra_cycles = 0;
+
+        let prev = *cycles;
+        *cycles += taken;
+        let periods = *cycles / CLOCK_SYNC_CYCLES - prev / CLOCK_SYNC_CYCLES;
+        if periods == 0 {
+            return false
+        }
+        self.sys_clk.increment(periods * CLOCK_SYNC_CYCLES / timing::CLOCK_DIVIDER);
+        true
+    }
+
     pub fn run(&mut self, num_instrs: u32) -> BreakReason {
         let mut cycles = self.cycles;
-        let mut irq_known_pending = false;
+        let mut step_over_brk = self.step_over_brk.take();
 
         for _ in 0..num_instrs {
+            if self.waiting_for_irq {
+                if !self.irq_line.is_high() {
+                    self.cycles = cycles;
+                    return BreakReason::WFI;
+                }
+                self.waiting_for_irq = false;
+                self.irq_known_pending = true;
+            }
+
             let addr = self.regs[15] - self.get_pc_offset();
 
-            cycles = cycles.wrapping_add(1);
-            // Amortize the cost of checking for IRQs, updating clock
-            if cycles % 128 == 0 {
-                self.sys_clk.increment(128 * 8); // Probably speeds up time but w/e
-                irq_known_pending = self.irq_line.is_high();
-            }
-            if irq_known_pending && bf!((self.cpsr).disable_irq_bit) == 0 && self.irq_line.is_high() {
-                trace!("ARM9 IRQ triggered!");
+            if self.irq_due() {
+                trace!("{:?} IRQ triggered!", self.version);
                 self.enter_exception(addr+4, Mode::Irq);
-                irq_known_pending = false;
+                self.add_cycles(timing::PIPELINE_REFILL);
+                self.retire(addr | 1);
+                self.irq_known_pending = false;
+                if self.charge_cycles(&mut cycles) {
+                    self.irq_known_pending = self.irq_line.is_high();
+                }
                 continue
             }
 
-            if self.find_toggle_breakpoint(addr) {
+            if step_over_brk.take() != Some(addr) && self.check_breakpoint(addr) {
+                self.step_over_brk = Some(addr);
+                self.cycles = cycles;
                 return BreakReason::Breakpoint;
             }
 
-            if bf!((self.cpsr).thumb_bit) == 0 {
+            let mode = bf!((self.cpsr).mode);
+            self.mpu.privileged = mode != Mode::Usr as u32;
+            let accessor = match self.version {
+                ArmVersion::V5TE => Accessor::Arm9(addr),
+                ArmVersion::V6K => Accessor::Arm11(addr),
+            };
+            self.mpu.memory.set_accessor(accessor);
+            let thumb = bf!((self.cpsr).thumb_bit) == 1;
+            let instr = if thumb {
+                assert_eq!(addr & 0b1, 0);
+                self.mpu.imem_read::<u16>(addr) as u32
+            } else {
                 assert_eq!(addr & 0b11, 0);
-                let instr = cpu::decoder_arm::ArmInstruction::decode(self.mpu.imem_read::<u32>(addr));
-                cpu::interpret_arm(self, instr);
+                self.mpu.imem_read::<u32>(addr)
+            };
+
+            let fetch_fault = self.mpu.take_fault();
+            if let Some(reason) = fetch_fault.and_then(|fault| self.bus_break(fault)) {
+                // Nothing sensible was fetched, so stop before executing it
+                self.cycles = cycles;
+                return reason;
+            }
+            if let Some(fault) = fetch_fault {
+                self.abort(addr, fault, true);
+            } else if thumb {
+                cpu::interpret_thumb(self, cpu::decoder_thumb::ThumbInstruction::decode(instr as u16));
             } else {
-                assert_eq!(addr & 0b1, 0);
-                let instr = cpu::decoder_thumb::ThumbInstruction::decode(self.mpu.imem_read::<u16>(addr));
-                cpu::interpret_thumb(self, instr);
+                cpu::interpret_arm(self, cpu::decoder_arm::ArmInstruction::decode(instr));
+            }
+
+            if let Some(fault) = self.mpu.take_fault() {
+                if let Some(reason) = self.bus_break(fault) {
+                    // The access left the registers alone, so rewinding the PC stops the core
+                    // on the faulting instruction as if it never ran
+                    self.branch(addr);
+                    self.cycles = cycles;
+                    return reason;
+                }
+                self.abort(addr, fault, false);
+            }
+
+            if self.tracer.is_some() {
+                self.trace_instr(addr, instr, thumb, mode);
+            }
+            self.retire(addr);
+
+            // Amortize the cost of checking for IRQs
+            if self.charge_cycles(&mut cycles) {
+                self.irq_known_pending = self.irq_line.is_high();
+            }
+
+            // Watchpoints stop the core after the accessing instruction has completed
+            if let Some((kind, watch_addr)) = self.mpu.take_watch_hit() {
+                self.cycles = cycles;
+                return BreakReason::Watchpoint(kind, watch_addr);
+            }
+            if self.bkpt_hit {
+                self.bkpt_hit = false;
+                self.cycles = cycles;
+                return BreakReason::Breakpoint;
             }
         }
 
@@ -169,7 +443,101 @@ impl Cpu {
         BreakReason::LimitReached
     }
 
+    // Whether the core takes an IRQ before executing its next instruction
+    fn irq_due(&self) -> bool {
+        (self.irq_known_pending || self.waiting_for_irq)
+            && bf!((self.cpsr).disable_irq_bit) == 0 && self.irq_line.is_high()
+    }
+
+    /// Executes exactly one instruction for the debugger, even if there's a breakpoint on it.
+    ///
+    /// If an IRQ comes first, the core only enters the IRQ handler and `IrqEntry` is returned.
+    /// With `over_irqs`, the handler is run instead until it returns to the interrupted
+    /// instruction, which is then executed; breakpoints and watchpoints in the handler still
+    /// stop the core there. If the IRQ is still raised once the handler returns, the core
+    /// enters the handler again and stops there with `IrqEntry`, rather than stepping over
+    /// the same IRQ forever.
+    pub fn step(&mut self, over_irqs: bool) -> BreakReason {
+        let addr = self.regs[15] - self.get_pc_offset();
+        let mode = bf!((self.cpsr).mode);
+
+        if self.irq_due() {
+            if !over_irqs {
+                self.run(1);
+                return BreakReason::IrqEntry
+            }
+
+            self.run(1);
+            let mut handled = false;
+            for _ in 0..STEP_OVER_IRQ_LIMIT {
+                match self.run(1) {
+                    BreakReason::LimitReached => {}
+                    reason => return reason
+                }
+                let returned = self.regs[15] - self.get_pc_offset() == addr
+                    && bf!((self.cpsr).mode) == mode;
+                if returned {
+                    handled = true;
+                    break
+                }
+            }
+            if !handled {
+                warn!("{:?} IRQ handler did not return to 0x{:X} within {} instructions",
+                      self.version, addr, STEP_OVER_IRQ_LIMIT);
+                return BreakReason::LimitReached
+            }
+            if self.irq_due() {
+                self.run(1);
+                return BreakReason::IrqEntry
+            }
+        }
+
+        self.skip_breakpoint_here();
+        self.run(1)
+    }
+
     pub fn enter_exception(&mut self, return_loc: u32, mode: Mode) {
+        let vector_offs = match mode {
+            Mode::Irq => 0x00,
+            Mode::Fiq => unimplemented!(),
+            Mode::Svc => 0x10,
+            Mode::Und => 0x18,
+            Mode::Abt => 0x28, // Data abort
+            Mode::Sys | Mode::Usr => panic!("No exception associated with {:?}", mode)
+        };
+        self.enter_exception_vector(return_loc, mode, vector_offs);
+    }
+
+    /// Takes a Prefetch Abort, which shares Abort mode with Data Aborts but has its own vector
+    pub fn enter_prefetch_abort(&mut self, return_loc: u32) {
+        self.enter_exception_vector(return_loc, Mode::Abt, 0x20);
+    }
+
+    // Accesses to unmapped memory stop the core instead of aborting, if the bus is set up so
+    fn bus_break(&self, fault: MemFault) -> Option<BreakReason> {
+        if fault.kind != caches::FaultKind::External
+                || self.mpu.memory.unmapped_policy() != mem::UnmappedPolicy::Break {
+            return None
+        }
+        Some(BreakReason::BusError(mem::BusError { addr: fault.addr, is_write: fault.is_write }))
+    }
+
+    // Raises the abort for an access the MPU refused while fetching or executing the
+    // instruction at `addr`. Aborted loads and stores don't write any registers, so the handler
+    // can retry the instruction.
+    fn abort(&mut self, addr: u32, fault: MemFault, prefetch: bool) {
+        warn!("{} abort at 0x{:X}: {:?} fault on 0x{:X}",
+              if prefetch { "Prefetch" } else { "Data" }, addr, fault.kind, fault.addr);
+        self.coproc_syscnt.record_abort(&fault, prefetch);
+        if prefetch {
+            self.enter_prefetch_abort(addr + 4);
+        } else {
+            self.enter_exception(addr + 8, Mode::Abt);
+        }
+        self.add_cycles(timing::PIPELINE_REFILL);
+    }
+
+    fn enter_exception_vector(&mut self, return_loc: u32, mode: Mode, vector_offs: u32) {
         let R14_exc = return_loc;
         let SPSR_exc = self.cpsr;
 
@@ -181,19 +549,458 @@ impl Cpu {
         bf!((self.cpsr).thumb_bit = 0);
         bf!((self.cpsr).disable_irq_bit = 1);
 
-        // These vectors look like 0x080000XX because that's where the bootrom redirects them
-        let mut vector_addr = match mode {
-            Mode::Irq => 0x08000000,
-            Mode::Fiq => unimplemented!(),
-            Mode::Svc => 0x08000010,
-            Mode::Und => 0x08000018,
-            Mode::Abt => 0x08000028,
-            Mode::Sys | Mode::Usr => panic!("No exception associated with {:?}", mode)
+        // These vectors look like 0x080000XX (ARM9) or 0x1FFFFFXX (ARM11) because that's where
+        // the bootroms redirect them
+        let vector_base = match self.version {
+            ArmVersion::V5TE => 0x08000000,
+            ArmVersion::V6K => 0x1FFFFFA0,
         };
-        self.branch(vector_addr);
+        self.exclusive_addr = None;
+        match self.arch_vectors {
+            Some(base) => self.branch(base + arch_vector(vector_offs)),
+            None => self.branch(vector_base + vector_offs),
+        }
+    }
+
+    fn check_breakpoint(&mut self, addr: u32) -> bool {
+        if self.breakpoints.is_empty() {
+            return false
+        }
+        let triggered = match self.breakpoints.get(&addr) {
+            Some(brk) => brk.condition.as_ref().map_or(true, |cond| cond.eval(self)),
+            None => false
+        };
+        if triggered {
+            self.breakpoints.get_mut(&addr).unwrap().hits += 1;
+        }
+        triggered
+    }
+}
+
+// Offset of an exception's entry in the architectural vector table, from its offset among the
+// bootrom's redirected vectors
+fn arch_vector(redirect_offs: u32) -> u32 {
+    match redirect_offs {
+        0x00 => 0x18, // IRQ
+        0x08 => 0x1C, // FIQ
+        0x10 => 0x08, // SWI
+        0x18 => 0x04, // Undefined instruction
+        0x20 => 0x0C, // Prefetch abort
+        0x28 => 0x10, // Data abort
+        _ => unreachable!()
+    }
+}
+
+// Breakpoints and watchpoints are debugger state rather than machine state, so they are
+// left alone
+impl Snapshot for Cpu {
+    fn save(&self, w: &mut snapshot::Writer) {
+        self.regs.save(w);
+        for psr in [self.cpsr, self.spsr_fiq, self.spsr_irq, self.spsr_svc,
+                    self.spsr_abt, self.spsr_und].iter() {
+            w.put(psr.raw());
+        }
+        self.coproc_syscnt.save(w);
+        self.mpu.save(w);
+        w.put_bool(self.exclusive_addr.is_some());
+        w.put(self.exclusive_addr.unwrap_or(0));
+        w.put_bool(self.arch_vectors.is_some());
+        w.put(self.arch_vectors.unwrap_or(0));
+        w.put(self.cycles as u64);
+        self.sys_clk.save(w);
+        w.put_bool(self.waiting_for_irq);
+        w.put_bool(self.irq_known_pending);
+        w.put(self.instrs);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.regs.load(r)?;
+        for psr in [&mut self.cpsr, &mut self.spsr_fiq, &mut self.spsr_irq, &mut self.spsr_svc,
+                    &mut self.spsr_abt, &mut self.spsr_und].iter_mut() {
+            psr.set_raw(r.get()?);
+        }
+        let mode = bf!((self.cpsr).mode);
+        if Mode::try_from_num(mode).is_none() {
+            bail!(snapshot::ErrorKind::Mismatch(format!("invalid CPSR mode {:#X}", mode)));
+        }
+        self.coproc_syscnt.load(r)?;
+        self.mpu.load(r)?;
+        let exclusive = r.get_bool()?;
+        let exclusive_addr = r.get()?;
+        self.exclusive_addr = if exclusive { Some(exclusive_addr) } else { None };
+        let arch_vectors = r.get_bool()?;
+        let vector_base = r.get()?;
+        self.arch_vectors = if arch_vectors { Some(vector_base) } else { None };
+        self.cycles = r.get::<u64>()? as usize;
+        self.sys_clk.load(r)?;
+        self.waiting_for_irq = r.get_bool()?;
+        self.irq_known_pending = r.get_bool()?;
+        self.instrs = r.get()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::tcm::{Tcm, TcmConfig};
+    use cpu::testutil::{make_cpu, map_vectors};
+
+    #[test]
+    fn armv6k_instructions() {
+        let mut cpu = make_cpu(ArmVersion::V6K, &[
+            0xE3A00C02, // mov r0, #0x200
+            0xE59F1014, // ldr r1, [pc, #0x14]
+            0xE1902F9F, // ldrex r2, [r0]
+            0xE1803F91, // strex r3, r1, [r0]
+            0xE1804F91, // strex r4, r1, [r0]
+            0xE6BF5F31, // rev r5, r1
+            0xE6EF6071, // uxtb r6, r1
+            0xE6AF7471, // sxtb r7, r1, ror #8
+            0x1234A680
+        ]);
+        cpu.run(8);
+
+        assert_eq!(cpu.mpu.memory.read::<u32>(0x200), 0x1234A680);
+        assert_eq!(cpu.regs[3], 0);
+        assert_eq!(cpu.regs[4], 1);
+        assert_eq!(cpu.regs[5], 0x80A63412);
+        assert_eq!(cpu.regs[6], 0x80);
+        assert_eq!(cpu.regs[7], 0xFFFFFFA6);
+    }
+
+    #[test]
+    fn armv6k_unsupported() {
+        // Big-endian data accesses aren't emulated
+        let mut cpu = make_cpu(ArmVersion::V6K, &[
+            0xF1010200, // setend be
+        ]);
+        cpu.run(1);
+        assert_eq!(bf!((cpu.cpsr).mode), Mode::Und as u32);
+        assert_eq!(bf!((cpu.cpsr).big_endian_bit), 0);
+
+        let mut cpu = make_cpu(ArmVersion::V6K, &[
+            0xE3A01007, // mov r1, #7
+            0xE1B01F9F, // ldrexd r1, [r0]
+        ]);
+        cpu.run(2);
+        assert_eq!(bf!((cpu.cpsr).mode), Mode::Und as u32);
+        assert_eq!(cpu.regs[1], 7);
+        assert_eq!(cpu.exclusive_addr, None);
+    }
+
+    #[test]
+    fn wfi() {
+        let mut cpu = make_cpu(ArmVersion::V6K, &[
+            0xE320F003, // wfi
+            0xE3A00001, // mov r0, #1
+        ]);
+
+        match cpu.run(8) {
+            BreakReason::WFI => {}
+            _ => panic!("ARM11 did not wait for an interrupt")
+        }
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 4);
+        assert_eq!(cpu.regs[0], 0);
+    }
+
+    #[test]
+    fn write_watchpoint() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3A00C02, // mov r0, #0x200
+            0xE5801000, // str r1, [r0]
+            0xE3A02001, // mov r2, #1
+        ]);
+        cpu.mpu.watchpoints.push(Watchpoint { addr: 0x200, len: 4, kind: WatchKind::Read });
+        cpu.mpu.watchpoints.push(Watchpoint { addr: 0x202, len: 1, kind: WatchKind::Write });
+
+        match cpu.run(8) {
+            BreakReason::Watchpoint(WatchKind::Write, 0x202) => {}
+            _ => panic!("Write watchpoint was not triggered")
+        }
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 8);
+        assert_eq!(cpu.regs[2], 0);
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3A00000, // mov r0, #0
+            0xE2800001, // loop: add r0, r0, #1
+            0xEAFFFFFD, // b loop
+        ]);
+        cpu.breakpoints.insert(0x4, Breakpoint {
+            condition: Some("r0 >= 2".parse().unwrap()),
+            hits: 0
+        });
+
+        for expected in 2..4 {
+            match cpu.run(100) {
+                BreakReason::Breakpoint => {}
+                _ => panic!("Breakpoint was not triggered")
+            }
+            assert_eq!(cpu.regs[0], expected);
+        }
+        assert_eq!(cpu.breakpoints[&0x4].hits, 2);
+    }
+
+    const IRQ_PROGRAM: [u32; 2] = [
+        0xE3A00001, // mov r0, #1
+        0xE3A01002, // mov r1, #2
+    ];
+
+    // Masks IRQs in the interrupted code, so it can go on once the handler returns
+    const MASKING_HANDLER: [u32; 5] = [
+        0xE3A03003, // mov r3, #3
+        0xE14F2000, // mrs r2, spsr
+        0xE3822080, // orr r2, r2, #0x80
+        0xE161F002, // msr spsr_c, r2
+        0xE25EF004, // subs pc, lr, #4
+    ];
+
+    // Raises an IRQ, which the core takes before its next instruction
+    fn raise_irq(cpu: &mut Cpu) {
+        let (mut irq_tx, irq_line) = irq::make_channel();
+        irq_tx.set_enabled(!0);
+        irq_tx.add(irq::IrqType::Timer0);
+        cpu.irq_line = irq_line;
+        cpu.irq_known_pending = true;
+        bf!((cpu.cpsr).disable_irq_bit = 0);
+    }
+
+    #[test]
+    fn step_into_irq() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &IRQ_PROGRAM);
+        map_vectors(&mut cpu, &MASKING_HANDLER);
+        raise_irq(&mut cpu);
+        match cpu.step(false) {
+            BreakReason::IrqEntry => {}
+            _ => panic!("Step did not stop at the IRQ handler")
+        }
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000000);
+        assert_eq!(cpu.regs[0], 0);
+    }
+
+    #[test]
+    fn step_over_irq() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &IRQ_PROGRAM);
+        map_vectors(&mut cpu, &MASKING_HANDLER);
+        raise_irq(&mut cpu);
+        match cpu.step(true) {
+            BreakReason::LimitReached => {}
+            _ => panic!("Step stopped in the IRQ handler")
+        }
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 4);
+        assert_eq!((cpu.regs[0], cpu.regs[3]), (1, 3));
+
+        // Stepping runs the instruction at the PC, even with a breakpoint on it
+        cpu.breakpoints.insert(0x4, Default::default());
+        match cpu.step(true) {
+            BreakReason::LimitReached => {}
+            _ => panic!("Step stopped at the breakpoint it started on")
+        }
+        assert_eq!(cpu.regs[1], 2);
+    }
+
+    #[test]
+    fn step_over_raised_irq() {
+        // The handler returns without clearing or masking the IRQ, after running long enough
+        // for the core to see that it's still raised
+        let mut cpu = make_cpu(ArmVersion::V5TE, &IRQ_PROGRAM);
+        map_vectors(&mut cpu, &[
+            0xE3A03C01, // mov r3, #0x100
+            0xE2533001, // subs r3, r3, #1
+            0x1AFFFFFD, // bne 0x08000004
+            0xE25EF004, // subs pc, lr, #4
+        ]);
+        raise_irq(&mut cpu);
+        match cpu.step(true) {
+            BreakReason::IrqEntry => {}
+            _ => panic!("Step did not stop on entering the IRQ handler again")
+        }
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000000);
+        assert_eq!((cpu.regs[0], cpu.regs[3]), (0, 0));
+    }
+
+    #[test]
+    fn dsp_instructions() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3E00102, // mvn r0, #0x80000000
+            0xE3A01001, // mov r1, #1
+            0xE1012050, // qadd r2, r0, r1
+            0xE1630180, // smulbb r3, r0, r1
+            0xE16401A0, // smultb r4, r0, r1
+            0xE1256180, // smlawb r5, r0, r1, r6
+        ]);
+        cpu.run(6);
+
+        assert_eq!(cpu.regs[2], 0x7FFFFFFF);
+        assert_eq!(bf!((cpu.cpsr).q_bit), 1);
+        assert_eq!(cpu.regs[3], 0xFFFFFFFF);
+        assert_eq!(cpu.regs[4], 0x7FFF);
+        assert_eq!(cpu.regs[5], 0x7FFF);
+    }
+
+    #[test]
+    fn undefined_instruction() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3A00001, // mov r0, #1
+            0xE7F000F0, // udf
+        ]);
+        cpu.run(2);
+
+        assert_eq!(bf!((cpu.cpsr).mode), Mode::Und as u32);
+        assert_eq!(cpu.regs[14], 0x8);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000018);
     }
 
-    pub fn find_toggle_breakpoint(&mut self, addr: u32) -> bool {
-        !self.breakpoints.is_empty() && self.breakpoints.remove(&addr)
+    #[test]
+    fn thumb_traps() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE28F0001, // add r0, pc, #1
+            0xE12FFF10, // bx r0
+            0xBE01C100, // stmia r1!, {}; bkpt #1
+            0x0000DF02, // swi #2; lsl r0, r0, #0
+        ]);
+
+        match cpu.run(100) {
+            BreakReason::Breakpoint => {}
+            _ => panic!("BKPT did not stop the core")
+        }
+        assert_eq!(cpu.regs[1], 0x40);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0xC);
+
+        cpu.run(1);
+        assert_eq!(bf!((cpu.cpsr).thumb_bit), 0);
+        assert_eq!(cpu.regs[14], 0xE);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000010);
+
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE28F0001, // add r0, pc, #1
+            0xE12FFF10, // bx r0
+            0x0000DE07, // udf #7; lsl r0, r0, #0
+        ]);
+        cpu.run(3);
+        assert_eq!(bf!((cpu.cpsr).mode), Mode::Und as u32);
+        assert_eq!(cpu.regs[14], 0xA);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000018);
+    }
+
+    #[test]
+    fn instruction_timing() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3A00003, // mov r0, #3
+            0xE3E02000, // mvn r2, #0
+            0xE0010290, // mul r1, r0, r2
+            0xE3A03C01, // mov r3, #0x100
+            0xE0040390, // mul r4, r0, r3
+            0xEAFFFFFF, // b next
+        ]);
+        cpu.run(6);
+
+        assert_eq!(cpu.regs[4], 0x300);
+        assert_eq!(cpu.cycles, 1 + 1 + 2 + 1 + 3 + 3);
+    }
+
+    #[test]
+    fn mpu_aborts() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3A00017, // mov r0, #0x17
+            0xEE060F10, // mcr p15, 0, r0, c6, c0, 0 ; Region 0: 4KB at 0x0
+            0xE3A00003, // mov r0, #3
+            0xEE050F70, // mcr p15, 0, r0, c5, c0, 3 ; Instructions: full access
+            0xE3A00005, // mov r0, #5
+            0xEE050F50, // mcr p15, 0, r0, c5, c0, 2 ; Data: privileged read-only
+            0xE3A00001, // mov r0, #1
+            0xEE010F10, // mcr p15, 0, r0, c1, c0, 0 ; Enable the MPU
+            0xE3A01C01, // mov r1, #0x100
+            0xE5912000, // ldr r2, [r1]
+            0xE5810000, // str r0, [r1]
+        ]);
+        cpu.run(11);
+
+        assert_eq!(bf!((cpu.cpsr).mode), Mode::Abt as u32);
+        assert_eq!(cpu.regs[14], 0x30);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000028);
+        assert_eq!(cpu.mpu.memory.read::<u32>(0x100), 0);
+        let (dfsr, _, far) = cpu.get_coprocessor(15).unwrap().fault_regs();
+        assert_eq!((dfsr, far), (0x80D, 0x100));
+
+        // The vectors aren't covered by any region
+        cpu.run(1);
+        assert_eq!(cpu.regs[14], 0x0800002C);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000020);
+        let (_, ifsr, far) = cpu.get_coprocessor(15).unwrap().fault_regs();
+        assert_eq!((ifsr, far), (0, 0x08000028));
+    }
+
+    #[test]
+    fn aborted_load() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3A00007, // mov r0, #7
+            0xE3A01601, // mov r1, #0x100000
+            0xE4910004, // ldr r0, [r1], #4
+        ]);
+        cpu.run(3);
+
+        assert_eq!(bf!((cpu.cpsr).mode), Mode::Abt as u32);
+        assert_eq!(cpu.regs[14], 0x10);
+        assert_eq!(cpu.regs[0], 7);
+        assert_eq!(cpu.regs[1], 0x100000);
+    }
+
+    #[test]
+    fn bus_errors() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3A01601, // mov r1, #0x100000
+            0xE5912000, // ldr r2, [r1]
+            0xE5912000, // ldr r2, [r1]
+        ]);
+        cpu.run(2);
+        assert_eq!(bf!((cpu.cpsr).mode), Mode::Abt as u32);
+        assert_eq!(cpu.regs[14], 0x0C);
+        let (dfsr, _, far) = cpu.get_coprocessor(15).unwrap().fault_regs();
+        assert_eq!((dfsr, far), (0x8, 0x100000));
+
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3A01601, // mov r1, #0x100000
+            0xE5912000, // ldr r2, [r1]
+            0xE5912000, // ldr r2, [r1]
+        ]);
+        cpu.mpu.memory.set_unmapped_policy(mem::UnmappedPolicy::Break);
+        cpu.regs[2] = 5;
+        match cpu.run(3) {
+            BreakReason::BusError(err) => assert_eq!(err.addr, 0x100000),
+            _ => panic!("Expected the core to stop at the bus error")
+        }
+        assert_eq!(bf!((cpu.cpsr).mode), Mode::Svc as u32);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x4);
+        assert_eq!(cpu.regs[2], 5);
+    }
+
+    #[test]
+    fn tcm_remapping() {
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[
+            0xE3A00601, // mov r0, #0x100000
+            0xE380000A, // orr r0, r0, #0xA
+            0xEE090F11, // mcr p15, 0, r0, c9, c1, 0 ; DTCM: 16KB at 0x100000
+            0xE3A00801, // mov r0, #0x10000
+            0xEE010F10, // mcr p15, 0, r0, c1, c0, 0 ; Enable the DTCM
+            0xE3A01601, // mov r1, #0x100000
+            0xE3A02055, // mov r2, #0x55
+            0xE5812000, // str r2, [r1]
+            0xE3A00000, // mov r0, #0
+            0xEE010F10, // mcr p15, 0, r0, c1, c0, 0 ; Disable it again
+        ]);
+        let disabled = TcmConfig { base: 0, size: 0x4000, enabled: false, load_mode: false };
+        let itcm = Tcm::new(0x20, disabled, &mut cpu.mpu.memory);
+        let dtcm = Tcm::new(0x10, disabled, &mut cpu.mpu.memory);
+        cpu.mpu.attach_tcms(itcm, dtcm);
+
+        cpu.run(8);
+        assert_eq!(cpu.mpu.memory.read::<u32>(0x100000), 0x55);
+
+        cpu.run(2);
+        assert!(cpu.mpu.memory.is_free(0x100000, 0x4000));
     }
 }
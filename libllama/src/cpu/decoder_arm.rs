@@ -1,7 +1,16 @@
 define_insts!(ArmInstruction: u32, {
     with [ {0b1111}.4; {}.28 ] // Unconditional instructions
     {
-        mod_blx: [ {0b1111101}.7; h_bit.1; signed_imm_24.24 ]
+        clrex: [ {0b11110101011111111111000000011111}.32 ],
+        cps: [ {0b111100010000}.12; imod.2; mmod.1; {0b00000000}.8; a_bit.1; i_bit.1; f_bit.1; {0}.1; mode.5 ],
+        mod_blx: [ {0b1111101}.7; h_bit.1; signed_imm_24.24 ],
+        pld: [ {0b111101}.6; i_bit.1; {1}.1; u_bit.1; {0b101}.3; rn.4; {0b1111}.4; addr_mode.12 ],
+        rfe: [ {0b1111100}.7; p_bit.1; u_bit.1; {0}.1; w_bit.1; {1}.1; rn.4; {0b0000101000000000}.16 ],
+        setend: [ {0b1111000100000001000000}.22; e_bit.1; {0b000000000}.9 ],
+        srs: [ {0b1111100}.7; p_bit.1; u_bit.1; {1}.1; w_bit.1; {0b0110100000101000}.16; mode.5 ],
+        // Must come last: every other encoding with a condition of 0b1111 is undefined, rather
+        // than the conditional instruction it would otherwise decode as
+        undefined_nv: [ {0b1111}.4; encoding.28 ]
     }
 
     with [ {}.4; {0b000}.3; {}.20; {0}.1; {}.4 ] // Data processing immediate shift
@@ -29,16 +38,30 @@ define_insts!(ArmInstruction: u32, {
     with [ {}.4; {0b00010}.5; {}.2; {0}.1; {}.15; {0}.1; {}.4 ] // Misc instructions 1
       or [ {}.4; {0b00010}.5; {}.2; {0}.1; {}.12; {0}.1; {}.2; {1}.1; {}.4 ] // Misc instructions 2
     {
+        bkpt: [ {0b1110}.4; {0b00010010}.8; immed_hi.12; {0b0111}.4; immed_lo.4 ],
         blx_2: [ cond.4; {0b000100101111111111110011}.24; rm.4 ],
         bx: [ cond.4; {0b000100101111111111110001}.24; rm.4 ],
         clz: [ cond.4; {0b000101101111}.12; rd.4; {0b1111}.4; {0b0001}.4; rm.4 ],
         mrs: [ cond.4; {0b00010}.5; r_bit.1; {0b00}.2; {0b1111}.4; rd.4; {0b000000000000}.12 ],
-        msr_2: [ cond.4; {0b00010}.5; r_bit.1; {0b10}.2; field_mask.4; {0b111100000000}.12; rm.4 ]
+        msr_2: [ cond.4; {0b00010}.5; r_bit.1; {0b10}.2; field_mask.4; {0b111100000000}.12; rm.4 ],
+        qadd: [ cond.4; {0b00010000}.8; rn.4; rd.4; {0b00000101}.8; rm.4 ],
+        qdadd: [ cond.4; {0b00010100}.8; rn.4; rd.4; {0b00000101}.8; rm.4 ],
+        qdsub: [ cond.4; {0b00010110}.8; rn.4; rd.4; {0b00000101}.8; rm.4 ],
+        qsub: [ cond.4; {0b00010010}.8; rn.4; rd.4; {0b00000101}.8; rm.4 ],
+        smlalxy: [ cond.4; {0b00010100}.8; rd_hi.4; rd_lo.4; rs.4; {1}.1; y.1; x.1; {0}.1; rm.4 ],
+        smlawy: [ cond.4; {0b00010010}.8; rd.4; rn.4; rs.4; {1}.1; y.1; {0b00}.2; rm.4 ],
+        smlaxy: [ cond.4; {0b00010000}.8; rd.4; rn.4; rs.4; {1}.1; y.1; x.1; {0}.1; rm.4 ],
+        smulwy: [ cond.4; {0b00010010}.8; rd.4; {0b0000}.4; rs.4; {1}.1; y.1; {0b10}.2; rm.4 ],
+        smulxy: [ cond.4; {0b00010110}.8; rd.4; {0b0000}.4; rs.4; {1}.1; y.1; x.1; {0}.1; rm.4 ]
     }
 
     with [ {}.4; {0b000}.3; {}.17; {1}.1; {}.2; {1}.1; {}.4 ] // Multiplies, extra loads/stores
     {
         ldrd: [ cond.4; {0b000}.3; p_bit.1; u_bit.1; i_bit.1; w_bit.1; {0}.1; rn.4; rd.4; addr_mode_hi.4; {0b1101}.4; addr_mode_lo.4 ],
+        ldrex: [ cond.4; {0b00011001}.8; rn.4; rd.4; {0b111110011111}.12 ],
+        ldrexb: [ cond.4; {0b00011101}.8; rn.4; rd.4; {0b111110011111}.12 ],
+        ldrexd: [ cond.4; {0b00011011}.8; rn.4; rd.4; {0b111110011111}.12 ],
+        ldrexh: [ cond.4; {0b00011111}.8; rn.4; rd.4; {0b111110011111}.12 ],
         ldrh: [ cond.4; {0b000}.3; p_bit.1; u_bit.1; i_bit.1; w_bit.1; {1}.1; rn.4; rd.4; addr_mode_hi.4; {0b1011}.4; addr_mode_lo.4 ],
         ldrsb: [ cond.4; {0b000}.3; p_bit.1; u_bit.1; i_bit.1; w_bit.1; {1}.1; rn.4; rd.4; addr_mode_hi.4; {0b1101}.4; addr_mode_lo.4 ],
         ldrsh: [ cond.4; {0b000}.3; p_bit.1; u_bit.1; i_bit.1; w_bit.1; {1}.1; rn.4; rd.4; addr_mode_hi.4; {0b1111}.4; addr_mode_lo.4 ],
@@ -47,9 +70,14 @@ define_insts!(ArmInstruction: u32, {
         smlal: [ cond.4; {0b0000111}.7; s_bit.1; rd_hi.4; rd_lo.4; rs.4; {0b1001}.4; rm.4 ],
         smull: [ cond.4; {0b0000110}.7; s_bit.1; rd_hi.4; rd_lo.4; rs.4; {0b1001}.4; rm.4 ],
         strd: [ cond.4; {0b000}.3; p_bit.1; u_bit.1; i_bit.1; w_bit.1; {0}.1; rn.4; rd.4; addr_mode_hi.4; {0b1111}.4; addr_mode_lo.4 ],
+        strex: [ cond.4; {0b00011000}.8; rn.4; rd.4; {0b11111001}.8; rm.4 ],
+        strexb: [ cond.4; {0b00011100}.8; rn.4; rd.4; {0b11111001}.8; rm.4 ],
+        strexd: [ cond.4; {0b00011010}.8; rn.4; rd.4; {0b11111001}.8; rm.4 ],
+        strexh: [ cond.4; {0b00011110}.8; rn.4; rd.4; {0b11111001}.8; rm.4 ],
         strh: [ cond.4; {0b000}.3; p_bit.1; u_bit.1; i_bit.1; w_bit.1; {0}.1; rn.4; rd.4; addr_mode_hi.4; {0b1011}.4; addr_mode_lo.4 ],
         swp: [ cond.4; {0b00010000}.8; rn.4; rd.4; {0b0000}.4; {0b1001}.4; rm.4 ],
         swpb: [ cond.4; {0b00010100}.8; rn.4; rd.4; {0b0000}.4; {0b1001}.4; rm.4 ],
+        umaal: [ cond.4; {0b00000100}.8; rd_hi.4; rd_lo.4; rs.4; {0b1001}.4; rm.4 ],
         umlal: [ cond.4; {0b0000101}.7; s_bit.1; rd_hi.4; rd_lo.4; rs.4; {0b1001}.4; rm.4 ],
         umull: [ cond.4; {0b0000100}.7; s_bit.1; rd_hi.4; rd_lo.4; rs.4; {0b1001}.4; rm.4 ]
     }
@@ -63,6 +91,29 @@ define_insts!(ArmInstruction: u32, {
         strb: [ cond.4; {0b01}.2; i_bit.1; p_bit.1; u_bit.1; {1}.1; w_bit.1; {0}.1; rn.4; rd.4; addr_mode.12 ]
     }
 
+    with [ {}.4; {0b011}.3; {}.20; {1}.1; {}.4 ] // Media instructions
+    {
+        pkhbt: [ cond.4; {0b01101000}.8; rn.4; rd.4; shift_imm.5; {0b001}.3; rm.4 ],
+        pkhtb: [ cond.4; {0b01101000}.8; rn.4; rd.4; shift_imm.5; {0b101}.3; rm.4 ],
+        rev: [ cond.4; {0b011010111111}.12; rd.4; {0b11110011}.8; rm.4 ],
+        rev16: [ cond.4; {0b011010111111}.12; rd.4; {0b11111011}.8; rm.4 ],
+        revsh: [ cond.4; {0b011011111111}.12; rd.4; {0b11111011}.8; rm.4 ],
+        ssat: [ cond.4; {0b0110101}.7; sat_imm.5; rd.4; shift_imm.5; shift.1; {0b01}.2; rm.4 ],
+        sxtb: [ cond.4; {0b011010101111}.12; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        sxtb16: [ cond.4; {0b011010001111}.12; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        sxth: [ cond.4; {0b011010111111}.12; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        sxtab: [ cond.4; {0b01101010}.8; rn.4; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        sxtab16: [ cond.4; {0b01101000}.8; rn.4; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        sxtah: [ cond.4; {0b01101011}.8; rn.4; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        usat: [ cond.4; {0b0110111}.7; sat_imm.5; rd.4; shift_imm.5; shift.1; {0b01}.2; rm.4 ],
+        uxtb: [ cond.4; {0b011011101111}.12; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        uxtb16: [ cond.4; {0b011011001111}.12; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        uxth: [ cond.4; {0b011011111111}.12; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        uxtab: [ cond.4; {0b01101110}.8; rn.4; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        uxtab16: [ cond.4; {0b01101100}.8; rn.4; rd.4; rotate.2; {0b000111}.6; rm.4 ],
+        uxtah: [ cond.4; {0b01101111}.8; rn.4; rd.4; rotate.2; {0b000111}.6; rm.4 ]
+    }
+
     with [ {}.4; {0b100}.3; {}.25 ] // Load/store multiple
     {
         ldm_1: [ cond.4; {0b100}.3; p_bit.1; u_bit.1; {0}.1; w_bit.1; {1}.1; rn.4; register_list.16 ],
@@ -75,8 +126,16 @@ define_insts!(ArmInstruction: u32, {
     with [ {}.32 ] // Other
     {
         bbl: [ cond.4; {0b101}.3; link_bit.1; signed_imm_24.24 ],
+        cdp: [ cond.4; {0b1110}.4; opcode_1.4; crn.4; crd.4; cp_num.4; opcode_2.3; {0}.1; crm.4 ],
+        hint: [ cond.4; {0b0011001000001111}.16; {0b0000}.4; hint.8 ],
         mcr: [ cond.4; {0b1110}.4; opcode_1.3; {0}.1; crn.4; rd.4; cp_num.4; opcode_2.3; {1}.1; crm.4 ],
+        mcrr: [ cond.4; {0b11000100}.8; rn.4; rd.4; cp_num.4; opcode.4; crm.4 ],
         mrc: [ cond.4; {0b1110}.4; opcode_1.3; {1}.1; crn.4; rd.4; cp_num.4; opcode_2.3; {1}.1; crm.4 ],
+        mrrc: [ cond.4; {0b11000101}.8; rn.4; rd.4; cp_num.4; opcode.4; crm.4 ],
+        // LDC and STC must come after MCRR and MRRC, which take up their encodings with
+        // P, U and W all clear
+        ldc: [ cond.4; {0b110}.3; p_bit.1; u_bit.1; n_bit.1; w_bit.1; {1}.1; rn.4; crd.4; cp_num.4; offset_8.8 ],
+        stc: [ cond.4; {0b110}.3; p_bit.1; u_bit.1; n_bit.1; w_bit.1; {0}.1; rn.4; crd.4; cp_num.4; offset_8.8 ],
         msr_1: [ cond.4; {0b00110}.5; r_bit.1; {0b10}.2; field_mask.4; {0b1111}.4; shifter_operand.12 ],
         swi: [ cond.4; {0b1111}.4; swi_index.24 ]
     }
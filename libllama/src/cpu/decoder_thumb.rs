@@ -12,9 +12,13 @@ define_insts!(ThumbInstruction: u16, {
         and: [ {0b0100000000}.10; rm.3; rd.3 ],
         asr_1: [ {0b00010}.5; immed_5.5; rm.3; rd.3 ],
         asr_2: [ {0b0100000100}.10; rs.3; rd.3 ],
+        // Must come before b_1: conditions 0b1110 and 0b1111 encode these rather than branches
+        swi: [ {0b11011111}.8; immed_8.8 ],
+        udf: [ {0b11011110}.8; immed_8.8 ],
         b_1: [ {0b1101}.4; cond.4; signed_imm_8.8 ],
         bic: [ {0b0100001110}.10; rm.3; rd.3 ],
         bkpt: [ {0b10111110}.8; immed_8.8 ],
+        blx_1: [ {0b11101}.5; offset_11.11 ],
         branch: [ {0b111}.3; h_bits.2; offset_11.11 ],
         blx_2: [ {0b010001111}.9; h2.1; rm.3; {0b000}.3 ],
         bx: [ {0b010001110}.9; h2.1; rm.3; {0b000}.3 ],
@@ -22,6 +26,7 @@ define_insts!(ThumbInstruction: u16, {
         cmp_1: [ {0b00101}.5; rn.3; immed_8.8 ],
         cmp_2: [ {0b0100001010}.10; rm.3; rn.3 ],
         cmp_3: [ {0b01000101}.8; h1.1; h2.1; rm.3; rn.3 ],
+        cps: [ {0b10110110011}.11; imod.1; {0}.1; a_bit.1; i_bit.1; f_bit.1 ],
         eor: [ {0b0100000001}.10; rm.3; rd.3 ],
         ldmia: [ {0b11001}.5; rn.3; register_list.8 ],
         ldr_1: [ {0b01101}.5; immed_5.5; rn.3; rd.3 ],
@@ -47,8 +52,12 @@ define_insts!(ThumbInstruction: u16, {
         orr: [ {0b0100001100}.10; rm.3; rd.3 ],
         pop: [ {0b1011110}.7; r_bit.1; register_list.8 ],
         push: [ {0b1011010}.7; r_bit.1; register_list.8 ],
+        rev: [ {0b1011101000}.10; rn.3; rd.3 ],
+        rev16: [ {0b1011101001}.10; rn.3; rd.3 ],
+        revsh: [ {0b1011101011}.10; rn.3; rd.3 ],
         ror: [ {0b0100000111}.10; rs.3; rd.3 ],
         sbc: [ {0b0100000110}.10; rm.3; rd.3 ],
+        setend: [ {0b101101100101}.12; e_bit.1; {0b000}.3 ],
         stmia: [ {0b11000}.5; rn.3; register_list.8 ],
         str_1: [ {0b01100}.5; immed_5.5; rn.3; rd.3 ],
         str_2: [ {0b0101000}.7; rm.3; rn.3; rd.3 ],
@@ -61,7 +70,10 @@ define_insts!(ThumbInstruction: u16, {
         sub_2: [ {0b00111}.5; rd.3; immed_8.8 ],
         sub_3: [ {0b0001101}.7; rm.3; rn.3; rd.3 ],
         sub_4: [ {0b101100001}.9; immed_7.7 ],
-        swi: [ {0b11011111}.8; immed_8.8 ],
-        tst: [ {0b0100001000}.10; rm.3; rn.3 ]
+        sxtb: [ {0b1011001001}.10; rm.3; rd.3 ],
+        sxth: [ {0b1011001000}.10; rm.3; rd.3 ],
+        tst: [ {0b0100001000}.10; rm.3; rn.3 ],
+        uxtb: [ {0b1011001011}.10; rm.3; rd.3 ],
+        uxth: [ {0b1011001010}.10; rm.3; rd.3 ]
     }
 });
\ No newline at end of file
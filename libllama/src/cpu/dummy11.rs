@@ -44,22 +44,12 @@ pub mod modes {
         {
             let (a, bn) = (&mut program.arena, program.base_node);
 
-            const PXI_SYNC_ADDR: u32 = 0x10163000;
-            fn pxisync_read(hw: &Dummy11HW) -> u8 {
-                (hw.memory.read::<u32>(PXI_SYNC_ADDR) >> 8) as u8
-            }
-            fn pxisync_write(hw: &mut Dummy11HW, val: u8) {
-                let current = hw.memory.read::<u32>(PXI_SYNC_ADDR);
-                let new = current & 0xFFFFFF00 | val as u32;
-                hw.memory.write::<u32>(PXI_SYNC_ADDR, new);
-            }
-
-            let while_node = dmnode!(in a, bn; while |_, hw| Ok(pxisync_read(hw) != 9));
+            let while_node = dmnode!(in a, bn; while |_, hw| Ok(hw.pxi_sync_read() != 9));
             {
                 dmnode!(in a, while_node; do |_, _| Ok(thread::yield_now()));
             }
 
-            dmnode!(in a, bn; do |_, hw| Ok(pxisync_write(hw, 11)));
+            dmnode!(in a, bn; do |_, hw| Ok(hw.pxi_sync_write(11)));
         }
         program.build()
     }
@@ -84,12 +74,79 @@ pub mod modes {
         }
         program.build()
     }
+
+    /// Sends every word the ARM9 puts in the PXI FIFO straight back to it
+    pub fn pxi_echo() -> BoxedSteppable {
+        // A received word waiting for room in the send FIFO
+        let mut program = Program::<Option<u32>>::new(None);
+        {
+            let (a, bn) = (&mut program.arena, program.base_node);
+
+            dmnode!(in a, bn; do |_, hw| Ok(hw.pxi_enable_fifos()));
+
+            let while_node = dmnode!(in a, bn; while |_, _| Ok(true));
+            {
+                dmnode!(in a, while_node; do |pending, hw| {
+                    if pending.is_none() {
+                        *pending = hw.pxi_recv();
+                    }
+                    if let Some(word) = *pending {
+                        if hw.pxi_send(word) {
+                            *pending = None;
+                        }
+                    }
+                    Ok(())
+                });
+                dmnode!(in a, while_node; do |_, _| Ok(thread::yield_now()));
+            }
+        }
+        program.build()
+    }
 }
 
 struct Dummy11HW {
     memory: mem::MemController
 }
 
+const PXI_SYNC11: u32 = 0x10163000;
+const PXI_CNT11: u32 = 0x10163004;
+const PXI_SEND11: u32 = 0x10163008;
+const PXI_RECV11: u32 = 0x1016300C;
+
+// Helpers for programs that talk to the ARM9 through the ARM11 view of the PXI registers
+impl Dummy11HW {
+    fn pxi_sync_read(&self) -> u8 {
+        self.memory.read::<u32>(PXI_SYNC11) as u8
+    }
+
+    fn pxi_sync_write(&mut self, val: u8) {
+        let current = self.memory.read::<u32>(PXI_SYNC11);
+        let new = current & 0xFFFF00FF | (val as u32) << 8;
+        self.memory.write::<u32>(PXI_SYNC11, new);
+    }
+
+    fn pxi_enable_fifos(&mut self) {
+        let cnt = self.memory.read::<u16>(PXI_CNT11);
+        self.memory.write::<u16>(PXI_CNT11, cnt | (1 << 15));
+    }
+
+    /// Returns false if the send FIFO is full
+    fn pxi_send(&mut self, word: u32) -> bool {
+        if self.memory.read::<u16>(PXI_CNT11) & (1 << 1) != 0 {
+            return false
+        }
+        self.memory.write::<u32>(PXI_SEND11, word);
+        true
+    }
+
+    fn pxi_recv(&mut self) -> Option<u32> {
+        if self.memory.read::<u16>(PXI_CNT11) & (1 << 8) != 0 {
+            return None
+        }
+        Some(self.memory.read::<u32>(PXI_RECV11))
+    }
+}
+
 pub struct Dummy11 {
     hw: Dummy11HW,
     program: BoxedSteppable
@@ -108,6 +165,10 @@ impl Dummy11 {
     pub fn step(&mut self) -> BreakReason {
         self.program.0.step(&mut self.hw)
     }
+
+    pub fn memory_mut(&mut self) -> &mut mem::MemController {
+        &mut self.hw.memory
+    }
 }
 
 type OpFn<State> = fn(&mut State, &mut Dummy11HW) -> Result<(), ()>;
@@ -242,4 +303,54 @@ fn next_node<State>(arena: &Arena<ProgramNodeInner<State>>, node_id: NodeId) ->
     } else {
         None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use hwcore::{self, HwCore};
+    use ldr;
+    use mem;
+    use msgs;
+
+    struct PxiEchoLoader;
+
+    impl ldr::Loader for PxiEchoLoader {
+        fn entrypoint(&self) -> u32 {
+            0x08000000
+        }
+
+        fn load(&self, _controller: &mut mem::MemController) {}
+
+        fn arm11_state(&self) -> hwcore::Arm11State {
+            hwcore::Arm11State::PxiEcho
+        }
+    }
+
+    const PXI_CNT9: u32 = 0x10008004;
+    const PXI_SEND9: u32 = 0x10008008;
+    const PXI_RECV9: u32 = 0x1000800C;
+
+    #[test]
+    fn pxi_echo() {
+        let hw = HwCore::new(msgs::Pump::new(), &PxiEchoLoader);
+        {
+            let mut hw9 = hw.hardware9.lock().unwrap();
+            let memory = &mut hw9.arm9.mpu.memory;
+            memory.write::<u16>(PXI_CNT9, 1 << 15);
+            memory.write::<u32>(PXI_SEND9, 0x12345678);
+            memory.write::<u32>(PXI_SEND9, 0x9ABCDEF0);
+        }
+        {
+            let mut hw11 = hw.hardware11.lock().unwrap();
+            for _ in 0..16 {
+                hw11.run(1);
+            }
+        }
+
+        let mut hw9 = hw.hardware9.lock().unwrap();
+        let memory = &mut hw9.arm9.mpu.memory;
+        assert_eq!(memory.read::<u32>(PXI_RECV9), 0x12345678);
+        assert_eq!(memory.read::<u32>(PXI_RECV9), 0x9ABCDEF0);
+        assert!(memory.read::<u16>(PXI_CNT9) & (1 << 8) != 0);
+    }
+}
@@ -1,6 +1,26 @@
 use cpu;
 use cpu::Cpu;
 use cpu::decoder_arm as arm;
+use cpu::instructions_arm::undefined;
+
+// Neither core has a coprocessor that implements anything beyond register transfers, so these
+// are all rejected as the hardware would
+fn instr_unsupported_coproc(cpu: &mut Cpu, cond: u32, cp_num: u32, name: &str) -> cpu::InstrStatus {
+    if !cpu::cond_passed(cond, &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    warn!("{} is not supported by CP{}", name, cp_num);
+    undefined(cpu)
+}
+
+pub fn cdp(cpu: &mut Cpu, data: arm::cdp::InstrDesc) -> cpu::InstrStatus {
+    instr_unsupported_coproc(cpu, bf!(data.cond), bf!(data.cp_num), "CDP")
+}
+
+pub fn ldc(cpu: &mut Cpu, data: arm::ldc::InstrDesc) -> cpu::InstrStatus {
+    instr_unsupported_coproc(cpu, bf!(data.cond), bf!(data.cp_num), "LDC")
+}
 
 pub fn mcr(cpu: &mut Cpu, data: arm::mcr::InstrDesc) -> cpu::InstrStatus {
     if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
@@ -13,15 +33,21 @@ pub fn mcr(cpu: &mut Cpu, data: arm::mcr::InstrDesc) -> cpu::InstrStatus {
     let opcode_1 = bf!(data.opcode_1) as usize;
     let opcode_2 = bf!(data.opcode_2) as usize;
 
-    let cp_effect = {
-        let coproc = cpu.get_coprocessor(bf!(data.cp_num) as usize);
-        coproc.move_in(crn, crm, opcode_1, opcode_2, src_val)
+    let cp_effect = cpu.get_coprocessor(bf!(data.cp_num) as usize)
+        .map(|coproc| coproc.move_in(crn, crm, opcode_1, opcode_2, src_val));
+    let cp_effect = match cp_effect {
+        Some(effect) => effect,
+        None => return instr_unsupported_coproc(cpu, bf!(data.cond), bf!(data.cp_num), "MCR")
     };
     cp_effect(cpu);
 
     cpu::InstrStatus::InBlock
 }
 
+pub fn mcrr(cpu: &mut Cpu, data: arm::mcrr::InstrDesc) -> cpu::InstrStatus {
+    instr_unsupported_coproc(cpu, bf!(data.cond), bf!(data.cp_num), "MCRR")
+}
+
 pub fn mrc(cpu: &mut Cpu, data: arm::mrc::InstrDesc) -> cpu::InstrStatus {
     if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
@@ -33,9 +59,11 @@ pub fn mrc(cpu: &mut Cpu, data: arm::mrc::InstrDesc) -> cpu::InstrStatus {
     let opcode_2 = bf!(data.opcode_2) as usize;
     let rd = bf!(data.rd);
 
-    let retval = {
-        let coproc = cpu.get_coprocessor(bf!(data.cp_num) as usize);
-        coproc.move_out(crn, crm, opcode_1, opcode_2)
+    let retval = cpu.get_coprocessor(bf!(data.cp_num) as usize)
+        .map(|coproc| coproc.move_out(crn, crm, opcode_1, opcode_2));
+    let retval = match retval {
+        Some(val) => val,
+        None => return instr_unsupported_coproc(cpu, bf!(data.cond), bf!(data.cp_num), "MRC")
     };
 
     if rd == 15 {
@@ -49,3 +77,11 @@ pub fn mrc(cpu: &mut Cpu, data: arm::mrc::InstrDesc) -> cpu::InstrStatus {
 
     cpu::InstrStatus::InBlock
 }
+
+pub fn mrrc(cpu: &mut Cpu, data: arm::mrrc::InstrDesc) -> cpu::InstrStatus {
+    instr_unsupported_coproc(cpu, bf!(data.cond), bf!(data.cp_num), "MRRC")
+}
+
+pub fn stc(cpu: &mut Cpu, data: arm::stc::InstrDesc) -> cpu::InstrStatus {
+    instr_unsupported_coproc(cpu, bf!(data.cond), bf!(data.cp_num), "STC")
+}
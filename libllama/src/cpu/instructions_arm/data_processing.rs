@@ -1,6 +1,7 @@
 use cpu;
 use cpu::Cpu;
 use cpu::decoder_arm as arm;
+use cpu::timing;
 
 fn shifter_lsl(pre_shift: u32, amount: usize, c_bit: bool) -> (u32, bool) {
     if amount == 0 {
@@ -99,7 +100,7 @@ struct BarrelShifterOut {
     pc_advanced: bool, // To work around the fact that PC @ +12 when shifting by a register value
 }
 
-fn get_shifter_val(instr_data: u32, cpu: &Cpu) -> BarrelShifterOut {
+fn get_shifter_val(instr_data: u32, cpu: &mut Cpu) -> BarrelShifterOut {
     // Just to make it a little bit easier to use this
     let instr_data = arm::add::InstrDesc::new(instr_data);
 
@@ -121,6 +122,8 @@ fn get_shifter_val(instr_data: u32, cpu: &Cpu) -> BarrelShifterOut {
     let amount = if !is_reg_shift {
         bits!(shifter_bits, 7 => 11) as usize
     } else {
+        // Reading the shift amount from a register takes an extra cycle
+        cpu.add_cycles(1);
         let reg = bits!(shifter_bits, 8 => 11) as usize;
         getreg(cpu, is_reg_shift, reg) as usize
     };
@@ -345,6 +348,7 @@ pub fn instr_mul64_accumulate(cpu: &mut Cpu, data: arm::umlal::InstrDesc, signed
     } else {
         (base_val as u64).wrapping_mul(multiplier as u64)
     };
+    cpu.add_cycles(timing::multiply_cycles(multiplier, signed) + 2);
 
     let val_lo = wrapping_sum!(mul_val as u32, cpu.regs[rd_lo]);
     let val_lo_carry = checked_sum!(mul_val as u32, cpu.regs[rd_lo]).is_none();
@@ -436,6 +440,7 @@ pub fn mla(cpu: &mut Cpu, data: arm::mla::InstrDesc) -> cpu::InstrStatus {
     let val = (base_val * multiplier + accumulated) as u32;
 
     cpu.regs[bf!(data.rd) as usize] = val;
+    cpu.add_cycles(timing::multiply_cycles(multiplier as u32, true) + 1);
 
     if bf!(data.s_bit) == 1 {
         bf!((cpu.cpsr).n_bit = bit!(val, 31));
@@ -459,6 +464,7 @@ pub fn mul(cpu: &mut Cpu, data: arm::mul::InstrDesc) -> cpu::InstrStatus {
     let val = (base_val * multiplier) as u32;
 
     cpu.regs[bf!(data.rd) as usize] = val;
+    cpu.add_cycles(timing::multiply_cycles(multiplier as u32, true));
 
     if bf!(data.s_bit) == 1 {
         bf!((cpu.cpsr).n_bit = bit!(val, 31));
@@ -496,6 +502,7 @@ pub fn smull(cpu: &mut Cpu, data: arm::smull::InstrDesc) -> cpu::InstrStatus {
     let base_val = cpu.regs[bf!(data.rm) as usize] as i32;
     let multiplier = cpu.regs[bf!(data.rs) as usize] as i32;
     let val = (base_val as i64).wrapping_mul(multiplier as i64) as u64;
+    cpu.add_cycles(timing::multiply_cycles(multiplier as u32, true) + 1);
 
     cpu.regs[bf!(data.rd_hi) as usize] = (val >> 32) as u32;
     cpu.regs[bf!(data.rd_lo) as usize] = val as u32;
@@ -520,6 +527,25 @@ pub fn tst(cpu: &mut Cpu, data: arm::tst::InstrDesc) -> cpu::InstrStatus {
     instr_test(cpu, data, false)
 }
 
+pub fn umaal(cpu: &mut Cpu, data: arm::umaal::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let base_val = cpu.regs[bf!(data.rm) as usize] as u64;
+    let multiplier = cpu.regs[bf!(data.rs) as usize] as u64;
+    let addend_hi = cpu.regs[bf!(data.rd_hi) as usize] as u64;
+    let addend_lo = cpu.regs[bf!(data.rd_lo) as usize] as u64;
+    // Can't overflow: (2^32-1)^2 + 2*(2^32-1) == 2^64-1
+    let val = base_val * multiplier + addend_hi + addend_lo;
+    cpu.add_cycles(timing::multiply_cycles(multiplier as u32, false) + 2);
+
+    cpu.regs[bf!(data.rd_hi) as usize] = (val >> 32) as u32;
+    cpu.regs[bf!(data.rd_lo) as usize] = val as u32;
+
+    cpu::InstrStatus::InBlock
+}
+
 pub fn umlal(cpu: &mut Cpu, data: arm::umlal::InstrDesc) -> cpu::InstrStatus {
     instr_mul64_accumulate(cpu, data, false)
 }
@@ -532,6 +558,7 @@ pub fn umull(cpu: &mut Cpu, data: arm::umull::InstrDesc) -> cpu::InstrStatus {
     let base_val = cpu.regs[bf!(data.rm) as usize] as u64;
     let multiplier = cpu.regs[bf!(data.rs) as usize] as u64;
     let val = base_val.wrapping_mul(multiplier);
+    cpu.add_cycles(timing::multiply_cycles(multiplier as u32, false) + 1);
 
     cpu.regs[bf!(data.rd_hi) as usize] = (val >> 32) as u32;
     cpu.regs[bf!(data.rd_lo) as usize] = val as u32;
@@ -0,0 +1,152 @@
+use cpu;
+use cpu::Cpu;
+use cpu::decoder_arm as arm;
+
+use std::i32;
+
+fn saturating_add(cpu: &mut Cpu, a: i32, b: i32) -> i32 {
+    match a.checked_add(b) {
+        Some(val) => val,
+        None => {
+            bf!((cpu.cpsr).q_bit = 1);
+            if b < 0 { i32::MIN } else { i32::MAX }
+        }
+    }
+}
+
+fn saturating_sub(cpu: &mut Cpu, a: i32, b: i32) -> i32 {
+    match a.checked_sub(b) {
+        Some(val) => val,
+        None => {
+            bf!((cpu.cpsr).q_bit = 1);
+            if b < 0 { i32::MAX } else { i32::MIN }
+        }
+    }
+}
+
+// Accumulates without saturating, but still flags overflow in the Q bit
+fn overflowing_accumulate(cpu: &mut Cpu, product: i32, acc: i32) -> u32 {
+    let (val, overflowed) = product.overflowing_add(acc);
+    if overflowed {
+        bf!((cpu.cpsr).q_bit = 1);
+    }
+    val as u32
+}
+
+fn halfword(val: u32, top: u32) -> i32 {
+    if top == 1 {
+        (val >> 16) as i16 as i32
+    } else {
+        val as i16 as i32
+    }
+}
+
+fn instr_saturating_arith(cpu: &mut Cpu, data: arm::qadd::InstrDesc,
+                          double: bool, subtract: bool) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm_val = cpu.regs[bf!(data.rm) as usize] as i32;
+    let mut rn_val = cpu.regs[bf!(data.rn) as usize] as i32;
+    if double {
+        rn_val = saturating_add(cpu, rn_val, rn_val);
+    }
+
+    let val = if subtract {
+        saturating_sub(cpu, rm_val, rn_val)
+    } else {
+        saturating_add(cpu, rm_val, rn_val)
+    };
+
+    cpu.regs[bf!(data.rd) as usize] = val as u32;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn qadd(cpu: &mut Cpu, data: arm::qadd::InstrDesc) -> cpu::InstrStatus {
+    instr_saturating_arith(cpu, data, false, false)
+}
+
+pub fn qdadd(cpu: &mut Cpu, data: arm::qdadd::InstrDesc) -> cpu::InstrStatus {
+    instr_saturating_arith(cpu, arm::qadd::InstrDesc::new(data.raw()), true, false)
+}
+
+pub fn qdsub(cpu: &mut Cpu, data: arm::qdsub::InstrDesc) -> cpu::InstrStatus {
+    instr_saturating_arith(cpu, arm::qadd::InstrDesc::new(data.raw()), true, true)
+}
+
+pub fn qsub(cpu: &mut Cpu, data: arm::qsub::InstrDesc) -> cpu::InstrStatus {
+    instr_saturating_arith(cpu, arm::qadd::InstrDesc::new(data.raw()), false, true)
+}
+
+pub fn smlalxy(cpu: &mut Cpu, data: arm::smlalxy::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rd_hi = bf!(data.rd_hi) as usize;
+    let rd_lo = bf!(data.rd_lo) as usize;
+
+    let product = halfword(cpu.regs[bf!(data.rm) as usize], bf!(data.x))
+                * halfword(cpu.regs[bf!(data.rs) as usize], bf!(data.y));
+    let acc = ((cpu.regs[rd_hi] as u64) << 32) | (cpu.regs[rd_lo] as u64);
+    let val = acc.wrapping_add(product as i64 as u64);
+
+    cpu.regs[rd_hi] = (val >> 32) as u32;
+    cpu.regs[rd_lo] = val as u32;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn smlawy(cpu: &mut Cpu, data: arm::smlawy::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm_val = cpu.regs[bf!(data.rm) as usize] as i32 as i64;
+    let rs_half = halfword(cpu.regs[bf!(data.rs) as usize], bf!(data.y)) as i64;
+    let product = ((rm_val * rs_half) >> 16) as i32;
+    let acc = cpu.regs[bf!(data.rn) as usize] as i32;
+
+    let val = overflowing_accumulate(cpu, product, acc);
+    cpu.regs[bf!(data.rd) as usize] = val;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn smlaxy(cpu: &mut Cpu, data: arm::smlaxy::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let product = halfword(cpu.regs[bf!(data.rm) as usize], bf!(data.x))
+                * halfword(cpu.regs[bf!(data.rs) as usize], bf!(data.y));
+    let acc = cpu.regs[bf!(data.rn) as usize] as i32;
+
+    let val = overflowing_accumulate(cpu, product, acc);
+    cpu.regs[bf!(data.rd) as usize] = val;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn smulwy(cpu: &mut Cpu, data: arm::smulwy::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm_val = cpu.regs[bf!(data.rm) as usize] as i32 as i64;
+    let rs_half = halfword(cpu.regs[bf!(data.rs) as usize], bf!(data.y)) as i64;
+    let product = (rm_val * rs_half) >> 16;
+
+    cpu.regs[bf!(data.rd) as usize] = product as u32;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn smulxy(cpu: &mut Cpu, data: arm::smulxy::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let product = halfword(cpu.regs[bf!(data.rm) as usize], bf!(data.x))
+                * halfword(cpu.regs[bf!(data.rs) as usize], bf!(data.y));
+
+    cpu.regs[bf!(data.rd) as usize] = product as u32;
+    cpu::InstrStatus::InBlock
+}
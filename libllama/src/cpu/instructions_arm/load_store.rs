@@ -67,8 +67,7 @@ mod addressing {
         match (p_bit, w_bit) {
             (true, false)  => (LsAddr(mod_addr), WbAddr(base_addr)), // Pre-indexed, writeback disabled
             (true, true)   => (LsAddr(mod_addr), WbAddr(mod_addr)), // Pre-indexed, writeback enabled
-            (false, false) => (LsAddr(base_addr), WbAddr(mod_addr)), // Post-indexed, writeback enabled
-            (false, true)  => panic!("Invalid writeback mode!") // UNPREDICTABLE
+            (false, _)     => (LsAddr(base_addr), WbAddr(mod_addr)), // Post-indexed, writeback enabled
         }
     }
 
@@ -104,6 +103,19 @@ mod addressing {
     }
 }
 
+// LDRT/STRT and their byte forms are the post-indexed encodings with W set, and access
+// memory with User mode permissions
+fn with_user_perms<T, F>(cpu: &mut Cpu, user: bool, f: F) -> T
+    where F: FnOnce(&mut Cpu) -> T {
+    let privileged = cpu.mpu.privileged;
+    if user {
+        cpu.mpu.privileged = false;
+    }
+    let res = f(cpu);
+    cpu.mpu.privileged = privileged;
+    res
+}
+
 enum MiscLsType {
     Doubleword,
     Halfword,
@@ -119,13 +131,20 @@ fn instr_load(cpu: &mut Cpu, data: arm::ldr::InstrDesc, byte: bool) -> cpu::Inst
     let rd = bf!(data.rd);
     let (addr, wb) = addressing::decode_normal(data.raw(), cpu);
 
+    let user_access = bf!(data.p_bit) == 0 && bf!(data.w_bit) == 1;
+
     // TODO: determine behavior based on CP15 r1 bit_U (22)
-    let val = if byte {
+    let val = with_user_perms(cpu, user_access, |cpu| if byte {
         cpu.mpu.dmem_read::<u8>(addr.0) as u32
     } else {
         cpu.mpu.dmem_read::<u32>(addr.0 & !0b11)
             .rotate_right(8 * bits!(addr.0, 0 => 1))
-    };
+    });
+
+    // An aborted load writes back neither the base nor the destination
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
 
     // Writeback
     cpu.regs[bf!(data.rn) as usize] = wb.0;
@@ -148,24 +167,29 @@ fn instr_load_misc(cpu: &mut Cpu, data: arm::ldrh::InstrDesc, ty: MiscLsType) ->
 
     let rd = bf!(data.rd) as usize;
     let (addr, wb) = addressing::decode_misc(data.raw(), cpu);
-    // Writeback
-    cpu.regs[bf!(data.rn) as usize] = wb.0;
 
     // TODO: determine behavior based on CP15 r1 bit_U (22)
     let val = match ty {
         MiscLsType::Doubleword => {
             assert!((rd % 2 == 0) && (rd != 14) && (addr.0 % 4 == 0));
-            let val = cpu.mpu.dmem_read::<u64>(addr.0);
-            cpu.regs[rd] = val as u32;
-            cpu.regs[rd+1] = (val >> 32) as u32;
-            return cpu::InstrStatus::InBlock
+            cpu.mpu.dmem_read::<u64>(addr.0)
         }
-        MiscLsType::Halfword => cpu.mpu.dmem_read::<u16>(addr.0) as u32,
-        MiscLsType::SignedByte => sign_extend(cpu.mpu.dmem_read::<u8>(addr.0) as u32, 8) as u32,
-        MiscLsType::SignedHalfword => sign_extend(cpu.mpu.dmem_read::<u16>(addr.0) as u32, 16) as u32
+        MiscLsType::Halfword => cpu.mpu.dmem_read::<u16>(addr.0) as u64,
+        MiscLsType::SignedByte => sign_extend(cpu.mpu.dmem_read::<u8>(addr.0) as u32, 8) as u32 as u64,
+        MiscLsType::SignedHalfword => sign_extend(cpu.mpu.dmem_read::<u16>(addr.0) as u32, 16) as u32 as u64
     };
 
-    cpu.regs[bf!(data.rd) as usize] = val;
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    // Writeback
+    cpu.regs[bf!(data.rn) as usize] = wb.0;
+
+    cpu.regs[rd] = val as u32;
+    if let MiscLsType::Doubleword = ty {
+        cpu.regs[rd+1] = (val >> 32) as u32;
+    }
 
     cpu::InstrStatus::InBlock
 }
@@ -178,14 +202,17 @@ fn instr_store(cpu: &mut Cpu, data: arm::str::InstrDesc, byte: bool) -> cpu::Ins
     let (addr, wb) = addressing::decode_normal(data.raw(), cpu);
     let val = cpu.regs[bf!(data.rd) as usize];
 
-    // Writeback
-    cpu.regs[bf!(data.rn) as usize] = wb.0;
-
-    if byte {
+    let user_access = bf!(data.p_bit) == 0 && bf!(data.w_bit) == 1;
+    with_user_perms(cpu, user_access, |cpu| if byte {
         cpu.mpu.dmem_write::<u8>(addr.0, val as u8);
     } else {
         cpu.mpu.dmem_write::<u32>(addr.0 & !0b11, val);
-    };
+    });
+
+    // Writeback, unless the store aborted
+    if !cpu.mpu.faulted() {
+        cpu.regs[bf!(data.rn) as usize] = wb.0;
+    }
 
     cpu::InstrStatus::InBlock
 }
@@ -198,8 +225,6 @@ fn instr_store_misc(cpu: &mut Cpu, data: arm::strh::InstrDesc, ty: MiscLsType) -
     let (addr, wb) = addressing::decode_misc(data.raw(), cpu);
     let rd = bf!(data.rd) as usize;
 
-    // Writeback
-    cpu.regs[bf!(data.rn) as usize] = wb.0;
     // TODO: determine behavior based on CP15 r1 bit_U (22)
     match ty {
         MiscLsType::Doubleword => {
@@ -211,6 +236,11 @@ fn instr_store_misc(cpu: &mut Cpu, data: arm::strh::InstrDesc, ty: MiscLsType) -
         _ => panic!("Invalid miscellaneous store type!")
     }
 
+    // Writeback, unless the store aborted
+    if !cpu.mpu.faulted() {
+        cpu.regs[bf!(data.rn) as usize] = wb.0;
+    }
+
     cpu::InstrStatus::InBlock
 }
 
@@ -264,7 +294,13 @@ pub fn swp(cpu: &mut Cpu, data: arm::swp::InstrDesc) -> cpu::InstrStatus {
     let new_val = cpu.regs[bf!(data.rm) as usize];
 
     let tmp = cpu.mpu.dmem_read::<u32>(addr);
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
     cpu.mpu.dmem_write::<u32>(addr, new_val);
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
     cpu.regs[bf!(data.rd) as usize] = tmp;
 
     cpu::InstrStatus::InBlock
@@ -280,7 +316,13 @@ pub fn swpb(cpu: &mut Cpu, data: arm::swpb::InstrDesc) -> cpu::InstrStatus {
     let new_val = cpu.regs[bf!(data.rm) as usize];
 
     let tmp = cpu.mpu.dmem_read::<u8>(addr);
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
     cpu.mpu.dmem_write::<u8>(addr, new_val as u8);
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
     cpu.regs[bf!(data.rd) as usize] = tmp as u32;
 
     cpu::InstrStatus::InBlock
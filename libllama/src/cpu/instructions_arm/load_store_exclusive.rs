@@ -0,0 +1,119 @@
+use cpu;
+use cpu::Cpu;
+use cpu::decoder_arm as arm;
+
+// Only one core runs on each bus, so the local monitor alone decides whether a store succeeds
+
+enum ExclusiveSize {
+    Byte,
+    Halfword,
+    Word,
+    Doubleword
+}
+
+// The doubleword forms transfer an even register and the one after it, and are unpredictable
+// for anything else; they raise Undefined here
+fn valid_pair(reg: usize) -> bool {
+    reg % 2 == 0 && reg != 14
+}
+
+fn instr_load_exclusive(cpu: &mut Cpu, data: arm::ldrex::InstrDesc, size: ExclusiveSize) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let addr = cpu.regs[bf!(data.rn) as usize];
+    let rd = bf!(data.rd) as usize;
+
+    let val = match size {
+        ExclusiveSize::Byte => cpu.mpu.dmem_read::<u8>(addr) as u64,
+        ExclusiveSize::Halfword => cpu.mpu.dmem_read::<u16>(addr) as u64,
+        ExclusiveSize::Word => cpu.mpu.dmem_read::<u32>(addr) as u64,
+        ExclusiveSize::Doubleword => {
+            if !valid_pair(rd) {
+                return cpu::instructions_arm::undefined(cpu);
+            }
+            cpu.mpu.dmem_read::<u64>(addr)
+        }
+    };
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    cpu.regs[rd] = val as u32;
+    if let ExclusiveSize::Doubleword = size {
+        cpu.regs[rd+1] = (val >> 32) as u32;
+    }
+    cpu.exclusive_addr = Some(addr);
+    cpu::InstrStatus::InBlock
+}
+
+fn instr_store_exclusive(cpu: &mut Cpu, data: arm::strex::InstrDesc, size: ExclusiveSize) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let addr = cpu.regs[bf!(data.rn) as usize];
+    let rm = bf!(data.rm) as usize;
+
+    let success = cpu.exclusive_addr == Some(addr);
+    if success {
+        match size {
+            ExclusiveSize::Byte => cpu.mpu.dmem_write::<u8>(addr, cpu.regs[rm] as u8),
+            ExclusiveSize::Halfword => cpu.mpu.dmem_write::<u16>(addr, cpu.regs[rm] as u16),
+            ExclusiveSize::Word => cpu.mpu.dmem_write::<u32>(addr, cpu.regs[rm]),
+            ExclusiveSize::Doubleword => {
+                if !valid_pair(rm) {
+                    return cpu::instructions_arm::undefined(cpu);
+                }
+                let val = (cpu.regs[rm] as u64) | ((cpu.regs[rm+1] as u64) << 32);
+                cpu.mpu.dmem_write::<u64>(addr, val)
+            }
+        }
+    }
+
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    cpu.exclusive_addr = None;
+    cpu.regs[bf!(data.rd) as usize] = if success { 0 } else { 1 };
+    cpu::InstrStatus::InBlock
+}
+
+pub fn clrex(cpu: &mut Cpu, _data: arm::clrex::InstrDesc) -> cpu::InstrStatus {
+    cpu.exclusive_addr = None;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn ldrex(cpu: &mut Cpu, data: arm::ldrex::InstrDesc) -> cpu::InstrStatus {
+    instr_load_exclusive(cpu, data, ExclusiveSize::Word)
+}
+
+pub fn ldrexb(cpu: &mut Cpu, data: arm::ldrexb::InstrDesc) -> cpu::InstrStatus {
+    instr_load_exclusive(cpu, arm::ldrex::InstrDesc::new(data.raw()), ExclusiveSize::Byte)
+}
+
+pub fn ldrexd(cpu: &mut Cpu, data: arm::ldrexd::InstrDesc) -> cpu::InstrStatus {
+    instr_load_exclusive(cpu, arm::ldrex::InstrDesc::new(data.raw()), ExclusiveSize::Doubleword)
+}
+
+pub fn ldrexh(cpu: &mut Cpu, data: arm::ldrexh::InstrDesc) -> cpu::InstrStatus {
+    instr_load_exclusive(cpu, arm::ldrex::InstrDesc::new(data.raw()), ExclusiveSize::Halfword)
+}
+
+pub fn strex(cpu: &mut Cpu, data: arm::strex::InstrDesc) -> cpu::InstrStatus {
+    instr_store_exclusive(cpu, data, ExclusiveSize::Word)
+}
+
+pub fn strexb(cpu: &mut Cpu, data: arm::strexb::InstrDesc) -> cpu::InstrStatus {
+    instr_store_exclusive(cpu, arm::strex::InstrDesc::new(data.raw()), ExclusiveSize::Byte)
+}
+
+pub fn strexd(cpu: &mut Cpu, data: arm::strexd::InstrDesc) -> cpu::InstrStatus {
+    instr_store_exclusive(cpu, arm::strex::InstrDesc::new(data.raw()), ExclusiveSize::Doubleword)
+}
+
+pub fn strexh(cpu: &mut Cpu, data: arm::strexh::InstrDesc) -> cpu::InstrStatus {
+    instr_store_exclusive(cpu, arm::strex::InstrDesc::new(data.raw()), ExclusiveSize::Halfword)
+}
@@ -20,18 +20,35 @@ fn decode_addressing_mode(instr_data: u32, cpu: &mut Cpu) -> (u32, u32) {
     }
 }
 
+// Reads the words for every register in the list, so nothing is written back to the registers
+// if one of the reads aborts
+fn read_list(cpu: &mut Cpu, mut addr: u32, register_list: u32) -> [u32; 16] {
+    let mut vals = [0u32; 16];
+    for i in 0..16 {
+        if bit!(register_list, i) == 1 {
+            vals[i] = cpu.mpu.dmem_read::<u32>(addr);
+            addr += 4;
+        }
+    }
+    vals
+}
+
 pub fn ldm_1(cpu: &mut Cpu, data: arm::ldm_1::InstrDesc) -> cpu::InstrStatus {
     if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
     }
 
-    let (mut addr, writeback) = decode_addressing_mode(data.raw(), cpu);
+    let (addr, writeback) = decode_addressing_mode(data.raw(), cpu);
     let register_list = bf!(data.register_list);
 
+    let vals = read_list(cpu, addr, register_list as u32);
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
+
     for i in 0..15 {
         if bit!(register_list, i) == 1 {
-            cpu.regs[i] = cpu.mpu.dmem_read::<u32>(addr);
-            addr += 4;
+            cpu.regs[i] = vals[i];
         }
     }
 
@@ -40,7 +57,7 @@ pub fn ldm_1(cpu: &mut Cpu, data: arm::ldm_1::InstrDesc) -> cpu::InstrStatus {
     }
 
     if bit!(register_list, 15) == 1 {
-        let val = cpu.mpu.dmem_read::<u32>(addr);
+        let val = vals[15];
         bf!((cpu.cpsr).thumb_bit = bit!(val, 0));
         cpu.branch(val & 0xFFFFFFFE);
         return cpu::InstrStatus::Branched;
@@ -54,15 +71,19 @@ pub fn ldm_2(cpu: &mut Cpu, data: arm::ldm_2::InstrDesc) -> cpu::InstrStatus {
         return cpu::InstrStatus::InBlock;
     }
 
-    let (mut addr, _) = decode_addressing_mode(data.raw(), cpu);
+    let (addr, _) = decode_addressing_mode(data.raw(), cpu);
     let register_list = bf!(data.register_list);
 
+    let vals = read_list(cpu, addr, register_list as u32);
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
+
     let current_mode = cpu::Mode::from_num(bf!((cpu.cpsr).mode));
     cpu.regs.swap(cpu::Mode::Usr);
     for i in 0..14 {
         if bit!(register_list, i) == 1 {
-            cpu.regs[i] = cpu.mpu.dmem_read::<u32>(addr);
-            addr += 4;
+            cpu.regs[i] = vals[i];
         }
     }
     cpu.regs.swap(current_mode);
@@ -75,13 +96,17 @@ pub fn ldm_3(cpu: &mut Cpu, data: arm::ldm_3::InstrDesc) -> cpu::InstrStatus {
         return cpu::InstrStatus::InBlock;
     }
 
-    let (mut addr, writeback) = decode_addressing_mode(data.raw(), cpu);
+    let (addr, writeback) = decode_addressing_mode(data.raw(), cpu);
     let register_list = bf!(data.register_list);
 
+    let vals = read_list(cpu, addr, register_list as u32 | (1 << 15)); // PC is always loaded
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
+
     for i in 0..15 {
         if bit!(register_list, i) == 1 {
-            cpu.regs[i] = cpu.mpu.dmem_read::<u32>(addr);
-            addr += 4;
+            cpu.regs[i] = vals[i];
         }
     }
 
@@ -90,7 +115,7 @@ pub fn ldm_3(cpu: &mut Cpu, data: arm::ldm_3::InstrDesc) -> cpu::InstrStatus {
     }
 
     cpu.spsr_make_current();
-    let dest = cpu.mpu.dmem_read::<u32>(addr);
+    let dest = vals[15];
     cpu.branch(dest & 0xFFFFFFFE);
     cpu::InstrStatus::Branched
 }
@@ -110,7 +135,7 @@ pub fn stm_1(cpu: &mut Cpu, data: arm::stm_1::InstrDesc) -> cpu::InstrStatus {
         }
     }
 
-    if bf!(data.w_bit) == 1 {
+    if bf!(data.w_bit) == 1 && !cpu.mpu.faulted() {
         cpu.regs[bf!(data.rn) as usize] = writeback;
     }
 
@@ -136,4 +161,61 @@ pub fn stm_2(cpu: &mut Cpu, data: arm::stm_2::InstrDesc) -> cpu::InstrStatus {
     cpu.regs.swap(current_mode);
 
     return cpu::InstrStatus::InBlock;
-}
\ No newline at end of file
+}
+// Start address and writeback value for the two-word transfers of RFE and SRS
+fn two_word_addresses(base: u32, p_bit: bool, u_bit: bool) -> (u32, u32) {
+    match (p_bit, u_bit) {
+        (false, true)  => (base, base + 8), // Increment after
+        (true, true)   => (base + 4, base + 8), // Increment before
+        (false, false) => (base - 4, base - 8), // Decrement after
+        (true, false)  => (base - 8, base - 8) // Decrement before
+    }
+}
+
+pub fn rfe(cpu: &mut Cpu, data: arm::rfe::InstrDesc) -> cpu::InstrStatus {
+    let rn = bf!(data.rn) as usize;
+    let (addr, writeback) = two_word_addresses(cpu.regs[rn], bf!(data.p_bit) == 1, bf!(data.u_bit) == 1);
+
+    let dest = cpu.mpu.dmem_read::<u32>(addr);
+    let psr = cpu.mpu.dmem_read::<u32>(addr + 4);
+    if cpu.mpu.faulted() {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    if bf!(data.w_bit) == 1 {
+        cpu.regs[rn] = writeback;
+    }
+
+    cpu.cpsr.set_raw(psr);
+    cpu.regs.swap(cpu::Mode::from_num(bf!((cpu.cpsr).mode)));
+    if bf!((cpu.cpsr).thumb_bit) == 1 {
+        cpu.branch(dest & 0xFFFFFFFE);
+    } else {
+        cpu.branch(dest & 0xFFFFFFFC);
+    }
+    cpu::InstrStatus::Branched
+}
+
+pub fn srs(cpu: &mut Cpu, data: arm::srs::InstrDesc) -> cpu::InstrStatus {
+    let current_mode = cpu::Mode::from_num(bf!((cpu.cpsr).mode));
+    let target_mode = cpu::Mode::from_num(bf!(data.mode));
+
+    // The stack used is the banked SP of the given mode
+    cpu.regs.swap(target_mode);
+    let base = cpu.regs[13];
+    cpu.regs.swap(current_mode);
+
+    let (addr, writeback) = two_word_addresses(base, bf!(data.p_bit) == 1, bf!(data.u_bit) == 1);
+    let lr = cpu.regs[14];
+    let spsr = cpu.get_current_spsr().raw();
+    cpu.mpu.dmem_write::<u32>(addr, lr);
+    cpu.mpu.dmem_write::<u32>(addr + 4, spsr);
+
+    if bf!(data.w_bit) == 1 && !cpu.mpu.faulted() {
+        cpu.regs.swap(target_mode);
+        cpu.regs[13] = writeback;
+        cpu.regs.swap(current_mode);
+    }
+
+    cpu::InstrStatus::InBlock
+}
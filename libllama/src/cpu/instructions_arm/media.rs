@@ -0,0 +1,196 @@
+use cpu;
+use cpu::Cpu;
+use cpu::decoder_arm as arm;
+
+enum ExtendType {
+    SignedByte,
+    SignedByte16,
+    SignedHalfword,
+    UnsignedByte,
+    UnsignedByte16,
+    UnsignedHalfword
+}
+
+// The non-accumulating extends are encoded as their accumulating forms with Rn == 15
+fn instr_extend(cpu: &mut Cpu, data: arm::sxtab::InstrDesc, ty: ExtendType) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rn = bf!(data.rn) as usize;
+    let operand = cpu.regs[bf!(data.rm) as usize].rotate_right(bf!(data.rotate) * 8);
+    let base_val = if rn == 15 { 0 } else { cpu.regs[rn] };
+
+    let add_halves = |lo: u16, hi: u16| {
+        let lo = (base_val as u16).wrapping_add(lo) as u32;
+        let hi = ((base_val >> 16) as u16).wrapping_add(hi) as u32;
+        lo | (hi << 16)
+    };
+
+    let val = match ty {
+        ExtendType::SignedByte => base_val.wrapping_add(operand as u8 as i8 as i32 as u32),
+        ExtendType::SignedHalfword => base_val.wrapping_add(operand as u16 as i16 as i32 as u32),
+        ExtendType::UnsignedByte => base_val.wrapping_add(operand & 0xFF),
+        ExtendType::UnsignedHalfword => base_val.wrapping_add(operand & 0xFFFF),
+        ExtendType::SignedByte16 => add_halves(operand as u8 as i8 as i16 as u16,
+                                               (operand >> 16) as u8 as i8 as i16 as u16),
+        ExtendType::UnsignedByte16 => add_halves(operand as u8 as u16,
+                                                 (operand >> 16) as u8 as u16),
+    };
+
+    cpu.regs[bf!(data.rd) as usize] = val;
+    cpu::InstrStatus::InBlock
+}
+
+fn instr_saturate(cpu: &mut Cpu, data: arm::ssat::InstrDesc, signed: bool) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm_val = cpu.regs[bf!(data.rm) as usize];
+    let shift_imm = bf!(data.shift_imm);
+    let operand = if bf!(data.shift) == 0 {
+        (rm_val << shift_imm) as i32 as i64
+    } else if shift_imm == 0 {
+        // ASR #32
+        ((rm_val as i32) >> 31) as i64
+    } else {
+        ((rm_val as i32) >> shift_imm) as i64
+    };
+
+    let (min, max) = if signed {
+        let sat_bits = bf!(data.sat_imm) + 1;
+        (-(1i64 << (sat_bits - 1)), (1i64 << (sat_bits - 1)) - 1)
+    } else {
+        (0, (1i64 << bf!(data.sat_imm)) - 1)
+    };
+
+    let val = if operand < min {
+        min
+    } else if operand > max {
+        max
+    } else {
+        operand
+    };
+
+    if val != operand {
+        bf!((cpu.cpsr).q_bit = 1);
+    }
+    cpu.regs[bf!(data.rd) as usize] = val as u32;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn pkhbt(cpu: &mut Cpu, data: arm::pkhbt::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rn_val = cpu.regs[bf!(data.rn) as usize];
+    let rm_val = cpu.regs[bf!(data.rm) as usize];
+    let val = (rn_val & 0xFFFF) | ((rm_val << bf!(data.shift_imm)) & 0xFFFF0000);
+
+    cpu.regs[bf!(data.rd) as usize] = val;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn pkhtb(cpu: &mut Cpu, data: arm::pkhtb::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rn_val = cpu.regs[bf!(data.rn) as usize];
+    let rm_val = cpu.regs[bf!(data.rm) as usize] as i32;
+    let shift_imm = bf!(data.shift_imm);
+    // A shift of 0 encodes ASR #32
+    let shifted = (if shift_imm == 0 { rm_val >> 31 } else { rm_val >> shift_imm }) as u32;
+    let val = (rn_val & 0xFFFF0000) | (shifted & 0xFFFF);
+
+    cpu.regs[bf!(data.rd) as usize] = val;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn rev(cpu: &mut Cpu, data: arm::rev::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm_val = cpu.regs[bf!(data.rm) as usize];
+    cpu.regs[bf!(data.rd) as usize] = rm_val.swap_bytes();
+    cpu::InstrStatus::InBlock
+}
+
+pub fn rev16(cpu: &mut Cpu, data: arm::rev16::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm_val = cpu.regs[bf!(data.rm) as usize];
+    cpu.regs[bf!(data.rd) as usize] = ((rm_val & 0x00FF00FF) << 8) | ((rm_val >> 8) & 0x00FF00FF);
+    cpu::InstrStatus::InBlock
+}
+
+pub fn revsh(cpu: &mut Cpu, data: arm::revsh::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm_val = cpu.regs[bf!(data.rm) as usize];
+    cpu.regs[bf!(data.rd) as usize] = (rm_val as u16).swap_bytes() as i16 as i32 as u32;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn ssat(cpu: &mut Cpu, data: arm::ssat::InstrDesc) -> cpu::InstrStatus {
+    instr_saturate(cpu, data, true)
+}
+
+pub fn usat(cpu: &mut Cpu, data: arm::usat::InstrDesc) -> cpu::InstrStatus {
+    instr_saturate(cpu, arm::ssat::InstrDesc::new(data.raw()), false)
+}
+
+pub fn sxtab(cpu: &mut Cpu, data: arm::sxtab::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, data, ExtendType::SignedByte)
+}
+
+pub fn sxtab16(cpu: &mut Cpu, data: arm::sxtab16::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::SignedByte16)
+}
+
+pub fn sxtah(cpu: &mut Cpu, data: arm::sxtah::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::SignedHalfword)
+}
+
+pub fn sxtb(cpu: &mut Cpu, data: arm::sxtb::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::SignedByte)
+}
+
+pub fn sxtb16(cpu: &mut Cpu, data: arm::sxtb16::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::SignedByte16)
+}
+
+pub fn sxth(cpu: &mut Cpu, data: arm::sxth::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::SignedHalfword)
+}
+
+pub fn uxtab(cpu: &mut Cpu, data: arm::uxtab::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::UnsignedByte)
+}
+
+pub fn uxtab16(cpu: &mut Cpu, data: arm::uxtab16::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::UnsignedByte16)
+}
+
+pub fn uxtah(cpu: &mut Cpu, data: arm::uxtah::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::UnsignedHalfword)
+}
+
+pub fn uxtb(cpu: &mut Cpu, data: arm::uxtb::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::UnsignedByte)
+}
+
+pub fn uxtb16(cpu: &mut Cpu, data: arm::uxtb16::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::UnsignedByte16)
+}
+
+pub fn uxth(cpu: &mut Cpu, data: arm::uxth::InstrDesc) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::sxtab::InstrDesc::new(data.raw()), ExtendType::UnsignedHalfword)
+}
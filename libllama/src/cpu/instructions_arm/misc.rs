@@ -2,6 +2,36 @@ use cpu;
 use cpu::Cpu;
 use cpu::decoder_arm as arm;
 
+pub fn bkpt(cpu: &mut Cpu, data: arm::bkpt::InstrDesc) -> cpu::InstrStatus {
+    let immed = (bf!(data.immed_hi) << 4) | bf!(data.immed_lo);
+    info!("Hit BKPT #0x{:X} at 0x{:X}", immed, cpu.regs[15] - cpu.get_pc_offset());
+
+    // With no debug monitor installed, there's nothing useful for the prefetch abort this
+    // would raise to do, so hand control to the debugger instead
+    cpu.trap_bkpt();
+    cpu::InstrStatus::InBlock
+}
+
+pub fn hint(cpu: &mut Cpu, data: arm::hint::InstrDesc) -> cpu::InstrStatus {
+    if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    match bf!(data.hint) {
+        3 => cpu.wait_for_irq(), // WFI
+        // NOP, YIELD, WFE and SEV: no other core is emulated on this bus to send or wait for
+        // events, so treating WFE as a NOP keeps spin loops spinning instead of deadlocking
+        _ => {}
+    }
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn pld(_cpu: &mut Cpu, _data: arm::pld::InstrDesc) -> cpu::InstrStatus {
+    // Only a hint, which the emulated memory system has no use for
+    cpu::InstrStatus::InBlock
+}
+
 pub fn swi(cpu: &mut Cpu, data: arm::swi::InstrDesc) -> cpu::InstrStatus {
     if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
@@ -10,4 +40,15 @@ pub fn swi(cpu: &mut Cpu, data: arm::swi::InstrDesc) -> cpu::InstrStatus {
     let next_instr = cpu.regs[15] - cpu.get_pc_offset() / 2;
     cpu.enter_exception(next_instr, cpu::Mode::Svc);
     cpu::InstrStatus::Branched
-}
\ No newline at end of file
+}
+
+/// Raises the Undefined Instruction exception, as the core does for encodings that neither it
+/// nor its coprocessors implement.
+pub fn undefined(cpu: &mut Cpu) -> cpu::InstrStatus {
+    let addr = cpu.regs[15] - cpu.get_pc_offset();
+    warn!("Undefined instruction at 0x{:X}", addr);
+
+    let next_instr = cpu.regs[15] - cpu.get_pc_offset() / 2;
+    cpu.enter_exception(next_instr, cpu::Mode::Und);
+    cpu::InstrStatus::Branched
+}
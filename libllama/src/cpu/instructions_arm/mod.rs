@@ -1,15 +1,21 @@
 mod branch;
 mod coprocessor;
 mod data_processing;
+mod dsp;
 mod load_store;
+mod load_store_exclusive;
 mod load_store_multiple;
+mod media;
 mod misc;
 mod program_status;
 
 pub use self::branch::*;
 pub use self::coprocessor::*;
 pub use self::data_processing::*;
+pub use self::dsp::*;
 pub use self::load_store::*;
+pub use self::load_store_exclusive::*;
 pub use self::load_store_multiple::*;
+pub use self::media::*;
 pub use self::misc::*;
 pub use self::program_status::*;
@@ -2,6 +2,31 @@ use cpu;
 use cpu::Cpu;
 use cpu::decoder_arm as arm;
 
+pub fn cps(cpu: &mut Cpu, data: arm::cps::InstrDesc) -> cpu::InstrStatus {
+    let current_mode = cpu::Mode::from_num(bf!((cpu.cpsr).mode));
+    if let cpu::Mode::Usr = current_mode {
+        // CPS has no effect in user mode
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let imod = bf!(data.imod);
+    if bit!(imod, 1) == 1 {
+        // 0b10 enables the selected exceptions, 0b11 disables them
+        let disable = bit!(imod, 0);
+        if bf!(data.a_bit) == 1 { bf!((cpu.cpsr).disable_abort_bit = disable); }
+        if bf!(data.i_bit) == 1 { bf!((cpu.cpsr).disable_irq_bit = disable); }
+        if bf!(data.f_bit) == 1 { bf!((cpu.cpsr).disable_fiq_bit = disable); }
+    }
+
+    if bf!(data.mmod) == 1 {
+        let mode = bf!(data.mode);
+        bf!((cpu.cpsr).mode = mode);
+        cpu.regs.swap(cpu::Mode::from_num(mode));
+    }
+
+    cpu::InstrStatus::InBlock
+}
+
 pub fn mrs(cpu: &mut Cpu, data: arm::mrs::InstrDesc) -> cpu::InstrStatus {
     if !cpu::cond_passed(bf!(data.cond), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
@@ -35,8 +60,11 @@ pub fn instr_msr(cpu: &mut Cpu, data: arm::msr_1::InstrDesc, immediate: bool) ->
         cpu.regs[bits!(shifter_operand, 0 => 3) as usize]
     };
 
-    let unalloc_mask = 0x07FFFF00u32;
-    let user_mask    = 0xF8000000u32;
+    let (unalloc_mask, user_mask) = match cpu.version {
+        cpu::ArmVersion::V5TE => (0x07FFFF00u32, 0xF8000000u32),
+        // ARMv6 adds the GE, E and A bits
+        cpu::ArmVersion::V6K => (0x06F0FC00u32, 0xF80F0200u32),
+    };
     let priv_mask    = 0x0000000Fu32;
     let state_mask   = 0x00000020u32;
 
@@ -78,4 +106,14 @@ pub fn msr_1(cpu: &mut Cpu, data: arm::msr_1::InstrDesc) -> cpu::InstrStatus {
 
 pub fn msr_2(cpu: &mut Cpu, data: arm::msr_2::InstrDesc) -> cpu::InstrStatus {
     instr_msr(cpu, arm::msr_1::InstrDesc::new(data.raw()), false)
-}
\ No newline at end of file
+}
+pub fn setend(cpu: &mut Cpu, data: arm::setend::InstrDesc) -> cpu::InstrStatus {
+    if bf!(data.e_bit) == 1 {
+        // Big-endian data accesses aren't emulated, so don't let the program carry on with the
+        // wrong byte order
+        warn!("SETEND BE is not supported");
+        return cpu::instructions_arm::undefined(cpu);
+    }
+    bf!((cpu.cpsr).big_endian_bit = 0);
+    cpu::InstrStatus::InBlock
+}
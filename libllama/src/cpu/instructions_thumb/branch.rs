@@ -25,13 +25,7 @@ pub fn branch(cpu: &mut Cpu, data: thumb::branch::InstrDesc) -> cpu::InstrStatus
             cpu.branch(addr);
             cpu::InstrStatus::Branched
         },
-        0b01 => {
-            let addr = (cpu.regs[14] + (offset_11 << 1) as u32) & 0xFFFFFFFC;
-            cpu.regs[14] = (cpu.regs[15] - 2) as u32 | 1;
-            bf!((cpu.cpsr).thumb_bit = 0);
-            cpu.branch(addr);
-            cpu::InstrStatus::Branched
-        },
+        0b01 => blx_1(cpu, thumb::blx_1::InstrDesc::new(data.raw())),
         0b10 => {
             cpu.regs[14] = (cpu.regs[15] as i32 + (sign_extend(offset_11 as u32, 11) << 12)) as u32;
             cpu::InstrStatus::InBlock
@@ -46,6 +40,21 @@ pub fn branch(cpu: &mut Cpu, data: thumb::branch::InstrDesc) -> cpu::InstrStatus
     }
 }
 
+pub fn blx_1(cpu: &mut Cpu, data: thumb::blx_1::InstrDesc) -> cpu::InstrStatus {
+    let offset_11 = bf!(data.offset_11);
+
+    // The suffix can only reach word-aligned ARM code
+    if bit!(offset_11, 0) == 1 {
+        return cpu::instructions_arm::undefined(cpu);
+    }
+
+    let addr = (cpu.regs[14] + (offset_11 << 1) as u32) & 0xFFFFFFFC;
+    cpu.regs[14] = (cpu.regs[15] - 2) as u32 | 1;
+    bf!((cpu.cpsr).thumb_bit = 0);
+    cpu.branch(addr);
+    cpu::InstrStatus::Branched
+}
+
 pub fn blx_2(cpu: &mut Cpu, data: thumb::blx_2::InstrDesc) -> cpu::InstrStatus {
     let rm = bf!(data.rm) | (bf!(data.h2) << 3);
     let addr = cpu.regs[rm as usize];
@@ -2,6 +2,7 @@ use cpu;
 use cpu::Cpu;
 use cpu::decoder_arm as arm;
 use cpu::decoder_thumb as thumb;
+use cpu::timing;
 
 enum ProcessInstrBitOp {
     And,
@@ -115,6 +116,13 @@ pub fn bic(cpu: &mut Cpu, data: thumb::bic::InstrDesc) -> cpu::InstrStatus {
     instr_bitwise(cpu, thumb::and::InstrDesc::new(data.raw()), ProcessInstrBitOp::AndNot)
 }
 
+pub fn cmn(cpu: &mut Cpu, data: thumb::cmn::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b111000010111_0000_0000_00000000_0000
+                                      | ((bf!(data.rn) as u32) << 16)
+                                                         | ((bf!(data.rm) as u32) << 0);
+    cpu::instructions_arm::cmn(cpu, arm::cmn::InstrDesc::new(arminst))
+}
+
 pub fn cmp_1(cpu: &mut Cpu, data: thumb::cmp_1::InstrDesc) -> cpu::InstrStatus {
     let base_val = cpu.regs[bf!(data.rn) as usize];
     let immed = bf!(data.immed_8) as u32;
@@ -252,6 +260,7 @@ pub fn mul(cpu: &mut Cpu, data: thumb::mul::InstrDesc) -> cpu::InstrStatus {
 
     let val = (rm * rd) as u32;
     cpu.regs[bf!(data.rd) as usize] = val;
+    cpu.add_cycles(timing::multiply_cycles(rd as u32, true));
 
     bf!((cpu.cpsr).n_bit = bit!(val, 31));
     bf!((cpu.cpsr).z_bit = (val == 0) as u32);
@@ -277,6 +286,27 @@ pub fn orr(cpu: &mut Cpu, data: thumb::orr::InstrDesc) -> cpu::InstrStatus {
     instr_bitwise(cpu, thumb::and::InstrDesc::new(data.raw()), ProcessInstrBitOp::Or)
 }
 
+pub fn rev(cpu: &mut Cpu, data: thumb::rev::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b111001101011_1111_0000_11110011_0000
+                                           | ((bf!(data.rd) as u32) << 12)
+                                                         | ((bf!(data.rn) as u32) << 0);
+    cpu::instructions_arm::rev(cpu, arm::rev::InstrDesc::new(arminst))
+}
+
+pub fn rev16(cpu: &mut Cpu, data: thumb::rev16::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b111001101011_1111_0000_11111011_0000
+                                           | ((bf!(data.rd) as u32) << 12)
+                                                         | ((bf!(data.rn) as u32) << 0);
+    cpu::instructions_arm::rev16(cpu, arm::rev16::InstrDesc::new(arminst))
+}
+
+pub fn revsh(cpu: &mut Cpu, data: thumb::revsh::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b111001101111_1111_0000_11111011_0000
+                                           | ((bf!(data.rd) as u32) << 12)
+                                                         | ((bf!(data.rn) as u32) << 0);
+    cpu::instructions_arm::revsh(cpu, arm::revsh::InstrDesc::new(arminst))
+}
+
 pub fn ror(cpu: &mut Cpu, data: thumb::ror::InstrDesc) -> cpu::InstrStatus {
     let arminst: u32 = 0b1110000110110000_0000_0000_0111_0000
                                           | ((bf!(data.rd) as u32) << 12)
@@ -323,6 +353,20 @@ pub fn sub_4(cpu: &mut Cpu, data: thumb::sub_4::InstrDesc) -> cpu::InstrStatus {
     cpu::instructions_arm::sub(cpu, arm::sub::InstrDesc::new(arminst))
 }
 
+pub fn sxtb(cpu: &mut Cpu, data: thumb::sxtb::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b111001101010_1111_0000_00000111_0000
+                                           | ((bf!(data.rd) as u32) << 12)
+                                                         | ((bf!(data.rm) as u32) << 0);
+    cpu::instructions_arm::sxtb(cpu, arm::sxtb::InstrDesc::new(arminst))
+}
+
+pub fn sxth(cpu: &mut Cpu, data: thumb::sxth::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b111001101011_1111_0000_00000111_0000
+                                           | ((bf!(data.rd) as u32) << 12)
+                                                         | ((bf!(data.rm) as u32) << 0);
+    cpu::instructions_arm::sxth(cpu, arm::sxth::InstrDesc::new(arminst))
+}
+
 pub fn tst(cpu: &mut Cpu, data: thumb::tst::InstrDesc) -> cpu::InstrStatus {
     let base_val = cpu.regs[bf!(data.rn) as usize];
     let val = base_val & cpu.regs[bf!(data.rm) as usize];
@@ -331,4 +375,18 @@ pub fn tst(cpu: &mut Cpu, data: thumb::tst::InstrDesc) -> cpu::InstrStatus {
     bf!((cpu.cpsr).z_bit = (val == 0) as u32);
 
     cpu::InstrStatus::InBlock
-}
\ No newline at end of file
+}
+
+pub fn uxtb(cpu: &mut Cpu, data: thumb::uxtb::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b111001101110_1111_0000_00000111_0000
+                                           | ((bf!(data.rd) as u32) << 12)
+                                                         | ((bf!(data.rm) as u32) << 0);
+    cpu::instructions_arm::uxtb(cpu, arm::uxtb::InstrDesc::new(arminst))
+}
+
+pub fn uxth(cpu: &mut Cpu, data: thumb::uxth::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b111001101111_1111_0000_00000111_0000
+                                           | ((bf!(data.rd) as u32) << 12)
+                                                         | ((bf!(data.rm) as u32) << 0);
+    cpu::instructions_arm::uxth(cpu, arm::uxth::InstrDesc::new(arminst))
+}
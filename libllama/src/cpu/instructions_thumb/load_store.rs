@@ -3,8 +3,20 @@ use cpu::Cpu;
 use cpu::decoder_arm as arm;
 use cpu::decoder_thumb as thumb;
 
+// With an empty register list, nothing is transferred but the base still moves past the sixteen
+// words a full list would have covered
+fn empty_list_writeback(cpu: &mut Cpu, rn: usize) -> cpu::InstrStatus {
+    cpu.regs[rn] = cpu.regs[rn].wrapping_add(0x40);
+    cpu::InstrStatus::InBlock
+}
+
 pub fn ldmia(cpu: &mut Cpu, data: thumb::ldmia::InstrDesc) -> cpu::InstrStatus {
+    if bf!(data.register_list) == 0 {
+        return empty_list_writeback(cpu, bf!(data.rn) as usize);
+    }
+
     // W = (rn not in register list) ? 1 : 0
+    // Unlike the ARM LDM, a loaded base is never overwritten by the writeback
     let w_bit = bf!(data.register_list) & (1 << bf!(data.rn)) == 0;
     let arminst: u32 = 0b1110100010_0_1_0000_00000000_00000000
                                     | ((w_bit as u32) << 21)
@@ -19,7 +31,10 @@ pub fn ldr_1(cpu: &mut Cpu, data: thumb::ldr_1::InstrDesc) -> cpu::InstrStatus {
 
     let addr = base_val + immed_5 * 4;
     // TODO: determine behavior based on CP15 r1 bit_U (22)
-    cpu.regs[bf!(data.rd) as usize] = cpu.mpu.dmem_read::<u32>(addr);
+    let val = cpu.mpu.dmem_read::<u32>(addr);
+    if !cpu.mpu.faulted() {
+        cpu.regs[bf!(data.rd) as usize] = val;
+    }
 
     cpu::InstrStatus::InBlock
 }
@@ -35,7 +50,10 @@ pub fn ldr_2(cpu: &mut Cpu, data: thumb::ldr_2::InstrDesc) -> cpu::InstrStatus {
 pub fn ldr_3(cpu: &mut Cpu, data: thumb::ldr_3::InstrDesc) -> cpu::InstrStatus {
     let immed_8 = bf!(data.immed_8) as u32;
     let addr = (cpu.regs[15] & 0xFFFFFFFC) + immed_8 * 4;
-    cpu.regs[bf!(data.rd) as usize] = cpu.mpu.dmem_read::<u32>(addr);
+    let val = cpu.mpu.dmem_read::<u32>(addr);
+    if !cpu.mpu.faulted() {
+        cpu.regs[bf!(data.rd) as usize] = val;
+    }
 
     cpu::InstrStatus::InBlock
 }
@@ -111,6 +129,11 @@ pub fn push(cpu: &mut Cpu, data: thumb::push::InstrDesc) -> cpu::InstrStatus {
 }
 
 pub fn stmia(cpu: &mut Cpu, data: thumb::stmia::InstrDesc) -> cpu::InstrStatus {
+    if bf!(data.register_list) == 0 {
+        return empty_list_writeback(cpu, bf!(data.rn) as usize);
+    }
+
+    // If the base is in the register list, its original value is stored
     let arminst: u32 = 0b111010001010_0000_00000000_00000000
                                       | ((bf!(data.rn) as u32) << 16)
                                                     | ((bf!(data.register_list) as u32) << 0);
@@ -0,0 +1,33 @@
+use cpu;
+use cpu::Cpu;
+use cpu::decoder_arm as arm;
+use cpu::decoder_thumb as thumb;
+
+pub fn bkpt(cpu: &mut Cpu, data: thumb::bkpt::InstrDesc) -> cpu::InstrStatus {
+    let immed_8 = bf!(data.immed_8) as u32;
+    let arminst: u32 = 0b111000010010_000000000000_0111_0000
+                                      | ((immed_8 >> 4) << 8)
+                                                         | ((immed_8 & 0xF) << 0);
+    cpu::instructions_arm::bkpt(cpu, arm::bkpt::InstrDesc::new(arminst))
+}
+
+pub fn cps(cpu: &mut Cpu, data: thumb::cps::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b111100010000_00_0_0_0000000_000_0_00000
+                                   | ((0b10 | bf!(data.imod) as u32) << 18)
+                                                    | ((bf!(data.a_bit) as u32) << 8)
+                                                     | ((bf!(data.i_bit) as u32) << 7)
+                                                      | ((bf!(data.f_bit) as u32) << 6);
+    cpu::instructions_arm::cps(cpu, arm::cps::InstrDesc::new(arminst))
+}
+
+pub fn setend(cpu: &mut Cpu, data: thumb::setend::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b1111000100000001000000_0_000000000
+                                             | ((bf!(data.e_bit) as u32) << 9);
+    cpu::instructions_arm::setend(cpu, arm::setend::InstrDesc::new(arminst))
+}
+
+pub fn swi(cpu: &mut Cpu, data: thumb::swi::InstrDesc) -> cpu::InstrStatus {
+    let arminst: u32 = 0b11101111_0000000000000000_00000000
+                                                | ((bf!(data.immed_8) as u32) << 0);
+    cpu::instructions_arm::swi(cpu, arm::swi::InstrDesc::new(arminst))
+}
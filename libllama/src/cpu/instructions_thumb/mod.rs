@@ -1,7 +1,9 @@
 mod branch;
 mod data_processing;
 mod load_store;
+mod misc;
 
 pub use self::branch::*;
 pub use self::data_processing::*;
 pub use self::load_store::*;
+pub use self::misc::*;
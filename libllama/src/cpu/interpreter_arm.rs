@@ -1,7 +1,8 @@
-use cpu::Cpu;
-use cpu::decoder_arm::ArmInstruction;
+use cpu::{ArmVersion, Cpu};
+use cpu::decoder_arm::{self as arm, ArmInstruction};
 use cpu::instructions_arm;
 use cpu::regs::Psr;
+use cpu::timing;
 
 pub enum InstrStatus {
     InBlock, // Advance PC by instruction width
@@ -47,18 +48,23 @@ pub fn interpret_arm(cpu: &mut Cpu, instr: ArmInstruction) {
     #[cfg(feature = "trace_instructions")]
     trace!("Instruction {:#X}: {:?}", cpu.regs[15] - cpu.get_pc_offset(), instr);
 
+    let v6 = cpu.version >= ArmVersion::V6K;
+
     let status = match instr {
         ArmInstruction::adc(data) => instructions_arm::adc(cpu, data),
         ArmInstruction::add(data) => instructions_arm::add(cpu, data),
         ArmInstruction::and(data) => instructions_arm::and(cpu, data),
         ArmInstruction::bic(data) => instructions_arm::bic(cpu, data),
         ArmInstruction::bbl(data) => instructions_arm::bbl(cpu, data),
+        ArmInstruction::bkpt(data) => instructions_arm::bkpt(cpu, data),
         ArmInstruction::blx_2(data) => instructions_arm::blx(cpu, data),
         ArmInstruction::bx(data) => instructions_arm::bx(cpu, data),
+        ArmInstruction::cdp(data) => instructions_arm::cdp(cpu, data),
         ArmInstruction::clz(data) => instructions_arm::clz(cpu, data),
         ArmInstruction::cmn(data) => instructions_arm::cmn(cpu, data),
         ArmInstruction::cmp(data) => instructions_arm::cmp(cpu, data),
         ArmInstruction::eor(data) => instructions_arm::eor(cpu, data),
+        ArmInstruction::ldc(data) => instructions_arm::ldc(cpu, data),
         ArmInstruction::ldm_1(data) => instructions_arm::ldm_1(cpu, data),
         ArmInstruction::ldm_2(data) => instructions_arm::ldm_2(cpu, data),
         ArmInstruction::ldm_3(data) => instructions_arm::ldm_3(cpu, data),
@@ -69,20 +75,32 @@ pub fn interpret_arm(cpu: &mut Cpu, instr: ArmInstruction) {
         ArmInstruction::ldrsb(data) => instructions_arm::ldrsb(cpu, data),
         ArmInstruction::ldrsh(data) => instructions_arm::ldrsh(cpu, data),
         ArmInstruction::mcr(data) => instructions_arm::mcr(cpu, data),
+        ArmInstruction::mcrr(data) => instructions_arm::mcrr(cpu, data),
         ArmInstruction::mla(data) => instructions_arm::mla(cpu, data),
         ArmInstruction::mov(data) => instructions_arm::mov(cpu, data),
         ArmInstruction::mrc(data) => instructions_arm::mrc(cpu, data),
+        ArmInstruction::mrrc(data) => instructions_arm::mrrc(cpu, data),
         ArmInstruction::mrs(data) => instructions_arm::mrs(cpu, data),
         ArmInstruction::msr_1(data) => instructions_arm::msr_1(cpu, data),
         ArmInstruction::msr_2(data) => instructions_arm::msr_2(cpu, data),
         ArmInstruction::mul(data) => instructions_arm::mul(cpu, data),
         ArmInstruction::mvn(data) => instructions_arm::mvn(cpu, data),
         ArmInstruction::orr(data) => instructions_arm::orr(cpu, data),
+        ArmInstruction::qadd(data) => instructions_arm::qadd(cpu, data),
+        ArmInstruction::qdadd(data) => instructions_arm::qdadd(cpu, data),
+        ArmInstruction::qdsub(data) => instructions_arm::qdsub(cpu, data),
+        ArmInstruction::qsub(data) => instructions_arm::qsub(cpu, data),
         ArmInstruction::rsb(data) => instructions_arm::rsb(cpu, data),
         ArmInstruction::rsc(data) => instructions_arm::rsc(cpu, data),
         ArmInstruction::sbc(data) => instructions_arm::sbc(cpu, data),
         ArmInstruction::smlal(data) => instructions_arm::smlal(cpu, data),
+        ArmInstruction::smlalxy(data) => instructions_arm::smlalxy(cpu, data),
+        ArmInstruction::smlawy(data) => instructions_arm::smlawy(cpu, data),
+        ArmInstruction::smlaxy(data) => instructions_arm::smlaxy(cpu, data),
         ArmInstruction::smull(data) => instructions_arm::smull(cpu, data),
+        ArmInstruction::smulwy(data) => instructions_arm::smulwy(cpu, data),
+        ArmInstruction::smulxy(data) => instructions_arm::smulxy(cpu, data),
+        ArmInstruction::stc(data) => instructions_arm::stc(cpu, data),
         ArmInstruction::stm_1(data) => instructions_arm::stm_1(cpu, data),
         ArmInstruction::stm_2(data) => instructions_arm::stm_2(cpu, data),
         ArmInstruction::str(data) => instructions_arm::str(cpu, data),
@@ -99,12 +117,52 @@ pub fn interpret_arm(cpu: &mut Cpu, instr: ArmInstruction) {
         ArmInstruction::umull(data) => instructions_arm::umull(cpu, data),
 
         ArmInstruction::mod_blx(data) => instructions_arm::mod_blx(cpu, data),
+        ArmInstruction::pld(data) => instructions_arm::pld(cpu, data),
+
+        // ARMv6K
+        ArmInstruction::clrex(data) if v6 => instructions_arm::clrex(cpu, data),
+        ArmInstruction::cps(data) if v6 => instructions_arm::cps(cpu, data),
+        ArmInstruction::hint(data) if v6 => instructions_arm::hint(cpu, data),
+        // Before ARMv6K, hints were MSRs with an empty field mask
+        ArmInstruction::hint(data) => instructions_arm::msr_1(cpu, arm::msr_1::InstrDesc::new(data.raw())),
+        ArmInstruction::ldrex(data) if v6 => instructions_arm::ldrex(cpu, data),
+        ArmInstruction::ldrexb(data) if v6 => instructions_arm::ldrexb(cpu, data),
+        ArmInstruction::ldrexd(data) if v6 => instructions_arm::ldrexd(cpu, data),
+        ArmInstruction::ldrexh(data) if v6 => instructions_arm::ldrexh(cpu, data),
+        ArmInstruction::pkhbt(data) if v6 => instructions_arm::pkhbt(cpu, data),
+        ArmInstruction::pkhtb(data) if v6 => instructions_arm::pkhtb(cpu, data),
+        ArmInstruction::rev(data) if v6 => instructions_arm::rev(cpu, data),
+        ArmInstruction::rev16(data) if v6 => instructions_arm::rev16(cpu, data),
+        ArmInstruction::revsh(data) if v6 => instructions_arm::revsh(cpu, data),
+        ArmInstruction::rfe(data) if v6 => instructions_arm::rfe(cpu, data),
+        ArmInstruction::setend(data) if v6 => instructions_arm::setend(cpu, data),
+        ArmInstruction::srs(data) if v6 => instructions_arm::srs(cpu, data),
+        ArmInstruction::ssat(data) if v6 => instructions_arm::ssat(cpu, data),
+        ArmInstruction::strex(data) if v6 => instructions_arm::strex(cpu, data),
+        ArmInstruction::strexb(data) if v6 => instructions_arm::strexb(cpu, data),
+        ArmInstruction::strexd(data) if v6 => instructions_arm::strexd(cpu, data),
+        ArmInstruction::strexh(data) if v6 => instructions_arm::strexh(cpu, data),
+        ArmInstruction::sxtab(data) if v6 => instructions_arm::sxtab(cpu, data),
+        ArmInstruction::sxtab16(data) if v6 => instructions_arm::sxtab16(cpu, data),
+        ArmInstruction::sxtah(data) if v6 => instructions_arm::sxtah(cpu, data),
+        ArmInstruction::sxtb(data) if v6 => instructions_arm::sxtb(cpu, data),
+        ArmInstruction::sxtb16(data) if v6 => instructions_arm::sxtb16(cpu, data),
+        ArmInstruction::sxth(data) if v6 => instructions_arm::sxth(cpu, data),
+        ArmInstruction::umaal(data) if v6 => instructions_arm::umaal(cpu, data),
+        ArmInstruction::usat(data) if v6 => instructions_arm::usat(cpu, data),
+        ArmInstruction::uxtab(data) if v6 => instructions_arm::uxtab(cpu, data),
+        ArmInstruction::uxtab16(data) if v6 => instructions_arm::uxtab16(cpu, data),
+        ArmInstruction::uxtah(data) if v6 => instructions_arm::uxtah(cpu, data),
+        ArmInstruction::uxtb(data) if v6 => instructions_arm::uxtb(cpu, data),
+        ArmInstruction::uxtb16(data) if v6 => instructions_arm::uxtb16(cpu, data),
+        ArmInstruction::uxth(data) if v6 => instructions_arm::uxth(cpu, data),
 
-        _ => panic!("Unimplemented instruction! {:#X}: {:?}", cpu.regs[15] - cpu.get_pc_offset(), instr)
+        // Unknown encodings, along with ARMv6K instructions on an ARMv5TE core
+        _ => instructions_arm::undefined(cpu)
     };
 
     match status {
         InstrStatus::InBlock => cpu.regs[15] += 4,
-        InstrStatus::Branched => {},
+        InstrStatus::Branched => cpu.add_cycles(timing::PIPELINE_REFILL),
     }
 }
@@ -1,11 +1,15 @@
-use cpu::{Cpu, InstrStatus};
+use cpu::{ArmVersion, Cpu, InstrStatus};
 use cpu::decoder_thumb::ThumbInstruction;
+use cpu::instructions_arm;
 use cpu::instructions_thumb;
+use cpu::timing;
 
 pub fn interpret_thumb(cpu: &mut Cpu, instr: ThumbInstruction) {
     #[cfg(feature = "trace_instructions")]
     trace!("Instruction {:#X}: {:?}", cpu.regs[15] - cpu.get_pc_offset(), instr);
 
+    let v6 = cpu.version >= ArmVersion::V6K;
+
     let status = match instr {
         ThumbInstruction::adc(data) => instructions_thumb::adc(cpu, data),
         ThumbInstruction::add_1(data) => instructions_thumb::add_1(cpu, data),
@@ -20,9 +24,12 @@ pub fn interpret_thumb(cpu: &mut Cpu, instr: ThumbInstruction) {
         ThumbInstruction::asr_2(data) => instructions_thumb::asr_2(cpu, data),
         ThumbInstruction::b_1(data) => instructions_thumb::b_1(cpu, data),
         ThumbInstruction::bic(data) => instructions_thumb::bic(cpu, data),
+        ThumbInstruction::bkpt(data) => instructions_thumb::bkpt(cpu, data),
+        ThumbInstruction::blx_1(data) => instructions_thumb::blx_1(cpu, data),
         ThumbInstruction::blx_2(data) => instructions_thumb::blx_2(cpu, data),
         ThumbInstruction::branch(data) => instructions_thumb::branch(cpu, data),
         ThumbInstruction::bx(data) => instructions_thumb::bx(cpu, data),
+        ThumbInstruction::cmn(data) => instructions_thumb::cmn(cpu, data),
         ThumbInstruction::cmp_1(data) => instructions_thumb::cmp_1(cpu, data),
         ThumbInstruction::cmp_2(data) => instructions_thumb::cmp_2(cpu, data),
         ThumbInstruction::cmp_3(data) => instructions_thumb::cmp_3(cpu, data),
@@ -56,6 +63,7 @@ pub fn interpret_thumb(cpu: &mut Cpu, instr: ThumbInstruction) {
         ThumbInstruction::sub_2(data) => instructions_thumb::sub_2(cpu, data),
         ThumbInstruction::sub_3(data) => instructions_thumb::sub_3(cpu, data),
         ThumbInstruction::sub_4(data) => instructions_thumb::sub_4(cpu, data),
+        ThumbInstruction::swi(data) => instructions_thumb::swi(cpu, data),
         ThumbInstruction::sbc(data) => instructions_thumb::sbc(cpu, data),
         ThumbInstruction::stmia(data) => instructions_thumb::stmia(cpu, data),
         ThumbInstruction::str_1(data) => instructions_thumb::str_1(cpu, data),
@@ -66,11 +74,24 @@ pub fn interpret_thumb(cpu: &mut Cpu, instr: ThumbInstruction) {
         ThumbInstruction::strh_1(data) => instructions_thumb::strh_1(cpu, data),
         ThumbInstruction::strh_2(data) => instructions_thumb::strh_2(cpu, data),
         ThumbInstruction::tst(data) => instructions_thumb::tst(cpu, data),
-        _ => panic!("Unimplemented instruction! {:#X}: {:?}", cpu.regs[15] - cpu.get_pc_offset(), instr)
+
+        // ARMv6K
+        ThumbInstruction::cps(data) if v6 => instructions_thumb::cps(cpu, data),
+        ThumbInstruction::rev(data) if v6 => instructions_thumb::rev(cpu, data),
+        ThumbInstruction::rev16(data) if v6 => instructions_thumb::rev16(cpu, data),
+        ThumbInstruction::revsh(data) if v6 => instructions_thumb::revsh(cpu, data),
+        ThumbInstruction::setend(data) if v6 => instructions_thumb::setend(cpu, data),
+        ThumbInstruction::sxtb(data) if v6 => instructions_thumb::sxtb(cpu, data),
+        ThumbInstruction::sxth(data) if v6 => instructions_thumb::sxth(cpu, data),
+        ThumbInstruction::uxtb(data) if v6 => instructions_thumb::uxtb(cpu, data),
+        ThumbInstruction::uxth(data) if v6 => instructions_thumb::uxth(cpu, data),
+
+        // Unknown encodings, along with ARMv6K instructions on an ARMv5TE core
+        _ => instructions_arm::undefined(cpu)
     };
 
     match status {
         InstrStatus::InBlock => cpu.regs[15] += 2,
-        InstrStatus::Branched => {},
+        InstrStatus::Branched => cpu.add_cycles(timing::PIPELINE_REFILL),
     }
 }
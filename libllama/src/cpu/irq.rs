@@ -3,6 +3,8 @@ use std::sync::Arc;
 use std::sync::RwLock;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+use snapshot;
+
 #[derive(Debug)]
 pub enum IrqType {
     Dmac1_0 = (1 << 0),
@@ -108,6 +110,24 @@ impl IrqRequests {
     }
 }
 
+impl snapshot::Snapshot for IrqRequests {
+    fn save(&self, w: &mut snapshot::Writer) {
+        let inner = self.inner.read().unwrap();
+        w.put(inner.pending);
+        w.put(inner.enabled);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        let pending = r.get()?;
+        let enabled = r.get()?;
+        self.mod_inner(|inner| {
+            inner.pending = pending;
+            inner.enabled = enabled;
+        });
+        Ok(())
+    }
+}
+
 
 #[derive(Clone)]
 pub struct IrqLine {
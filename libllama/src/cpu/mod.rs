@@ -5,17 +5,27 @@ mod define_inst;
 pub mod decoder_arm;
 pub mod decoder_thumb;
 
+mod breakpoints;
 mod caches;
 mod coproc;
 mod interpreter_arm;
 mod interpreter_thumb;
+mod tcm;
+mod timing;
 
+pub use self::breakpoints::{Breakpoint, BrkCondition};
+pub use self::caches::{FaultKind, MemFault, Watchpoint, WatchKind};
 pub use self::cpu::*;
 pub use self::interpreter_arm::*;
 pub use self::interpreter_thumb::*;
+pub use self::tcm::{Tcm, TcmConfig, ITCM_BOOT, DTCM_BOOT};
 
 pub mod dummy11;
 pub mod instructions_arm;
 pub mod instructions_thumb;
 pub mod irq;
-pub mod regs;
\ No newline at end of file
+pub mod regs;
+pub mod trace;
+
+#[cfg(test)]
+pub mod testutil;
@@ -1,4 +1,5 @@
 use cpu;
+use snapshot;
 
 use std::iter::{Chain, Iterator, Take};
 use std::ops;
@@ -9,6 +10,8 @@ bitfield!(Psr: u32, {
     thumb_bit: 5 => 5,
     disable_fiq_bit: 6 => 6,
     disable_irq_bit: 7 => 7,
+    disable_abort_bit: 8 => 8,
+    big_endian_bit: 9 => 9,
     q_bit: 27 => 27,
     v_bit: 28 => 28,
     c_bit: 29 => 29,
@@ -55,6 +58,56 @@ impl GpRegs {
         }
         self.mode = mode;
     }
+
+    /// Returns register `i` as `mode` sees it, whether or not that's the current mode
+    pub fn banked(&self, mode: cpu::Mode, i: usize) -> u32 {
+        let (bank, pos) = GpBanks::locate(mode, i);
+        if i == 15 || bank as u32 == GpBanks::locate(self.mode, i).0 as u32 {
+            self.active[i]
+        } else {
+            self.banks.get(bank)[pos]
+        }
+    }
+
+    /// Sets register `i` as `mode` sees it, whether or not that's the current mode
+    pub fn set_banked(&mut self, mode: cpu::Mode, i: usize, val: u32) {
+        let (bank, pos) = GpBanks::locate(mode, i);
+        if i == 15 || bank as u32 == GpBanks::locate(self.mode, i).0 as u32 {
+            self.active[i] = val;
+        } else {
+            self.banks.get_mut(bank)[pos] = val;
+        }
+    }
+}
+
+impl snapshot::Snapshot for GpRegs {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.active);
+        w.put(self.mode as u32);
+        w.put(self.banks.sys_bank);
+        w.put(self.banks.svc_bank);
+        w.put(self.banks.abt_bank);
+        w.put(self.banks.und_bank);
+        w.put(self.banks.irq_bank);
+        w.put(self.banks.fiq_bank);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.active = r.get()?;
+        let mode = r.get()?;
+        self.mode = match cpu::Mode::try_from_num(mode) {
+            Some(mode) => mode,
+            None => bail!(snapshot::ErrorKind::Mismatch(format!("invalid register mode {:#X}",
+                                                                mode)))
+        };
+        self.banks.sys_bank = r.get()?;
+        self.banks.svc_bank = r.get()?;
+        self.banks.abt_bank = r.get()?;
+        self.banks.und_bank = r.get()?;
+        self.banks.irq_bank = r.get()?;
+        self.banks.fiq_bank = r.get()?;
+        Ok(())
+    }
 }
 
 impl ops::Index<usize> for GpRegs {
@@ -82,6 +135,39 @@ struct GpBanks {
 }
 
 impl GpBanks {
+    // Which bank holds register `i` for `mode`, named by the mode that owns it, and where
+    fn locate(mode: cpu::Mode, i: usize) -> (cpu::Mode, usize) {
+        match mode {
+            cpu::Mode::Fiq if i >= 7 => (cpu::Mode::Fiq, i - 7),
+            cpu::Mode::Svc | cpu::Mode::Abt | cpu::Mode::Und | cpu::Mode::Irq if i >= 13 => {
+                (mode, i - 13)
+            }
+            _ => (cpu::Mode::Sys, i)
+        }
+    }
+
+    fn get(&self, bank: cpu::Mode) -> &[u32] {
+        match bank {
+            cpu::Mode::Sys | cpu::Mode::Usr => &self.sys_bank,
+            cpu::Mode::Svc => &self.svc_bank,
+            cpu::Mode::Abt => &self.abt_bank,
+            cpu::Mode::Und => &self.und_bank,
+            cpu::Mode::Irq => &self.irq_bank,
+            cpu::Mode::Fiq => &self.fiq_bank,
+        }
+    }
+
+    fn get_mut(&mut self, bank: cpu::Mode) -> &mut [u32] {
+        match bank {
+            cpu::Mode::Sys | cpu::Mode::Usr => &mut self.sys_bank,
+            cpu::Mode::Svc => &mut self.svc_bank,
+            cpu::Mode::Abt => &mut self.abt_bank,
+            cpu::Mode::Und => &mut self.und_bank,
+            cpu::Mode::Irq => &mut self.irq_bank,
+            cpu::Mode::Fiq => &mut self.fiq_bank,
+        }
+    }
+
     fn build_iter<'a>(&'a mut self, mode: cpu::Mode) -> Box<Iterator<Item=&mut u32> + 'a> {
         match mode {
             cpu::Mode::Sys | cpu::Mode::Usr => Box::new(self.sys_bank.iter_mut()),
@@ -92,4 +178,44 @@ impl GpBanks {
             cpu::Mode::Fiq => Box::new(self.sys_bank.iter_mut().take(7).chain(self.fiq_bank.iter_mut()))
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn banked_access() {
+        let mut regs = GpRegs::new(cpu::Mode::Svc);
+        regs[8] = 8;
+        regs[13] = 0x1000;
+        regs.set_banked(cpu::Mode::Irq, 13, 0x2000);
+        regs.set_banked(cpu::Mode::Fiq, 8, 0x88);
+        assert_eq!(regs.banked(cpu::Mode::Svc, 13), 0x1000);
+        assert_eq!(regs.banked(cpu::Mode::Usr, 8), 8);
+
+        regs.swap(cpu::Mode::Irq);
+        assert_eq!(regs[8], 8);
+        assert_eq!(regs[13], 0x2000);
+        assert_eq!(regs.banked(cpu::Mode::Svc, 13), 0x1000);
+
+        regs.swap(cpu::Mode::Fiq);
+        assert_eq!(regs[8], 0x88);
+        assert_eq!(regs.banked(cpu::Mode::Irq, 13), 0x2000);
+    }
+
+    #[test]
+    fn reject_bad_mode() {
+        let regs = GpRegs::new(cpu::Mode::Svc);
+        let mut w = snapshot::Writer::new();
+        w.put(regs.active);
+        w.put(0u32);
+        let data = w.into_inner();
+
+        let mut r = snapshot::Reader::new(&data).unwrap();
+        let mut loaded = GpRegs::new(cpu::Mode::Usr);
+        match *snapshot::Snapshot::load(&mut loaded, &mut r).unwrap_err().kind() {
+            snapshot::ErrorKind::Mismatch(_) => {}
+            ref kind => panic!("Unexpected error {:?}", kind)
+        }
+    }
+}
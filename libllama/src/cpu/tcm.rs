@@ -0,0 +1,135 @@
+use mem;
+use snapshot::{self, Snapshot};
+
+/// Where a TCM is placed, as set through CP15
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TcmConfig {
+    pub base: u32,
+    /// Size of the area the TCM is mirrored across, in bytes
+    pub size: u64,
+    pub enabled: bool,
+    /// Reads go to the bus instead, so software can copy data into the TCM over itself
+    pub load_mode: bool,
+}
+
+impl TcmConfig {
+    pub fn contains(&self, addr: u32) -> bool {
+        self.enabled && addr >= self.base && (addr as u64) < self.base as u64 + self.size
+    }
+}
+
+/// Where the bootrom leaves the ITCM: mirrored across the first 128MB
+pub const ITCM_BOOT: TcmConfig = TcmConfig {
+    base: 0x00000000,
+    size: 0x08000000,
+    enabled: true,
+    load_mode: false,
+};
+
+/// Where the bootrom leaves the DTCM
+pub const DTCM_BOOT: TcmConfig = TcmConfig {
+    base: 0xFFF00000,
+    size: 0x4000,
+    enabled: true,
+    load_mode: false,
+};
+
+/// Tightly-coupled memory of the ARM9, which is mapped wherever CP15 places it.
+///
+/// It takes priority over anything else mapped where it's placed, which comes back once the
+/// TCM is moved away. TCMs created first take priority over later ones.
+pub struct Tcm {
+    block: mem::SharedMemoryBlock,
+    config: TcmConfig,
+    // Index of the TCM's overlay in the memory map
+    overlay: usize,
+}
+
+impl Tcm {
+    /// Creates a `kbs` KB TCM, mapped according to `config`
+    pub fn new(kbs: usize, config: TcmConfig, memory: &mut mem::MemController) -> Tcm {
+        let block = mem::SharedMemoryBlock::new(kbs);
+        let mut tcm = Tcm {
+            overlay: memory.add_overlay(block.clone()),
+            block: block,
+            config: config,
+        };
+        tcm.map(memory);
+        tcm
+    }
+
+    pub fn config(&self) -> TcmConfig {
+        self.config
+    }
+
+    /// Moves the TCM, if the configuration changed
+    pub fn configure(&mut self, config: TcmConfig, memory: &mut mem::MemController) {
+        if config != self.config {
+            self.config = config;
+            self.map(memory);
+        }
+    }
+
+    // Mirrors the block across the configured area. An area smaller than the block still gets
+    // all of it.
+    fn map(&mut self, memory: &mut mem::MemController) {
+        if self.config.enabled {
+            memory.map_overlay(self.overlay, self.config.base, self.config.size,
+                               self.config.load_mode);
+        } else {
+            memory.unmap_overlay(self.overlay);
+        }
+    }
+
+    pub fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.config.base);
+        w.put(self.config.size);
+        w.put_bool(self.config.enabled);
+        w.put_bool(self.config.load_mode);
+        self.block.save(w);
+    }
+
+    /// Restores the TCM's contents and placement
+    pub fn load(&mut self, r: &mut snapshot::Reader, memory: &mut mem::MemController)
+            -> snapshot::Result<()> {
+        let config = TcmConfig {
+            base: r.get()?,
+            size: r.get()?,
+            enabled: r.get_bool()?,
+            load_mode: r.get_bool()?,
+        };
+        self.block.load(r)?;
+        self.configure(config, memory);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shadow_mapped_memory() {
+        let ram = mem::SharedMemoryBlock::new(16);
+        let mut memory = mem::MemController::new();
+        memory.map_region(0x100000, mem::AddressBlock::SharedRam(ram));
+        memory.write::<u32>(0x100000, 0x11);
+        memory.write::<u32>(0x102000, 0x22);
+
+        let config = TcmConfig { base: 0x100000, size: 0x2000, enabled: true, load_mode: false };
+        let mut tcm = Tcm::new(4, config, &mut memory);
+        assert_eq!(memory.read::<u32>(0x100000), 0);
+        memory.write::<u32>(0x101000, 0x33);
+        assert_eq!(memory.read::<u32>(0x100000), 0x33);
+        // Past the TCM's area, the RAM is still there
+        assert_eq!(memory.read::<u32>(0x102000), 0x22);
+
+        // Reads see through the TCM while loading it
+        tcm.configure(TcmConfig { load_mode: true, ..config }, &mut memory);
+        assert_eq!(memory.read::<u32>(0x100000), 0x11);
+
+        tcm.configure(TcmConfig { enabled: false, ..config }, &mut memory);
+        assert_eq!(memory.read::<u32>(0x100000), 0x11);
+        assert_eq!(memory.read::<u32>(0x101000), 0);
+    }
+}
@@ -0,0 +1,29 @@
+use clock;
+use cpu::{irq, ArmVersion, Cpu};
+use mem;
+
+/// Makes a core of the given version with `program` at the start of a 1KB RAM block mapped
+/// at 0, reset to run from there
+pub fn make_cpu(version: ArmVersion, program: &[u32]) -> Cpu {
+    let ram = mem::SharedMemoryBlock::new(1);
+    let mut memory = mem::MemController::new();
+    memory.map_region(0x0, mem::AddressBlock::SharedRam(ram));
+    for (i, instr) in program.iter().enumerate() {
+        memory.write::<u32>(i as u32 * 4, *instr);
+    }
+
+    let (irq_tx, irq_line) = irq::make_channel();
+    let mut cpu = Cpu::new(version, memory, irq_line, clock::make_channel(irq_tx));
+    cpu.reset(0);
+    cpu
+}
+
+/// Maps RAM over the ARM9 exception vectors at 0x08000000, starting with `handler`, which
+/// the IRQ vector runs
+pub fn map_vectors(cpu: &mut Cpu, handler: &[u32]) {
+    let vectors = mem::SharedMemoryBlock::new(1);
+    cpu.mpu.memory.map_region(0x08000000, mem::AddressBlock::SharedRam(vectors));
+    for (i, instr) in handler.iter().enumerate() {
+        cpu.mpu.memory.write::<u32>(0x08000000 + i as u32 * 4, *instr);
+    }
+}
@@ -0,0 +1,51 @@
+// Instruction timing model
+//
+// Every instruction takes one cycle, plus:
+// - internal cycles, e.g. for register-specified shifts and multiplies;
+// - a pipeline refill whenever it branches, including into an exception vector;
+// - wait states for each memory access (instruction fetches included) that goes out on the
+//   bus, taken from the region being accessed. TCM accesses and cache hits are free, while a
+//   cache miss also waits for the line to be filled, and for a dirty line to be written back
+//   first if one had to be evicted.
+
+/// Cycles lost refilling the pipeline after a branch
+pub const PIPELINE_REFILL: usize = 2;
+
+/// Core cycles per SysClock tick: the ARM9 runs at twice the bus clock the timers count.
+pub const CLOCK_DIVIDER: usize = 2;
+
+/// Cycles spent fetching the rest of a 32-byte cache line after the word that missed
+pub const LINE_FILL: usize = 7;
+
+/// Internal cycles taken by a multiply, which terminates early once the remaining top bits
+/// of the multiplier are all zeroes (or, for signed multiplies, all ones).
+pub fn multiply_cycles(multiplier: u32, signed: bool) -> usize {
+    let done = |shift: u32| {
+        let rest = multiplier >> shift;
+        rest == 0 || (signed && rest == !0 >> shift)
+    };
+
+    if done(8) {
+        1
+    } else if done(16) {
+        2
+    } else if done(24) {
+        3
+    } else {
+        4
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn multiply_early_termination() {
+        assert_eq!(multiply_cycles(0x000000FF, false), 1);
+        assert_eq!(multiply_cycles(0xFFFFFF80, true), 1);
+        assert_eq!(multiply_cycles(0xFFFFFF80, false), 4);
+        assert_eq!(multiply_cycles(0x00012345, false), 3);
+        assert_eq!(multiply_cycles(0xFFFF8000, true), 2);
+    }
+}
@@ -0,0 +1,388 @@
+// Execution traces
+//
+// A trace starts with the magic, a u32 format version and a byte for the core's architecture
+// (0 for ARMv5TE, 1 for ARMv6K), followed by one record per executed instruction. All values
+// are little-endian. Each record holds:
+// - a flags byte: bit 0 for Thumb, bit 1 if the CPSR changed, bit 2 if memory was written;
+// - the instruction's address (u32) and encoding (u16 in Thumb, u32 otherwise);
+// - the CPSR mode bits the instruction ran in (u8);
+// - a u16 mask of which of r0-r14 changed, then their new values (u32 each);
+// - the new CPSR (u32), if it changed;
+// - if memory was written, a count (u8) and then, for each write, its address (u32), size
+//   (u8) and the bytes written.
+// Changes are relative to the previous record, so they include anything that happened in
+// between, like interrupt entry. The first record holds every register. The PC isn't stored
+// for each instruction, as the next record's address shows where it went.
+
+use std::io::{Read, Write};
+
+use cpu::ArmVersion;
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
+    errors {
+        BadMagic {
+            description("file is not a llama execution trace")
+        }
+        BadVersion(version: u32) {
+            description("unsupported execution trace version")
+            display("unsupported execution trace version {}", version)
+        }
+        Malformed(what: &'static str) {
+            description("execution trace is malformed")
+            display("execution trace is malformed: {}", what)
+        }
+    }
+}
+
+const MAGIC: &'static [u8; 8] = b"LLAMATRC";
+const VERSION: u32 = 1;
+
+const FLAG_THUMB: u8 = 1 << 0;
+const FLAG_CPSR: u8 = 1 << 1;
+const FLAG_WRITES: u8 = 1 << 2;
+
+/// A data write made by an instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemWrite {
+    pub addr: u32,
+    /// Size of the write in bytes, at most 8
+    pub size: u8,
+    pub value: u64,
+}
+
+/// Registers visible to an instruction: r0-r14 and the CPSR
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RegState {
+    pub regs: [u32; 15],
+    pub cpsr: u32,
+}
+
+/// One executed instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u32,
+    pub instr: u32,
+    pub thumb: bool,
+    /// Mode the instruction ran in, as CPSR mode bits
+    pub mode: u8,
+    /// Registers the instruction changed, with their new values
+    pub regs: Vec<(usize, u32)>,
+    /// The new CPSR, if the instruction changed it
+    pub cpsr: Option<u32>,
+    pub writes: Vec<MemWrite>,
+}
+
+fn put_u16(buf: &mut Vec<u8>, val: u16) {
+    buf.extend_from_slice(&[val as u8, (val >> 8) as u8]);
+}
+
+fn put_u32(buf: &mut Vec<u8>, val: u32) {
+    buf.extend_from_slice(&[val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8]);
+}
+
+/// Writes an execution trace as instructions are executed
+pub struct TraceRecorder {
+    out: Box<Write + Send>,
+    buf: Vec<u8>,
+    // Registers as of the last record
+    last: Option<RegState>,
+}
+
+impl TraceRecorder {
+    /// Starts a trace of a core implementing `version`, writing the header right away
+    pub fn new(mut out: Box<Write + Send>, version: ArmVersion) -> Result<TraceRecorder> {
+        let mut header = MAGIC.to_vec();
+        put_u32(&mut header, VERSION);
+        header.push(match version {
+            ArmVersion::V5TE => 0,
+            ArmVersion::V6K => 1,
+        });
+        out.write_all(&header)?;
+
+        Ok(TraceRecorder {
+            out: out,
+            buf: Vec::new(),
+            last: None,
+        })
+    }
+
+    /// Records an instruction that ran in `mode`, given the registers after it ran
+    pub fn record(&mut self, pc: u32, instr: u32, thumb: bool, mode: u32,
+                  after: &RegState, writes: &[MemWrite]) -> Result<()> {
+        let buf = &mut self.buf;
+        buf.clear();
+
+        let changed = |before: &RegState| (0..15).filter(|&i| before.regs[i] != after.regs[i])
+            .fold(0u16, |mask, i| mask | (1 << i));
+        let (mask, cpsr_changed) = match self.last {
+            Some(ref last) => (changed(last), last.cpsr != after.cpsr),
+            None => (0x7FFF, true)
+        };
+
+        let mut flags = 0;
+        if thumb { flags |= FLAG_THUMB; }
+        if cpsr_changed { flags |= FLAG_CPSR; }
+        if !writes.is_empty() { flags |= FLAG_WRITES; }
+        buf.push(flags);
+
+        put_u32(buf, pc);
+        if thumb {
+            put_u16(buf, instr as u16);
+        } else {
+            put_u32(buf, instr);
+        }
+        buf.push((mode & 0x1F) as u8);
+
+        put_u16(buf, mask);
+        for i in (0..15).filter(|&i| mask & (1 << i) != 0) {
+            put_u32(buf, after.regs[i]);
+        }
+        if flags & FLAG_CPSR != 0 {
+            put_u32(buf, after.cpsr);
+        }
+
+        if flags & FLAG_WRITES != 0 {
+            // Only block transfers make more than a couple, and those can't exceed 16
+            buf.push(writes.len() as u8);
+            for write in writes {
+                put_u32(buf, write.addr);
+                buf.push(write.size);
+                for i in 0..write.size {
+                    buf.push((write.value >> (8 * i)) as u8);
+                }
+            }
+        }
+
+        self.out.write_all(buf)?;
+        self.last = Some(*after);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads back an execution trace, one instruction at a time
+pub struct TraceReader<R: Read> {
+    input: R,
+    version: ArmVersion,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> Result<TraceReader<R>> {
+        let mut header = [0u8; 13];
+        input.read_exact(&mut header).map_err(|_| Error::from(ErrorKind::BadMagic))?;
+        if &header[..8] != MAGIC {
+            bail!(ErrorKind::BadMagic);
+        }
+        let version = read_u32(&header[8..12]);
+        if version != VERSION {
+            bail!(ErrorKind::BadVersion(version));
+        }
+        let arch = match header[12] {
+            0 => ArmVersion::V5TE,
+            1 => ArmVersion::V6K,
+            _ => bail!(ErrorKind::Malformed("unknown architecture")),
+        };
+
+        Ok(TraceReader {
+            input: input,
+            version: arch,
+        })
+    }
+
+    /// Architecture of the traced core
+    pub fn version(&self) -> ArmVersion {
+        self.version
+    }
+
+    fn get(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        self.input.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn get_u32(&mut self) -> Result<u32> {
+        Ok(read_u32(&self.get(4)?))
+    }
+
+    /// Reads the next instruction, or None at the end of the trace
+    pub fn next_entry(&mut self) -> Result<Option<TraceEntry>> {
+        let mut flags = [0u8];
+        if self.input.read(&mut flags)? == 0 {
+            return Ok(None)
+        }
+        let flags = flags[0];
+        let thumb = flags & FLAG_THUMB != 0;
+
+        let pc = self.get_u32()?;
+        let instr = if thumb {
+            read_u32(&self.get(2)?)
+        } else {
+            self.get_u32()?
+        };
+        let mode = self.get(1)?[0];
+
+        let mask = read_u32(&self.get(2)?);
+        let mut regs = Vec::new();
+        for i in (0..15).filter(|&i| mask & (1 << i) != 0) {
+            regs.push((i, self.get_u32()?));
+        }
+        let cpsr = if flags & FLAG_CPSR != 0 {
+            Some(self.get_u32()?)
+        } else {
+            None
+        };
+
+        let mut writes = Vec::new();
+        if flags & FLAG_WRITES != 0 {
+            let count = self.get(1)?[0];
+            for _ in 0..count {
+                let addr = self.get_u32()?;
+                let size = self.get(1)?[0];
+                if size > 8 {
+                    bail!(ErrorKind::Malformed("write larger than 8 bytes"));
+                }
+                let value = self.get(size as usize)?.iter().rev()
+                    .fold(0u64, |val, byte| (val << 8) | *byte as u64);
+                writes.push(MemWrite { addr: addr, size: size, value: value });
+            }
+        }
+
+        Ok(Some(TraceEntry {
+            pc: pc,
+            instr: instr,
+            thumb: thumb,
+            mode: mode,
+            regs: regs,
+            cpsr: cpsr,
+            writes: writes,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = Result<TraceEntry>;
+
+    fn next(&mut self) -> Option<Result<TraceEntry>> {
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => None,
+            Err(x) => Some(Err(x)),
+        }
+    }
+}
+
+// Reads up to 4 little-endian bytes
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes.iter().rev().fold(0, |val, byte| (val << 8) | *byte as u32)
+}
+
+/// Rebuilds the register state over the course of a trace
+pub struct TraceReplay {
+    pub state: RegState,
+    /// Number of instructions applied so far
+    pub instrs: u64,
+}
+
+impl TraceReplay {
+    pub fn new() -> TraceReplay {
+        TraceReplay {
+            state: RegState { regs: [0; 15], cpsr: 0 },
+            instrs: 0,
+        }
+    }
+
+    pub fn apply(&mut self, entry: &TraceEntry) {
+        for &(reg, val) in entry.regs.iter() {
+            self.state.regs[reg] = val;
+        }
+        if let Some(cpsr) = entry.cpsr {
+            self.state.cpsr = cpsr;
+        }
+        self.instrs += 1;
+    }
+}
+
+/// Finds the first instruction where two traces disagree, returning its index in both along
+/// with the differing entries. An entry is None where one trace ended before the other.
+pub fn first_divergence<A, B>(a: A, b: B)
+        -> Result<Option<(u64, Option<TraceEntry>, Option<TraceEntry>)>>
+        where A: Iterator<Item=Result<TraceEntry>>, B: Iterator<Item=Result<TraceEntry>> {
+    let mut a = a;
+    let mut b = b;
+    let mut index = 0;
+    loop {
+        let entry_a = match a.next() { Some(x) => Some(x?), None => None };
+        let entry_b = match b.next() { Some(x) => Some(x?), None => None };
+        match (entry_a, entry_b) {
+            (None, None) => return Ok(None),
+            (Some(ref x), Some(ref y)) if x == y => {}
+            (x, y) => return Ok(Some((index, x, y))),
+        }
+        index += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_and_read_back() {
+        use std::io::Cursor;
+        use std::sync::{Arc, Mutex};
+
+        // Lets the test get at what the recorder wrote
+        struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+        impl Write for SharedBuf {
+            fn write(&mut self, data: &[u8]) -> ::std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(data);
+                Ok(data.len())
+            }
+            fn flush(&mut self) -> ::std::io::Result<()> { Ok(()) }
+        }
+
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let mut recorder = TraceRecorder::new(Box::new(SharedBuf(out.clone())), ArmVersion::V5TE).unwrap();
+
+        let mut after = RegState { regs: [0; 15], cpsr: 0x13 };
+        after.regs[2] = 0x12345678;
+        let writes = [MemWrite { addr: 0x100, size: 2, value: 0xBEEF }];
+        recorder.record(0x08000000, 0xE1C120B0, false, 0x13, &after, &writes).unwrap();
+        let mut thumb_after = after;
+        thumb_after.regs[0] = 1;
+        thumb_after.cpsr = 0x33;
+        recorder.record(0x08000004, 0x4770, true, 0x13, &thumb_after, &[]).unwrap();
+
+        let data = out.lock().unwrap().clone();
+        let reader = TraceReader::new(Cursor::new(data.clone())).unwrap();
+        let entries: Vec<_> = reader.map(|entry| entry.unwrap()).collect();
+        assert_eq!(entries.len(), 2);
+        // The first record holds every register
+        assert_eq!(entries[0].regs.len(), 15);
+        assert_eq!(entries[0].writes, writes.to_vec());
+        assert_eq!(entries[1].regs, vec![(0, 1)]);
+        assert_eq!((entries[1].instr, entries[1].thumb, entries[1].cpsr), (0x4770, true, Some(0x33)));
+
+        let mut replay = TraceReplay::new();
+        for entry in entries.iter() {
+            replay.apply(entry);
+        }
+        assert_eq!(replay.state, thumb_after);
+
+        let same = first_divergence(TraceReader::new(Cursor::new(data.clone())).unwrap(),
+                                    TraceReader::new(Cursor::new(data.clone())).unwrap());
+        assert_eq!(same.unwrap(), None);
+        let truncated = &data[..data.len() - 1];
+        let diverged = first_divergence(TraceReader::new(Cursor::new(data.clone())).unwrap(),
+                                        TraceReader::new(Cursor::new(truncated)).unwrap());
+        assert!(diverged.is_err());
+    }
+}
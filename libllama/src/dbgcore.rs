@@ -1,8 +1,10 @@
+use std::io::Write;
 use std::sync;
 
 use cpu;
 pub use cpu::irq::IrqType;
 use hwcore;
+use ldr;
 
 #[derive(Clone)]
 pub struct DbgCore {
@@ -16,9 +18,11 @@ impl DbgCore {
         }
     }
 
+    /// Locks the system for the debugger. The state left behind by a panic while emulating
+    /// can still be inspected, so poisoned locks are taken over rather than given up on.
     pub fn ctx<'a>(&'a mut self) -> DbgContext<'a> {
         DbgContext {
-            hwcore: self.hw.lock().unwrap()
+            hwcore: self.hw.lock().unwrap_or_else(sync::PoisonError::into_inner)
         }
     }
 }
@@ -49,75 +53,222 @@ impl<'a> DbgContext<'a> {
     }
 
     pub fn hw<'b>(&'b mut self) -> DbgHwContext<'b> {
+        // Blocks while emulation is still running
+        let hw = self.hwcore.hardware9.lock().unwrap_or_else(sync::PoisonError::into_inner);
         DbgHwContext {
-            // Will panic if still running
-            hw: self.hwcore.hardware9.lock().unwrap()
+            hw: HwGuard::Arm9(hw)
         }
     }
 
+    /// Like `hw`, but for the ARM11. Returns `None` unless the ARM11 is being emulated.
+    pub fn hw11<'b>(&'b mut self) -> Option<DbgHwContext<'b>> {
+        // Blocks while emulation is still running
+        let hw = self.hwcore.hardware11.lock().unwrap_or_else(sync::PoisonError::into_inner);
+        if hw.arm11().is_none() {
+            return None
+        }
+        Some(DbgHwContext {
+            hw: HwGuard::Arm11(hw)
+        })
+    }
+
+    /// Symbols of the loaded program, which may be empty
+    pub fn symbols(&self) -> sync::Arc<ldr::SymbolTable> {
+        self.hwcore.symbols.clone()
+    }
+
     pub fn trigger_irq(&mut self, irq: IrqType) {
         self.hwcore_mut().irq_tx.add(irq);
     }
 }
 
+enum HwGuard<'a> {
+    Arm9(sync::MutexGuard<'a, hwcore::Hardware9>),
+    Arm11(sync::MutexGuard<'a, hwcore::Hardware11>),
+}
+
 pub struct DbgHwContext<'a> {
-    hw: sync::MutexGuard<'a, hwcore::Hardware9>
+    hw: HwGuard<'a>
 }
 
 impl<'a> DbgHwContext<'a> {
+    fn cpu(&self) -> &cpu::Cpu {
+        match self.hw {
+            HwGuard::Arm9(ref hw) => &hw.arm9,
+            HwGuard::Arm11(ref hw) => hw.arm11().unwrap(),
+        }
+    }
+
+    fn cpu_mut(&mut self) -> &mut cpu::Cpu {
+        match self.hw {
+            HwGuard::Arm9(ref mut hw) => &mut hw.arm9,
+            HwGuard::Arm11(ref mut hw) => hw.arm11_mut().unwrap(),
+        }
+    }
+
     pub fn read_mem(&self, address: u32, bytes: &mut [u8]) {
-        self.hw.arm9.mpu.memory.read_buf(address, bytes)
+        self.cpu().mpu.debug_read_buf(address, bytes)
     }
 
     pub fn write_mem(&mut self, address: u32, bytes: &[u8]) {
-        self.hw.arm9.mpu.memory.write_buf(address, bytes);
-        self.hw.arm9.mpu.icache.invalidate();
-        self.hw.arm9.mpu.dcache.invalidate();
+        self.cpu_mut().mpu.debug_write_buf(address, bytes);
     }
 
     pub fn read_reg(&self, reg: usize) -> u32 {
-        self.hw.arm9.regs[reg]
+        self.cpu().regs[reg]
     }
 
     pub fn write_reg(&mut self, reg: usize, value: u32) {
-        self.hw.arm9.regs[reg] = value;
+        self.cpu_mut().regs[reg] = value;
     }
 
     pub fn read_cpsr(&self) -> u32 {
-        self.hw.arm9.cpsr.raw()
+        self.cpu().cpsr.raw()
     }
 
-    pub fn write_cpsr(&mut self, value: u32) {
-        self.hw.arm9.cpsr.set_raw(value);
-        let mode_num = bf!((self.hw.arm9.cpsr).mode);
-        self.hw.arm9.regs.swap(cpu::Mode::from_num(mode_num));
+    /// Returns false, leaving the CPSR as it was, if `value` doesn't hold a valid mode
+    pub fn write_cpsr(&mut self, value: u32) -> bool {
+        let cpu = self.cpu_mut();
+        let mode = match cpu::Mode::try_from_num(value & 0x1F) {
+            Some(mode) => mode,
+            None => return false
+        };
+        cpu.cpsr.set_raw(value);
+        cpu.regs.swap(mode);
+        true
+    }
+
+    pub fn mode(&self) -> cpu::Mode {
+        cpu::Mode::from_num(bf!((self.cpu().cpsr).mode))
+    }
+
+    /// Reads register `reg` as `mode` sees it, even if the core is in another mode
+    pub fn read_banked_reg(&self, mode: cpu::Mode, reg: usize) -> u32 {
+        self.cpu().regs.banked(mode, reg)
+    }
+
+    pub fn write_banked_reg(&mut self, mode: cpu::Mode, reg: usize, value: u32) {
+        self.cpu_mut().regs.set_banked(mode, reg, value);
+    }
+
+    /// Reads the SPSR of `mode`. User and system mode have none, so theirs reads as 0.
+    pub fn read_spsr(&self, mode: cpu::Mode) -> u32 {
+        let cpu = self.cpu();
+        match mode {
+            cpu::Mode::Fiq => cpu.spsr_fiq.raw(),
+            cpu::Mode::Irq => cpu.spsr_irq.raw(),
+            cpu::Mode::Svc => cpu.spsr_svc.raw(),
+            cpu::Mode::Abt => cpu.spsr_abt.raw(),
+            cpu::Mode::Und => cpu.spsr_und.raw(),
+            cpu::Mode::Usr | cpu::Mode::Sys => 0,
+        }
+    }
+
+    /// Writes the SPSR of `mode`, unless it's user or system mode
+    pub fn write_spsr(&mut self, mode: cpu::Mode, value: u32) {
+        let cpu = self.cpu_mut();
+        let spsr = match mode {
+            cpu::Mode::Fiq => &mut cpu.spsr_fiq,
+            cpu::Mode::Irq => &mut cpu.spsr_irq,
+            cpu::Mode::Svc => &mut cpu.spsr_svc,
+            cpu::Mode::Abt => &mut cpu.spsr_abt,
+            cpu::Mode::Und => &mut cpu.spsr_und,
+            cpu::Mode::Usr | cpu::Mode::Sys => return,
+        };
+        spsr.set_raw(value);
+    }
+
+    /// Names of the CP15 registers `read_cp15` and `write_cp15` can access, by index
+    pub fn cp15_regs(&mut self) -> Vec<&'static str> {
+        self.cpu_mut().get_coprocessor(15).unwrap().debug_regs()
+    }
+
+    pub fn read_cp15(&mut self, index: usize) -> u32 {
+        self.cpu_mut().get_coprocessor(15).unwrap().debug_read(index)
+    }
+
+    /// Writes a CP15 register, with the same effect on the core as an MCR
+    pub fn write_cp15(&mut self, index: usize, value: u32) {
+        let cpu = self.cpu_mut();
+        let effect = cpu.get_coprocessor(15).unwrap().debug_write(index, value);
+        effect(cpu);
     }
 
     pub fn pause_addr(&self) -> u32 {
-        self.hw.arm9.regs[15] - self.hw.arm9.get_pc_offset()
+        let cpu = self.cpu();
+        cpu.regs[15] - cpu.get_pc_offset()
     }
 
     pub fn branch_to(&mut self, addr: u32) {
-        self.hw.arm9.branch(addr);
+        self.cpu_mut().branch(addr);
     }
 
     pub fn is_thumb(&self) -> bool {
-        bf!((self.hw.arm9.cpsr).thumb_bit) == 1
+        bf!((self.cpu().cpsr).thumb_bit) == 1
     }
 
-    pub fn step(&mut self) {
-        self.hw.arm9.run(1);
+    /// Executes exactly one instruction. An IRQ taken first stops the core at the start of
+    /// the handler, unless `over_irqs` is set to run the whole handler.
+    pub fn step(&mut self, over_irqs: bool) -> cpu::BreakReason {
+        match self.hw {
+            // Goes through the ARM9's execution history, if it's being recorded
+            HwGuard::Arm9(ref mut hw) => hw.step(over_irqs),
+            HwGuard::Arm11(ref mut hw) => hw.arm11_mut().unwrap().step(over_irqs),
+        }
     }
 
     pub fn set_breakpoint(&mut self, addr: u32) {
-        self.hw.arm9.breakpoints.insert(addr);
+        self.cpu_mut().breakpoints.entry(addr).or_insert_with(Default::default);
+    }
+
+    /// Sets a breakpoint that only triggers when `cond` holds, replacing the condition of any
+    /// existing breakpoint at `addr`
+    pub fn set_conditional_breakpoint(&mut self, addr: u32, cond: cpu::BrkCondition) {
+        let brk = self.cpu_mut().breakpoints.entry(addr).or_insert_with(Default::default);
+        brk.condition = Some(cond);
     }
 
     pub fn has_breakpoint(&mut self, addr: u32) -> bool {
-        self.hw.arm9.breakpoints.contains(&addr)
+        self.cpu().breakpoints.contains_key(&addr)
     }
 
     pub fn del_breakpoint(&mut self, addr: u32) {
-        self.hw.arm9.breakpoints.remove(&addr);
+        self.cpu_mut().breakpoints.remove(&addr);
+    }
+
+    /// Returns all breakpoints, sorted by address
+    pub fn breakpoints(&self) -> Vec<(u32, cpu::Breakpoint)> {
+        let mut brks: Vec<_> = self.cpu().breakpoints.iter()
+            .map(|(addr, brk)| (*addr, brk.clone()))
+            .collect();
+        brks.sort_by_key(|&(addr, _)| addr);
+        brks
+    }
+
+    pub fn set_watchpoint(&mut self, wp: cpu::Watchpoint) {
+        if !self.has_watchpoint(wp) {
+            self.cpu_mut().mpu.watchpoints.push(wp);
+        }
+    }
+
+    pub fn has_watchpoint(&self, wp: cpu::Watchpoint) -> bool {
+        self.cpu().mpu.watchpoints.contains(&wp)
+    }
+
+    pub fn del_watchpoint(&mut self, wp: cpu::Watchpoint) {
+        self.cpu_mut().mpu.watchpoints.retain(|other| *other != wp);
+    }
+
+    pub fn watchpoints(&self) -> &[cpu::Watchpoint] {
+        &self.cpu().mpu.watchpoints
+    }
+
+    /// Starts recording an execution trace of the core to `out`
+    pub fn start_trace(&mut self, out: Box<Write + Send>) -> cpu::trace::Result<()> {
+        self.cpu_mut().start_trace(out)
     }
-}
\ No newline at end of file
+
+    pub fn stop_trace(&mut self) -> cpu::trace::Result<()> {
+        self.cpu_mut().stop_trace()
+    }
+}
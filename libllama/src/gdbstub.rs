@@ -1,14 +1,29 @@
+use std::cmp;
+use std::collections::HashSet;
+use std::fmt;
+#[cfg(unix)]
+use std::fs;
 use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::{Add, AddAssign};
-use std::str;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::{self, FromStr};
 use std::sync::atomic::Ordering;
 use std::thread;
 use std::time::Duration;
 
 use mio;
 use mio::tcp::{TcpListener, TcpStream};
+#[cfg(unix)]
+use mio::unix::EventedFd;
 
-use cpu::BreakReason;
+use commands;
+use cpu::{self, BreakReason, Watchpoint, WatchKind};
 use dbgcore;
 use hwcore::Message;
 use msgs;
@@ -26,6 +41,10 @@ error_chain! {
         NoResponse {
             description("client should not expect a response")
         }
+        BadAddress(addr: String) {
+            description("invalid GDB stub address")
+            display("invalid GDB stub address `{}`", addr)
+        }
     }
 }
 
@@ -37,60 +56,420 @@ fn parse_next_hex<'a, I: Iterator<Item=&'a str>>(it: &mut I) -> Result<u32> {
     Ok(utils::from_hex(parse_next(it)?)?)
 }
 
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn decode_hex(data: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(data.len() / 2);
+    for i in 0..data.len() / 2 {
+        bytes.push(utils::from_hex(&data[2*i..2*(i+1)])? as u8);
+    }
+    Ok(bytes)
+}
+
+/// Decodes the binary data of an `X` packet, where `}` escapes the following byte XORed
+/// with 0x20. Packets keep each byte as the char with the same value.
+fn decode_binary(data: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut escaped = false;
+    for b in data.chars().map(|c| c as u32 as u8) {
+        if escaped {
+            bytes.push(b ^ 0x20);
+            escaped = false;
+        } else if b == b'}' {
+            escaped = true;
+        } else {
+            bytes.push(b);
+        }
+    }
+    bytes
+}
+
+
+/// Each core is shown to the client as a thread, numbered like this
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Thread {
+    Arm9 = 1,
+    Arm11 = 2,
+}
+
+impl Thread {
+    fn from_id(id: u32) -> Option<Thread> {
+        match id {
+            1 => Some(Thread::Arm9),
+            2 => Some(Thread::Arm11),
+            _ => None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Thread::Arm9 => "ARM9",
+            Thread::Arm11 => "ARM11",
+        }
+    }
+}
+
+/// Threads that exist; the ARM11 only does while it's being emulated
+fn threads(dbg: &mut dbgcore::DbgContext) -> Vec<Thread> {
+    let mut threads = vec![Thread::Arm9];
+    if dbg.hw11().is_some() {
+        threads.push(Thread::Arm11);
+    }
+    threads
+}
+
+/// Parses a thread id from `H` and `vCont` packets. `-1` (all threads) and `0` (any thread)
+/// give `None`.
+fn parse_thread(id: &str, dbg: &mut dbgcore::DbgContext) -> Result<Option<Thread>> {
+    if id == "-1" || id == "0" {
+        return Ok(None)
+    }
+    match Thread::from_id(utils::from_hex(id)?) {
+        Some(thread) if threads(dbg).contains(&thread) => Ok(Some(thread)),
+        _ => bail!(ErrorKind::Parse)
+    }
+}
+
+fn core_hw<'a>(dbg: &'a mut dbgcore::DbgContext, thread: Thread) -> dbgcore::DbgHwContext<'a> {
+    if thread == Thread::Arm11 && dbg.hw11().is_some() {
+        dbg.hw11().unwrap()
+    } else {
+        dbg.hw()
+    }
+}
+
+// GDB's register numbers past the r0-r15 of org.gnu.gdb.arm.core, as laid out by the target
+// description
+const CPSR_REGNUM: usize = 25;
+const SPSR_REGNUM: usize = 26;
+const BANKED_REGNUM: usize = 27;
+
+const BANKED_REGS: [(&'static str, cpu::Mode, usize); 22] = [
+    ("r8_usr", cpu::Mode::Usr, 8), ("r9_usr", cpu::Mode::Usr, 9),
+    ("r10_usr", cpu::Mode::Usr, 10), ("r11_usr", cpu::Mode::Usr, 11),
+    ("r12_usr", cpu::Mode::Usr, 12), ("r13_usr", cpu::Mode::Usr, 13),
+    ("r14_usr", cpu::Mode::Usr, 14),
+    ("r8_fiq", cpu::Mode::Fiq, 8), ("r9_fiq", cpu::Mode::Fiq, 9),
+    ("r10_fiq", cpu::Mode::Fiq, 10), ("r11_fiq", cpu::Mode::Fiq, 11),
+    ("r12_fiq", cpu::Mode::Fiq, 12), ("r13_fiq", cpu::Mode::Fiq, 13),
+    ("r14_fiq", cpu::Mode::Fiq, 14),
+    ("r13_irq", cpu::Mode::Irq, 13), ("r14_irq", cpu::Mode::Irq, 14),
+    ("r13_svc", cpu::Mode::Svc, 13), ("r14_svc", cpu::Mode::Svc, 14),
+    ("r13_abt", cpu::Mode::Abt, 13), ("r14_abt", cpu::Mode::Abt, 14),
+    ("r13_und", cpu::Mode::Und, 13), ("r14_und", cpu::Mode::Und, 14),
+];
+
+const BANKED_SPSRS: [(&'static str, cpu::Mode); 5] = [
+    ("spsr_fiq", cpu::Mode::Fiq), ("spsr_irq", cpu::Mode::Irq), ("spsr_svc", cpu::Mode::Svc),
+    ("spsr_abt", cpu::Mode::Abt), ("spsr_und", cpu::Mode::Und),
+];
 
-fn cmd_step(ctx: &mut GdbCtx) -> Result<String> {
-    ctx.dbg.hw().step();
-    let break_data = BreakData::new(BreakReason::LimitReached, ctx.dbg);
-    let signal = break_data.to_signal();
+enum GdbReg {
+    Gp(usize),
+    Pc,
+    Cpsr,
+    /// The SPSR of the current mode
+    Spsr,
+    Banked(cpu::Mode, usize),
+    BankedSpsr(cpu::Mode),
+    /// The CP15 registers of each core are numbered separately, the ARM9's first
+    Cp15(Thread, usize),
+}
+
+fn cp15_regs(dbg: &mut dbgcore::DbgContext, thread: Thread) -> Vec<&'static str> {
+    if thread == Thread::Arm11 {
+        dbg.hw11().map_or(Vec::new(), |mut hw| hw.cp15_regs())
+    } else {
+        dbg.hw().cp15_regs()
+    }
+}
+
+fn gdb_reg(num: usize, dbg: &mut dbgcore::DbgContext) -> Option<GdbReg> {
+    let spsrs = BANKED_REGNUM + BANKED_REGS.len();
+    let cp15 = spsrs + BANKED_SPSRS.len();
+    let cp15_arm11 = cp15 + cp15_regs(dbg, Thread::Arm9).len();
+    let end = cp15_arm11 + cp15_regs(dbg, Thread::Arm11).len();
+    Some(match num {
+        0 ... 14 => GdbReg::Gp(num),
+        15 => GdbReg::Pc,
+        CPSR_REGNUM => GdbReg::Cpsr,
+        SPSR_REGNUM => GdbReg::Spsr,
+        n if n >= BANKED_REGNUM && n < spsrs => {
+            let (_, mode, reg) = BANKED_REGS[n - BANKED_REGNUM];
+            GdbReg::Banked(mode, reg)
+        }
+        n if n >= spsrs && n < cp15 => GdbReg::BankedSpsr(BANKED_SPSRS[n - spsrs].1),
+        n if n >= cp15 && n < cp15_arm11 => GdbReg::Cp15(Thread::Arm9, n - cp15),
+        n if n >= cp15_arm11 && n < end => GdbReg::Cp15(Thread::Arm11, n - cp15_arm11),
+        _ => return None
+    })
+}
+
+fn read_gdb_reg(reg: GdbReg, hw: &mut dbgcore::DbgHwContext) -> u32 {
+    match reg {
+        GdbReg::Gp(reg) => hw.read_reg(reg),
+        GdbReg::Pc => hw.pause_addr(),
+        GdbReg::Cpsr => hw.read_cpsr(),
+        GdbReg::Spsr => { let mode = hw.mode(); hw.read_spsr(mode) }
+        GdbReg::Banked(mode, reg) => hw.read_banked_reg(mode, reg),
+        GdbReg::BankedSpsr(mode) => hw.read_spsr(mode),
+        GdbReg::Cp15(_, index) => hw.read_cp15(index),
+    }
+}
+
+/// Returns false if `value` can't be written, e.g. a CPSR without a valid mode
+fn write_gdb_reg(reg: GdbReg, value: u32, hw: &mut dbgcore::DbgHwContext) -> bool {
+    match reg {
+        GdbReg::Gp(reg) => hw.write_reg(reg, value),
+        GdbReg::Pc => hw.branch_to(value),
+        GdbReg::Cpsr => return hw.write_cpsr(value),
+        GdbReg::Spsr => { let mode = hw.mode(); hw.write_spsr(mode, value) }
+        GdbReg::Banked(mode, reg) => hw.write_banked_reg(mode, reg, value),
+        GdbReg::BankedSpsr(mode) => hw.write_spsr(mode, value),
+        GdbReg::Cp15(_, index) => hw.write_cp15(index, value),
+    }
+    true
+}
+
+/// Builds the target description, which tells the client about the registers past r0-r15
+/// and CPSR and how to number them
+fn target_xml(dbg: &mut dbgcore::DbgContext) -> String {
+    let mut xml = String::new();
+    xml += "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n";
+    xml += "<target version=\"1.0\">\n<architecture>arm</architecture>\n";
+
+    xml += "<feature name=\"org.gnu.gdb.arm.core\">\n";
+    for reg in 0..13 {
+        xml += &format!("<reg name=\"r{}\" bitsize=\"32\"/>\n", reg);
+    }
+    xml += "<reg name=\"sp\" bitsize=\"32\" type=\"data_ptr\"/>\n";
+    xml += "<reg name=\"lr\" bitsize=\"32\"/>\n";
+    xml += "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\"/>\n";
+    xml += &format!("<reg name=\"cpsr\" bitsize=\"32\" regnum=\"{}\"/>\n", CPSR_REGNUM);
+    xml += "</feature>\n";
+
+    // Registers are numbered on from the previous one, so only the first needs a regnum
+    xml += "<feature name=\"org.llama.arm.banked\">\n";
+    xml += &format!("<reg name=\"spsr\" bitsize=\"32\" regnum=\"{}\"/>\n", SPSR_REGNUM);
+    for &(name, _, _) in BANKED_REGS.iter() {
+        xml += &format!("<reg name=\"{}\" bitsize=\"32\"/>\n", name);
+    }
+    for &(name, _) in BANKED_SPSRS.iter() {
+        xml += &format!("<reg name=\"{}\" bitsize=\"32\"/>\n", name);
+    }
+    xml += "</feature>\n";
+
+    // Each core has its own CP15 registers, which read as unavailable from the other core
+    let features = [(Thread::Arm9, "org.llama.arm.cp15", "cp15_"),
+                    (Thread::Arm11, "org.llama.arm11.cp15", "cp15_arm11_")];
+    for &(thread, feature, prefix) in features.iter() {
+        let names = cp15_regs(dbg, thread);
+        if names.is_empty() {
+            continue
+        }
+        xml += &format!("<feature name=\"{}\">\n", feature);
+        for name in names {
+            xml += &format!("<reg name=\"{}{}\" bitsize=\"32\" type=\"uint32\" \
+                             group=\"cp15\"/>\n", prefix, name);
+        }
+        xml += "</feature>\n";
+    }
+    xml += "</target>\n";
+    xml
+}
+
+fn cmd_step(ctx: &mut GdbCtx, thread: Option<Thread>) -> Result<String> {
+    let thread = thread.or(ctx.threads.exec).unwrap_or(ctx.threads.regs);
+    let over_irqs = ctx.dbg.hwcore().step_over_irqs();
+    // Entering an IRQ handler is reported like any other completed step, stopped at the
+    // handler's first instruction
+    let reason = match core_hw(ctx.dbg, thread).step(over_irqs) {
+        reason @ BreakReason::Breakpoint
+        | reason @ BreakReason::Watchpoint(..)
+        | reason @ BreakReason::BusError(..) => reason,
+        _ => BreakReason::LimitReached
+    };
+    let break_data = BreakData::new(reason, thread, ctx.dbg);
+    let signal = break_data.to_signal(ctx.session);
     *ctx.last_halt = break_data;
     Ok(signal)
 }
 
+// Both cores always run together, whichever thread is continued
 fn cmd_continue(ctx: &mut GdbCtx) -> Result<String> {
     ctx.dbg.resume();
     bail!(ErrorKind::NoResponse)
 }
 
+fn cmd_reverse(ctx: &mut GdbCtx, cont: bool) -> Result<String> {
+    let res = if cont {
+        ctx.dbg.hwcore_mut().reverse_continue().map(|found| (found, !found))
+    } else {
+        ctx.dbg.hwcore_mut().reverse_step(1).map(|went| (false, went == 0))
+    };
+    let (at_brk, at_start) = match res {
+        Ok(stop) => stop,
+        Err(e) => {
+            warn!("GDB client tried to execute in reverse; {}", e);
+            return Ok("E01".to_owned())
+        }
+    };
+
+    let reason = if at_brk { BreakReason::Breakpoint } else { BreakReason::LimitReached };
+    // Only the ARM9 is recorded
+    let break_data = BreakData::new(reason, Thread::Arm9, ctx.dbg);
+    let mut signal = break_data.to_signal(ctx.session);
+    if at_start {
+        // Tells the client there's no more recorded execution to go back through
+        signal += "replaylog:begin;";
+    }
+    *ctx.last_halt = break_data;
+    Ok(signal)
+}
+
 struct BreakData {
     reason: BreakReason,
+    thread: Thread,
+    /// Whether the client stopped the cores with Ctrl-C
+    interrupted: bool,
     r15: u32,
     r13: u32
 }
 
 impl BreakData {
-    fn new(reason: BreakReason, dbg: &mut dbgcore::DbgContext) -> BreakData {
-        let hw = dbg.hw();
+    fn new(reason: BreakReason, thread: Thread, dbg: &mut dbgcore::DbgContext) -> BreakData {
+        let hw = core_hw(dbg, thread);
         BreakData {
             reason: reason,
+            thread: thread,
+            interrupted: false,
             r15: hw.pause_addr(),
             r13: hw.read_reg(13),
         }
     }
 
-    fn to_signal(&self) -> String {
+    fn to_signal(&self, session: &Session) -> String {
         let reason_str = match self.reason {
-            BreakReason::Breakpoint => format!(";{}:", "swbreak"),
+            BreakReason::Breakpoint => {
+                let hw = session.hw_breakpoints.contains(&(self.thread, self.r15));
+                format!(";{}:", if hw { "hwbreak" } else { "swbreak" })
+            }
+            BreakReason::Watchpoint(kind, addr) => {
+                let name = match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                format!(";{}:{:X}", name, addr)
+            }
             _ => String::new(),
         };
-        format!("T05{:02X}:{:08X};{:02X}:{:08X}{};", 15, self.r15.swap_bytes(),
-                                                     13, self.r13.swap_bytes(),
-                                                     reason_str)
+        // Accesses to unmapped memory are reported as SIGSEGV, Ctrl-C as SIGINT and
+        // everything else as SIGTRAP
+        let signal = match self.reason {
+            BreakReason::BusError(..) => 0x0B,
+            _ if self.interrupted => 0x02,
+            _ => 0x05,
+        };
+        format!("T{:02X}{:02X}:{:08X};{:02X}:{:08X};thread:{:X}{};",
+                signal, 15, self.r15.swap_bytes(), 13, self.r13.swap_bytes(),
+                self.thread as u32, reason_str)
     }
 }
 
-fn handle_gdb_cmd_q(cmd: &str, ctx: &mut GdbCtx) -> Result<String> {
+fn handle_gdb_cmd_q(cmd: &str, stream: &mut Write, ctx: &mut GdbCtx) -> Result<String> {
     let mut s = cmd.splitn(2, ':');
     let ty = parse_next(&mut s)?;
     let mut out = String::new();
     match ty {
         "Supported" => {
-            out += "PacketSize=400;BreakpointCommands+;swbreak+";
+            out += &format!("PacketSize={:X};BreakpointCommands+;swbreak+;hwbreak+",
+                            PACKET_SIZE);
+            out += ";ReverseStep+;ReverseContinue+;qXfer:features:read+;QStartNoAckMode+";
+        }
+        "Xfer" => {
+            let mut args = parse_next(&mut s)?.splitn(4, ':');
+            let object = parse_next(&mut args)?;
+            let op = parse_next(&mut args)?;
+            let annex = parse_next(&mut args)?;
+            if object != "features" || op != "read" {
+                warn!("GDB client tried to transfer unsupported object {}:{}", object, op);
+                return Ok(out)
+            }
+            if annex != "target.xml" {
+                return Ok("E00".to_owned())
+            }
+
+            let mut range = parse_next(&mut args)?.split(',');
+            let offset = parse_next_hex(&mut range)? as usize;
+            let length = parse_next_hex(&mut range)? as usize;
+            let xml = target_xml(ctx.dbg);
+            if offset >= xml.len() {
+                out += "l";
+            } else {
+                // `l` marks the last chunk of the document
+                let end = cmp::min(offset + length, xml.len());
+                out += if end == xml.len() { "l" } else { "m" };
+                out += &xml[offset..end];
+            }
+        }
+        "C" => {
+            out += &format!("QC{:X}", ctx.threads.regs as u32);
+        }
+        "fThreadInfo" => {
+            let ids: Vec<String> = threads(ctx.dbg).iter()
+                .map(|thread| format!("{:X}", *thread as u32))
+                .collect();
+            out += "m";
+            out += &ids.join(",");
+        }
+        "sThreadInfo" => {
+            // Every thread was listed by qfThreadInfo
+            out += "l";
+        }
+        ty if ty.starts_with("ThreadExtraInfo,") => {
+            let id = utils::from_hex(&ty["ThreadExtraInfo,".len()..])?;
+            if let Some(thread) = Thread::from_id(id) {
+                out += &encode_hex(thread.name().as_bytes());
+            }
+        }
+        ty if ty.starts_with("Rcmd,") => {
+            // `monitor <command>` runs a debugger command, as typed into the frontend
+            let command = decode_hex(&ty["Rcmd,".len()..])?;
+            let command = String::from_utf8_lossy(&command);
+            trace!("GDB client running monitor command `{}`", command);
+            commands::handle(ctx.dbg, &mut PacketOutput(stream), command.split_whitespace());
+            out += "OK";
         }
         _ => warn!("GDB client tried to run unsupported `q` command {}", ty)
     }
     Ok(out)
 }
 
+/// Sends monitor command output to the client as `O` packets, a line at a time
+struct PacketOutput<'a>(&'a mut Write);
+
+impl<'a> PacketOutput<'a> {
+    fn write_line(&mut self, msg: &str) {
+        let packet = format!("O{}", encode_hex(format!("{}\n", msg).as_bytes()));
+        if let Err(e) = write_gdb_packet(&packet, &mut *self.0) {
+            warn!("Could not send monitor command output to GDB client; {}", e);
+        }
+    }
+}
+
+impl<'a> commands::Output for PacketOutput<'a> {
+    fn info(&mut self, msg: &str) {
+        self.write_line(msg);
+    }
+
+    fn error(&mut self, msg: &str) {
+        self.write_line(&format!("Error: {}", msg));
+    }
+}
+
 fn handle_gdb_cmd_v(cmd: &str, ctx: &mut GdbCtx) -> Result<String> {
     let mut s = cmd.splitn(2, |c| c == ',' || c == ':' || c == ';');
     let ty = parse_next(&mut s)?;
@@ -102,13 +481,16 @@ fn handle_gdb_cmd_v(cmd: &str, ctx: &mut GdbCtx) -> Result<String> {
             for thread in threads {
                 let mut thread_data = thread.split(':');
                 let action = parse_next(&mut thread_data)?;
-                let thread_name = thread_data.next();
-                if let Some(name) = thread_name {
-                    assert!(name == "-1");
-                }
+                let thread = match thread_data.next() {
+                    Some(id) => match parse_thread(id, ctx.dbg) {
+                        Ok(thread) => thread,
+                        Err(_) => return Ok("E01".to_owned())
+                    },
+                    None => None
+                };
                 match action {
                     "c" => return cmd_continue(ctx),
-                    "s" => return cmd_step(ctx),
+                    "s" => return cmd_step(ctx, thread),
                     _ => warn!("GDB client tried to run unsupported `vCont` action {}", action)
                 }
             }
@@ -127,14 +509,14 @@ fn handle_gdb_cmd_v(cmd: &str, ctx: &mut GdbCtx) -> Result<String> {
 }
 
 
-fn handle_gdb_cmd(cmd: &str, ctx: &mut GdbCtx) -> Result<String> {
+fn handle_gdb_cmd(cmd: &str, stream: &mut Write, ctx: &mut GdbCtx) -> Result<String> {
     let ty = parse_next(&mut cmd.chars())?;
     let params = &cmd[1..];
     let mut out = String::new();
     ctx.dbg.pause();
     match ty {
         'g' => {
-            let hw = ctx.dbg.hw();
+            let hw = ctx.hw();
             for reg in 0..15 {
                 out += &format!("{:08X}", hw.read_reg(reg).swap_bytes());
             }
@@ -142,85 +524,208 @@ fn handle_gdb_cmd(cmd: &str, ctx: &mut GdbCtx) -> Result<String> {
             out += &format!("{:08X}", hw.read_cpsr().swap_bytes());
         }
         'G' => {
-            let mut hw = ctx.dbg.hw();
+            let mut hw = ctx.hw();
             let nth_val = |index: usize| -> Result<u32> {
                 let param_reg_range = 8*index..8*(index+1);
                 let val = utils::from_hex(&params[param_reg_range])?;
                 Ok(val.swap_bytes())
             };
 
+            // register at 16: CPSR, checked first so a bad mode leaves every register alone
+            let cpsr = nth_val(16)?;
+            if cpu::Mode::try_from_num(cpsr & 0x1F).is_none() {
+                warn!("GDB client tried to write CPSR 0x{:08X} with an invalid mode", cpsr);
+                return Ok("E00".to_owned())
+            }
+
             for reg in 0..15 {
                 hw.write_reg(reg, nth_val(reg)?);
             }
             // register at 15: PC
             hw.branch_to(nth_val(15)?);
-            // register at 16: CPSR
-            hw.write_cpsr(nth_val(16)?);
+            hw.write_cpsr(cpsr);
             out += "OK";
         }
         'm' => {
-            let hw = ctx.dbg.hw();
+            let hw = ctx.hw();
             let mut params = params.split(',');
             let addr = parse_next_hex(&mut params)?;
-            let size = parse_next_hex(&mut params)?;
-            let mut buf = [0u8];
-            for b in 0..size {
-                hw.read_mem(addr+b, &mut buf);
-                out += &format!("{:02X}", buf[0]);
-            }
+            let size = parse_next_hex(&mut params)? as usize;
+            // Shorter replies are allowed, and have to fit in a packet
+            let mut buf = vec![0u8; cmp::min(size, PACKET_SIZE / 2)];
+            hw.read_mem(addr, &mut buf);
+            out += &encode_hex(&buf);
         }
         'M' => {
-            let mut hw = ctx.dbg.hw();
             let mut params = params.split(|c| c == ',' || c == ':');
             let addr = parse_next_hex(&mut params)?;
-            let size = parse_next_hex(&mut params)?;
-            let data = parse_next(&mut params)?;
-            for b in 0..size {
-                let data_byte_range = 2*(b as usize)..2*((b as usize)+1);
-                let byte = utils::from_hex(&data[data_byte_range])? as u8;
-                hw.write_mem(addr+b, &[byte]);
+            let size = parse_next_hex(&mut params)? as usize;
+            let data = decode_hex(parse_next(&mut params)?)?;
+            if data.len() != size {
+                out += "E01";
+            } else {
+                ctx.hw().write_mem(addr, &data);
+                out += "OK";
+            }
+        }
+        'X' => {
+            let mut params = params.splitn(2, ':');
+            let mut range = parse_next(&mut params)?.split(',');
+            let addr = parse_next_hex(&mut range)?;
+            let size = parse_next_hex(&mut range)? as usize;
+            let data = decode_binary(parse_next(&mut params)?);
+            if data.len() != size {
+                out += "E01";
+            } else {
+                ctx.hw().write_mem(addr, &data);
+                out += "OK";
             }
-            out += "OK";
         }
         'p' => {
-            let hw = ctx.dbg.hw();
-            let reg = utils::from_hex(&params)? as usize;
-            let regval = match reg {
-                0 ... 14 => hw.read_reg(reg),
-                15 => hw.pause_addr(),
-                25 => hw.read_cpsr(),
-                n => panic!("GDB requested bad register value {}", n)
-            };
-            out += &format!("{:08X}", regval.swap_bytes());
+            let num = utils::from_hex(&params)? as usize;
+            let thread = ctx.threads.regs;
+            match gdb_reg(num, ctx.dbg) {
+                Some(GdbReg::Cp15(core, _)) if core != thread => out += "xxxxxxxx",
+                Some(reg) => {
+                    let regval = read_gdb_reg(reg, &mut ctx.hw());
+                    out += &format!("{:08X}", regval.swap_bytes());
+                }
+                None => {
+                    warn!("GDB client tried to read unknown register {}", num);
+                    out += "E00";
+                }
+            }
+        }
+        'P' => {
+            let mut params = params.split('=');
+            let num = parse_next_hex(&mut params)? as usize;
+            let value = parse_next_hex(&mut params)?.swap_bytes();
+            let thread = ctx.threads.regs;
+            match gdb_reg(num, ctx.dbg) {
+                Some(GdbReg::Cp15(core, _)) if core != thread => out += "E00",
+                Some(reg) => {
+                    if write_gdb_reg(reg, value, &mut ctx.hw()) {
+                        out += "OK";
+                    } else {
+                        warn!("GDB client tried to write invalid value 0x{:08X} to register {}",
+                              value, num);
+                        out += "E00";
+                    }
+                }
+                None => {
+                    warn!("GDB client tried to write unknown register {}", num);
+                    out += "E00";
+                }
+            }
         }
         'q' => {
-            return handle_gdb_cmd_q(params, ctx);
+            return handle_gdb_cmd_q(params, stream, ctx);
+        }
+        'Q' => {
+            match params {
+                "StartNoAckMode" => {
+                    // This reply is still acknowledged, but nothing after it
+                    ctx.session.no_ack = true;
+                    out += "OK";
+                }
+                _ => warn!("GDB client tried to run unsupported `Q` command {}", params)
+            }
         }
         's' => {
-            return cmd_step(ctx);
+            return cmd_step(ctx, None);
         }
         'c' => {
             return cmd_continue(ctx);
         }
+        'b' => {
+            match params {
+                "s" => return cmd_reverse(ctx, false),
+                "c" => return cmd_reverse(ctx, true),
+                _ => warn!("GDB client tried to run unsupported `b` command {}", params)
+            }
+        }
         'v' => {
             return handle_gdb_cmd_v(params, ctx);
         }
         'z' | 'Z' => {
-            let mut hw = ctx.dbg.hw();
+            let symbols = ctx.dbg.symbols();
             let mut params = params.split(',');
             let brk_ty = parse_next(&mut params)?;
             let addr = parse_next_hex(&mut params)?;
-            let kind = parse_next(&mut params)?;
-            assert!(brk_ty == "0");
-            if ty == 'Z' {
-                hw.set_breakpoint(addr);
-            } else {
-                hw.del_breakpoint(addr);
+            let kind = parse_next_hex(&mut params)?;
+            let watch_kind = match brk_ty {
+                // Hardware breakpoints work the same as software ones here
+                "0" | "1" => None,
+                "2" => Some(WatchKind::Write),
+                "3" => Some(WatchKind::Read),
+                "4" => Some(WatchKind::Access),
+                _ => {
+                    // An empty reply tells the client this type is unsupported
+                    warn!("GDB client tried to use unsupported breakpoint type {}", brk_ty);
+                    return Ok(out)
+                }
+            };
+            trace!("GDB client {} breakpoint type {} at {}",
+                   if ty == 'Z' { "set" } else { "removed" }, brk_ty, symbols.describe(addr));
+
+            let key = (ctx.threads.regs, addr);
+            // Both kinds share the core's breakpoint, which stays until neither is left
+            let remove_brk = match (ty, brk_ty) {
+                ('Z', "0") => { ctx.session.sw_breakpoints.insert(key); false }
+                ('Z', "1") => { ctx.session.hw_breakpoints.insert(key); false }
+                ('z', "0") => {
+                    ctx.session.sw_breakpoints.remove(&key);
+                    !ctx.session.hw_breakpoints.contains(&key)
+                }
+                ('z', "1") => {
+                    ctx.session.hw_breakpoints.remove(&key);
+                    !ctx.session.sw_breakpoints.contains(&key)
+                }
+                _ => false
+            };
+
+            let mut hw = ctx.hw();
+            // For watchpoints, `kind` is the number of bytes to watch
+            match (ty, watch_kind) {
+                ('Z', None) => hw.set_breakpoint(addr),
+                ('z', None) => if remove_brk { hw.del_breakpoint(addr) },
+                ('Z', Some(watch_kind)) => hw.set_watchpoint(Watchpoint {
+                    addr: addr,
+                    len: kind,
+                    kind: watch_kind
+                }),
+                (_, Some(watch_kind)) => hw.del_watchpoint(Watchpoint {
+                    addr: addr,
+                    len: kind,
+                    kind: watch_kind
+                }),
+                _ => unreachable!()
             }
             out += "OK";
         }
         '?' => {
-            out += &ctx.last_halt.to_signal();
+            out += &ctx.last_halt.to_signal(ctx.session);
+        }
+        'H' => {
+            // `Hg` picks the core for register, memory and breakpoint packets, and `Hc` the
+            // core to step
+            let op = parse_next(&mut params.chars())?;
+            match parse_thread(&params[1..], ctx.dbg) {
+                Ok(thread) => {
+                    if op == 'g' {
+                        ctx.threads.regs = thread.unwrap_or(Thread::Arm9);
+                    } else {
+                        ctx.threads.exec = thread;
+                    }
+                    out += "OK";
+                }
+                Err(_) => out += "E01",
+            }
+        }
+        'T' => {
+            let alive = Thread::from_id(utils::from_hex(params)?)
+                .map_or(false, |thread| threads(ctx.dbg).contains(&thread));
+            out += if alive { "OK" } else { "E01" };
         }
         x => {
             warn!("GDB client tried to run unsupported command {}", x);
@@ -248,39 +753,48 @@ enum PacketType {
     CtrlC,
     AckOk,
     AckErr,
-    EndOfPacket,
+    Incomplete,
     Malformed,
 }
 
-fn load_packet<I: Iterator<Item = u8>>(it: &mut I) -> PacketType {
-    let mut it = it.skip_while(|b| *b != 0x03 && *b != b'$' && *b != b'-' && *b != b'+');
-    match it.next() {
-        Some(0x3) => return PacketType::CtrlC,
-        Some(b'$') => {}
-        Some(b'+') => return PacketType::AckOk,
-        Some(b'-') => return PacketType::AckErr,
-        None => return PacketType::EndOfPacket,
-        _ => return PacketType::Malformed
+/// Parses the first packet in `data`, along with how many bytes it took up. Each byte of a
+/// command becomes the char with the same value, so binary data in `X` packets survives.
+fn load_packet(data: &[u8]) -> (PacketType, usize) {
+    let start = match data.iter().position(|b| *b == 0x03 || *b == b'$' || *b == b'-'
+                                               || *b == b'+') {
+        Some(start) => start,
+        None => return (PacketType::Incomplete, data.len())
+    };
+    match data[start] {
+        0x03 => return (PacketType::CtrlC, start + 1),
+        b'+' => return (PacketType::AckOk, start + 1),
+        b'-' => return (PacketType::AckErr, start + 1),
+        _ => {}
     }
 
+    let end = match data[start..].iter().position(|b| *b == b'#') {
+        Some(end) if start + end + 3 <= data.len() => start + end,
+        // Wait for the rest of the packet
+        _ => return (PacketType::Incomplete, start)
+    };
+
     let mut string = String::new();
     let mut checksum = Checksum(0);
-    for b in it.by_ref().take_while(|b| *b != b'#') {
+    for b in data[start + 1 .. end].iter().cloned() {
         string.push(b as char);
         checksum += b;
     }
 
-    if let (Some(top), Some(bot)) = (it.next(), it.next()) {
-        let packet_checksum = str::from_utf8(&[top, bot]).ok()
-            .and_then(|s| utils::from_hex(s).ok());
-        if Some(checksum.0) == packet_checksum {
-            return PacketType::Command(string)
-        }
+    let packet_checksum = str::from_utf8(&data[end + 1 .. end + 3]).ok()
+        .and_then(|s| utils::from_hex(s).ok());
+    if Some(checksum.0) == packet_checksum {
+        (PacketType::Command(string), end + 3)
+    } else {
+        (PacketType::Malformed, end + 3)
     }
-    return PacketType::Malformed
 }
 
-fn write_gdb_packet(data: &str, stream: &mut TcpStream) -> Result<()> {
+fn write_gdb_packet(data: &str, stream: &mut Write) -> Result<()> {
     let checksum = data.bytes().fold(Checksum(0), |checksum, b| checksum + b);
 
     trace!("Replying with GDB packet: ${}#{:02X}", data, checksum.0);
@@ -289,59 +803,294 @@ fn write_gdb_packet(data: &str, stream: &mut TcpStream) -> Result<()> {
     Ok(())
 }
 
-fn handle_gdb_packet(data: &[u8], stream: &mut TcpStream, ctx: &mut GdbCtx) -> Result<()> {
-    trace!("Recieving GDB packet: {}", str::from_utf8(data).unwrap());
-    let mut it = data.iter().cloned();
+/// Handles the whole packets received so far, leaving any partial one in `data`
+fn handle_gdb_packet(data: &mut Vec<u8>, stream: &mut Write, ctx: &mut GdbCtx) -> Result<()> {
+    trace!("Recieving GDB data: {}", String::from_utf8_lossy(data));
     loop {
-        match load_packet(&mut it) {
+        let (packet, len) = load_packet(data);
+        data.drain(..len);
+        match packet {
             PacketType::Command(cmd) => {
-                stream.write(b"+")?;
-                stream.flush()?;
-                match handle_gdb_cmd(&cmd, ctx) {
+                if !ctx.session.no_ack {
+                    stream.write_all(b"+")?;
+                    stream.flush()?;
+                }
+                match handle_gdb_cmd(&cmd, stream, ctx) {
                     Ok(out) => write_gdb_packet(&out, stream)?,
                     Err(e) => {
-                        if let &ErrorKind::NoResponse = e.kind() {}
-                        else { return Err(e) }
+                        let (no_response, io_error) = match *e.kind() {
+                            ErrorKind::NoResponse => (true, false),
+                            ErrorKind::Io(_) => (false, true),
+                            _ => (false, false),
+                        };
+                        if io_error {
+                            return Err(e)
+                        } else if !no_response {
+                            // Bad packets get an error reply rather than dropping the client
+                            warn!("Could not handle GDB packet `{}`; {}", cmd, e);
+                            write_gdb_packet("E01", stream)?;
+                        }
                     }
                 }
-
             }
             PacketType::CtrlC => {
-                ctx.dbg.pause();
                 trace!("Recieved GDB packet with CTRL-C signal!");
+                if ctx.dbg.running() {
+                    // The stop is reported once both cores have halted
+                    ctx.session.interrupted = true;
+                    ctx.dbg.pause();
+                } else {
+                    // Nothing is going to halt, so report the stop right away
+                    ctx.last_halt.interrupted = true;
+                    write_gdb_packet(&ctx.last_halt.to_signal(ctx.session), stream)?;
+                }
             }
             PacketType::AckOk => {},
             PacketType::AckErr => error!("GDB client replied with error packet!"),
-            PacketType::EndOfPacket => {
+            PacketType::Incomplete => {
                 return Ok(())
             }
             PacketType::Malformed => {
-                trace!("Recieved malformed data {:?}", data);
-                stream.write(b"-")?;
-                stream.flush()?;
-                return Ok(())
+                warn!("Recieved malformed GDB packet");
+                if !ctx.session.no_ack {
+                    stream.write_all(b"-")?;
+                    stream.flush()?;
+                }
             }
         }
     }
 }
 
+/// Threads picked by the client with `H` packets
+struct ThreadSel {
+    regs: Thread,
+    /// `None` to step whichever thread `regs` is
+    exec: Option<Thread>,
+}
+
+impl ThreadSel {
+    fn new() -> ThreadSel {
+        ThreadSel { regs: Thread::Arm9, exec: None }
+    }
+}
+
+/// State of the connection to a client, which starts over when a new client connects
+struct Session {
+    /// Set by `QStartNoAckMode`, after which packets aren't acknowledged either way
+    no_ack: bool,
+    /// Set by Ctrl-C until the cores report halting
+    interrupted: bool,
+    /// Breakpoints set with `Z0`
+    sw_breakpoints: HashSet<(Thread, u32)>,
+    /// Breakpoints set with `Z1`, so stops at them are reported as `hwbreak`. They're
+    /// otherwise no different from the `Z0` kind.
+    hw_breakpoints: HashSet<(Thread, u32)>,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            no_ack: false,
+            interrupted: false,
+            sw_breakpoints: HashSet::new(),
+            hw_breakpoints: HashSet::new(),
+        }
+    }
+}
+
 struct GdbCtx<'a, 'b: 'a> {
     dbg: &'a mut dbgcore::DbgContext<'b>,
     last_halt: &'a mut BreakData,
+    threads: &'a mut ThreadSel,
+    session: &'a mut Session,
+}
+
+impl<'a, 'b> GdbCtx<'a, 'b> {
+    /// The core picked for register and memory accesses
+    fn hw<'c>(&'c mut self) -> dbgcore::DbgHwContext<'c> {
+        core_hw(self.dbg, self.threads.regs)
+    }
+}
+
+/// Where the stub listens for GDB clients
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BindAddr {
+    Tcp(SocketAddr),
+    /// A Unix domain socket at this path, which is replaced if it already exists
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Default for BindAddr {
+    fn default() -> BindAddr {
+        BindAddr::Tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 4567))
+    }
+}
+
+impl FromStr for BindAddr {
+    type Err = Error;
+
+    /// Parses `<ip>:<port>`, a port alone to listen on localhost, or `unix:<path>`
+    fn from_str(s: &str) -> Result<BindAddr> {
+        if s.starts_with("unix:") {
+            return unix_addr(&s["unix:".len()..])
+        }
+        if let Ok(port) = s.parse::<u16>() {
+            return Ok(BindAddr::Tcp(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                                                    port)))
+        }
+        s.parse::<SocketAddr>()
+            .map(BindAddr::Tcp)
+            .map_err(|_| ErrorKind::BadAddress(s.to_owned()).into())
+    }
+}
+
+#[cfg(unix)]
+fn unix_addr(path: &str) -> Result<BindAddr> {
+    Ok(BindAddr::Unix(PathBuf::from(path)))
+}
+
+#[cfg(not(unix))]
+fn unix_addr(path: &str) -> Result<BindAddr> {
+    bail!(ErrorKind::BadAddress(format!("unix:{}", path)))
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BindAddr::Tcp(ref addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            BindAddr::Unix(ref path) => write!(f, "unix:{}", path.display()),
+        }
+    }
 }
 
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn bind(addr: &BindAddr) -> io::Result<Listener> {
+        match *addr {
+            BindAddr::Tcp(ref addr) => Ok(Listener::Tcp(TcpListener::bind(addr)?)),
+            #[cfg(unix)]
+            BindAddr::Unix(ref path) => {
+                // Left behind if the last run didn't shut down cleanly
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                listener.set_nonblocking(true)?;
+                Ok(Listener::Unix(listener, path.clone()))
+            }
+        }
+    }
+
+    fn register(&self, poll: &mio::Poll) -> io::Result<()> {
+        match *self {
+            Listener::Tcp(ref listener) => {
+                poll.register(listener, TOKEN_LISTENER, mio::Ready::readable(),
+                              mio::PollOpt::edge())
+            }
+            #[cfg(unix)]
+            Listener::Unix(ref listener, _) => {
+                poll.register(&EventedFd(&listener.as_raw_fd()), TOKEN_LISTENER,
+                              mio::Ready::readable(), mio::PollOpt::edge())
+            }
+        }
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match *self {
+            Listener::Tcp(ref listener) => listener.accept().map(|(socket, _)| Stream::Tcp(socket)),
+            #[cfg(unix)]
+            Listener::Unix(ref listener, _) => {
+                let (socket, _) = listener.accept()?;
+                socket.set_nonblocking(true)?;
+                Ok(Stream::Unix(socket))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, ref path) = *self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn register(&self, poll: &mio::Poll) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref socket) => {
+                poll.register(socket, TOKEN_CLIENT, mio::Ready::readable(), mio::PollOpt::edge())
+            }
+            #[cfg(unix)]
+            Stream::Unix(ref socket) => {
+                poll.register(&EventedFd(&socket.as_raw_fd()), TOKEN_CLIENT,
+                              mio::Ready::readable(), mio::PollOpt::edge())
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut socket) => socket.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut socket) => socket.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut socket) => socket.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut socket) => socket.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut socket) => socket.flush(),
+            #[cfg(unix)]
+            Stream::Unix(ref mut socket) => socket.flush(),
+        }
+    }
+}
+
+/// Largest packet the client may send, and the largest reply to `m` packets
+const PACKET_SIZE: usize = 0x400;
+
 const TOKEN_LISTENER: mio::Token = mio::Token(1024);
 const TOKEN_CLIENT: mio::Token = mio::Token(1025);
 
 pub struct GdbStub {
     debugger: dbgcore::DbgCore,
+    addr: BindAddr,
     gdb_thread: Option<thread::JoinHandle<msgs::Client<Message>>>
 }
 
 impl GdbStub {
-    pub fn new(msg_client: msgs::Client<Message>, debugger: dbgcore::DbgCore) -> GdbStub {
+    pub fn new(msg_client: msgs::Client<Message>, debugger: dbgcore::DbgCore,
+               addr: BindAddr) -> GdbStub {
         let mut stub = GdbStub {
             debugger: debugger,
+            addr: addr,
             gdb_thread: None
         };
         stub.start(msg_client);
@@ -350,55 +1099,103 @@ impl GdbStub {
 
     pub fn start(&mut self, msg_client: msgs::Client<Message>) {
         let mut debugger = self.debugger.clone();
+        let addr = self.addr.clone();
         self.gdb_thread = Some(thread::Builder::new().name("GDBStub".to_owned()).spawn(move || {
             use mio::Events;
 
-            let poll = mio::Poll::new()
-                .expect("Could not create mio polling instance!");
-            let listener = TcpListener::bind(&"127.0.0.1:4567".parse().unwrap())
-                .expect("Could not bind TcpListener to port!");
-
-            poll.register(&listener, TOKEN_LISTENER, mio::Ready::readable(), mio::PollOpt::edge())
-                .expect("Could not register TcpListener to mio!");
-
-            let mut connection = Connection {
-                listener: &listener,
-                poll: poll,
-                socket: None,
+            let connection = mio::Poll::new().and_then(|poll| {
+                let listener = Listener::bind(&addr)?;
+                listener.register(&poll)?;
+                Ok(Connection {
+                    listener: listener,
+                    poll: poll,
+                    socket: None,
+                    inbuf: Vec::new(),
+                })
+            });
+            let mut connection = match connection {
+                Ok(connection) => connection,
+                Err(e) => {
+                    error!("Could not start GDB stub on {}; {}", addr, e);
+                    return msg_client
+                }
             };
 
             let mut events = Events::with_capacity(1024);
 
-            info!("Starting GDB stub on port 4567...");
+            info!("Starting GDB stub on {}...", addr);
 
-            let mut last_halt = BreakData::new(BreakReason::Trapped, &mut debugger.ctx());
+            let mut last_halt = BreakData::new(BreakReason::Trapped, Thread::Arm9,
+                                               &mut debugger.ctx());
+            let mut thread_sel = ThreadSel::new();
+            let mut session = Session::new();
+            // Both cores report when they halt. A stop is reported once both have, as coming
+            // from whichever core stopped the other.
+            let mut halts: (Option<BreakReason>, Option<BreakReason>) = (None, None);
             't: loop {
-                connection.poll.poll(&mut events, Some(Duration::from_millis(100)))
-                    .expect("Could not poll for network events!");
+                if let Err(e) = connection.poll.poll(&mut events, Some(Duration::from_millis(100))) {
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        error!("Could not poll for GDB client events; {}", e);
+                        break 't
+                    }
+                }
 
                 let mut ctx = GdbCtx {
                     dbg: &mut debugger.ctx(),
-                    last_halt: &mut last_halt
+                    last_halt: &mut last_halt,
+                    threads: &mut thread_sel,
+                    session: &mut session,
                 };
 
                 for event in &events {
-                    handle_event(&event, &mut connection, |buf, stream| {
-                        handle_gdb_packet(buf, stream, &mut ctx).unwrap();
+                    let connected = handle_event(&event, &mut connection, |buf, stream| {
+                        handle_gdb_packet(buf, stream, &mut ctx)
                     });
+                    if connected {
+                        *ctx.threads = ThreadSel::new();
+                        *ctx.session = Session::new();
+                    }
                 }
 
                 for msg in msg_client.try_iter() {
                     match msg {
                         Message::Quit => break 't,
-                        Message::Arm9Halted(reason) => {
-                            if let Some(ref mut stream) = connection.socket {
-                                let break_data = BreakData::new(reason, ctx.dbg);
-                                write_gdb_packet(&break_data.to_signal(), stream);
-                            }
-                        }
+                        Message::Arm9Halted(reason) => halts.0 = Some(reason),
+                        Message::Arm11Halted(reason) => halts.1 = Some(reason),
                         _ => {}
                     }
                 }
+
+                if halts.0.is_some() && halts.1.is_some() {
+                    let halted = (halts.0.take().unwrap(), halts.1.take().unwrap());
+                    let (reason, thread) = match halted {
+                        (BreakReason::Trapped, BreakReason::Trapped) => {
+                            (BreakReason::Trapped, Thread::Arm9)
+                        }
+                        (BreakReason::Trapped, reason) => (reason, Thread::Arm11),
+                        (reason, _) => (reason, Thread::Arm9),
+                    };
+                    // Other stops that happen to arrive with Ctrl-C still report their reason
+                    let interrupted = match reason {
+                        BreakReason::Trapped => ctx.session.interrupted,
+                        _ => false
+                    };
+                    let mut break_data = BreakData::new(reason, thread, ctx.dbg);
+                    break_data.interrupted = interrupted;
+                    ctx.session.interrupted = false;
+                    *ctx.last_halt = break_data;
+
+                    let res = match connection.socket {
+                        Some(ref mut stream) => {
+                            write_gdb_packet(&ctx.last_halt.to_signal(ctx.session), stream)
+                        }
+                        None => Ok(())
+                    };
+                    if let Err(e) = res {
+                        warn!("Lost connection to GDB client; {}", e);
+                        connection.disconnect();
+                    }
+                }
             }
             msg_client
         }).unwrap())
@@ -411,45 +1208,291 @@ impl GdbStub {
     }
 }
 
-struct Connection<'a> {
-    listener: &'a TcpListener,
+struct Connection {
+    listener: Listener,
     poll: mio::Poll,
-    socket: Option<TcpStream>,
+    socket: Option<Stream>,
+    /// Data received from the client that doesn't make up a whole packet yet
+    inbuf: Vec<u8>,
 }
 
+impl Connection {
+    fn disconnect(&mut self) {
+        // Dropping the socket closes it, which also unregisters it from `poll`
+        self.socket = None;
+        self.inbuf.clear();
+    }
+}
+
+/// Accepts new clients and passes data from the current one to `client_responder`. Returns
+/// whether a new client connected, replacing any previous one.
 fn handle_event<F>(event: &mio::Event, connection: &mut Connection, mut client_responder: F)
-        where F: FnMut(&[u8], &mut TcpStream) {
+        -> bool where F: FnMut(&mut Vec<u8>, &mut Write) -> Result<()> {
 
     let mut buf = [0u8; 1024];
     match event.token() {
         TOKEN_LISTENER => {
-            match connection.listener.accept() {
-                Ok((socket, _)) => {
-                    info!("GDB stub accepting connection");
-                    connection.poll.register(&socket, TOKEN_CLIENT, mio::Ready::readable(),
-                                             mio::PollOpt::edge())
-                        .expect("Could not register TCP client to mio!");
-                    connection.socket = Some(socket);
+            let mut connected = false;
+            loop {
+                let socket = match connection.listener.accept() {
+                    Ok(socket) => socket,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        break; // No more clients waiting to connect
+                    }
+                    Err(e) => {
+                        warn!("GDB stub could not accept connection; {}", e);
+                        break
+                    }
+                };
+                if connection.socket.is_some() {
+                    info!("GDB stub replacing connection with a new client");
                 }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    return; // Socket is not ready anymore, stop accepting
+                connection.disconnect();
+                if let Err(e) = socket.register(&connection.poll) {
+                    warn!("GDB stub could not listen to new client; {}", e);
+                    continue
                 }
-                e => panic!("GDB stub IO error! {:?}", e)
+                info!("GDB stub accepting connection");
+                connection.socket = Some(socket);
+                connected = true;
             }
+            connected
         }
-        TOKEN_CLIENT => for i in 0..128 {
-            match connection.socket.as_mut().unwrap().read(&mut buf) {
-                Ok(0) => {
-                    connection.socket = None;
-                    break;
-                }
-                Ok(l) => client_responder(&buf[..l], connection.socket.as_mut().unwrap()),
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    continue; // Socket is not ready anymore, stop reading
+        TOKEN_CLIENT => {
+            loop {
+                let res = match connection.socket {
+                    Some(ref mut socket) => socket.read(&mut buf),
+                    None => break
+                };
+                let res = match res {
+                    Ok(0) => {
+                        info!("GDB client disconnected");
+                        connection.disconnect();
+                        break
+                    }
+                    Ok(l) => {
+                        connection.inbuf.extend_from_slice(&buf[..l]);
+                        let socket: &mut Write = connection.socket.as_mut().unwrap();
+                        client_responder(&mut connection.inbuf, socket)
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        break; // Socket is not ready anymore, stop reading
+                    }
+                    Err(e) => Err(e.into())
+                };
+                if let Err(e) = res {
+                    warn!("Lost connection to GDB client; {}", e);
+                    connection.disconnect();
+                    break
                 }
-                e => panic!("GDB stub IO error! {:?}", e), // Unexpected error
             }
-        },
+            false
+        }
         _ => unimplemented!()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hwcore::{self, HwCore};
+    use ldr;
+    use mem;
+
+    struct TestLoader {
+        arm11: bool,
+    }
+
+    impl ldr::Loader for TestLoader {
+        fn entrypoint(&self) -> u32 {
+            0x08000000
+        }
+
+        fn load(&self, _controller: &mut mem::MemController) {}
+
+        fn arm11_state(&self) -> hwcore::Arm11State {
+            if self.arm11 {
+                hwcore::Arm11State::Emulated(0x1FF80000)
+            } else {
+                hwcore::Arm11State::None
+            }
+        }
+    }
+
+    /// Runs `f` against a session with a freshly reset system, the ARM11 emulated if `arm11`
+    fn with_ctx<F: FnOnce(&mut GdbCtx)>(arm11: bool, f: F) {
+        let hw = HwCore::new(msgs::Pump::new(), &TestLoader { arm11: arm11 });
+        let mut debugger = dbgcore::DbgCore::bind(hw);
+        let mut dbg = debugger.ctx();
+        let mut last_halt = BreakData::new(BreakReason::LimitReached, Thread::Arm9, &mut dbg);
+        let mut threads = ThreadSel::new();
+        let mut session = Session::new();
+        f(&mut GdbCtx {
+            dbg: &mut dbg,
+            last_halt: &mut last_halt,
+            threads: &mut threads,
+            session: &mut session,
+        });
+    }
+
+    fn run_cmd(cmd: &str, ctx: &mut GdbCtx) -> String {
+        handle_gdb_cmd(cmd, &mut Vec::new(), ctx).unwrap()
+    }
+
+    #[test]
+    fn split_packets() {
+        let data = b"+$m0,4#fd$M0,1:F";
+        let (packet, len) = load_packet(data);
+        assert!(match packet { PacketType::AckOk => true, _ => false });
+        assert_eq!(len, 1);
+
+        let (packet, len) = load_packet(&data[1..]);
+        assert!(match packet { PacketType::Command(ref cmd) => cmd == "m0,4", _ => false });
+        assert_eq!(len, 8);
+
+        // The rest hasn't all arrived yet
+        let (packet, len) = load_packet(&data[9..]);
+        assert!(match packet { PacketType::Incomplete => true, _ => false });
+        assert_eq!(len, 0);
+
+        let (packet, len) = load_packet(b"\x03");
+        assert!(match packet { PacketType::CtrlC => true, _ => false });
+        assert_eq!(len, 1);
+    }
+
+    #[test]
+    fn binary_data() {
+        let data: String = [0x00, 0x7D, 0x5D, 0xFF, 0x7D, 0x03].iter()
+            .map(|b| *b as char)
+            .collect();
+        assert_eq!(decode_binary(&data), vec![0x00, 0x7D, 0xFF, 0x23]);
+    }
+
+    #[test]
+    fn parse_bind_addr() {
+        assert_eq!("4567".parse::<BindAddr>().unwrap(), BindAddr::default());
+        assert_eq!("0.0.0.0:1234".parse::<BindAddr>().unwrap(),
+                   BindAddr::Tcp("0.0.0.0:1234".parse().unwrap()));
+        assert!("localhost".parse::<BindAddr>().is_err());
+    }
+
+    #[test]
+    fn thread_info() {
+        with_ctx(true, |ctx| {
+            assert_eq!(run_cmd("qfThreadInfo", ctx), "m1,2");
+            assert_eq!(run_cmd("qsThreadInfo", ctx), "l");
+            assert_eq!(run_cmd("T2", ctx), "OK");
+        });
+        with_ctx(false, |ctx| {
+            assert_eq!(run_cmd("qfThreadInfo", ctx), "m1");
+            assert_eq!(run_cmd("qsThreadInfo", ctx), "l");
+            assert_eq!(run_cmd("T2", ctx), "E01");
+        });
+    }
+
+    #[test]
+    fn select_threads() {
+        with_ctx(true, |ctx| {
+            assert_eq!(run_cmd("qC", ctx), "QC1");
+            assert_eq!(run_cmd("Hg2", ctx), "OK");
+            assert_eq!(ctx.threads.regs, Thread::Arm11);
+            assert_eq!(run_cmd("qC", ctx), "QC2");
+            assert_eq!(run_cmd("Hc1", ctx), "OK");
+            assert_eq!(ctx.threads.exec, Some(Thread::Arm9));
+            assert_eq!(run_cmd("Hc-1", ctx), "OK");
+            assert_eq!(ctx.threads.exec, None);
+            // Neither selection changes for threads that don't exist
+            assert_eq!(run_cmd("Hg3", ctx), "E01");
+            assert_eq!(ctx.threads.regs, Thread::Arm11);
+        });
+        with_ctx(false, |ctx| {
+            assert_eq!(run_cmd("Hg2", ctx), "E01");
+            assert_eq!(ctx.threads.regs, Thread::Arm9);
+        });
+    }
+
+    #[test]
+    fn breakpoint_kinds() {
+        with_ctx(false, |ctx| {
+            assert_eq!(run_cmd("Z0,8000100,4", ctx), "OK");
+            assert_eq!(run_cmd("Z1,8000100,4", ctx), "OK");
+            // Either kind keeps the breakpoint while the other is still set
+            assert_eq!(run_cmd("z1,8000100,4", ctx), "OK");
+            assert!(ctx.hw().has_breakpoint(0x08000100));
+            assert_eq!(run_cmd("Z1,8000100,4", ctx), "OK");
+            assert_eq!(run_cmd("z0,8000100,4", ctx), "OK");
+            assert!(ctx.hw().has_breakpoint(0x08000100));
+            assert_eq!(run_cmd("z1,8000100,4", ctx), "OK");
+            assert!(!ctx.hw().has_breakpoint(0x08000100));
+        });
+    }
+
+    #[test]
+    fn reject_bad_cpsr() {
+        with_ctx(false, |ctx| {
+            let cpsr = ctx.hw().read_cpsr();
+            assert_eq!(run_cmd("P19=00000000", ctx), "E00");
+            assert_eq!(ctx.hw().read_cpsr(), cpsr);
+
+            let regs: String = (0..16).map(|_| "78563412").collect();
+            assert_eq!(run_cmd(&format!("G{}00000000", regs), ctx), "E00");
+            assert!(ctx.hw().read_reg(0) != 0x12345678);
+
+            assert_eq!(run_cmd("P19=D3000000", ctx), "OK");
+            assert_eq!(ctx.hw().read_cpsr(), 0xD3);
+            assert_eq!(run_cmd(&format!("G{}DF000000", regs), ctx), "OK");
+            assert_eq!(ctx.hw().read_reg(0), 0x12345678);
+            assert_eq!(ctx.hw().read_cpsr(), 0xDF);
+        });
+    }
+
+    /// Decodes the `O` packets a monitor command sent back into the text they carry
+    fn monitor_output(mut stream: &[u8]) -> String {
+        let mut text = String::new();
+        while !stream.is_empty() {
+            let (packet, len) = load_packet(stream);
+            match packet {
+                PacketType::Command(ref data) if data.starts_with('O') => {
+                    text += &String::from_utf8(decode_hex(&data[1..]).unwrap()).unwrap();
+                }
+                _ => panic!("Unexpected output {}", String::from_utf8_lossy(stream))
+            }
+            stream = &stream[len..];
+        }
+        text
+    }
+
+    #[test]
+    fn monitor_command() {
+        with_ctx(false, |ctx| {
+            let mut stream = Vec::new();
+            let cmd = format!("Rcmd,{}", encode_hex(b"irqstep over"));
+            assert_eq!(handle_gdb_cmd_q(&cmd, &mut stream, ctx).unwrap(), "OK");
+            assert!(ctx.dbg.hwcore().step_over_irqs());
+            assert_eq!(monitor_output(&stream), "Stepping runs through IRQ handlers\n");
+
+            let mut stream = Vec::new();
+            let cmd = format!("Rcmd,{}", encode_hex(b"bogus"));
+            assert_eq!(handle_gdb_cmd_q(&cmd, &mut stream, ctx).unwrap(), "OK");
+            assert_eq!(monitor_output(&stream), "Error: Unrecognized command `bogus`\n");
+        });
+    }
+
+    #[test]
+    fn stop_reply_thread() {
+        with_ctx(true, |ctx| {
+            assert_eq!(run_cmd("Hc2", ctx), "OK");
+            let signal = run_cmd("s", ctx);
+            assert!(signal.starts_with("T05"), "bad stop reply {}", signal);
+            assert!(signal.contains("0F:0400F81F;"), "bad stop reply {}", signal);
+            assert!(signal.contains("thread:2;"), "bad stop reply {}", signal);
+            // The last stop is reported again for `?`
+            assert_eq!(run_cmd("?", ctx), signal);
+
+            assert_eq!(run_cmd("Hc1", ctx), "OK");
+            let signal = run_cmd("s", ctx);
+            assert!(signal.contains("0F:04000008;"), "bad stop reply {}", signal);
+            assert!(signal.contains("thread:1;"), "bad stop reply {}", signal);
+        });
+    }
+}
@@ -0,0 +1,373 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::sync::Arc;
+
+use cpu;
+use snapshot::{self, Snapshot};
+
+error_chain! {
+    links {
+        Snapshot(snapshot::Error, snapshot::ErrorKind);
+    }
+
+    errors {
+        NotRecording {
+            description("execution history is not being recorded")
+        }
+    }
+}
+
+/// Instructions between checkpoints, unless another interval is asked for
+pub const DEFAULT_INTERVAL: u64 = 1000000;
+/// Checkpoints kept before the oldest is dropped, unless another count is asked for
+pub const DEFAULT_CHECKPOINTS: usize = 16;
+/// Memory the checkpoints may hold, in MB, unless another cap is asked for
+pub const DEFAULT_MEMORY_MB: usize = 256;
+
+/// How often checkpoints are taken and how many are kept, which bounds both how far back
+/// execution can go and how much memory the recording takes
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Instructions between checkpoints
+    pub interval: u64,
+    /// Checkpoints kept before the oldest is dropped
+    pub max_checkpoints: usize,
+    /// Bytes of saved state the checkpoints may hold between them. The oldest ones are
+    /// dropped to stay under it, though the latest is always kept.
+    pub max_bytes: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            interval: DEFAULT_INTERVAL,
+            max_checkpoints: DEFAULT_CHECKPOINTS,
+            max_bytes: DEFAULT_MEMORY_MB << 20,
+        }
+    }
+}
+
+const PAGE_SIZE: usize = 0x1000;
+
+// A saved machine state, split into pages. Pages that didn't change since the previous
+// checkpoint are shared with it, so each checkpoint mostly costs what was written since.
+struct Checkpoint {
+    instrs: u64,
+    pages: Vec<Arc<Vec<u8>>>,
+}
+
+impl Checkpoint {
+    fn pack(instrs: u64, data: &[u8], prev: Option<&Checkpoint>,
+            zero_page: &Arc<Vec<u8>>) -> Checkpoint {
+        let pages = data.chunks(PAGE_SIZE).enumerate().map(|(i, chunk)| {
+            if let Some(page) = prev.and_then(|prev| prev.pages.get(i)) {
+                if page.as_slice() == chunk {
+                    return page.clone()
+                }
+            }
+            if zero_page.as_slice() == chunk {
+                zero_page.clone()
+            } else {
+                Arc::new(chunk.to_vec())
+            }
+        }).collect();
+
+        Checkpoint {
+            instrs: instrs,
+            pages: pages,
+        }
+    }
+
+    fn unpack(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pages.len() * PAGE_SIZE);
+        for page in self.pages.iter() {
+            data.extend_from_slice(page);
+        }
+        data
+    }
+}
+
+/// Recorded execution of a core, which the debugger can step and continue backwards through.
+///
+/// Checkpoints of the core, its memory and the given devices are taken periodically, along
+/// with a log of every executed instruction. Going back restores the closest earlier
+/// checkpoint and replays forward from it, which relies on the core behaving the same way
+/// each time. Anything driven by other threads (the other core, HID input) isn't rewound, and
+/// changes made from the debugger are lost when going back past them.
+pub struct History {
+    devices: Vec<(&'static str, Box<Snapshot + Send>)>,
+    config: Config,
+    zero_page: Arc<Vec<u8>>,
+    checkpoints: VecDeque<Checkpoint>,
+    // Addresses of the instructions executed since the oldest checkpoint, as logged by the
+    // core; entry i ran at instruction count `log_start + i`
+    log: VecDeque<u32>,
+    log_start: u64,
+}
+
+impl History {
+    /// Starts recording `cpu`, checkpointing as `config` says. `devices` are saved and
+    /// restored along with the core, each under the given section name.
+    pub fn start(cpu: &mut cpu::Cpu, devices: Vec<(&'static str, Box<Snapshot + Send>)>,
+                 config: Config) -> History {
+        cpu.log_pcs(true);
+        let mut history = History {
+            devices: devices,
+            config: Config {
+                interval: cmp::max(config.interval, 1),
+                max_checkpoints: cmp::max(config.max_checkpoints, 1),
+                max_bytes: config.max_bytes,
+            },
+            zero_page: Arc::new(vec![0; PAGE_SIZE]),
+            checkpoints: VecDeque::new(),
+            log: VecDeque::new(),
+            log_start: 0,
+        };
+        history.restart(cpu);
+        history
+    }
+
+    /// Stops recording `cpu`
+    pub fn stop(self, cpu: &mut cpu::Cpu) {
+        cpu.log_pcs(false);
+    }
+
+    /// Forgets everything recorded so far, starting again from the current state
+    pub fn restart(&mut self, cpu: &mut cpu::Cpu) {
+        cpu.take_pc_log();
+        self.checkpoints.clear();
+        self.log.clear();
+        self.log_start = cpu.instr_count();
+        self.checkpoint(cpu);
+    }
+
+    /// Earliest instruction count execution can go back to
+    pub fn oldest(&self) -> u64 {
+        self.log_start
+    }
+
+    fn log_end(&self) -> u64 {
+        self.log_start + self.log.len() as u64
+    }
+
+    /// Picks up what `cpu` executed since the last call. Must be called after the core is run
+    /// forward, before going back again.
+    pub fn record(&mut self, cpu: &mut cpu::Cpu) {
+        let executed = cpu.take_pc_log();
+        let start = cpu.instr_count() - executed.len() as u64;
+        if start < self.log_start || start > self.log_end() {
+            // The core was changed behind our back, e.g. by loading a snapshot
+            self.restart(cpu);
+            return
+        }
+
+        // Running on from a point that was gone back to replaces what used to follow it
+        self.truncate(start);
+        self.log.extend(executed);
+
+        let last = self.checkpoints.back().map_or(0, |checkpoint| checkpoint.instrs);
+        if cpu.instr_count() >= last + self.config.interval {
+            self.checkpoint(cpu);
+        }
+    }
+
+    /// Goes back by up to `count` instructions, stopping at the oldest checkpoint. Returns
+    /// how far back the core went.
+    pub fn step_back(&mut self, cpu: &mut cpu::Cpu, count: u64) -> Result<u64> {
+        let now = cpu.instr_count();
+        let target = cmp::max(now.saturating_sub(count), self.log_start);
+        self.seek(cpu, target)?;
+        Ok(now - cpu.instr_count())
+    }
+
+    /// Goes back to the last time the core ran into one of its breakpoints, or to the oldest
+    /// checkpoint if it didn't. Returns whether a breakpoint was found.
+    pub fn continue_back(&mut self, cpu: &mut cpu::Cpu) -> Result<bool> {
+        let end = cmp::min(cpu.instr_count(), self.log_end());
+        for instrs in (self.log_start..end).rev() {
+            let addr = self.log[(instrs - self.log_start) as usize];
+            // IRQ entries have bit 0 set, and never match a breakpoint
+            if !cpu.breakpoints.contains_key(&addr) {
+                continue
+            }
+            // Conditions can only be checked with the core back in that state
+            self.seek(cpu, instrs)?;
+            if cpu.at_breakpoint() {
+                return Ok(true)
+            }
+        }
+
+        let oldest = self.log_start;
+        self.seek(cpu, oldest)?;
+        Ok(false)
+    }
+
+    fn truncate(&mut self, instrs: u64) {
+        self.log.truncate((instrs - self.log_start) as usize);
+        while self.checkpoints.back().map_or(false, |checkpoint| checkpoint.instrs > instrs) {
+            self.checkpoints.pop_back();
+        }
+    }
+
+    fn checkpoint(&mut self, cpu: &cpu::Cpu) {
+        let data = self.save(cpu);
+        let checkpoint = Checkpoint::pack(cpu.instr_count(), &data, self.checkpoints.back(),
+                                          &self.zero_page);
+        self.checkpoints.push_back(checkpoint);
+
+        while self.checkpoints.len() > 1 && (self.checkpoints.len() > self.config.max_checkpoints
+                                             || self.held_bytes() > self.config.max_bytes) {
+            self.checkpoints.pop_front();
+            let oldest = self.checkpoints[0].instrs;
+            let dropped = (oldest - self.log_start) as usize;
+            self.log.drain(..dropped);
+            self.log_start = oldest;
+        }
+    }
+
+    /// Bytes of saved state held by the checkpoints, counting each shared page once
+    pub fn held_bytes(&self) -> usize {
+        let mut seen = HashSet::new();
+        let mut bytes = 0;
+        for checkpoint in self.checkpoints.iter() {
+            for page in checkpoint.pages.iter() {
+                let ptr = &**page as *const Vec<u8> as usize;
+                if seen.insert(ptr) {
+                    bytes += page.len();
+                }
+            }
+        }
+        bytes
+    }
+
+    fn save(&self, cpu: &cpu::Cpu) -> Vec<u8> {
+        let mut w = snapshot::Writer::new();
+        w.section("cpu");
+        cpu.save(&mut w);
+        w.section("mem");
+        cpu.mpu.memory.save(&mut w);
+        for &(name, ref device) in self.devices.iter() {
+            w.section(name);
+            device.save(&mut w);
+        }
+        w.into_inner()
+    }
+
+    fn restore(&mut self, cpu: &mut cpu::Cpu, index: usize) -> Result<()> {
+        let data = self.checkpoints[index].unpack();
+        let mut r = snapshot::Reader::new(&data)?;
+        r.section("cpu")?;
+        cpu.load(&mut r)?;
+        r.section("mem")?;
+        cpu.mpu.memory.load(&mut r)?;
+        for device in self.devices.iter_mut() {
+            r.section(device.0)?;
+            device.1.load(&mut r)?;
+        }
+        Ok(())
+    }
+
+    // Restores the latest checkpoint at or before instruction count `target`, then replays
+    // the core up to it
+    fn seek(&mut self, cpu: &mut cpu::Cpu, target: u64) -> Result<()> {
+        let latest = self.checkpoints.iter().rposition(|checkpoint| checkpoint.instrs <= target);
+        let index = match latest {
+            Some(index) => index,
+            None => return Ok(())
+        };
+        self.restore(cpu, index)?;
+
+        // Breakpoints and watchpoints were already stopped at (or not) the first time around
+        let breakpoints = mem::replace(&mut cpu.breakpoints, HashMap::new());
+        let watchpoints = mem::replace(&mut cpu.mpu.watchpoints, Vec::new());
+        while cpu.instr_count() < target {
+            let before = cpu.instr_count();
+            cpu.run(cmp::min(target - before, 1000) as u32);
+            if cpu.instr_count() == before {
+                warn!("Could not replay past instruction {}; the core is waiting for an \
+                       interrupt from another thread", before);
+                break
+            }
+        }
+        // Everything replayed is already in the log
+        cpu.take_pc_log();
+        cpu.breakpoints = breakpoints;
+        cpu.mpu.watchpoints = watchpoints;
+
+        // Don't stop right away on resuming from here
+        cpu.skip_breakpoint_here();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::testutil::make_cpu;
+
+    fn config(interval: u64) -> Config {
+        Config { interval: interval, ..Config::default() }
+    }
+
+    #[test]
+    fn step_and_continue_back() {
+        let mut cpu = make_cpu(cpu::ArmVersion::V5TE, &[
+            0xE2800001, // add r0, r0, #1
+            0xE2811002, // add r1, r1, #2
+            0xEAFFFFFC, // b 0
+        ]);
+        let mut history = History::start(&mut cpu, Vec::new(), config(10));
+        for _ in 0..10 {
+            cpu.run(7);
+            history.record(&mut cpu);
+        }
+        assert_eq!(cpu.instr_count(), 70);
+        assert_eq!((cpu.regs[0], cpu.regs[1]), (24, 46));
+
+        assert_eq!(history.step_back(&mut cpu, 5).unwrap(), 5);
+        assert_eq!(cpu.instr_count(), 65);
+        assert_eq!((cpu.regs[0], cpu.regs[1]), (22, 44));
+
+        cpu.breakpoints.insert(4, Default::default());
+        assert!(history.continue_back(&mut cpu).unwrap());
+        assert_eq!(cpu.instr_count(), 64);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 4);
+        assert_eq!((cpu.regs[0], cpu.regs[1]), (22, 42));
+
+        // Resuming runs past the breakpoint, and replaces the old future
+        cpu.run(3);
+        history.record(&mut cpu);
+        assert_eq!(cpu.instr_count(), 67);
+        assert_eq!(history.log_end(), 67);
+
+        assert_eq!(history.step_back(&mut cpu, 1000).unwrap(), 67);
+        assert_eq!(history.oldest(), 0);
+        assert_eq!((cpu.regs[0], cpu.regs[1]), (0, 0));
+    }
+
+    #[test]
+    fn checkpoint_limits() {
+        let program = [
+            0xE2800001, // add r0, r0, #1
+            0xEAFFFFFD, // b 0
+        ];
+        let run = |config: Config| {
+            let mut cpu = make_cpu(cpu::ArmVersion::V5TE, &program);
+            let mut history = History::start(&mut cpu, Vec::new(), config);
+            for _ in 0..10 {
+                cpu.run(7);
+                history.record(&mut cpu);
+            }
+            history
+        };
+
+        // Checkpoints were taken at 0, 14, 28, 42, 56 and 70 instructions
+        let history = run(Config { interval: 10, max_checkpoints: 2, ..Config::default() });
+        assert_eq!(history.oldest(), 56);
+
+        let history = run(Config { interval: 10, max_bytes: 0, ..Config::default() });
+        assert_eq!(history.oldest(), 70);
+        assert!(history.held_bytes() > 0);
+    }
+}
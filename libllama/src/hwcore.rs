@@ -1,13 +1,16 @@
+use std::cmp;
 use std::sync::{self, Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
 use clock;
 use cpu;
+use history;
 use ldr;
 use mem;
 use io;
 use msgs;
+use snapshot::{self, Snapshot};
 
 
 
@@ -39,13 +42,15 @@ impl msgs::Ident for Message {
 
 
 fn map_memory_regions(arm9_io: io::IoRegsArm9, shared_io: io::IoRegsShared)
-        -> (mem::MemController, mem::MemController, mem::MemController) {
-    let arm9_itcm = mem::SharedMemoryBlock::new(0x20);
-    let arm9_ram = mem::UniqueMemoryBlock::new(0x400);
+        -> (mem::MemController, mem::MemController, mem::MemController, mem::MemController,
+            (cpu::Tcm, cpu::Tcm), mem::SharedMemoryBlock) {
+    let arm9_ram = mem::SharedMemoryBlock::new(0x400);
     let arm9_io = mem::IoMemoryBlock::new(io::IoRegion::Arm9(arm9_io), 0x400);
-    let arm9_dtcm = mem::UniqueMemoryBlock::new(0x10);
     let arm9_bootrom = mem::UniqueMemoryBlock::new(0x40);
 
+    let arm11_bootrom = mem::SharedMemoryBlock::new(0x10);
+    let arm11_private = mem::SharedMemoryBlock::new(0x8);
+
     let shared_io = mem::IoMemoryBlock::new(io::IoRegion::Shared(shared_io), 0x400);
     let vram = mem::SharedMemoryBlock::new(0x1800);
     let dsp_ram = mem::SharedMemoryBlock::new(0x200);
@@ -53,28 +58,47 @@ fn map_memory_regions(arm9_io: io::IoRegsArm9, shared_io: io::IoRegsShared)
     let fcram = mem::SharedMemoryBlock::new(0x20000);
 
     let mut controller9 = mem::MemController::new();
-    for i in 0..0x1000 {
-        controller9.map_region(i * 0x8000, mem::AddressBlock::SharedRam(arm9_itcm.clone()));
-    }
-    controller9.map_region(0x08000000, mem::AddressBlock::UniqueRam(arm9_ram));
-    controller9.map_region(0x10000000, mem::AddressBlock::Io(arm9_io));
-    controller9.map_region(0x10100000, mem::AddressBlock::Io(shared_io.clone()));
-    controller9.map_region(0x18000000, mem::AddressBlock::SharedRam(vram.clone()));
-    controller9.map_region(0x1FF00000, mem::AddressBlock::SharedRam(dsp_ram.clone()));
-    controller9.map_region(0x1FF80000, mem::AddressBlock::SharedRam(axi_wram.clone()));
-    controller9.map_region(0x20000000, mem::AddressBlock::SharedRam(fcram.clone()));
-    controller9.map_region(0xFFF00000, mem::AddressBlock::UniqueRam(arm9_dtcm));
-    controller9.map_region(0xFFFF0000, mem::AddressBlock::UniqueRam(arm9_bootrom));
+    // The TCMs move around as CP15 is programmed, so the ARM9's MPU takes them over later
+    let arm9_itcm = cpu::Tcm::new(0x20, cpu::ITCM_BOOT, &mut controller9);
+    let arm9_dtcm = cpu::Tcm::new(0x10, cpu::DTCM_BOOT, &mut controller9);
+    // Wait states are in ARM9 cycles. They're estimates of how far each region is from the
+    // core, rather than measurements; the TCMs are accessed without any.
+    controller9.map_region_timed(0x08000000, mem::AddressBlock::SharedRam(arm9_ram.clone()), 1);
+    controller9.map_region_timed(0x10000000, mem::AddressBlock::Io(arm9_io.clone()), 4);
+    controller9.map_region_timed(0x10100000, mem::AddressBlock::Io(shared_io.clone()), 8);
+    controller9.map_region_timed(0x18000000, mem::AddressBlock::SharedRam(vram.clone()), 4);
+    controller9.map_region_timed(0x1FF00000, mem::AddressBlock::SharedRam(dsp_ram.clone()), 4);
+    controller9.map_region_timed(0x1FF80000, mem::AddressBlock::SharedRam(axi_wram.clone()), 4);
+    controller9.map_region_timed(0x20000000, mem::AddressBlock::SharedRam(fcram.clone()), 8);
+    controller9.map_region_timed(0xFFFF0000, mem::AddressBlock::UniqueRam(arm9_bootrom), 1);
 
     let mut controller11 = mem::MemController::new();
+    controller11.map_region(0x00000000, mem::AddressBlock::SharedRam(arm11_bootrom.clone()));
+    controller11.map_region(0x00010000, mem::AddressBlock::SharedRam(arm11_bootrom.clone()));
     controller11.map_region(0x10100000, mem::AddressBlock::Io(shared_io.clone()));
+    // TODO: The MPCore private region (SCU, GIC, timers) is only backed by RAM for now
+    controller11.map_region(0x17E00000, mem::AddressBlock::SharedRam(arm11_private.clone()));
+    controller11.map_region(0x18000000, mem::AddressBlock::SharedRam(vram.clone()));
+    controller11.map_region(0x1FF00000, mem::AddressBlock::SharedRam(dsp_ram.clone()));
     controller11.map_region(0x1FF80000, mem::AddressBlock::SharedRam(axi_wram.clone()));
     controller11.map_region(0x20000000, mem::AddressBlock::SharedRam(fcram.clone()));
+    controller11.map_region(0xFFFF0000, mem::AddressBlock::SharedRam(arm11_bootrom.clone()));
 
     let mut controller_pica = mem::MemController::new();
     controller_pica.map_region(0x20000000, mem::AddressBlock::SharedRam(fcram.clone()));
 
-    return (controller9, controller11, controller_pica);
+    // The ARM9 NDMA controller sits on the ARM9 bus, but can't see the TCMs or the bootrom
+    let mut controller_ndma = mem::MemController::new();
+    controller_ndma.map_region(0x08000000, mem::AddressBlock::SharedRam(arm9_ram.clone()));
+    controller_ndma.map_region(0x10000000, mem::AddressBlock::Io(arm9_io.clone()));
+    controller_ndma.map_region(0x10100000, mem::AddressBlock::Io(shared_io.clone()));
+    controller_ndma.map_region(0x18000000, mem::AddressBlock::SharedRam(vram.clone()));
+    controller_ndma.map_region(0x1FF00000, mem::AddressBlock::SharedRam(dsp_ram.clone()));
+    controller_ndma.map_region(0x1FF80000, mem::AddressBlock::SharedRam(axi_wram.clone()));
+    controller_ndma.map_region(0x20000000, mem::AddressBlock::SharedRam(fcram.clone()));
+
+    return (controller9, controller11, controller_pica, controller_ndma, (arm9_itcm, arm9_dtcm),
+            arm11_private);
 }
 
 fn write_fb_pointers(cpu: &mut cpu::Cpu) {
@@ -88,11 +112,66 @@ fn write_fb_pointers(cpu: &mut cpu::Cpu) {
 }
 
 pub struct Hardware9 {
-    pub arm9: cpu::Cpu
+    pub arm9: cpu::Cpu,
+    /// Recorded ARM9 execution, while reverse execution is enabled
+    pub history: Option<history::History>,
+}
+
+impl Hardware9 {
+    /// Runs up to `num_instrs` ARM9 instructions, adding them to the execution history
+    pub fn run(&mut self, num_instrs: u32) -> cpu::BreakReason {
+        let reason = self.arm9.run(num_instrs);
+        if let Some(ref mut history) = self.history {
+            history.record(&mut self.arm9);
+        }
+        reason
+    }
+
+    /// Single-steps the ARM9 for the debugger, see `cpu::Cpu::step`
+    pub fn step(&mut self, over_irqs: bool) -> cpu::BreakReason {
+        let reason = self.arm9.step(over_irqs);
+        if let Some(ref mut history) = self.history {
+            history.record(&mut self.arm9);
+        }
+        reason
+    }
+}
+
+pub enum Hardware11 {
+    Dummy(cpu::dummy11::Dummy11),
+    Emulated(cpu::Cpu),
 }
 
-pub struct Hardware11 {
-    pub dummy11: cpu::dummy11::Dummy11
+impl Hardware11 {
+    /// Runs up to `num_instrs` ARM11 instructions. The dummy ARM11 takes a single step of its
+    /// handshake program instead.
+    pub fn run(&mut self, num_instrs: u32) -> cpu::BreakReason {
+        match *self {
+            Hardware11::Dummy(ref mut dummy11) => dummy11.step(),
+            Hardware11::Emulated(ref mut arm11) => arm11.run(num_instrs),
+        }
+    }
+
+    pub fn memory_mut(&mut self) -> &mut mem::MemController {
+        match *self {
+            Hardware11::Dummy(ref mut dummy11) => dummy11.memory_mut(),
+            Hardware11::Emulated(ref mut arm11) => &mut arm11.mpu.memory,
+        }
+    }
+
+    pub fn arm11(&self) -> Option<&cpu::Cpu> {
+        match *self {
+            Hardware11::Dummy(_) => None,
+            Hardware11::Emulated(ref arm11) => Some(arm11),
+        }
+    }
+
+    pub fn arm11_mut(&mut self) -> Option<&mut cpu::Cpu> {
+        match *self {
+            Hardware11::Dummy(_) => None,
+            Hardware11::Emulated(ref mut arm11) => Some(arm11),
+        }
+    }
 }
 
 pub struct HwCore {
@@ -107,47 +186,78 @@ pub struct HwCore {
     io_thread: thread::JoinHandle<()>,
 
     mem_pica: mem::MemController,
+    // The only ARM11 RAM the ARM9 can't see, kept here for snapshots
+    arm11_private: mem::SharedMemoryBlock,
     pub irq_tx: cpu::irq::IrqRequests,
+    pub symbols: Arc<ldr::SymbolTable>,
+    step_over_irqs: bool,
 }
 
 #[derive(Clone, Copy, Debug)]
 pub enum Arm11State {
     BootSync,
     KernelSync,
-    None
+    /// Sends every PXI word the ARM9 sends back to it
+    PxiEcho,
+    None,
+    /// Runs ARM11 code on an interpreter, starting at the given address
+    Emulated(u32),
 }
 
 impl HwCore {
     pub fn new(mut msg_pump: msgs::Pump<Message>, loader: &ldr::Loader) -> HwCore {
         let (irq_tx, irq_rx) = cpu::irq::make_channel();
-        let clk_tx = clock::make_channel(irq_tx.clone());
+        let mut clk_tx = clock::make_channel(irq_tx.clone());
         let clk_rx = clk_tx.clone();
 
         let hardware_io = io::new_devices(irq_tx.clone(), clk_rx);
+        clk_tx.connect_ndma(hardware_io.0.ndma.clone());
 
         let (io9, io11) = hardware_io.clone();
-        let (mut mem9, mem11, mem_pica) = map_memory_regions(io9, io11);
+        let (mut mem9, mem11, mem_pica, mem_ndma, (itcm, dtcm), arm11_private)
+            = map_memory_regions(io9, io11);
+        hardware_io.0.set_dma_memory(Some(mem_ndma));
         loader.load(&mut mem9);
 
-        let mut cpu = cpu::Cpu::new(mem9, irq_rx, clk_tx);
+        let mut cpu = cpu::Cpu::new(cpu::ArmVersion::V5TE, mem9, irq_rx, clk_tx);
+        cpu.mpu.attach_tcms(itcm, dtcm);
         cpu.reset(loader.entrypoint());
-        write_fb_pointers(&mut cpu);
+        if loader.runs_bootrom() {
+            cpu.power_on_cp15();
+        } else {
+            write_fb_pointers(&mut cpu);
+        }
 
         let arm11_state = loader.arm11_state();
         info!("Creating system with ARM11 mode {:?}...", arm11_state);
-        let dummy11_mode = match arm11_state {
-            Arm11State::BootSync => cpu::dummy11::modes::boot(),
-            Arm11State::KernelSync => cpu::dummy11::modes::kernel(),
-            Arm11State::None => cpu::dummy11::modes::idle()
-        };
 
         let hardware9 = Hardware9 {
-            arm9: cpu
+            arm9: cpu,
+            history: None,
         };
-        let hardware11 = Hardware11 {
-            dummy11: cpu::dummy11::Dummy11::new(mem11, dummy11_mode)
+        let hardware11 = match arm11_state {
+            Arm11State::Emulated(entrypoint) => {
+                // The ARM11 interrupt controller isn't emulated yet, so nothing raises this
+                // line. The ARM11 also keeps its own clock, separate from the ARM9 timers.
+                let (irq11_tx, irq11_rx) = cpu::irq::make_channel();
+                let clk11 = clock::make_channel(irq11_tx);
+
+                let mut arm11 = cpu::Cpu::new(cpu::ArmVersion::V6K, mem11, irq11_rx, clk11);
+                arm11.reset(entrypoint);
+                Hardware11::Emulated(arm11)
+            }
+            _ => {
+                let dummy11_mode = match arm11_state {
+                    Arm11State::BootSync => cpu::dummy11::modes::boot(),
+                    Arm11State::KernelSync => cpu::dummy11::modes::kernel(),
+                    Arm11State::PxiEcho => cpu::dummy11::modes::pxi_echo(),
+                    _ => cpu::dummy11::modes::idle()
+                };
+                Hardware11::Dummy(cpu::dummy11::Dummy11::new(mem11, dummy11_mode))
+            }
         };
 
+        let symbols = Arc::new(loader.symbols());
         let hardware9 = Arc::new(Mutex::new(hardware9));
         let hardware11 = Arc::new(Mutex::new(hardware11));
 
@@ -158,25 +268,27 @@ impl HwCore {
         let pump_thread = msg_pump.start();
 
         let hardware = hardware9.clone();
+        let syms = symbols.clone();
         let arm9_thread = thread::Builder::new().name("ARM9".to_owned()).spawn(move || {
             let client = client_arm9;
             loop {
                 emu_idle(&client) || break;
                 {
                     let mut hw_guard = hardware.lock().unwrap();
-                    arm9_run(&client, &mut hw_guard) || break;
+                    arm9_run(&client, &mut hw_guard, &syms) || break;
                 }
             }
         }).unwrap();
 
         let hardware = hardware11.clone();
+        let syms = symbols.clone();
         let arm11_thread = thread::Builder::new().name("ARM11".to_owned()).spawn(move || {
             let client = client_arm11;
             loop {
                 emu_idle(&client) || break;
                 {
                     let mut hw_guard = hardware.lock().unwrap();
-                    arm11_run(&client, &mut hw_guard) || break;
+                    arm11_run(&client, &mut hw_guard, &syms) || break;
                 }
             }
         }).unwrap();
@@ -198,7 +310,10 @@ impl HwCore {
             io_thread: io_thread,
 
             mem_pica: mem_pica,
+            arm11_private: arm11_private,
             irq_tx: irq_tx,
+            symbols: symbols,
+            step_over_irqs: false,
         }
     }
 
@@ -222,6 +337,182 @@ impl HwCore {
         self.hardware11.lock().unwrap();
     }
 
+    /// Runs the system on the calling thread instead of the emulation threads, stopping after
+    /// `num_instrs` ARM9 instructions or at a breakpoint or watchpoint on either core.
+    /// Time spent by the ARM9 waiting for an IRQ doesn't count, so this gives up with
+    /// `BreakReason::WFI` if the ARM9 stays idle for too long. Requires emulation to be stopped.
+    pub fn run_sync(&mut self, num_instrs: u64) -> cpu::BreakReason {
+        // How many chunks in a row the ARM11 gets to wake up a waiting ARM9
+        const MAX_IDLE_CHUNKS: u32 = 1000;
+
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let mut hw11 = self.hardware11.lock().unwrap();
+
+        let mut remaining = num_instrs;
+        let mut idle_chunks = 0;
+        while remaining > 0 {
+            let chunk = cmp::min(remaining, 1000);
+            let start = hw9.arm9.instr_count();
+            let waiting = match hw9.run(chunk as u32) {
+                reason @ cpu::BreakReason::Breakpoint
+                | reason @ cpu::BreakReason::Watchpoint(..)
+                | reason @ cpu::BreakReason::BusError(..) => return reason,
+                cpu::BreakReason::WFI => true,
+                _ => false
+            };
+            match hw11.run(chunk as u32) {
+                reason @ cpu::BreakReason::Breakpoint
+                | reason @ cpu::BreakReason::Watchpoint(..)
+                | reason @ cpu::BreakReason::BusError(..) => return reason,
+                _ => {}
+            }
+
+            let executed = hw9.arm9.instr_count() - start;
+            remaining = remaining.saturating_sub(executed);
+            if executed == 0 && waiting {
+                idle_chunks += 1;
+                if idle_chunks == MAX_IDLE_CHUNKS {
+                    return cpu::BreakReason::WFI
+                }
+            } else {
+                idle_chunks = 0;
+            }
+        }
+        cpu::BreakReason::LimitReached
+    }
+
+    /// Returns the tracer that records IO register accesses of both cores and DMA
+    pub fn io_tracer(&self) -> io::trace::IoTracer {
+        self.hardware_io.0.tracer.clone()
+    }
+
+    /// Whether single steps run through IRQ handlers rather than stopping on entering them
+    pub fn step_over_irqs(&self) -> bool {
+        self.step_over_irqs
+    }
+
+    pub fn set_step_over_irqs(&mut self, over: bool) {
+        self.step_over_irqs = over;
+    }
+
+    /// Sets what happens to accesses to unmapped memory from either core or the GPU.
+    /// Requires emulation to be stopped.
+    pub fn set_unmapped_policy(&mut self, policy: mem::UnmappedPolicy) {
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let mut hw11 = self.hardware11.lock().unwrap();
+        hw9.arm9.mpu.memory.set_unmapped_policy(policy);
+        hw11.memory_mut().set_unmapped_policy(policy);
+        self.mem_pica.set_unmapped_policy(policy);
+    }
+
+    /// Starts recording ARM9 execution so it can be stepped and continued backwards,
+    /// checkpointing as `config` says. Only the ARM9, memory and IO devices are rewound.
+    /// Requires emulation to be stopped.
+    pub fn enable_history(&mut self, config: history::Config) {
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let hw9 = &mut *hw9;
+        if let Some(history) = hw9.history.take() {
+            history.stop(&mut hw9.arm9);
+        }
+        let devices = vec![
+            ("io9", Box::new(self.hardware_io.0.clone()) as Box<Snapshot + Send>),
+            ("io_shared", Box::new(self.hardware_io.1.clone()) as Box<Snapshot + Send>),
+        ];
+        hw9.history = Some(history::History::start(&mut hw9.arm9, devices, config));
+    }
+
+    /// Stops recording ARM9 execution and drops what was recorded.
+    /// Requires emulation to be stopped.
+    pub fn disable_history(&mut self) {
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let hw9 = &mut *hw9;
+        if let Some(history) = hw9.history.take() {
+            history.stop(&mut hw9.arm9);
+        }
+    }
+
+    /// Requires emulation to be stopped.
+    pub fn history_enabled(&self) -> bool {
+        self.hardware9.lock().unwrap().history.is_some()
+    }
+
+    /// Moves the ARM9 back by up to `count` instructions, returning how far back it went.
+    /// Requires emulation to be stopped.
+    pub fn reverse_step(&mut self, count: u64) -> history::Result<u64> {
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let hw9 = &mut *hw9;
+        match hw9.history {
+            Some(ref mut history) => history.step_back(&mut hw9.arm9, count),
+            None => bail!(history::ErrorKind::NotRecording)
+        }
+    }
+
+    /// Moves the ARM9 back to the last breakpoint it ran into, or as far back as was recorded.
+    /// Returns whether a breakpoint was found. Requires emulation to be stopped.
+    pub fn reverse_continue(&mut self) -> history::Result<bool> {
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let hw9 = &mut *hw9;
+        match hw9.history {
+            Some(ref mut history) => history.continue_back(&mut hw9.arm9),
+            None => bail!(history::ErrorKind::NotRecording)
+        }
+    }
+
+    /// Serializes the CPUs, the RAM of both cores and the IO devices' state. Requires emulation
+    /// to be stopped. The dummy11 handshake state, the ARM11 bootrom (which is never loaded)
+    /// and the NAND/SD images on disk are not included.
+    pub fn save_snapshot(&mut self) -> Vec<u8> {
+        let hw9 = self.hardware9.lock().unwrap();
+        let hw11 = self.hardware11.lock().unwrap();
+        let mut w = snapshot::Writer::new();
+        w.section("arm9");
+        hw9.arm9.save(&mut w);
+        // Every RAM region the ARM11 can see, except its bootrom and private region, is also
+        // mapped for the ARM9
+        w.section("mem");
+        hw9.arm9.mpu.memory.save(&mut w);
+        w.section("arm11_private");
+        self.arm11_private.save(&mut w);
+        if let Some(arm11) = hw11.arm11() {
+            w.section("arm11");
+            arm11.save(&mut w);
+        }
+        w.section("io9");
+        self.hardware_io.0.save(&mut w);
+        w.section("io_shared");
+        self.hardware_io.1.save(&mut w);
+        w.into_inner()
+    }
+
+    /// Restores a snapshot made by `save_snapshot` for the same system configuration.
+    /// If this fails, the machine may have been partially restored.
+    pub fn load_snapshot(&mut self, data: &[u8]) -> snapshot::Result<()> {
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let mut hw11 = self.hardware11.lock().unwrap();
+        let mut r = snapshot::Reader::new(data)?;
+        r.section("arm9")?;
+        hw9.arm9.load(&mut r)?;
+        r.section("mem")?;
+        hw9.arm9.mpu.memory.load(&mut r)?;
+        r.section("arm11_private")?;
+        self.arm11_private.load(&mut r)?;
+        if let Some(arm11) = hw11.arm11_mut() {
+            r.section("arm11")?;
+            arm11.load(&mut r)?;
+        }
+        r.section("io9")?;
+        self.hardware_io.0.load(&mut r)?;
+        r.section("io_shared")?;
+        self.hardware_io.1.load(&mut r)?;
+
+        // Going back from here mustn't replay what ran before the snapshot was loaded
+        let hw9 = &mut *hw9;
+        if let Some(ref mut history) = hw9.history {
+            history.restart(&mut hw9.arm9);
+        }
+        Ok(())
+    }
+
     pub fn copy_framebuffers(&mut self, fbs: &mut Framebuffers) {
         fbs.top_screen.resize({ let (w, h, d) = fbs.top_screen_size; w*h*d }, 0);
         fbs.bot_screen.resize({ let (w, h, d) = fbs.bot_screen_size; w*h*d }, 0);
@@ -233,6 +524,13 @@ impl HwCore {
     }
 }
 
+impl Drop for HwCore {
+    fn drop(&mut self) {
+        // The NDMA controller's memory map holds on to the IO devices, including itself
+        self.hardware_io.0.set_dma_memory(None);
+    }
+}
+
 fn io_run(client: &msgs::Client<Message>, hardware: (io::IoRegsArm9, io::IoRegsShared)) {
     let (io9, shared) = hardware;
     for msg in client.iter() {
@@ -246,7 +544,23 @@ fn io_run(client: &msgs::Client<Message>, hardware: (io::IoRegsArm9, io::IoRegsS
     }
 }
 
-fn arm9_run(client: &msgs::Client<Message>, hardware: &mut Hardware9) -> bool {
+fn log_halt(core: &str, cpu: &cpu::Cpu, reason: &cpu::BreakReason, symbols: &ldr::SymbolTable) {
+    let pc = symbols.describe(cpu.regs[15] - cpu.get_pc_offset());
+    match *reason {
+        cpu::BreakReason::Watchpoint(kind, addr) => {
+            let addr = symbols.describe(addr);
+            info!("{} {:?} watchpoint hit by access to {} @ {}!", core, kind, addr, pc)
+        }
+        cpu::BreakReason::BusError(err) => {
+            let kind = if err.is_write { "write to" } else { "read from" };
+            info!("{} stopped by {} unmapped address 0x{:08X} @ {}!", core, kind, err.addr, pc)
+        }
+        _ => info!("{} breakpoint hit @ {}!", core, pc)
+    }
+}
+
+fn arm9_run(client: &msgs::Client<Message>, hardware: &mut Hardware9,
+            symbols: &ldr::SymbolTable) -> bool {
     let reason = 't: loop {
         for msg in client.try_iter() {
             match msg {
@@ -258,9 +572,16 @@ fn arm9_run(client: &msgs::Client<Message>, hardware: &mut Hardware9) -> bool {
             }
         }
 
-        if let reason @ cpu::BreakReason::Breakpoint = hardware.arm9.run(1000) {
-            info!("Breakpoint hit @ 0x{:X}!", hardware.arm9.regs[15] - hardware.arm9.get_pc_offset());
-            break 't reason
+        match hardware.run(1000) {
+            reason @ cpu::BreakReason::Breakpoint
+            | reason @ cpu::BreakReason::Watchpoint(..)
+            | reason @ cpu::BreakReason::BusError(..) => {
+                log_halt("ARM9", &hardware.arm9, &reason, symbols);
+                // Stop the other core too, so both can be inspected at the same point
+                client.send(Message::SuspendEmulation).unwrap();
+                break 't reason
+            }
+            _ => {}
         }
     };
 
@@ -268,29 +589,41 @@ fn arm9_run(client: &msgs::Client<Message>, hardware: &mut Hardware9) -> bool {
     true
 }
 
-fn arm11_run(client: &msgs::Client<Message>, hardware: &mut Hardware11) -> bool {
-    't: loop {
-        let break_reason = hardware.dummy11.step();
+fn arm11_run(client: &msgs::Client<Message>, hardware: &mut Hardware11,
+             symbols: &ldr::SymbolTable) -> bool {
+    let reason = 't: loop {
+        let break_reason = hardware.run(1000);
 
         let mut msg_opt = match break_reason {
             cpu::BreakReason::WFI => client.recv().ok(),
-            cpu::BreakReason::LimitReached => client.try_recv().ok(),
-            cpu::BreakReason::Breakpoint | cpu::BreakReason::Trapped => unimplemented!(),
+            // Only stepping enters IRQs by itself, and only suspending traps the core
+            cpu::BreakReason::LimitReached
+            | cpu::BreakReason::Trapped
+            | cpu::BreakReason::IrqEntry => client.try_recv().ok(),
+            reason @ cpu::BreakReason::Breakpoint
+            | reason @ cpu::BreakReason::Watchpoint(..)
+            | reason @ cpu::BreakReason::BusError(..) => {
+                if let Some(arm11) = hardware.arm11() {
+                    log_halt("ARM11", arm11, &reason, symbols);
+                }
+                client.send(Message::SuspendEmulation).unwrap();
+                break 't reason
+            }
         };
 
         while let Some(msg) = msg_opt {
             match msg {
                 Message::Quit => return false,
                 Message::SuspendEmulation => {
-                    break 't
+                    break 't cpu::BreakReason::Trapped
                 }
                 _ => {}
             }
             msg_opt = client.try_recv().ok();
         }
-    }
+    };
 
-    client.send(Message::Arm11Halted(cpu::BreakReason::Trapped)).unwrap();
+    client.send(Message::Arm11Halted(reason)).unwrap();
     true
 }
 
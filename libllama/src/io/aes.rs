@@ -6,6 +6,8 @@ use std::mem;
 use extprim::u128::u128 as u128_t;
 use openssl::symm;
 
+use io::ndma::{DmaStartup, DmaTriggers};
+use snapshot;
 use utils::bytes;
 
 pub fn keydb_path() -> String {
@@ -97,6 +99,24 @@ mod test {
         assert_eq!(Key::from_keypair(&Key { data: keyx }, &Key { data: keyy }, KeygenMode::THREEDS).data,
             Key { data: norm }.data);
     }
+
+    #[test]
+    fn reject_bad_mode() {
+        use snapshot::Snapshot;
+
+        let mut state = AesDeviceState::new(DmaTriggers::new());
+        state.active_process = Some(make_crypter(2, &state.active_key, &state.active_iv));
+        state.active_mode = 0;
+        let mut w = snapshot::Writer::new();
+        state.save(&mut w);
+        let data = w.into_inner();
+
+        let mut r = snapshot::Reader::new(&data).unwrap();
+        match *state.load(&mut r).unwrap_err().kind() {
+            snapshot::ErrorKind::Mismatch(_) => {}
+            ref kind => panic!("Unexpected error {:?}", kind)
+        }
+    }
 }
 
 #[derive(Default)]
@@ -110,6 +130,11 @@ pub struct AesDeviceState {
     active_process: Option<symm::Crypter>,
     bytes_left: usize,
 
+    // Enough to rebuild `active_process` from a snapshot; the IV tracks the chaining state
+    active_mode: u32,
+    active_key: Key,
+    active_iv: [u8; 0x10],
+
     key_slots: [Key; 0x40],
     keyx_slots: [Key; 0x40],
     keyfifo_state: KeyFifoState,
@@ -119,16 +144,21 @@ pub struct AesDeviceState {
     fifo_in_buf: VecDeque<u32>,
     fifo_out_buf: VecDeque<u32>,
     reg_ctr: [u8; 0x10],
+
+    dma_triggers: DmaTriggers,
 }
 
 unsafe impl Send for AesDeviceState {} // TODO: Not good!
 
-impl Default for AesDeviceState {
-    fn default() -> AesDeviceState {
+impl AesDeviceState {
+    pub fn new(dma_triggers: DmaTriggers) -> AesDeviceState {
         AesDeviceState {
             active_keyslot: 0,
             active_process: None,
             bytes_left: 0,
+            active_mode: 0,
+            active_key: Default::default(),
+            active_iv: [0; 0x10],
             key_slots: load_keys(),
             keyx_slots: [Default::default(); 0x40],
             keyfifo_state: Default::default(),
@@ -136,7 +166,8 @@ impl Default for AesDeviceState {
             keyyfifo_state: Default::default(),
             fifo_in_buf: VecDeque::new(),
             fifo_out_buf: VecDeque::new(),
-            reg_ctr: [0; 0x10]
+            reg_ctr: [0; 0x10],
+            dma_triggers: dma_triggers,
         }
     }
 }
@@ -168,6 +199,85 @@ pub fn dump_keys(dev: &AesDevice) -> [Key; 0x40] {
     dev._internal_state.key_slots
 }
 
+fn put_key_fifo(w: &mut snapshot::Writer, state: &KeyFifoState) {
+    w.put(state.pos as u64);
+    w.put(state.buf);
+}
+
+fn get_key_fifo(r: &mut snapshot::Reader, state: &mut KeyFifoState) -> snapshot::Result<()> {
+    state.pos = r.get::<u64>()? as usize;
+    state.buf = r.get()?;
+    Ok(())
+}
+
+fn put_word_fifo(w: &mut snapshot::Writer, fifo: &VecDeque<u32>) {
+    w.put(fifo.len() as u64);
+    for word in fifo.iter() {
+        w.put(*word);
+    }
+}
+
+fn get_word_fifo(r: &mut snapshot::Reader, fifo: &mut VecDeque<u32>) -> snapshot::Result<()> {
+    fifo.clear();
+    for _ in 0..r.get::<u64>()? {
+        fifo.push_back(r.get()?);
+    }
+    Ok(())
+}
+
+impl snapshot::Snapshot for AesDeviceState {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.active_keyslot as u64);
+        w.put(self.bytes_left as u64);
+        w.put_bool(self.active_process.is_some());
+        w.put(self.active_mode);
+        w.put(self.active_key.data);
+        w.put(self.active_iv);
+
+        for key in self.key_slots.iter().chain(self.keyx_slots.iter()) {
+            w.put(key.data);
+        }
+        put_key_fifo(w, &self.keyfifo_state);
+        put_key_fifo(w, &self.keyxfifo_state);
+        put_key_fifo(w, &self.keyyfifo_state);
+
+        put_word_fifo(w, &self.fifo_in_buf);
+        put_word_fifo(w, &self.fifo_out_buf);
+        w.put(self.reg_ctr);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.active_keyslot = r.get::<u64>()? as usize;
+        self.bytes_left = r.get::<u64>()? as usize;
+        let has_process = r.get_bool()?;
+        self.active_mode = r.get()?;
+        if has_process && !mode_supported(self.active_mode) {
+            bail!(snapshot::ErrorKind::Mismatch(format!("unsupported AES mode {}",
+                                                        self.active_mode)));
+        }
+        self.active_key.data = r.get()?;
+        self.active_iv = r.get()?;
+
+        for key in self.key_slots.iter_mut().chain(self.keyx_slots.iter_mut()) {
+            key.data = r.get()?;
+        }
+        get_key_fifo(r, &mut self.keyfifo_state)?;
+        get_key_fifo(r, &mut self.keyxfifo_state)?;
+        get_key_fifo(r, &mut self.keyyfifo_state)?;
+
+        get_word_fifo(r, &mut self.fifo_in_buf)?;
+        get_word_fifo(r, &mut self.fifo_out_buf)?;
+        self.reg_ctr = r.get()?;
+
+        self.active_process = if has_process {
+            Some(make_crypter(self.active_mode, &self.active_key, &self.active_iv))
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
 impl fmt::Debug for AesDeviceState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "AesDeviceState {{ }}")
@@ -234,23 +344,64 @@ fn reg_cnt_update(dev: &mut AesDevice) {
         trace!("Attempted to start AES crypto! mode: {}, keyslot: 0x{:X}, bytes: 0x{:X}, key: {}, iv: {}",
             mode, keyslot, bytes, key_str, iv_str);
 
-        let direction = if mode & 1 == 1 {
-            symm::Mode::Encrypt
-        } else {
-            symm::Mode::Decrypt
-        };
-        let (cypher, iv_ctr) = match mode {
-            2 | 3 => (symm::Cipher::aes_128_ctr(), Some(ctr.as_slice())),
-            4 | 5 => (symm::Cipher::aes_128_cbc(), Some(ctr.as_slice())),
-            6 | 7 => (symm::Cipher::aes_128_ecb(), None),
-            _ => unimplemented!()
-        };
-        let mut crypter = symm::Crypter::new(cypher, direction, &key.data[..], iv_ctr).unwrap();
-        crypter.pad(false);
-        dev._internal_state.active_process = Some(crypter);
+        dev._internal_state.active_process = Some(make_crypter(mode, &key, &ctr));
+        dev._internal_state.active_mode = mode;
+        dev._internal_state.active_key = key;
+        dev._internal_state.active_iv.copy_from_slice(&ctr);
 
         dev._internal_state.bytes_left = bytes as usize;
     }
+
+    update_dma_triggers(dev);
+}
+
+// FIFO-IN takes input for as long as the job needs it, and FIFO-OUT is drained by NDMA
+// in blocks of the configured size
+fn update_dma_triggers(dev: &mut AesDevice) {
+    let cnt = dev.cnt.get();
+    let state = &dev._internal_state;
+
+    let in_ready = state.active_process.is_some() && state.bytes_left > 0;
+    let out_block = (bf!(cnt @ RegCnt::fifo_out_dma_size) as usize + 1) * 4;
+    let out_ready = state.fifo_out_buf.len() >= out_block;
+
+    state.dma_triggers.set(DmaStartup::AesIn, in_ready);
+    state.dma_triggers.set(DmaStartup::AesOut, out_ready);
+}
+
+// Modes `make_crypter` can set up: CTR, CBC and ECB, each decrypting or encrypting
+fn mode_supported(mode: u32) -> bool {
+    match mode {
+        2 ... 7 => true,
+        _ => false
+    }
+}
+
+fn make_crypter(mode: u32, key: &Key, iv: &[u8]) -> symm::Crypter {
+    let direction = if mode & 1 == 1 {
+        symm::Mode::Encrypt
+    } else {
+        symm::Mode::Decrypt
+    };
+    let (cypher, iv_ctr) = match mode {
+        2 | 3 => (symm::Cipher::aes_128_ctr(), Some(iv)),
+        4 | 5 => (symm::Cipher::aes_128_cbc(), Some(iv)),
+        6 | 7 => (symm::Cipher::aes_128_ecb(), None),
+        _ => unimplemented!()
+    };
+    let mut crypter = symm::Crypter::new(cypher, direction, &key.data[..], iv_ctr).unwrap();
+    crypter.pad(false);
+    crypter
+}
+
+// Tracks the IV the crypter would continue with, had it been created now
+fn advance_iv(iv: &mut [u8; 0x10], mode: u32, block_in: &[u8], block_out: &[u8]) {
+    match mode {
+        2 | 3 => *iv = bytes::from_u128(bytes::to_u128(&iv[..]).wrapping_add(u128_t::new(1))),
+        4 => iv.copy_from_slice(&block_in[..0x10]),
+        5 => iv.copy_from_slice(&block_out[..0x10]),
+        _ => {}
+    }
 }
 
 fn reg_key_cnt_update(dev: &mut AesDevice) {
@@ -300,6 +451,8 @@ fn reg_fifo_in_update(dev: &mut AesDevice) {
             let mut dec_words = [0u32; 8]; // Double size because of library silliness
             unsafe {
                 active_process.update(bytes::from_val(&words), bytes::from_mut_val(&mut dec_words));
+                advance_iv(&mut dev._internal_state.active_iv, dev._internal_state.active_mode,
+                           bytes::from_val(&words), bytes::from_val(&dec_words));
             }
 
             let dec_words_iter = dec_words[..4].iter();
@@ -317,6 +470,7 @@ fn reg_fifo_in_update(dev: &mut AesDevice) {
         dev._internal_state.active_process = None;
         dev.cnt.set_unchecked(bf!(cnt @ RegCnt::busy as 0));
     }
+    update_dma_triggers(dev);
 }
 
 fn reg_fifo_out_onread(dev: &mut AesDevice) {
@@ -326,6 +480,7 @@ fn reg_fifo_out_onread(dev: &mut AesDevice) {
         }
         dev.fifo_out.set_unchecked(word);
     }
+    update_dma_triggers(dev);
 }
 
 #[derive(Clone, Copy)]
@@ -1,10 +1,11 @@
 use std::env;
 use std::fs::{File, OpenOptions};
-use std::io::{self, Read};
+use std::io::{self, Read, Seek};
 
 use extprim::u128::u128 as u128_t;
 
 use io::emmc::TransferType;
+use snapshot;
 use utils::bytes;
 
 #[derive(Clone, Copy)]
@@ -111,6 +112,47 @@ impl Card {
     }
 }
 
+// Card storage is backed by files on disk; only the file position is saved
+impl snapshot::Snapshot for Card {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.csr.raw());
+        w.put(bytes::from_u128(self.cid.raw()));
+        w.put(bytes::from_u128(self.csd.raw()));
+        w.put(self.rca);
+        w.put((&self.storage).seek(io::SeekFrom::Current(0)).unwrap_or(0));
+
+        w.put_bool(self.transfer.is_some());
+        if let Some(ref xfer) = self.transfer {
+            w.put(match xfer.loc { TransferLoc::Storage => 0u8, TransferLoc::RegScr => 1u8 });
+            w.put(match xfer.ty { TransferType::Read => 0u8, TransferType::Write => 1u8 });
+            w.put(xfer.blocks_left);
+            w.put(xfer.fifo_pos);
+            w.put(xfer.seek_pos);
+        }
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.csr.set_raw(r.get()?);
+        self.cid.set_raw(bytes::to_u128(&r.get::<[u8; 16]>()?));
+        self.csd.set_raw(bytes::to_u128(&r.get::<[u8; 16]>()?));
+        self.rca = r.get()?;
+        self.storage.seek(io::SeekFrom::Start(r.get()?))?;
+
+        self.transfer = if r.get_bool()? {
+            Some(ActiveTransfer {
+                loc: if r.get::<u8>()? == 0 { TransferLoc::Storage } else { TransferLoc::RegScr },
+                ty: if r.get::<u8>()? == 0 { TransferType::Read } else { TransferType::Write },
+                blocks_left: r.get()?,
+                fifo_pos: r.get()?,
+                seek_pos: r.get()?
+            })
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
 impl io::Read for Card {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
         let xfer = self.transfer.as_mut()
@@ -8,6 +8,8 @@ use std::mem;
 
 use io::emmc::card::Card;
 use cpu::irq;
+use io::ndma::{DmaStartup, DmaTriggers};
+use snapshot::{self, Snapshot};
 
 bfdesc!(RegCmd: u16, {
     command_index: 0 => 5,
@@ -86,14 +88,16 @@ pub enum TransferType {
 
 pub struct EmmcDeviceState {
     irq_reqs: irq::IrqRequests,
+    dma_triggers: DmaTriggers,
     irq_statuses: [u16; 2],
     cards: [Card; 2],
 }
 
 impl EmmcDeviceState {
-    pub fn new(irq_reqs: irq::IrqRequests) -> EmmcDeviceState {
+    pub fn new(irq_reqs: irq::IrqRequests, dma_triggers: DmaTriggers) -> EmmcDeviceState {
         EmmcDeviceState {
             irq_reqs: irq_reqs,
+            dma_triggers: dma_triggers,
             irq_statuses: [0 | (Status0::SigState as u16), 0],
             cards: [
                 Card::new(card::CardType::Sd, card::sd_storage(), card::sd_cid()),
@@ -103,6 +107,24 @@ impl EmmcDeviceState {
     }
 }
 
+// Pending IRQs are saved along with the IRQ device
+impl Snapshot for EmmcDeviceState {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.irq_statuses);
+        for card in self.cards.iter() {
+            card.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.irq_statuses = r.get()?;
+        for card in self.cards.iter_mut() {
+            card.load(r)?;
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for EmmcDeviceState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "EmmcDeviceState {{ }}")
@@ -173,6 +195,12 @@ fn trigger_status<S: Into<Status>>(dev: &mut EmmcDevice, status: S) {
     }
 }
 
+// NDMA is wired to the 32-bit FIFO, which has data (or room) for as long as a transfer runs
+fn update_dma_trigger(dev: &mut EmmcDevice) {
+    let ready = use_32bit(dev) && get_active_card(dev).get_transfer_mut().is_some();
+    dev._internal_state.dma_triggers.set(DmaStartup::Emmc, ready);
+}
+
 fn clear_status<S: Into<Status>>(dev: &mut EmmcDevice, status: S) {
     match status.into() {
         Status::Lo(s0) => {
@@ -201,6 +229,7 @@ fn reg_cmd_onupdate(dev: &mut EmmcDevice) {
 
     trigger_status(dev, Status0::CmdResponseEnd);
     clear_status(dev, Status1::CmdBusy);
+    update_dma_trigger(dev);
 }
 
 fn reg_irqstat_read(dev: &mut EmmcDevice, stat_index: usize) {
@@ -283,6 +312,7 @@ fn reg_fifo_mod(dev: &mut EmmcDevice, transfer_type: TransferType, is_32bit: boo
         trigger_status(dev, Status0::DataEnd);
         mode_sd::handle_cmd(dev, 12); // STOP_TRANSMISSION
     }
+    update_dma_trigger(dev);
 }
 
 iodevice!(EmmcDevice, {
@@ -334,6 +364,7 @@ iodevice!(EmmcDevice, {
         0x0D8 => data16_ctl: u16 {
             default = 0b00010000_00010000;
             write_bits = 0b00000000_00100010;
+            write_effect = update_dma_trigger;
         }
         0x0E0 => software_reset: u16 { write_bits = 0b1; }
         0x0F6 => protected: u16 { }
@@ -343,6 +374,7 @@ iodevice!(EmmcDevice, {
         0x0FE => unknown3: u16 { }
         0x100 => data32_ctl: u16 {
             write_bits = 0b00011111_00000010;
+            write_effect = update_dma_trigger;
         }
         0x104 => data32_blk_len: u16 { }
         0x108 => data32_blk_cnt: u16 { }
@@ -5,17 +5,19 @@ pub mod aes;
 mod config;
 mod emmc;
 mod irq;
-mod ndma;
+pub mod ndma;
 mod otp;
 mod pxi;
 mod rsa;
 mod sha;
 pub mod timer;
+pub mod trace;
 mod xdma;
 
 pub mod hid;
 
 use std::ptr;
+use std::slice;
 use std::sync::Arc;
 use std::default::Default;
 
@@ -23,7 +25,17 @@ use parking_lot::Mutex;
 
 use clock;
 use cpu::irq::IrqRequests;
+use mem;
 use io::regs::IoRegAccess;
+use io::trace::{Accessor, IoTracer};
+use snapshot::{self, Snapshot};
+
+/// Names of every device IO accesses can be traced to
+pub fn device_names() -> Vec<&'static str> {
+    let mut names = IoRegsArm9::device_names().to_vec();
+    names.extend_from_slice(IoRegsShared::device_names());
+    names
+}
 
 #[derive(Clone)]
 pub enum IoRegion {
@@ -38,20 +50,26 @@ pub fn new_devices(irq_requests: IrqRequests, clk: clock::SysClock) -> (IoRegsAr
         ($type:ty: $($arg:expr),+) => {{ Arc::new(Mutex::new(<$type>::new($($arg),*))) }};
     }
 
+    let dma_triggers = clk.dma_triggers.clone();
+    let pxi_link = pxi::PxiLink::new(irq_requests.clone());
+
     let cfg    = make_dev! { config::ConfigDevice };
     let irq    = make_dev! { irq::IrqDevice:     irq_requests.clone() };
-    let emmc   = make_dev! { emmc::EmmcDevice:   emmc::EmmcDeviceState::new(irq_requests.clone()) };
-    let ndma   = make_dev! { ndma::NdmaDevice:   Default::default() };
+    let emmc   = make_dev! { emmc::EmmcDevice:   emmc::EmmcDeviceState::new(irq_requests.clone(), dma_triggers.clone()) };
+    let ndma   = make_dev! { ndma::NdmaDevice:   ndma::NdmaDeviceState::new(irq_requests.clone(), dma_triggers.clone()) };
     let otp    = make_dev! { otp::OtpDevice:     Default::default() };
-    let pxi    = make_dev! { pxi::PxiDevice };
+    let pxi9   = make_dev! { pxi::PxiDevice:     pxi::PxiState::new(pxi::PxiSide::Arm9, pxi_link.clone()) };
     let timer  = make_dev! { timer::TimerDevice: clk.timer_states };
-    let aes    = make_dev! { aes::AesDevice:     Default::default() };
-    let sha    = make_dev! { sha::ShaDevice:     Default::default() };
+    let aes    = make_dev! { aes::AesDevice:     aes::AesDeviceState::new(dma_triggers.clone()) };
+    let sha    = make_dev! { sha::ShaDevice:     sha::ShaDeviceState::new(dma_triggers.clone()) };
     let rsa    = make_dev! { rsa::RsaDevice:     Default::default() };
     let xdma   = make_dev! { xdma::XdmaDevice };
     let cfgext = make_dev! { config::ConfigExtDevice };
 
+    let tracer = IoTracer::new();
+
     let hid    = make_dev! { hid::HidDevice };
+    let pxi11  = make_dev! { pxi::PxiDevice:     pxi::PxiState::new(pxi::PxiSide::Arm11, pxi_link) };
 
     (IoRegsArm9 {
         cfg:    cfg.clone(),
@@ -59,37 +77,65 @@ pub fn new_devices(irq_requests: IrqRequests, clk: clock::SysClock) -> (IoRegsAr
         emmc:   emmc.clone(),
         ndma:   ndma.clone(),
         otp:    otp.clone(),
-        pxi9:   pxi.clone(),
+        pxi9:   pxi9.clone(),
         timer:  timer.clone(),
         aes:    aes.clone(),
         sha:    sha.clone(),
         rsa:    rsa.clone(),
         xdma:   xdma.clone(),
         cfgext: cfgext.clone(),
+        dma_triggers: dma_triggers,
+        tracer: tracer.clone(),
     },
     IoRegsShared {
         hid:    hid.clone(),
-        pxi11:  pxi.clone(),
+        pxi11:  pxi11.clone(),
+        tracer: tracer,
     })
 }
 
 macro_rules! impl_rw {
     ($($num:expr => $name:tt),*) => {
-        pub unsafe fn read_reg(&self, offset: usize, buf: *mut u8, buf_size: usize) {
+        /// Names the devices' accesses are traced under
+        pub fn device_names() -> &'static [&'static str] {
+            &[$(stringify!($name)),*]
+        }
+
+        pub unsafe fn read_reg(&self, offset: usize, buf: *mut u8, buf_size: usize,
+                               accessor: Accessor) {
             match bits!(offset, 12 => 23) {
-                $($num => self.$name.lock().read_reg(offset & 0xFFF, buf, buf_size),)*
+                $($num => {
+                    let mut dev = self.$name.lock();
+                    dev.read_reg(offset & 0xFFF, buf, buf_size);
+                    if self.tracer.enabled() {
+                        self.tracer.record(accessor, stringify!($name), dev.reg_name(offset & 0xFFF),
+                                           offset & 0xFFF, slice::from_raw_parts(buf, buf_size), false);
+                    }
+                })*
                 _ => {
                     error!("Unimplemented IO register read at offset 0x{:X}", offset);
                     // If we can't find a register for it, just read zero bytes
                     ptr::write_bytes(buf, 0, buf_size);
                 }
             }
+            self.after_access();
         }
-        pub unsafe fn write_reg(&self, offset: usize, buf: *const u8, buf_size: usize) {
+        pub unsafe fn write_reg(&self, offset: usize, buf: *const u8, buf_size: usize,
+                                accessor: Accessor) {
             match bits!(offset, 12 => 23) {
-                $($num => self.$name.lock().write_reg(offset & 0xFFF, buf, buf_size),)*
+                $($num => {
+                    let mut dev = self.$name.lock();
+                    // Recorded first, so the trace stays in order if the write triggers more
+                    // accesses, e.g. by starting a DMA transfer
+                    if self.tracer.enabled() {
+                        self.tracer.record(accessor, stringify!($name), dev.reg_name(offset & 0xFFF),
+                                           offset & 0xFFF, slice::from_raw_parts(buf, buf_size), true);
+                    }
+                    dev.write_reg(offset & 0xFFF, buf, buf_size);
+                })*
                 _ => error!("Unimplemented IO register write at offset 0x{:X}", offset),
             };
+            self.after_access();
         }
     };
 }
@@ -112,9 +158,63 @@ pub struct IoRegsArm9 {
     // prng,
     pub otp:    Arc<Mutex< otp::OtpDevice >>,
     // arm7,
+
+    dma_triggers: ndma::DmaTriggers,
+    pub tracer: IoTracer,
+}
+
+impl Snapshot for IoRegsArm9 {
+    fn save(&self, w: &mut snapshot::Writer) {
+        self.cfg.lock().save(w);
+        self.irq.lock().save(w);
+        self.ndma.lock().save(w);
+        self.timer.lock().save(w);
+        self.emmc.lock().save(w);
+        self.pxi9.lock().save(w);
+        self.aes.lock().save(w);
+        self.sha.lock().save(w);
+        self.rsa.lock().save(w);
+        self.xdma.lock().save(w);
+        self.cfgext.lock().save(w);
+        self.otp.lock().save(w);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.cfg.lock().load(r)?;
+        self.irq.lock().load(r)?;
+        self.ndma.lock().load(r)?;
+        self.timer.lock().load(r)?;
+        self.emmc.lock().load(r)?;
+        self.pxi9.lock().load(r)?;
+        self.aes.lock().load(r)?;
+        self.sha.lock().load(r)?;
+        self.rsa.lock().load(r)?;
+        self.xdma.lock().load(r)?;
+        self.cfgext.lock().load(r)?;
+        self.otp.lock().load(r)
+    }
 }
 
 impl IoRegsArm9 {
+    /// Gives the NDMA controller its view of the bus. Pass `None` to break the reference
+    /// cycle between the controller and these devices before dropping them.
+    pub fn set_dma_memory(&self, memory: Option<mem::MemController>) {
+        ndma::connect_memory(&mut self.ndma.lock(), memory);
+    }
+
+    // Devices raise NDMA startup requests from inside their register effects, where the NDMA
+    // device can't be locked, so pending requests are serviced once the access has finished.
+    fn after_access(&self) {
+        if !self.dma_triggers.any() {
+            return
+        }
+        // Fails when the access was made by a transfer; the running transfer picks up new
+        // requests itself
+        if let Some(mut ndma) = self.ndma.try_lock() {
+            ndma::handle_triggers(&mut ndma);
+        }
+    }
+
     impl_rw! {
         0x00 => cfg,
         0x01 => irq,
@@ -153,9 +253,25 @@ pub struct IoRegsShared {
     pub pxi11: Arc<Mutex< pxi::PxiDevice >>,
     // ntrcard,
     // mp,
+
+    pub tracer: IoTracer,
+}
+
+impl Snapshot for IoRegsShared {
+    fn save(&self, w: &mut snapshot::Writer) {
+        self.hid.lock().save(w);
+        self.pxi11.lock().save(w);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.hid.lock().load(r)?;
+        self.pxi11.lock().load(r)
+    }
 }
 
 impl IoRegsShared {
+    fn after_access(&self) { }
+
     impl_rw! {
         0x46 => hid,
         0x63 => pxi11
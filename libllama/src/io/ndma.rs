@@ -1,5 +1,15 @@
-use std::cell::Cell;
-use std::rc::Rc;
+use std::cmp;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use cpu::irq::{IrqRequests, IrqType};
+use mem::MemController;
+use snapshot::{self, Snapshot};
+
+// Upper bound on blocks moved per call to `process_channels`, so a repeating channel fed by an
+// always-ready source can't lock up the emulator
+const MAX_BLOCKS_PER_PASS: usize = 0x400;
 
 bitfield!(RegGlobalCnt: u32, {
     enabled: 0 => 0,
@@ -20,16 +30,98 @@ bfdesc!(RegChannelCnt: u32, {
     enabled: 31 => 31
 });
 
-fn reg_chan_cnt_write(dev: &mut NdmaChannel) {
-    let chan_cnt = dev.chan_cnt.get();
-    if bf!(chan_cnt @ RegChannelCnt::enabled) == 1 {
-        unimplemented!()
+/// Peripheral events that can start a transfer, as selected by a channel's startup mode
+#[derive(Clone, Copy, Debug)]
+pub enum DmaStartup {
+    Timer0 = 0,
+    Timer1 = 1,
+    Timer2 = 2,
+    Timer3 = 3,
+    CtrCard0 = 4,
+    CtrCard1 = 5,
+    Emmc = 6,
+    Sdio = 7,
+    AesIn = 8,
+    AesOut = 9,
+    ShaIn = 10,
+    ShaOut = 11,
+}
+
+/// Startup requests raised by other devices.
+///
+/// Devices only set bits here and never lock the NDMA device, so they can raise requests from
+/// inside their own register effects; the requests are serviced after ARM9 IO accesses and
+/// whenever the system clock advances. The NDMA controller clears a request when it starts a
+/// block for it; devices with a FIFO re-raise it for as long as another block can be moved.
+/// Timer overflows are one-off events, so one that no channel was waiting for is dropped.
+#[derive(Clone)]
+pub struct DmaTriggers {
+    inner: Arc<AtomicUsize>
+}
+
+impl DmaTriggers {
+    pub fn new() -> DmaTriggers {
+        DmaTriggers {
+            inner: Arc::new(AtomicUsize::new(0))
+        }
+    }
+
+    pub fn set(&self, startup: DmaStartup, active: bool) {
+        let bit = 1 << (startup as usize);
+        if active {
+            self.inner.fetch_or(bit, Ordering::SeqCst);
+        } else {
+            self.inner.fetch_and(!bit, Ordering::SeqCst);
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.inner.load(Ordering::SeqCst) != 0
+    }
+
+    fn take(&self, startup_mode: u32) -> bool {
+        let bit = 1 << startup_mode;
+        self.inner.fetch_and(!bit, Ordering::SeqCst) & bit != 0
+    }
+
+    fn drop_timer_overflows(&self) {
+        let timers = (1 << DmaStartup::Timer0 as usize) | (1 << DmaStartup::Timer1 as usize)
+                   | (1 << DmaStartup::Timer2 as usize) | (1 << DmaStartup::Timer3 as usize);
+        self.inner.fetch_and(!timers, Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for DmaTriggers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DmaTriggers({:04X})", self.inner.load(Ordering::SeqCst))
+    }
+}
+
+/// Progress of a running channel; the address registers keep their start values for reloads
+#[derive(Debug, Default)]
+pub struct ChannelState {
+    src: u32,
+    dst: u32,
+    words_left: u32,
+}
+
+impl Snapshot for ChannelState {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.src);
+        w.put(self.dst);
+        w.put(self.words_left);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.src = r.get()?;
+        self.dst = r.get()?;
+        self.words_left = r.get()?;
+        Ok(())
     }
-    warn!("STUBBED: NDMA chan_cnt write {:08X}", chan_cnt);
 }
 
 iodevice!(NdmaChannel, {
-    internal_state: Rc<Cell<RegGlobalCnt>>;
+    internal_state: ChannelState;
     regs: {
         0x004 => src_addr: u32 { }
         0x008 => dst_addr: u32 { }
@@ -37,33 +129,213 @@ iodevice!(NdmaChannel, {
         0x010 => write_cnt: u32 { }
         0x014 => block_cnt: u32 { }
         0x018 => fill_data: u32 { }
-        0x01C => chan_cnt: u32 {
-            write_effect = reg_chan_cnt_write;
-        }
+        0x01C => chan_cnt: u32 { }
     }
 });
 
-#[derive(Debug)]
+fn chan_enabled(chan: &NdmaChannel) -> bool {
+    bf!((chan.chan_cnt.get()) @ RegChannelCnt::enabled) == 1
+}
+
 pub struct NdmaDeviceState {
-    global_cnt: Rc<Cell<RegGlobalCnt>>,
+    global_cnt: RegGlobalCnt,
     channels: [NdmaChannel; 8],
+    irq_reqs: IrqRequests,
+    dma_triggers: DmaTriggers,
+    // The controller's view of the bus; connected once the memory map exists
+    memory: Option<MemController>,
 }
 
-impl Default for NdmaDeviceState {
-    fn default() -> NdmaDeviceState {
-        let global_cnt = Rc::new(Cell::new(RegGlobalCnt::new(0)));
+impl NdmaDeviceState {
+    pub fn new(irq_reqs: IrqRequests, dma_triggers: DmaTriggers) -> NdmaDeviceState {
         NdmaDeviceState {
-            global_cnt: global_cnt.clone(),
+            global_cnt: RegGlobalCnt::new(0),
             channels: [
-                NdmaChannel::new(global_cnt.clone()), NdmaChannel::new(global_cnt.clone()),
-                NdmaChannel::new(global_cnt.clone()), NdmaChannel::new(global_cnt.clone()),
-                NdmaChannel::new(global_cnt.clone()), NdmaChannel::new(global_cnt.clone()),
-                NdmaChannel::new(global_cnt.clone()), NdmaChannel::new(global_cnt.clone())
+                NdmaChannel::new(Default::default()), NdmaChannel::new(Default::default()),
+                NdmaChannel::new(Default::default()), NdmaChannel::new(Default::default()),
+                NdmaChannel::new(Default::default()), NdmaChannel::new(Default::default()),
+                NdmaChannel::new(Default::default()), NdmaChannel::new(Default::default())
             ],
+            irq_reqs: irq_reqs,
+            dma_triggers: dma_triggers,
+            memory: None,
+        }
+    }
+}
+
+impl fmt::Debug for NdmaDeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NdmaDeviceState {{ global_cnt: {:08X}, channels: {:?} }}",
+               self.global_cnt.raw(), self.channels)
+    }
+}
+
+impl Snapshot for NdmaDeviceState {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.global_cnt.raw());
+        w.put(self.dma_triggers.inner.load(Ordering::SeqCst) as u32);
+        for channel in self.channels.iter() {
+            channel.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.global_cnt = RegGlobalCnt::new(r.get()?);
+        self.dma_triggers.inner.store(r.get::<u32>()? as usize, Ordering::SeqCst);
+        for channel in self.channels.iter_mut() {
+            channel.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn connect_memory(dev: &mut NdmaDevice, memory: Option<MemController>) {
+    dev._internal_state.memory = memory;
+}
+
+pub fn handle_triggers(dev: &mut NdmaDevice) {
+    process_channels(&mut dev._internal_state);
+    dev._internal_state.dma_triggers.drop_timer_overflows();
+}
+
+fn irq(index: usize) -> IrqType {
+    match index {
+        0 => IrqType::Dmac1_0,
+        1 => IrqType::Dmac1_1,
+        2 => IrqType::Dmac1_2,
+        3 => IrqType::Dmac1_3,
+        4 => IrqType::Dmac1_4,
+        5 => IrqType::Dmac1_5,
+        6 => IrqType::Dmac1_6,
+        7 => IrqType::Dmac1_7,
+        _ => unreachable!()
+    }
+}
+
+fn next_addr(addr: u32, writeback_mode: u32) -> u32 {
+    match writeback_mode {
+        0 => addr.wrapping_add(4),
+        1 => addr.wrapping_sub(4),
+        _ => addr
+    }
+}
+
+fn is_ndma_reg(addr: u32) -> bool {
+    addr >> 12 == 0x10002
+}
+
+fn chan_cnt_update(state: &mut NdmaDeviceState, index: usize) {
+    {
+        let chan = &mut state.channels[index];
+        let cnt = chan.chan_cnt.get();
+        trace!("Starting NDMA channel {}: src {:08X}, dst {:08X}, total {:X}, block {:X}, cnt {:08X}",
+               index, chan.src_addr.get(), chan.dst_addr.get(), chan.xfer_pos.get(),
+               chan.write_cnt.get(), cnt);
+
+        if bf!(cnt @ RegChannelCnt::dst_addr_writeback_mode) == 3 {
+            warn!("NDMA channel {} uses reserved destination writeback mode 3", index);
+        }
+
+        chan._internal_state = ChannelState {
+            src: chan.src_addr.get(),
+            dst: chan.dst_addr.get(),
+            words_left: chan.xfer_pos.get()
+        };
+    }
+    process_channels(state);
+}
+
+/// Moves one logical block (WRITE_CNT words) for a channel, and stops the channel if it's done
+fn run_block(state: &mut NdmaDeviceState, index: usize) {
+    let memory = match state.memory {
+        Some(ref mut memory) => memory,
+        None => {
+            error!("NDMA channel {} started before the controller was connected to memory!", index);
+            return
+        }
+    };
+    let chan = &mut state.channels[index];
+
+    let cnt = chan.chan_cnt.get();
+    let immediate = bf!(cnt @ RegChannelCnt::immed_mode) == 1;
+    let repeat = !immediate && bf!(cnt @ RegChannelCnt::repeat_mode) == 1;
+    let src_mode = bf!(cnt @ RegChannelCnt::src_addr_writeback_mode);
+    let dst_mode = bf!(cnt @ RegChannelCnt::dst_addr_writeback_mode);
+
+    // In immediate mode the block is the whole transfer and the total count is ignored
+    let block_words = chan.write_cnt.get() & 0xFFFFFF;
+    let num_words = if immediate || repeat {
+        block_words
+    } else {
+        cmp::min(block_words, chan._internal_state.words_left)
+    };
+
+    let fill = chan.fill_data.get();
+    let (mut src, mut dst) = (chan._internal_state.src, chan._internal_state.dst);
+    for _ in 0..num_words {
+        let word = if src_mode == 3 {
+            fill
+        } else if is_ndma_reg(src) {
+            error!("NDMA channel {} attempted to read NDMA registers @ {:08X}!", index, src);
+            0
+        } else {
+            memory.read::<u32>(src & !3)
+        };
+
+        if is_ndma_reg(dst) {
+            error!("NDMA channel {} attempted to write NDMA registers @ {:08X}!", index, dst);
+        } else {
+            memory.write::<u32>(dst & !3, word);
+        }
+
+        src = next_addr(src, src_mode);
+        dst = next_addr(dst, dst_mode);
+    }
+
+    let src_reload = bf!(cnt @ RegChannelCnt::src_addr_reload) == 1;
+    let dst_reload = bf!(cnt @ RegChannelCnt::dst_addr_reload) == 1;
+    chan._internal_state.src = if src_reload { chan.src_addr.get() } else { src };
+    chan._internal_state.dst = if dst_reload { chan.dst_addr.get() } else { dst };
+    chan._internal_state.words_left -= cmp::min(num_words, chan._internal_state.words_left);
+
+    if immediate || (!repeat && chan._internal_state.words_left == 0) {
+        trace!("NDMA channel {} finished", index);
+        chan.chan_cnt.set_unchecked(bf!(cnt @ RegChannelCnt::enabled as 0));
+        if bf!(cnt @ RegChannelCnt::enable_irq) == 1 {
+            state.irq_reqs.add(irq(index));
+        }
+    }
+}
+
+/// Runs every enabled channel whose startup condition is met, until none are left
+fn process_channels(state: &mut NdmaDeviceState) {
+    if bf!((state.global_cnt).enabled) == 0 {
+        return
+    }
+
+    let mut blocks = 0;
+    loop {
+        let mut progress = false;
+        for index in 0..state.channels.len() {
+            let cnt = state.channels[index].chan_cnt.get();
+            if bf!(cnt @ RegChannelCnt::enabled) == 0 {
+                continue
+            }
+
+            let ready = bf!(cnt @ RegChannelCnt::immed_mode) == 1
+                        || state.dma_triggers.take(bf!(cnt @ RegChannelCnt::startup_mode));
+            if ready {
+                run_block(state, index);
+                progress = true;
+                blocks += 1;
+            }
+        }
+
+        if !progress || blocks >= MAX_BLOCKS_PER_PASS {
+            break
         }
     }
 }
-unsafe impl Send for NdmaDeviceState { } // TODO: Bad!
 
 iodevice!(NdmaDevice, {
     internal_state: NdmaDeviceState;
@@ -71,7 +343,8 @@ iodevice!(NdmaDevice, {
         0x000 => global_cnt: u32 {
             write_effect = |dev: &mut NdmaDevice| {
                 let new_val = RegGlobalCnt::new(dev.global_cnt.get());
-                dev._internal_state.global_cnt.set(new_val);
+                dev._internal_state.global_cnt = new_val;
+                process_channels(&mut dev._internal_state);
             };
         }
     }
@@ -88,10 +361,111 @@ iodevice!(NdmaDevice, {
             write_effect = |dev: &mut NdmaDevice, buf_pos: usize, src: &[u8]| {
                 let channel = buf_pos / 0x1C;
                 let new_buf_pos = buf_pos % 0x1C + 4; // As if the pos was for channel 0
+                let was_enabled = chan_enabled(&dev._internal_state.channels[channel]);
                 unsafe {
                     dev._internal_state.channels[channel].write_reg(new_buf_pos, src.as_ptr(), src.len());
                 }
+                if !was_enabled && chan_enabled(&dev._internal_state.channels[channel]) {
+                    chan_cnt_update(&mut dev._internal_state, channel);
+                }
             };
         }
     }
-});
\ No newline at end of file
+});
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::irq;
+    use io::regs::IoRegAccess;
+    use mem;
+
+    fn make_ndma() -> (NdmaDevice, IrqRequests, DmaTriggers) {
+        let (irq_reqs, _) = irq::make_channel();
+        let dma_triggers = DmaTriggers::new();
+        let mut dev = NdmaDevice::new(NdmaDeviceState::new(irq_reqs.clone(), dma_triggers.clone()));
+
+        let mut memory = mem::MemController::new();
+        memory.map_region(0x20000000, mem::AddressBlock::SharedRam(mem::SharedMemoryBlock::new(4)));
+        connect_memory(&mut dev, Some(memory));
+        (dev, irq_reqs, dma_triggers)
+    }
+
+    fn write_reg(dev: &mut NdmaDevice, offset: usize, val: u32) {
+        unsafe { dev.write_reg(offset, &val as *const u32 as *const u8, 4); }
+    }
+
+    fn read_mem(dev: &NdmaDevice, addr: u32) -> u32 {
+        dev._internal_state.memory.as_ref().unwrap().read::<u32>(addr)
+    }
+
+    #[test]
+    fn immediate_fill() {
+        let (mut dev, irq_reqs, _) = make_ndma();
+        write_reg(&mut dev, 0x00, 1);
+        write_reg(&mut dev, 0x08, 0x20000000);
+        write_reg(&mut dev, 0x10, 4);
+        write_reg(&mut dev, 0x18, 0xDEADBEEF);
+        // Enabled, IRQ, immediate, fill data source
+        write_reg(&mut dev, 0x1C, 0xD0000000 | (3 << 13));
+
+        for i in 0..4 {
+            assert_eq!(read_mem(&dev, 0x20000000 + i * 4), 0xDEADBEEF);
+        }
+        assert_eq!(read_mem(&dev, 0x20000010), 0);
+        assert!(!chan_enabled(&dev._internal_state.channels[0]));
+        assert_eq!(irq_reqs.get_pending() & IrqType::Dmac1_0 as u32, IrqType::Dmac1_0 as u32);
+    }
+
+    #[test]
+    fn startup_blocks() {
+        let (mut dev, _, dma_triggers) = make_ndma();
+        for i in 0..4 {
+            dev._internal_state.memory.as_mut().unwrap().write::<u32>(0x20000000 + i * 4, i + 1);
+        }
+
+        // Channel 1: 4 words total, 2 per timer 0 overflow
+        write_reg(&mut dev, 0x00, 1);
+        write_reg(&mut dev, 0x20, 0x20000000);
+        write_reg(&mut dev, 0x24, 0x20000100);
+        write_reg(&mut dev, 0x28, 4);
+        write_reg(&mut dev, 0x2C, 2);
+        write_reg(&mut dev, 0x38, 0x80000000 | ((DmaStartup::Timer0 as u32) << 24));
+        assert_eq!(read_mem(&dev, 0x20000100), 0);
+
+        dma_triggers.set(DmaStartup::Timer0, true);
+        handle_triggers(&mut dev);
+        assert_eq!(read_mem(&dev, 0x20000104), 2);
+        assert_eq!(read_mem(&dev, 0x20000108), 0);
+        assert!(chan_enabled(&dev._internal_state.channels[1]));
+
+        dma_triggers.set(DmaStartup::Timer0, true);
+        handle_triggers(&mut dev);
+        assert_eq!(read_mem(&dev, 0x2000010C), 4);
+        assert!(!chan_enabled(&dev._internal_state.channels[1]));
+    }
+
+    #[test]
+    fn timer_overflows_are_edges() {
+        let (mut dev, _, dma_triggers) = make_ndma();
+        dev._internal_state.memory.as_mut().unwrap().write::<u32>(0x20000000, 1);
+        write_reg(&mut dev, 0x00, 1);
+
+        // Nothing waits for this overflow, so it can't start the channel enabled after it
+        dma_triggers.set(DmaStartup::Timer0, true);
+        handle_triggers(&mut dev);
+        assert!(!dma_triggers.any());
+
+        write_reg(&mut dev, 0x04, 0x20000000);
+        write_reg(&mut dev, 0x08, 0x20000100);
+        write_reg(&mut dev, 0x0C, 1);
+        write_reg(&mut dev, 0x10, 1);
+        write_reg(&mut dev, 0x1C, 0x80000000 | ((DmaStartup::Timer0 as u32) << 24));
+        assert_eq!(read_mem(&dev, 0x20000100), 0);
+
+        dma_triggers.set(DmaStartup::Timer0, true);
+        handle_triggers(&mut dev);
+        assert_eq!(read_mem(&dev, 0x20000100), 1);
+        assert!(!dma_triggers.any());
+    }
+}
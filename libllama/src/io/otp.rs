@@ -3,6 +3,8 @@ use std::fmt;
 use std::fs::OpenOptions;
 use std::io::Read;
 
+use snapshot;
+
 pub struct OtpDeviceState {
     otp: [u8; 0x100]
 }
@@ -29,6 +31,16 @@ impl Default for OtpDeviceState {
     }
 }
 
+impl snapshot::Snapshot for OtpDeviceState {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put_bytes(&self.otp[..]);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        r.get_bytes(&mut self.otp[..])
+    }
+}
+
 fn reg_otp_write(dev: &mut OtpDevice, buf_pos: usize, source: &[u8]) {
     dev._internal_state.otp[buf_pos .. buf_pos + source.len()].copy_from_slice(source);
 }
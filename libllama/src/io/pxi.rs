@@ -1,19 +1,364 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use cpu::irq::{IrqRequests, IrqType};
+use snapshot::{self, Snapshot};
+
+const FIFO_LEN: usize = 16;
+
 bfdesc!(RegSync: u32, {
     data_recv: 0 => 7,
-    data_sent: 8 => 15
+    data_sent: 8 => 15,
+    // The ARM9 side can also use bit 29, which targets a second ARM11 interrupt
+    trigger_irq: 29 => 30,
+    enable_irq: 31 => 31
+});
+
+bfdesc!(RegCnt: u16, {
+    send_empty: 0 => 0,
+    send_full: 1 => 1,
+    enable_send_irq: 2 => 2,
+    flush_send: 3 => 3,
+    recv_empty: 8 => 8,
+    recv_full: 9 => 9,
+    enable_recv_irq: 10 => 10,
+    error: 14 => 14,
+    enable_fifos: 15 => 15
 });
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PxiSide {
+    Arm9 = 0,
+    Arm11 = 1
+}
+
+/// One processor's half of the PXI link
+struct PxiEndpoint {
+    // Words sent by this side that the remote hasn't received yet
+    send_fifo: VecDeque<u32>,
+    sync_sent: u8,
+    sync_irq_enabled: bool,
+    send_irq_enabled: bool,
+    recv_irq_enabled: bool,
+    fifos_enabled: bool,
+    error: bool,
+    last_recv: u32,
+    // There's no ARM11 interrupt controller yet, so only the ARM9 gets interrupts
+    irq: Option<IrqRequests>,
+}
+
+impl PxiEndpoint {
+    fn new(irq: Option<IrqRequests>) -> PxiEndpoint {
+        PxiEndpoint {
+            send_fifo: VecDeque::with_capacity(FIFO_LEN),
+            sync_sent: 0,
+            sync_irq_enabled: false,
+            send_irq_enabled: false,
+            recv_irq_enabled: false,
+            fifos_enabled: false,
+            error: false,
+            last_recv: 0,
+            irq: irq
+        }
+    }
+
+    fn raise(&mut self, t: IrqType) {
+        match self.irq {
+            Some(ref mut irq) => irq.add(t),
+            None => trace!("Dropping PXI interrupt {:?} for the ARM11", t)
+        }
+    }
+}
+
+impl Snapshot for PxiEndpoint {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.send_fifo.len() as u64);
+        for word in self.send_fifo.iter() {
+            w.put(*word);
+        }
+        w.put(self.sync_sent);
+        w.put_bool(self.sync_irq_enabled);
+        w.put_bool(self.send_irq_enabled);
+        w.put_bool(self.recv_irq_enabled);
+        w.put_bool(self.fifos_enabled);
+        w.put_bool(self.error);
+        w.put(self.last_recv);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.send_fifo.clear();
+        for _ in 0..r.get::<u64>()? {
+            self.send_fifo.push_back(r.get()?);
+        }
+        self.sync_sent = r.get()?;
+        self.sync_irq_enabled = r.get_bool()?;
+        self.send_irq_enabled = r.get_bool()?;
+        self.recv_irq_enabled = r.get_bool()?;
+        self.fifos_enabled = r.get_bool()?;
+        self.error = r.get_bool()?;
+        self.last_recv = r.get()?;
+        Ok(())
+    }
+}
+
+/// The state shared by the ARM9 and ARM11 views of the PXI device
+#[derive(Clone)]
+pub struct PxiLink {
+    ends: Arc<Mutex<[PxiEndpoint; 2]>>
+}
+
+impl PxiLink {
+    pub fn new(irq9: IrqRequests) -> PxiLink {
+        PxiLink {
+            ends: Arc::new(Mutex::new([PxiEndpoint::new(Some(irq9)), PxiEndpoint::new(None)]))
+        }
+    }
+}
+
+pub struct PxiState {
+    side: PxiSide,
+    link: PxiLink,
+}
+
+impl PxiState {
+    pub fn new(side: PxiSide, link: PxiLink) -> PxiState {
+        PxiState {
+            side: side,
+            link: link
+        }
+    }
+}
+
+impl fmt::Debug for PxiState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PxiState {{ side: {:?} }}", self.side)
+    }
+}
+
+// Both views share one link, so only the ARM9 view saves it
+impl Snapshot for PxiState {
+    fn save(&self, w: &mut snapshot::Writer) {
+        if self.side == PxiSide::Arm9 {
+            for end in self.link.ends.lock().iter() {
+                end.save(w);
+            }
+        }
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        if self.side == PxiSide::Arm9 {
+            for end in self.link.ends.lock().iter_mut() {
+                end.load(r)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` with this side's endpoint and the remote's endpoint
+fn with_ends<T, F>(dev: &mut PxiDevice, f: F) -> T
+    where F: FnOnce(&mut PxiEndpoint, &mut PxiEndpoint) -> T {
+    let mut ends = dev._internal_state.link.ends.lock();
+    let (arm9, arm11) = ends.split_at_mut(1);
+    match dev._internal_state.side {
+        PxiSide::Arm9 => f(&mut arm9[0], &mut arm11[0]),
+        PxiSide::Arm11 => f(&mut arm11[0], &mut arm9[0]),
+    }
+}
+
+fn reg_sync_read(dev: &mut PxiDevice) {
+    let sync = with_ends(dev, |ours, remote| {
+        let mut sync = 0u32;
+        bf!(sync @ RegSync::data_recv = remote.sync_sent as u32);
+        bf!(sync @ RegSync::data_sent = ours.sync_sent as u32);
+        bf!(sync @ RegSync::enable_irq = ours.sync_irq_enabled as u32);
+        sync
+    });
+    dev.sync.set_unchecked(sync);
+}
+
 fn reg_sync_write(dev: &mut PxiDevice) {
-    warn!("STUBBED: Write to PXI_SYNC");
+    let sync = dev.sync.get();
+    let side = dev._internal_state.side;
+    with_ends(dev, |ours, remote| {
+        ours.sync_sent = bf!(sync @ RegSync::data_sent) as u8;
+        ours.sync_irq_enabled = bf!(sync @ RegSync::enable_irq) == 1;
+
+        if bf!(sync @ RegSync::trigger_irq) != 0 {
+            if remote.sync_irq_enabled {
+                remote.raise(IrqType::PxiSync);
+            } else {
+                trace!("{:?} PXI sync interrupt ignored by the remote side", side);
+            }
+        }
+    });
+    dev.sync.set_unchecked(bf!(sync @ RegSync::trigger_irq as 0));
+}
+
+fn reg_cnt_read(dev: &mut PxiDevice) {
+    let cnt = with_ends(dev, |ours, remote| {
+        let mut cnt = 0u16;
+        bf!(cnt @ RegCnt::send_empty = ours.send_fifo.is_empty() as u16);
+        bf!(cnt @ RegCnt::send_full = (ours.send_fifo.len() == FIFO_LEN) as u16);
+        bf!(cnt @ RegCnt::enable_send_irq = ours.send_irq_enabled as u16);
+        bf!(cnt @ RegCnt::recv_empty = remote.send_fifo.is_empty() as u16);
+        bf!(cnt @ RegCnt::recv_full = (remote.send_fifo.len() == FIFO_LEN) as u16);
+        bf!(cnt @ RegCnt::enable_recv_irq = ours.recv_irq_enabled as u16);
+        bf!(cnt @ RegCnt::error = ours.error as u16);
+        bf!(cnt @ RegCnt::enable_fifos = ours.fifos_enabled as u16);
+        cnt
+    });
+    dev.cnt.set_unchecked(cnt);
+}
+
+fn reg_cnt_write(dev: &mut PxiDevice) {
+    let cnt = dev.cnt.get();
+    with_ends(dev, |ours, remote| {
+        if bf!(cnt @ RegCnt::flush_send) == 1 {
+            ours.send_fifo.clear();
+        }
+        if bf!(cnt @ RegCnt::error) == 1 {
+            // Acknowledged by writing 1
+            ours.error = false;
+        }
+        ours.fifos_enabled = bf!(cnt @ RegCnt::enable_fifos) == 1;
+
+        // Enabling an interrupt whose condition already holds fires it right away
+        let send_irq = bf!(cnt @ RegCnt::enable_send_irq) == 1;
+        let recv_irq = bf!(cnt @ RegCnt::enable_recv_irq) == 1;
+        if send_irq && !ours.send_irq_enabled && ours.send_fifo.len() < FIFO_LEN {
+            ours.raise(IrqType::PxiNotFull);
+        }
+        if recv_irq && !ours.recv_irq_enabled && !remote.send_fifo.is_empty() {
+            ours.raise(IrqType::PxiNotEmpty);
+        }
+        ours.send_irq_enabled = send_irq;
+        ours.recv_irq_enabled = recv_irq;
+    });
+}
+
+fn reg_send_write(dev: &mut PxiDevice) {
+    let word = dev.send.get();
+    let side = dev._internal_state.side;
+    with_ends(dev, |ours, remote| {
+        if !ours.fifos_enabled {
+            warn!("{:?} wrote to PXI send FIFO while FIFOs are disabled", side);
+            return
+        }
+        if ours.send_fifo.len() == FIFO_LEN {
+            ours.error = true;
+            return
+        }
+
+        ours.send_fifo.push_back(word);
+        if ours.send_fifo.len() == 1 && remote.recv_irq_enabled {
+            remote.raise(IrqType::PxiNotEmpty);
+        }
+    });
+}
+
+fn reg_recv_read(dev: &mut PxiDevice) {
+    let side = dev._internal_state.side;
+    let word = with_ends(dev, |ours, remote| {
+        if !ours.fifos_enabled {
+            warn!("{:?} read from PXI receive FIFO while FIFOs are disabled", side);
+            return ours.last_recv
+        }
+
+        let was_full = remote.send_fifo.len() == FIFO_LEN;
+        match remote.send_fifo.pop_front() {
+            Some(word) => {
+                ours.last_recv = word;
+                if was_full && remote.send_irq_enabled {
+                    remote.raise(IrqType::PxiNotFull);
+                }
+            }
+            None => ours.error = true
+        }
+        ours.last_recv
+    });
+    dev.recv.set_unchecked(word);
 }
 
 iodevice!(PxiDevice, {
+    internal_state: PxiState;
     regs: {
         0x000 => sync: u32 {
+            write_bits = 0b11100000_00000000_11111111_00000000;
+            read_effect = reg_sync_read;
             write_effect = reg_sync_write;
         }
-        0x004 => cnt: u16 {}
-        0x008 => send: u32 { write_effect = |_| unimplemented!(); }
-        0x00C => recv: u32 { read_effect = |_| unimplemented!(); }
+        0x004 => cnt: u16 {
+            write_bits = 0b11000100_00001100;
+            read_effect = reg_cnt_read;
+            write_effect = reg_cnt_write;
+        }
+        0x008 => send: u32 { write_effect = reg_send_write; }
+        0x00C => recv: u32 {
+            write_bits = 0;
+            read_effect = reg_recv_read;
+        }
+    }
+});
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::irq;
+    use io::regs::IoRegAccess;
+
+    fn write_reg<T: Copy>(dev: &mut PxiDevice, offset: usize, val: T) {
+        unsafe { dev.write_reg(offset, &val as *const T as *const u8, ::std::mem::size_of::<T>()); }
+    }
+
+    fn read_reg<T: Copy + Default>(dev: &mut PxiDevice, offset: usize) -> T {
+        let mut val = T::default();
+        unsafe { dev.read_reg(offset, &mut val as *mut T as *mut u8, ::std::mem::size_of::<T>()); }
+        val
+    }
+
+    fn make_pair() -> (PxiDevice, PxiDevice, IrqRequests) {
+        let (irq9, _) = irq::make_channel();
+        let link = PxiLink::new(irq9.clone());
+        (PxiDevice::new(PxiState::new(PxiSide::Arm9, link.clone())),
+         PxiDevice::new(PxiState::new(PxiSide::Arm11, link)),
+         irq9)
     }
-});
\ No newline at end of file
+
+    #[test]
+    fn sync() {
+        let (mut pxi9, mut pxi11, irq9) = make_pair();
+        write_reg(&mut pxi9, 0x0, 0x80000900u32);
+        assert_eq!(read_reg::<u32>(&mut pxi11, 0x0) & 0xFF, 9);
+
+        write_reg(&mut pxi11, 0x0, 0x40000B00u32);
+        assert_eq!(read_reg::<u32>(&mut pxi9, 0x0) & 0xFFFF, 0x090B);
+        assert!(irq9.get_pending() & IrqType::PxiSync as u32 != 0);
+    }
+
+    #[test]
+    fn fifo() {
+        let (mut pxi9, mut pxi11, irq9) = make_pair();
+        write_reg(&mut pxi9, 0x4, 0x8400u16);
+        write_reg(&mut pxi11, 0x4, 0x8000u16);
+
+        for i in 0..FIFO_LEN as u32 {
+            write_reg(&mut pxi11, 0x8, i);
+        }
+        assert!(irq9.get_pending() & IrqType::PxiNotEmpty as u32 != 0);
+        assert_eq!(read_reg::<u16>(&mut pxi11, 0x4) & 0b11, 0b10);
+        assert_eq!(read_reg::<u16>(&mut pxi9, 0x4) & 0x300, 0x200);
+
+        // Overflowing sets the error bit instead of dropping a queued word
+        write_reg(&mut pxi11, 0x8, 0xFFu32);
+        assert_eq!(read_reg::<u16>(&mut pxi11, 0x4) & 0x4000, 0x4000);
+
+        for i in 0..FIFO_LEN as u32 {
+            assert_eq!(read_reg::<u32>(&mut pxi9, 0xC), i);
+        }
+        assert_eq!(read_reg::<u16>(&mut pxi9, 0x4) & 0x100, 0x100);
+    }
+}
@@ -6,6 +6,8 @@ use std::ops::BitAndAssign;
 use std::ops::BitOrAssign;
 use std::ops::Not;
 
+use snapshot;
+
 #[derive(Debug)]
 pub struct IoReg<T>
     where T: Copy + BitAnd<Output=T> + BitAndAssign
@@ -48,9 +50,26 @@ impl<T> IoReg<T>
     }
 }
 
+impl<T> snapshot::Snapshot for IoReg<T>
+    where T: Copy + BitAnd<Output=T> + BitAndAssign
+                  + BitOrAssign + Not<Output=T> {
+
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.val);
+    }
+
+    // Restores the raw value; register effects are not replayed
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.val = r.get()?;
+        Ok(())
+    }
+}
+
 pub trait IoRegAccess {
     unsafe fn read_reg(&mut self, offset: usize, buf: *mut u8, buf_size: usize);
     unsafe fn write_reg(&mut self, offset: usize, buf: *const u8, buf_size: usize);
+    /// Name of the register at `offset`, if one starts there
+    fn reg_name(&self, offset: usize) -> Option<&'static str>;
 }
 
 
@@ -88,6 +107,21 @@ macro_rules! __iodevice__ {
             }
         }
 
+        impl $crate::snapshot::Snapshot for $name {
+            fn save(&self, w: &mut $crate::snapshot::Writer) {
+                w.section(stringify!($name));
+                $( $crate::snapshot::Snapshot::save(&self.$reg_name, w); )*
+                $( <$instate as $crate::snapshot::Snapshot>::save(&self._internal_state, w); )*
+            }
+
+            fn load(&mut self, r: &mut $crate::snapshot::Reader) -> $crate::snapshot::Result<()> {
+                r.section(stringify!($name))?;
+                $( $crate::snapshot::Snapshot::load(&mut self.$reg_name, r)?; )*
+                $( <$instate as $crate::snapshot::Snapshot>::load(&mut self._internal_state, r)?; )*
+                Ok(())
+            }
+        }
+
         impl $crate::io::regs::IoRegAccess for $name {
             unsafe fn read_reg(&mut self, offset: usize, buf: *mut u8, buf_size: usize) {
                 trace!("Reading from {} at +0x{:X}", stringify!($name), offset);
@@ -140,6 +174,13 @@ macro_rules! __iodevice__ {
                     o @ _ => panic!("Unhandled {} register write: {} bytes @ 0x{:X}", stringify!($name), buf_size, o)
                 }
             }
+
+            fn reg_name(&self, offset: usize) -> Option<&'static str> {
+                match offset {
+                    $( $reg_offs => Some(stringify!($reg_name)), )*
+                    _ => None
+                }
+            }
         }
     )
 }
@@ -218,6 +259,13 @@ mod test {
         }
     });
 
+    #[test]
+    fn reg_names() {
+        let mmc_regs = MMCRegs::new();
+        assert_eq!(mmc_regs.reg_name(0x002), Some("reg2"));
+        assert_eq!(mmc_regs.reg_name(0x003), None);
+    }
+
     #[test]
     fn read_reg() {
         let mut mmc_regs = MMCRegs::new();
@@ -1,4 +1,5 @@
 use io::regs;
+use snapshot;
 
 use std::fmt;
 use std::mem;
@@ -55,6 +56,26 @@ impl Default for RsaDeviceState {
     }
 }
 
+impl snapshot::Snapshot for RsaDeviceState {
+    fn save(&self, w: &mut snapshot::Writer) {
+        for slot in self.slots.iter() {
+            w.put(slot.write_pos as u64);
+            w.put_bytes(&slot.buf[..]);
+        }
+        w.put_bytes(&self.modulus[..]);
+        w.put_bytes(&self.message[..]);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        for slot in self.slots.iter_mut() {
+            slot.write_pos = r.get::<u64>()? as usize;
+            r.get_bytes(&mut slot.buf[..])?;
+        }
+        r.get_bytes(&mut self.modulus[..])?;
+        r.get_bytes(&mut self.message[..])
+    }
+}
+
 impl fmt::Debug for RsaDeviceState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "RsaDeviceState {{ }}")
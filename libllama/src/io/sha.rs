@@ -1,4 +1,6 @@
+use io::ndma::{DmaStartup, DmaTriggers};
 use io::regs;
+use snapshot;
 
 use std::fmt;
 use std::mem;
@@ -16,10 +18,60 @@ bfdesc!(RegCnt: u32, {
     enable_irq1: 10 => 10
 });
 
-#[derive(Default)]
 pub struct ShaDeviceState {
     hasher: Option<Hasher>,
-    hash: [u8; 32]
+    hash: [u8; 32],
+
+    // OpenSSL hashers can't be serialized, so keep what we need to rebuild one
+    hash_mode: u32,
+    hashed_data: Vec<u8>,
+
+    dma_triggers: DmaTriggers,
+}
+
+impl ShaDeviceState {
+    pub fn new(dma_triggers: DmaTriggers) -> ShaDeviceState {
+        ShaDeviceState {
+            hasher: None,
+            hash: [0; 32],
+            hash_mode: 0,
+            hashed_data: Vec::new(),
+            dma_triggers: dma_triggers,
+        }
+    }
+}
+
+fn digest_for_mode(mode: u32) -> MessageDigest {
+    match mode {
+        0b00 => MessageDigest::sha256(),
+        0b01 => MessageDigest::sha224(),
+        _ => MessageDigest::sha1()
+    }
+}
+
+impl snapshot::Snapshot for ShaDeviceState {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put_bytes(&self.hash[..]);
+        w.put_bool(self.hasher.is_some());
+        w.put(self.hash_mode);
+        w.put_vec(&self.hashed_data);
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        r.get_bytes(&mut self.hash[..])?;
+        let has_hasher = r.get_bool()?;
+        self.hash_mode = r.get()?;
+        self.hashed_data = r.get_vec()?;
+
+        self.hasher = if has_hasher {
+            let mut hasher = Hasher::new(digest_for_mode(self.hash_mode)).unwrap();
+            hasher.update(&self.hashed_data).unwrap();
+            Some(hasher)
+        } else {
+            None
+        };
+        Ok(())
+    }
 }
 
 impl fmt::Debug for ShaDeviceState {
@@ -43,11 +95,14 @@ fn reg_cnt_update(dev: &mut ShaDevice) {
             dev._internal_state.hash = [0u8; 32];
             dev._internal_state.hash[0..hash_slice.len()].copy_from_slice(hash_slice);
         }
+        dev._internal_state.hashed_data.clear();
+        dev._internal_state.dma_triggers.set(DmaStartup::ShaOut, true);
         bf!(cnt @ RegCnt::final_round = 0);
     }
 
     else if bf!(cnt @ RegCnt::clear_fifo) == 1 {
         dev._internal_state.hasher = None;
+        dev._internal_state.hashed_data.clear();
     }
 
     else if bf!(cnt @ RegCnt::busy) == 0 {
@@ -57,15 +112,16 @@ fn reg_cnt_update(dev: &mut ShaDevice) {
         // Create new hasher
         assert_eq!(bf!(cnt @ RegCnt::big_endian), 1);
 
-        let mode = match bf!(cnt @ RegCnt::hash_mode) {
-            0b00 => MessageDigest::sha256(),
-            0b01 => MessageDigest::sha224(),
-            _ => MessageDigest::sha1()
-        };
-
-        dev._internal_state.hasher = Some(Hasher::new(mode).unwrap());
+        let mode = bf!(cnt @ RegCnt::hash_mode);
+        dev._internal_state.hasher = Some(Hasher::new(digest_for_mode(mode)).unwrap());
+        dev._internal_state.hash_mode = mode;
+        dev._internal_state.hashed_data.clear();
+        dev._internal_state.dma_triggers.set(DmaStartup::ShaOut, false);
     }
 
+    let has_hasher = dev._internal_state.hasher.is_some();
+    dev._internal_state.dma_triggers.set(DmaStartup::ShaIn, has_hasher);
+
     bf!(cnt @ RegCnt::busy = 0);
     dev.cnt.set_unchecked(cnt);
 }
@@ -88,6 +144,9 @@ fn reg_fifo_write(dev: &mut ShaDevice, buf_pos: usize, source: &[u8]) {
     };
 
     hasher.update(source).unwrap();
+    dev._internal_state.hashed_data.extend_from_slice(source);
+    // The FIFO never fills up here, so NDMA may always feed it another block
+    dev._internal_state.dma_triggers.set(DmaStartup::ShaIn, true);
 }
 
 iodevice!(ShaDevice, {
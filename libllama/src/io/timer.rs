@@ -6,7 +6,9 @@ use std::sync::Arc;
 use parking_lot::{Mutex, MutexGuard};
 
 use cpu::irq;
+use io::ndma;
 use io::regs::IoReg;
+use snapshot;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Prescaler {
@@ -276,6 +278,16 @@ fn irq(t_index: usize) -> irq::IrqType {
     }
 }
 
+fn dma_startup(t_index: usize) -> ndma::DmaStartup {
+    match t_index {
+        0 => ndma::DmaStartup::Timer0,
+        1 => ndma::DmaStartup::Timer1,
+        2 => ndma::DmaStartup::Timer2,
+        3 => ndma::DmaStartup::Timer3,
+        _ => unreachable!()
+    }
+}
+
 
 
 
@@ -290,6 +302,29 @@ impl TimerStates {
     }
 }
 
+impl snapshot::Snapshot for TimerStates {
+    fn save(&self, w: &mut snapshot::Writer) {
+        for state in self.0.iter() {
+            let state = state.lock();
+            w.put_bool(state.started);
+            w.put_bool(state.count_up);
+            w.put(state.val_cycles);
+            w.put(state.prescaler as u16);
+        }
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        for state in self.0.iter() {
+            let mut state = state.lock();
+            state.started = r.get_bool()?;
+            state.count_up = r.get_bool()?;
+            state.val_cycles = r.get()?;
+            state.prescaler = Prescaler::new(r.get::<u16>()? & 0b11);
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for TimerStates {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "TimerStates {{ }}")
@@ -315,7 +350,8 @@ impl TimerState {
     }
 }
 
-pub fn handle_clock_update(timer_states: &TimerStates, clock_diff: usize, irq_tx: &mut irq::IrqRequests) {
+pub fn handle_clock_update(timer_states: &TimerStates, clock_diff: usize, irq_tx: &mut irq::IrqRequests,
+                           dma_triggers: &ndma::DmaTriggers) {
     let iter_started = TimerIter::new(&timer_states).filter(|t| t.started());
 
     for mut timer in iter_started {
@@ -324,7 +360,8 @@ pub fn handle_clock_update(timer_states: &TimerStates, clock_diff: usize, irq_tx
         for (index, status) in overflows.iter().enumerate() {
             if *status {
                 // Overflow happened
-                irq_tx.add(irq(index))
+                irq_tx.add(irq(index));
+                dma_triggers.set(dma_startup(index), true);
             }
         }
     }
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::Mutex;
+
+/// How many accesses are kept before the oldest ones are dropped
+pub const TRACE_CAPACITY: usize = 0x4000;
+
+/// Who made a memory access
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Accessor {
+    /// The ARM9, executing the instruction at the given address
+    Arm9(u32),
+    /// The ARM11, executing the instruction at the given address
+    Arm11(u32),
+    /// Anything else on the bus, such as a DMA transfer
+    Other,
+}
+
+/// A traced IO register access
+#[derive(Clone, Debug)]
+pub struct IoAccess {
+    pub accessor: Accessor,
+    pub device: &'static str,
+    /// Name of the register, unless the access fell in a range of the device
+    pub reg: Option<&'static str>,
+    /// Offset of the access into the device
+    pub offset: u32,
+    pub size: usize,
+    /// Value read or written, truncated to the first 8 bytes
+    pub value: u64,
+    pub is_write: bool,
+}
+
+impl fmt::Display for IoAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.accessor {
+            Accessor::Arm9(pc) => write!(f, "ARM9 @ 0x{:08X}: ", pc)?,
+            Accessor::Arm11(pc) => write!(f, "ARM11 @ 0x{:08X}: ", pc)?,
+            Accessor::Other => write!(f, "Other: ")?,
+        }
+        write!(f, "{}{} {}", if self.is_write { "W" } else { "R" }, self.size * 8, self.device)?;
+        if let Some(reg) = self.reg {
+            write!(f, ".{}", reg)?;
+        }
+        write!(f, " (+0x{:03X}) = 0x{:X}", self.offset, self.value)
+    }
+}
+
+struct TraceLog {
+    // Devices to record accesses to, or all of them if empty
+    devices: Vec<String>,
+    entries: VecDeque<IoAccess>,
+}
+
+/// Records IO register accesses into a ring buffer while enabled. Shared by all IO regions.
+#[derive(Clone)]
+pub struct IoTracer {
+    enabled: Arc<AtomicBool>,
+    log: Arc<Mutex<TraceLog>>,
+}
+
+impl IoTracer {
+    pub fn new() -> IoTracer {
+        IoTracer {
+            enabled: Arc::new(AtomicBool::new(false)),
+            log: Arc::new(Mutex::new(TraceLog {
+                devices: Vec::new(),
+                entries: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Starts recording accesses to the named devices, or to all devices if none are given
+    pub fn start(&self, devices: Vec<String>) {
+        self.log.lock().devices = devices;
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops recording, keeping what was already recorded
+    pub fn stop(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        self.log.lock().entries.clear();
+    }
+
+    /// Returns the recorded accesses, oldest first, optionally only those to one device
+    pub fn entries(&self, device: Option<&str>) -> Vec<IoAccess> {
+        let log = self.log.lock();
+        log.entries.iter()
+            .filter(|access| device.map_or(true, |name| access.device == name))
+            .cloned()
+            .collect()
+    }
+
+    /// Writes the recorded accesses out as text, one per line
+    pub fn export(&self, w: &mut Write) -> ::std::io::Result<()> {
+        for access in self.entries(None) {
+            writeln!(w, "{}", access)?;
+        }
+        Ok(())
+    }
+
+    pub fn record(&self, accessor: Accessor, device: &'static str, reg: Option<&'static str>,
+                  offset: usize, buf: &[u8], is_write: bool) {
+        let mut log = self.log.lock();
+        if !log.devices.is_empty() && !log.devices.iter().any(|name| name == device) {
+            return
+        }
+
+        let value = buf.iter().take(8).enumerate()
+            .fold(0u64, |val, (i, byte)| val | (*byte as u64) << (8 * i));
+        if log.entries.len() == TRACE_CAPACITY {
+            log.entries.pop_front();
+        }
+        log.entries.push_back(IoAccess {
+            accessor: accessor,
+            device: device,
+            reg: reg,
+            offset: offset as u32,
+            size: buf.len(),
+            value: value,
+            is_write: is_write,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ring_buffer_and_filter() {
+        let tracer = IoTracer::new();
+        tracer.start(vec!["aes".to_owned()]);
+        tracer.record(Accessor::Arm9(0x08000000), "aes", Some("cnt"), 0, &[0x78, 0x56, 0x34, 0x12], true);
+        tracer.record(Accessor::Arm9(0x08000004), "sha", Some("cnt"), 0, &[1, 0, 0, 0], true);
+        let entries = tracer.entries(None);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].value, 0x12345678);
+        assert_eq!(format!("{}", entries[0]), "ARM9 @ 0x08000000: W32 aes.cnt (+0x000) = 0x12345678");
+
+        tracer.start(Vec::new());
+        for i in 0..TRACE_CAPACITY {
+            tracer.record(Accessor::Other, "timer", None, i & 0xFFF, &[0, 0], false);
+        }
+        assert_eq!(tracer.entries(None).len(), TRACE_CAPACITY);
+        assert!(tracer.entries(Some("aes")).is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::Read;
+
+use ldr;
+use hwcore;
+use mem;
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
+    errors {
+        BadSize(size: usize) {
+            description("ARM9 bootrom dump has the wrong size")
+            display("ARM9 bootrom dump is 0x{:X} bytes, expected 0x{:X}", size, BOOT9_SIZE)
+        }
+    }
+}
+
+const BOOT9_ADDR: u32 = 0xFFFF0000;
+const BOOT9_SIZE: usize = 0x10000;
+
+/// Powers the system on with a dump of the ARM9 bootrom, which then boots FIRM from NAND
+/// just like real hardware. The ARM11 bootrom isn't emulated, so the ARM11 side of its
+/// handshake is faked.
+pub struct Boot9Loader {
+    rom: Vec<u8>,
+}
+
+impl Boot9Loader {
+    pub fn from_file(path: &str) -> Result<Boot9Loader> {
+        let mut rom = Vec::new();
+        File::open(path)?.read_to_end(&mut rom)?;
+        if rom.len() != BOOT9_SIZE {
+            bail!(ErrorKind::BadSize(rom.len()))
+        }
+
+        Ok(Boot9Loader {
+            rom: rom
+        })
+    }
+}
+
+impl ldr::Loader for Boot9Loader {
+    fn entrypoint(&self) -> u32 {
+        // Reset vector
+        BOOT9_ADDR
+    }
+
+    fn load(&self, controller: &mut mem::MemController) {
+        controller.write_buf(BOOT9_ADDR, &self.rom);
+    }
+
+    fn arm11_state(&self) -> hwcore::Arm11State {
+        hwcore::Arm11State::BootSync
+    }
+
+    fn runs_bootrom(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hwcore::HwCore;
+    use msgs;
+
+    #[test]
+    fn powers_on() {
+        let mut rom = vec![0; BOOT9_SIZE];
+        rom[..4].copy_from_slice(&[0xF0, 0x00, 0xF0, 0xE7]); // udf
+        let hw = HwCore::new(msgs::Pump::new(), &Boot9Loader { rom: rom });
+
+        let mut hw9 = hw.hardware9.lock().unwrap();
+        let cpu = &mut hw9.arm9;
+        {
+            let cp15 = cpu.get_coprocessor(15).unwrap();
+            let regs = cp15.debug_regs();
+            let reg = |name: &str| cp15.debug_read(regs.iter().position(|&r| r == name).unwrap());
+            assert_eq!(reg("control"), 0x00002078);
+            assert_eq!(reg("dtcm"), 0);
+            assert_eq!(reg("itcm"), 0);
+        }
+        assert!(!cpu.mpu.enabled);
+
+        // Exceptions use the high vectors rather than the redirected ones
+        cpu.run(1);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0xFFFF0004);
+    }
+}
@@ -101,6 +101,14 @@ impl Desc {
         let arm11_state = match arm11_state_str {
             Some("bootSync") => Ok(hwcore::Arm11State::BootSync),
             Some("kernelSync") => Ok(hwcore::Arm11State::KernelSync),
+            Some("pxiEcho") => Ok(hwcore::Arm11State::PxiEcho),
+            Some("emulated") => {
+                let arm11_entrypoint_str = json["arm11EntryPoint"].as_str()
+                    .ok_or(ErrorKind::JsonItemError("arm11EntryPoint".to_owned(), DESC_FILENAME.to_owned()));
+                let arm11_entrypoint = utils::from_hex(arm11_entrypoint_str?)
+                    .chain_err(|| ErrorKind::JsonItemError("arm11EntryPoint".to_owned(), DESC_FILENAME.to_owned()));
+                Ok(hwcore::Arm11State::Emulated(arm11_entrypoint?))
+            }
             Some("none") | None => Ok(hwcore::Arm11State::None),
             Some(_) => Err(ErrorKind::JsonItemError("arm11State".to_owned(), DESC_FILENAME.to_owned()))
         };
@@ -0,0 +1,269 @@
+use std::fs::File;
+use std::io::Read;
+use std::str;
+
+use ldr::{self, read_u16, read_u32};
+use ldr::symbols::{Symbol, SymbolTable};
+use hwcore;
+use mem;
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
+    errors {
+        BadHeader(reason: &'static str) {
+            description("unsupported ELF file")
+            display("unsupported ELF file: {}", reason)
+        }
+        OutOfBounds(what: &'static str) {
+            description("ELF structure lies outside the file")
+            display("ELF {} lies outside the file", what)
+        }
+    }
+}
+
+pub const ELF_MAGIC: &'static [u8; 4] = b"\x7FELF";
+
+const EM_ARM: u16 = 40;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+fn get_range<'a>(data: &'a [u8], offset: u32, size: u32, what: &'static str) -> Result<&'a [u8]> {
+    let start = offset as usize;
+    match start.checked_add(size as usize) {
+        Some(end) if end <= data.len() => Ok(&data[start..end]),
+        _ => bail!(ErrorKind::OutOfBounds(what))
+    }
+}
+
+// Entry `index` of a table of `entsize`-byte entries at `offset`, of which `size` bytes are read
+fn get_table_entry<'a>(data: &'a [u8], offset: u32, index: u32, entsize: u32, size: u32,
+                       what: &'static str) -> Result<&'a [u8]> {
+    match index.checked_mul(entsize).and_then(|pos| offset.checked_add(pos)) {
+        Some(entry_offset) => get_range(data, entry_offset, size, what),
+        None => bail!(ErrorKind::OutOfBounds(what))
+    }
+}
+
+struct ElfSegment {
+    load_addr: u32,
+    data: Vec<u8>,
+}
+
+/// Loads a 32-bit little-endian ARM ELF executable, such as an unstripped payload straight out
+/// of a GCC or Rust toolchain.
+pub struct ElfLoader {
+    entrypoint: u32,
+    segments: Vec<ElfSegment>,
+    symbols: SymbolTable,
+}
+
+impl ElfLoader {
+    pub fn from_file(path: &str) -> Result<ElfLoader> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        ElfLoader::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<ElfLoader> {
+        if data.len() < 0x34 || &data[0..4] != ELF_MAGIC {
+            bail!(ErrorKind::BadHeader("bad magic"))
+        }
+        if data[4] != 1 || data[5] != 1 {
+            bail!(ErrorKind::BadHeader("not a 32-bit little-endian file"))
+        }
+        if read_u16(data, 0x12) != EM_ARM {
+            bail!(ErrorKind::BadHeader("not an ARM executable"))
+        }
+
+        // Thumb entrypoints have the lowest bit set, which the core starts in Thumb state for
+        let entrypoint = read_u32(data, 0x18);
+
+        let phoff = read_u32(data, 0x1C);
+        let phentsize = read_u16(data, 0x2A) as u32;
+        let phnum = read_u16(data, 0x2C) as u32;
+
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let phdr = get_table_entry(data, phoff, i, phentsize, 0x20, "program header")?;
+            if read_u32(phdr, 0x0) != PT_LOAD {
+                continue
+            }
+
+            let offset = read_u32(phdr, 0x4);
+            // Load to the physical address, so that initialized data meant to be copied out
+            // by the program's startup code is placed where that code expects it
+            let load_addr = read_u32(phdr, 0xC);
+            let file_size = read_u32(phdr, 0x10);
+            let mem_size = read_u32(phdr, 0x14);
+
+            let mut seg_data = get_range(data, offset, file_size, "segment")?.to_vec();
+            if mem_size > file_size {
+                seg_data.resize(mem_size as usize, 0);
+            }
+
+            info!("Found ELF segment with 0x{:X} bytes at 0x{:08X}", seg_data.len(), load_addr);
+            segments.push(ElfSegment {
+                load_addr: load_addr,
+                data: seg_data,
+            });
+        }
+
+        Ok(ElfLoader {
+            entrypoint: entrypoint,
+            segments: segments,
+            symbols: ElfLoader::read_symbols(data)?,
+        })
+    }
+
+    fn read_symbols(data: &[u8]) -> Result<SymbolTable> {
+        let shoff = read_u32(data, 0x20);
+        let shentsize = read_u16(data, 0x2E) as u32;
+        let shnum = read_u16(data, 0x30) as u32;
+
+        let section = |index: u32| get_table_entry(data, shoff, index, shentsize, 0x28,
+                                                   "section header");
+
+        let mut symbols = Vec::new();
+        for i in 0..shnum {
+            let shdr = section(i)?;
+            if read_u32(shdr, 0x4) != SHT_SYMTAB {
+                continue
+            }
+
+            let symtab = get_range(data, read_u32(shdr, 0x10), read_u32(shdr, 0x14),
+                                   "symbol table")?;
+            let strtab_hdr = section(read_u32(shdr, 0x18))?;
+            let strtab = get_range(data, read_u32(strtab_hdr, 0x10), read_u32(strtab_hdr, 0x14),
+                                   "string table")?;
+
+            for sym in symtab.chunks(0x10).filter(|sym| sym.len() == 0x10) {
+                let ty = sym[0xC] & 0xF;
+                let section_index = read_u16(sym, 0xE);
+                if (ty != STT_FUNC && ty != STT_OBJECT) || section_index == 0 {
+                    continue
+                }
+
+                let name_start = read_u32(sym, 0x0) as usize;
+                let name = strtab.get(name_start..)
+                    .and_then(|s| s.split(|b| *b == 0).next())
+                    .and_then(|s| str::from_utf8(s).ok());
+                let name = match name {
+                    Some(name) if !name.is_empty() => name,
+                    _ => continue
+                };
+
+                let mut addr = read_u32(sym, 0x4);
+                if ty == STT_FUNC {
+                    // Thumb functions have the lowest bit set
+                    addr &= !1;
+                }
+
+                symbols.push(Symbol {
+                    name: name.to_owned(),
+                    addr: addr,
+                    size: read_u32(sym, 0x8),
+                });
+            }
+        }
+
+        info!("Found {} ELF symbols", symbols.len());
+        Ok(SymbolTable::from_symbols(symbols))
+    }
+}
+
+impl ldr::Loader for ElfLoader {
+    fn entrypoint(&self) -> u32 {
+        self.entrypoint
+    }
+
+    fn load(&self, controller: &mut mem::MemController) {
+        for segment in self.segments.iter() {
+            controller.write_buf(segment.load_addr, &segment.data);
+        }
+    }
+
+    fn arm11_state(&self) -> hwcore::Arm11State {
+        hwcore::Arm11State::None
+    }
+
+    fn symbols(&self) -> SymbolTable {
+        self.symbols.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::testutil::make_cpu;
+    use cpu::ArmVersion;
+    use ldr::Loader;
+
+    fn put_u16(buf: &mut [u8], offset: usize, val: u16) {
+        buf[offset..offset + 2].copy_from_slice(&[val as u8, (val >> 8) as u8]);
+    }
+
+    fn put_u32(buf: &mut [u8], offset: usize, val: u32) {
+        put_u16(buf, offset, val as u16);
+        put_u16(buf, offset + 2, (val >> 16) as u16);
+    }
+
+    // An executable with one segment, whose 4 bytes of data are followed by 4 of zeroes
+    fn make_elf(entry: u32, load_addr: u32, data: &[u8; 4]) -> Vec<u8> {
+        let mut elf = vec![0u8; 0x54];
+        elf[0..4].copy_from_slice(ELF_MAGIC);
+        elf[4] = 1;
+        elf[5] = 1;
+        put_u16(&mut elf, 0x12, EM_ARM);
+        put_u32(&mut elf, 0x18, entry);
+        put_u32(&mut elf, 0x1C, 0x34);
+        put_u16(&mut elf, 0x2A, 0x20);
+        put_u16(&mut elf, 0x2C, 1);
+
+        let phdr = &mut elf[0x34..0x54];
+        put_u32(phdr, 0x0, PT_LOAD);
+        put_u32(phdr, 0x4, 0x54);
+        put_u32(phdr, 0x8, 0xFFFF0000);
+        put_u32(phdr, 0xC, load_addr);
+        put_u32(phdr, 0x10, 4);
+        put_u32(phdr, 0x14, 8);
+
+        elf.extend_from_slice(data);
+        elf
+    }
+
+    #[test]
+    fn load_segments() {
+        let elf = ElfLoader::from_bytes(&make_elf(0x101, 0x100, &[1, 2, 3, 4])).unwrap();
+        assert_eq!(elf.segments.len(), 1);
+        assert_eq!(elf.segments[0].load_addr, 0x100);
+        assert_eq!(elf.segments[0].data, [1, 2, 3, 4, 0, 0, 0, 0]);
+
+        let mut cpu = make_cpu(ArmVersion::V5TE, &[]);
+        cpu.mpu.memory.write::<u32>(0x104, 0xFFFFFFFF);
+        elf.load(&mut cpu.mpu.memory);
+        cpu.reset(elf.entrypoint());
+        assert_eq!(cpu.mpu.memory.read::<u32>(0x100), 0x04030201);
+        assert_eq!(cpu.mpu.memory.read::<u32>(0x104), 0);
+        assert_eq!(bf!((cpu.cpsr).thumb_bit), 1);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x100);
+    }
+
+    #[test]
+    fn reject_bad_section_headers() {
+        let mut data = make_elf(0x100, 0x100, &[1, 2, 3, 4]);
+        put_u32(&mut data, 0x20, 0xFFFFFFF0);
+        put_u16(&mut data, 0x2E, 0x28);
+        put_u16(&mut data, 0x30, 2);
+        let err = ElfLoader::from_bytes(&data).err().expect("Bad section headers were accepted");
+        match *err.kind() {
+            ErrorKind::OutOfBounds("section header") => {}
+            ref kind => panic!("Unexpected error {:?}", kind)
+        }
+    }
+}
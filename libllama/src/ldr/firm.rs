@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::io::Read;
+
+use openssl;
+use openssl::hash::{Hasher, MessageDigest};
+
+use ldr::{self, read_u32};
+use hwcore;
+use mem;
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+        Ssl(openssl::error::ErrorStack);
+    }
+
+    errors {
+        BadMagic {
+            description("not a FIRM image")
+        }
+        SectionOutOfBounds(index: usize) {
+            description("FIRM section lies outside the image")
+            display("FIRM section {} lies outside the image", index)
+        }
+        HashMismatch(index: usize) {
+            description("FIRM section does not match its hash")
+            display("FIRM section {} does not match its SHA-256 hash", index)
+        }
+    }
+}
+
+const HEADER_SIZE: usize = 0x200;
+const SECTION_HDRS_OFFSET: usize = 0x40;
+const SECTION_HDR_SIZE: usize = 0x30;
+const NUM_SECTIONS: usize = 4;
+
+struct FirmSection {
+    load_addr: u32,
+    data: Vec<u8>,
+}
+
+/// Boots a FIRM image (e.g. `boot.firm` or a decrypted `native_firm`) as the bootrom would
+/// after loading it: sections are copied to their load addresses and both cores start at
+/// the entrypoints in the header.
+pub struct FirmLoader {
+    arm9_entrypoint: u32,
+    arm11_entrypoint: u32,
+    sections: Vec<FirmSection>,
+}
+
+impl FirmLoader {
+    pub fn from_file(path: &str) -> Result<FirmLoader> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        FirmLoader::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<FirmLoader> {
+        if data.len() < HEADER_SIZE || &data[0..4] != b"FIRM" {
+            bail!(ErrorKind::BadMagic)
+        }
+
+        let mut sections = Vec::new();
+        for i in 0..NUM_SECTIONS {
+            let hdr = &data[SECTION_HDRS_OFFSET + i * SECTION_HDR_SIZE ..][..SECTION_HDR_SIZE];
+            let offset = read_u32(hdr, 0x0) as usize;
+            let load_addr = read_u32(hdr, 0x4);
+            let size = read_u32(hdr, 0x8) as usize;
+            let hash = &hdr[0x10..0x30];
+
+            // Unused section slots are left zeroed
+            if size == 0 {
+                continue
+            }
+
+            let section_data = match offset.checked_add(size) {
+                Some(end) if end <= data.len() => &data[offset..end],
+                _ => bail!(ErrorKind::SectionOutOfBounds(i))
+            };
+
+            let mut hasher = Hasher::new(MessageDigest::sha256())?;
+            hasher.update(section_data)?;
+            if &*hasher.finish2()? != hash {
+                bail!(ErrorKind::HashMismatch(i))
+            }
+
+            info!("Found FIRM section {} with 0x{:X} bytes at 0x{:08X}", i, size, load_addr);
+            sections.push(FirmSection {
+                load_addr: load_addr,
+                data: section_data.to_vec(),
+            });
+        }
+
+        Ok(FirmLoader {
+            arm11_entrypoint: read_u32(data, 0x8),
+            arm9_entrypoint: read_u32(data, 0xC),
+            sections: sections,
+        })
+    }
+}
+
+impl ldr::Loader for FirmLoader {
+    fn entrypoint(&self) -> u32 {
+        self.arm9_entrypoint
+    }
+
+    fn load(&self, controller: &mut mem::MemController) {
+        // The ARM9 can see every region FIRM sections get loaded to
+        for section in self.sections.iter() {
+            controller.write_buf(section.load_addr, &section.data);
+        }
+    }
+
+    fn arm11_state(&self) -> hwcore::Arm11State {
+        // Images that don't use the ARM11 leave its entrypoint zeroed
+        if self.arm11_entrypoint == 0 {
+            hwcore::Arm11State::None
+        } else {
+            hwcore::Arm11State::Emulated(self.arm11_entrypoint)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_firm(section: &[u8], corrupt: bool) -> Vec<u8> {
+        let mut firm = vec![0u8; HEADER_SIZE];
+        firm[0..4].copy_from_slice(b"FIRM");
+        firm[0x8..0xC].copy_from_slice(&[0x00, 0x00, 0xF8, 0x1F]);
+        firm[0xC..0x10].copy_from_slice(&[0x00, 0x00, 0x00, 0x08]);
+
+        let mut hasher = Hasher::new(MessageDigest::sha256()).unwrap();
+        hasher.update(section).unwrap();
+        let hash = hasher.finish2().unwrap();
+
+        let hdr = &mut firm[SECTION_HDRS_OFFSET..][..SECTION_HDR_SIZE];
+        hdr[0x0..0x4].copy_from_slice(&[0x00, 0x02, 0x00, 0x00]);
+        hdr[0x4..0x8].copy_from_slice(&[0x00, 0x00, 0x00, 0x08]);
+        hdr[0x8] = section.len() as u8;
+        hdr[0x10..0x30].copy_from_slice(&*hash);
+
+        firm.extend_from_slice(section);
+        if corrupt {
+            firm[HEADER_SIZE] ^= 1;
+        }
+        firm
+    }
+
+    #[test]
+    fn parse_header() {
+        let firm = FirmLoader::from_bytes(&make_firm(&[1, 2, 3, 4], false)).unwrap();
+        assert_eq!(firm.arm9_entrypoint, 0x08000000);
+        assert_eq!(firm.arm11_entrypoint, 0x1FF80000);
+        assert_eq!(firm.sections.len(), 1);
+        assert_eq!(firm.sections[0].load_addr, 0x08000000);
+        assert_eq!(firm.sections[0].data, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn reject_bad_hash() {
+        let err = FirmLoader::from_bytes(&make_firm(&[1, 2, 3, 4], true)).err()
+            .expect("Corrupted FIRM section was accepted");
+        match *err.kind() {
+            ErrorKind::HashMismatch(0) => {}
+            _ => panic!("Unexpected error {}", err)
+        }
+    }
+}
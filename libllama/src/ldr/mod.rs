@@ -1,11 +1,73 @@
+mod boot9;
 mod ctr9;
+mod elf;
+mod firm;
+mod symbols;
 
+pub use self::boot9::*;
 pub use self::ctr9::*;
+pub use self::elf::*;
+pub use self::firm::*;
+pub use self::symbols::*;
 use hwcore;
 use mem;
 
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
+    links {
+        Boot9(boot9::Error, boot9::ErrorKind);
+        Ctr9(ctr9::Error, ctr9::ErrorKind);
+        Elf(elf::Error, elf::ErrorKind);
+        Firm(firm::Error, firm::ErrorKind);
+    }
+}
+
 pub trait Loader {
     fn entrypoint(&self) -> u32;
     fn load(&self, controller: &mut mem::MemController);
     fn arm11_state(&self) -> hwcore::Arm11State;
-}
\ No newline at end of file
+
+    fn symbols(&self) -> SymbolTable {
+        SymbolTable::new()
+    }
+
+    /// Whether the loaded program is the ARM9 bootrom itself. If so, the system starts out
+    /// in its power-on state rather than the state the bootrom leaves behind for FIRM.
+    fn runs_bootrom(&self) -> bool {
+        false
+    }
+}
+
+/// Picks a loader for the given path: folders are ctr9 packages, and files are ELF executables
+/// or FIRM images
+pub fn from_path(path: &str) -> Result<Box<Loader>> {
+    if Path::new(path).is_dir() {
+        return Ok(Box::new(Ctr9Loader::from_folder(path)?))
+    }
+
+    let mut magic = [0u8; 4];
+    File::open(path)?.read_exact(&mut magic)?;
+    if &magic == ELF_MAGIC {
+        Ok(Box::new(ElfLoader::from_file(path)?))
+    } else {
+        Ok(Box::new(FirmLoader::from_file(path)?))
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    (buf[offset] as u16) | ((buf[offset + 1] as u16) << 8)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    (buf[offset] as u32)
+        | ((buf[offset + 1] as u32) << 8)
+        | ((buf[offset + 2] as u32) << 16)
+        | ((buf[offset + 3] as u32) << 24)
+}
@@ -0,0 +1,89 @@
+use utils::from_hex;
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    /// Size in bytes, or 0 if unknown
+    pub size: u32,
+}
+
+/// Symbols of the loaded program, for showing and entering addresses by name in the debuggers
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    // Sorted by address
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: Vec::new()
+        }
+    }
+
+    pub fn from_symbols(mut symbols: Vec<Symbol>) -> SymbolTable {
+        symbols.sort_by_key(|sym| sym.addr);
+        SymbolTable {
+            symbols: symbols
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|sym| sym.name == name).map(|sym| sym.addr)
+    }
+
+    /// Finds the symbol `addr` lies in, returning its name and the offset of `addr` into it
+    pub fn symbolize(&self, addr: u32) -> Option<(&str, u32)> {
+        let index = match self.symbols.binary_search_by_key(&addr, |sym| sym.addr) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let sym = &self.symbols[index];
+        let offset = addr - sym.addr;
+        if sym.size != 0 && offset >= sym.size {
+            return None
+        }
+        Some((&sym.name, offset))
+    }
+
+    /// Formats `addr` along with the symbol it lies in, if any
+    pub fn describe(&self, addr: u32) -> String {
+        match self.symbolize(addr) {
+            Some((name, 0)) => format!("0x{:X} <{}>", addr, name),
+            Some((name, offset)) => format!("0x{:X} <{}+0x{:X}>", addr, name, offset),
+            None => format!("0x{:X}", addr),
+        }
+    }
+
+    /// Parses a symbol name or hex address
+    pub fn resolve(&self, string: &str) -> Option<u32> {
+        self.lookup(string).or_else(|| from_hex(string).ok())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn symbolize() {
+        let symbols = SymbolTable::from_symbols(vec![
+            Symbol { name: "main".to_owned(), addr: 0x08001000, size: 0x20 },
+            Symbol { name: "_start".to_owned(), addr: 0x08000000, size: 0 },
+        ]);
+        assert_eq!(symbols.describe(0x08001000), "0x8001000 <main>");
+        assert_eq!(symbols.describe(0x08001004), "0x8001004 <main+0x4>");
+        assert_eq!(symbols.describe(0x08001020), "0x8001020");
+        assert_eq!(symbols.describe(0x08000100), "0x8000100 <_start+0x100>");
+        assert_eq!(symbols.describe(0x07FFFFFF), "0x7FFFFFF");
+        assert_eq!(symbols.resolve("main"), Some(0x08001000));
+        assert_eq!(symbols.resolve("08002000"), Some(0x08002000));
+        assert_eq!(symbols.resolve("nope"), None);
+    }
+}
@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate bitutils;
+extern crate capstone;
 #[macro_use]
 extern crate error_chain;
 extern crate extprim;
@@ -14,11 +15,14 @@ extern crate parking_lot;
 pub mod utils;
 
 pub mod clock;
+pub mod commands;
 pub mod cpu;
 pub mod dbgcore;
 pub mod gdbstub;
+pub mod history;
 pub mod hwcore;
 pub mod io;
 pub mod ldr;
 pub mod msgs;
 pub mod mem;
+pub mod snapshot;
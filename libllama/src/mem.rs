@@ -1,12 +1,15 @@
 use std;
 use std::cell;
 use std::cmp;
+use std::collections::HashSet;
 use std::ptr;
 use std::sync::Arc;
 
 use parking_lot::{Mutex, RwLock};
 
 use io;
+use io::trace::Accessor;
+use snapshot;
 
 const KB_SIZE: usize = 1024;
 pub type SharedMemoryNode = RwLock<[u8; KB_SIZE]>;
@@ -52,6 +55,11 @@ impl SharedMemoryBlock {
 
         SharedMemoryBlock(Arc::new(inner))
     }
+
+    // Identifies the underlying memory, regardless of how many times it is mapped
+    fn id(&self) -> usize {
+        &*self.0 as *const Vec<SharedMemoryNode> as usize
+    }
 }
 impl MemoryBlock for SharedMemoryBlock {
     fn get_bytes(&self) -> u32 {
@@ -106,6 +114,22 @@ impl IoMemoryBlock {
     pub fn new(variant: io::IoRegion, kbs: usize) -> IoMemoryBlock {
         IoMemoryBlock(kbs, variant)
     }
+
+    unsafe fn read_as(&self, accessor: Accessor, offset: usize, buf: *mut u8, buf_size: usize) {
+        match self.1 {
+            io::IoRegion::Arm9(ref x) => x.read_reg(offset, buf, buf_size, accessor),
+            io::IoRegion::Shared(ref x) => x.read_reg(offset, buf, buf_size, accessor),
+            _ => unimplemented!(),
+        }
+    }
+
+    unsafe fn write_as(&self, accessor: Accessor, offset: usize, buf: *const u8, buf_size: usize) {
+        match self.1 {
+            io::IoRegion::Arm9(ref x) => x.write_reg(offset, buf, buf_size, accessor),
+            io::IoRegion::Shared(ref x) => x.write_reg(offset, buf, buf_size, accessor),
+            _ => unimplemented!(),
+        }
+    }
 }
 impl MemoryBlock for IoMemoryBlock {
     fn get_bytes(&self) -> u32 {
@@ -113,19 +137,11 @@ impl MemoryBlock for IoMemoryBlock {
     }
 
     unsafe fn read_to_ptr(&self, offset: usize, buf: *mut u8, buf_size: usize) {
-        match self.1 {
-            io::IoRegion::Arm9(ref x) => x.read_reg(offset, buf, buf_size),
-            io::IoRegion::Shared(ref x) => x.read_reg(offset, buf, buf_size),
-            _ => unimplemented!(),
-        }
+        self.read_as(Accessor::Other, offset, buf, buf_size)
     }
 
     unsafe fn write_from_ptr(&self, offset: usize, buf: *const u8, buf_size: usize) {
-        match self.1 {
-            io::IoRegion::Arm9(ref x) => x.write_reg(offset, buf, buf_size),
-            io::IoRegion::Shared(ref x) => x.write_reg(offset, buf, buf_size),
-            _ => unimplemented!(),
-        }
+        self.write_as(Accessor::Other, offset, buf, buf_size)
     }
 }
 
@@ -160,74 +176,364 @@ impl MemoryBlock for AddressBlock {
     }
 }
 
+/// What happens to accesses that hit an address nothing is mapped at
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnmappedPolicy {
+    /// Report a bus error, which the ARM9 raises as an abort
+    Abort,
+    /// Read zeroes and ignore writes, without reporting anything
+    ReadZero,
+    /// Report a bus error, stopping the accessing core for the debugger on the instruction that
+    /// made the access, before it changes any registers
+    Break,
+}
+
+impl UnmappedPolicy {
+    pub fn from_name(name: &str) -> Option<UnmappedPolicy> {
+        match name {
+            "abort" => Some(UnmappedPolicy::Abort),
+            "zero" => Some(UnmappedPolicy::ReadZero),
+            "break" => Some(UnmappedPolicy::Break),
+            _ => None
+        }
+    }
+}
+
+/// An access to an address nothing is mapped at
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BusError {
+    pub addr: u32,
+    pub is_write: bool,
+}
+
+struct MappedRegion {
+    address: u32,
+    block: AddressBlock,
+    // Extra cycles taken by a bus access
+    wait_states: usize,
+}
+
+// A block mirrored across an area, over whatever regions are mapped there
+struct Overlay {
+    block: AddressBlock,
+    block_size: u32,
+    // Base address and size of the area, or None while the overlay is unmapped
+    area: Option<(u32, u64)>,
+    // Reads pass through to the regions underneath
+    write_only: bool,
+}
+
+impl Overlay {
+    fn overlaps(&self, address: u32, len: u32) -> bool {
+        match self.area {
+            Some((base, size)) => {
+                let (start, end) = (base as u64, base as u64 + size);
+                (address as u64) < end && address as u64 + len as u64 > start
+            }
+            None => false
+        }
+    }
+}
+
 pub struct MemController {
-    regions: Vec<(u32, AddressBlock)>,
+    regions: Vec<MappedRegion>,
+    // Checked before the regions, in the order they were added
+    overlays: Vec<Overlay>,
+    unmapped_policy: UnmappedPolicy,
+    // First bus error since the last call to take_bus_error
+    bus_error: cell::Cell<Option<BusError>>,
+    // Who's making accesses, as told to IO devices for tracing
+    accessor: Accessor,
 }
 
 impl MemController {
     pub fn new() -> MemController {
         MemController {
             regions: Vec::new(),
+            overlays: Vec::new(),
+            unmapped_policy: UnmappedPolicy::Abort,
+            bus_error: cell::Cell::new(None),
+            accessor: Accessor::Other,
+        }
+    }
+
+    /// Sets who further accesses are attributed to
+    pub fn set_accessor(&mut self, accessor: Accessor) {
+        self.accessor = accessor;
+    }
+
+    unsafe fn read_block(&self, block: &AddressBlock, offset: usize, buf: *mut u8, buf_size: usize) {
+        match *block {
+            AddressBlock::Io(ref inner) => inner.read_as(self.accessor, offset, buf, buf_size),
+            _ => block.read_to_ptr(offset, buf, buf_size)
+        }
+    }
+
+    unsafe fn write_block(&self, block: &AddressBlock, offset: usize, buf: *const u8, buf_size: usize) {
+        match *block {
+            AddressBlock::Io(ref inner) => inner.write_as(self.accessor, offset, buf, buf_size),
+            _ => block.write_from_ptr(offset, buf, buf_size)
+        }
+    }
+
+    pub fn unmapped_policy(&self) -> UnmappedPolicy {
+        self.unmapped_policy
+    }
+
+    pub fn set_unmapped_policy(&mut self, policy: UnmappedPolicy) {
+        self.unmapped_policy = policy;
+    }
+
+    /// Returns the first access to unmapped memory since the last call. Unless the policy is
+    /// to read zeroes, whoever made the access is expected to act on it.
+    pub fn take_bus_error(&self) -> Option<BusError> {
+        let error = self.bus_error.get();
+        self.bus_error.set(None);
+        error
+    }
+
+    // Unmapped reads return zeroes and unmapped writes are dropped, either way
+    fn unmapped_access(&self, addr: u32, is_write: bool) {
+        let kind = if is_write { "write to" } else { "read from" };
+        if self.unmapped_policy == UnmappedPolicy::ReadZero {
+            debug!("Ignoring {} unmapped address 0x{:08X}", kind, addr);
+            return
+        }
+
+        error!("Bus error: {} unmapped address 0x{:08X}", kind, addr);
+        if self.bus_error.get().is_none() {
+            self.bus_error.set(Some(BusError { addr: addr, is_write: is_write }));
         }
     }
 
     fn search_region(&self, address: u32) -> Result<usize, usize> {
-        self.regions.binary_search_by(|&(addr, _)| addr.cmp(&address))
+        self.regions.binary_search_by(|region| region.address.cmp(&address))
     }
 
-    fn match_address<'a>(&'a self, address: u32) -> Option<(u32, &'a AddressBlock)> {
+    fn match_index(&self, address: u32) -> Option<usize> {
         let index = match self.search_region(address) {
             Ok(a) => a,
+            Err(0) => return None,
             Err(a) => a - 1,
         };
-        if index > self.regions.len() {
-            return None;
-        }
 
-        let (block_addr, ref block) = self.regions[index];
-        if address - block_addr < block.get_bytes() {
-            return Some((block_addr, block));
+        let region = &self.regions[index];
+        if address - region.address < region.block.get_bytes() {
+            return Some(index);
         }
         None
     }
 
+    fn match_address<'a>(&'a self, address: u32, is_write: bool) -> Option<(u32, &'a AddressBlock)> {
+        for overlay in self.overlays.iter() {
+            if overlay.overlaps(address, 1) && (is_write || !overlay.write_only) {
+                let base = overlay.area.unwrap().0;
+                let offset = (address - base) % overlay.block_size;
+                return Some((address - offset, &overlay.block))
+            }
+        }
+
+        self.match_index(address).map(|index| {
+            let region = &self.regions[index];
+            (region.address, &region.block)
+        })
+    }
+
     pub fn map_region(&mut self, address: u32, region: AddressBlock) {
+        self.map_region_timed(address, region, 0);
+    }
+
+    /// Maps a region that takes `wait_states` extra cycles to access over the bus
+    pub fn map_region_timed(&mut self, address: u32, region: AddressBlock, wait_states: usize) {
         let insert_index = self.search_region(address).unwrap_err();
-        self.regions.insert(insert_index, (address, region));
+        self.regions.insert(insert_index, MappedRegion {
+            address: address,
+            block: region,
+            wait_states: wait_states,
+        });
+    }
+
+    /// Adds a block that can be mirrored over the memory map with `map_overlay`, shadowing
+    /// whatever is mapped underneath. Overlays added earlier take priority over later ones.
+    /// Returns the index to map it with.
+    pub fn add_overlay(&mut self, block: SharedMemoryBlock) -> usize {
+        self.overlays.push(Overlay {
+            block_size: block.get_bytes(),
+            block: AddressBlock::SharedRam(block),
+            area: None,
+            write_only: false,
+        });
+        self.overlays.len() - 1
+    }
+
+    /// Mirrors an overlay across the `size` bytes at `base`, or across the size of its block
+    /// if that's larger. Regions underneath are shadowed until it's moved or unmapped again.
+    /// With `write_only`, only writes go to the overlay, and reads to what's underneath.
+    pub fn map_overlay(&mut self, index: usize, base: u32, size: u64, write_only: bool) {
+        let overlay = &mut self.overlays[index];
+        overlay.area = Some((base, cmp::max(size, overlay.block_size as u64)));
+        overlay.write_only = write_only;
+    }
+
+    pub fn unmap_overlay(&mut self, index: usize) {
+        self.overlays[index].area = None;
+    }
+
+    /// Whether nothing is mapped anywhere in the `len` bytes starting at `address`
+    pub fn is_free(&self, address: u32, len: u32) -> bool {
+        if self.overlays.iter().any(|overlay| overlay.overlaps(address, len)) {
+            return false
+        }
+        let end = address as u64 + len as u64;
+        let next_start = match self.search_region(address) {
+            Ok(_) => return false,
+            Err(index) => self.regions.get(index).map_or(1 << 32, |region| region.address as u64)
+        };
+        self.match_index(address).is_none() && end <= next_start
+    }
+
+    /// Extra cycles taken by a bus access to `addr`, or 0 if nothing is mapped there.
+    /// Overlays are accessed without any.
+    pub fn wait_states(&self, addr: u32) -> usize {
+        if self.overlays.iter().any(|overlay| overlay.overlaps(addr, 1)) {
+            return 0
+        }
+        self.match_index(addr).map_or(0, |index| self.regions[index].wait_states)
     }
 
     pub fn read<T: Copy>(&self, addr: u32) -> T {
-        let (block_addr, block) = self.match_address(addr)
-            .unwrap_or_else(|| panic!("Could not match address 0x{:X}", addr));
+        let (block_addr, block) = match self.match_address(addr, false) {
+            Some(matched) => matched,
+            None => {
+                self.unmapped_access(addr, false);
+                return unsafe { std::mem::zeroed() }
+            }
+        };
         unsafe {
             let mut t: T = std::mem::uninitialized();
-            block.read_to_ptr((addr - block_addr) as usize, std::mem::transmute(&mut t), std::mem::size_of::<T>());
+            self.read_block(block, (addr - block_addr) as usize, std::mem::transmute(&mut t), std::mem::size_of::<T>());
             t
         }
     }
 
+    /// Reads RAM for the debugger, without touching IO devices or counting a bus error.
+    /// Returns None if `addr` isn't backed by RAM, since reading an IO register can change
+    /// the device's state.
+    pub fn peek<T: Copy>(&self, addr: u32) -> Option<T> {
+        let (block_addr, block) = match self.match_address(addr, false) {
+            Some(matched) => matched,
+            None => return None
+        };
+        let offset = (addr - block_addr) as usize;
+        match *block {
+            AddressBlock::Io(_) => return None,
+            _ if offset + std::mem::size_of::<T>() > block.get_bytes() as usize => return None,
+            _ => {}
+        }
+        unsafe {
+            let mut t: T = std::mem::uninitialized();
+            block.read_to_ptr(offset, std::mem::transmute(&mut t), std::mem::size_of::<T>());
+            Some(t)
+        }
+    }
+
     pub fn read_buf(&self, addr: u32, buf: &mut [u8]) {
-        let (block_addr, block) = self.match_address(addr)
-            .unwrap_or_else(|| panic!("Could not match address 0x{:X}", addr));
+        let (block_addr, block) = match self.match_address(addr, false) {
+            Some(matched) => matched,
+            None => {
+                self.unmapped_access(addr, false);
+                for byte in buf.iter_mut() {
+                    *byte = 0;
+                }
+                return
+            }
+        };
         unsafe {
-            block.read_to_ptr((addr - block_addr) as usize, buf.as_mut_ptr(), buf.len());
+            self.read_block(block, (addr - block_addr) as usize, buf.as_mut_ptr(), buf.len());
         }
     }
 
     pub fn write<T: Copy>(&mut self, addr: u32, data: T) {
-        let (block_addr, block) = self.match_address(addr)
-            .unwrap_or_else(|| panic!("Could not match address 0x{:X}", addr));
+        let (block_addr, block) = match self.match_address(addr, true) {
+            Some(matched) => matched,
+            None => return self.unmapped_access(addr, true)
+        };
         unsafe {
-            block.write_from_ptr((addr - block_addr) as usize, std::mem::transmute(&data), std::mem::size_of::<T>());
+            self.write_block(block, (addr - block_addr) as usize, std::mem::transmute(&data), std::mem::size_of::<T>());
         }
     }
 
     pub fn write_buf(&mut self, addr: u32, buf: &[u8]) {
-        let (block_addr, block) = self.match_address(addr)
-            .unwrap_or_else(|| panic!("Could not match address 0x{:X}", addr));
+        let (block_addr, block) = match self.match_address(addr, true) {
+            Some(matched) => matched,
+            None => return self.unmapped_access(addr, true)
+        };
         unsafe {
-            block.write_from_ptr((addr - block_addr) as usize, buf.as_ptr(), buf.len());
+            self.write_block(block, (addr - block_addr) as usize, buf.as_ptr(), buf.len());
+        }
+    }
+}
+
+const SNAPSHOT_IO: u8 = 0;
+const SNAPSHOT_MIRROR: u8 = 1;
+const SNAPSHOT_RAM: u8 = 2;
+
+impl MemController {
+    // Decides how each region is stored: IO state is saved by the devices themselves,
+    // and a shared block mapped more than once (e.g. the ARM11 bootrom) is only stored once.
+    // Overlays aren't stored, as their owners save them.
+    fn snapshot_kinds(&self) -> Vec<u8> {
+        let mut seen = HashSet::new();
+        self.regions.iter().map(|region| match region.block {
+            AddressBlock::Io(_) => SNAPSHOT_IO,
+            AddressBlock::SharedRam(ref inner) if !seen.insert(inner.id()) => SNAPSHOT_MIRROR,
+            _ => SNAPSHOT_RAM
+        }).collect()
+    }
+}
+
+impl snapshot::Snapshot for MemController {
+    fn save(&self, w: &mut snapshot::Writer) {
+        let kinds = self.snapshot_kinds();
+        w.put(self.regions.len() as u64);
+        for (region, kind) in self.regions.iter().zip(kinds) {
+            w.put(region.address);
+            w.put(region.block.get_bytes());
+            w.put(kind);
+            if kind == SNAPSHOT_RAM {
+                let buf = w.alloc_bytes(region.block.get_bytes() as usize);
+                unsafe { region.block.read_to_ptr(0, buf.as_mut_ptr(), buf.len()); }
+            }
+        }
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        let kinds = self.snapshot_kinds();
+        r.expect("memory region count", self.regions.len() as u64)?;
+        for (region, kind) in self.regions.iter().zip(kinds) {
+            r.expect("memory region address", region.address)?;
+            r.expect("memory region size", region.block.get_bytes())?;
+            r.expect("memory region kind", kind)?;
+            if kind == SNAPSHOT_RAM {
+                let buf = r.get_slice(region.block.get_bytes() as usize)?;
+                unsafe { region.block.write_from_ptr(0, buf.as_ptr(), buf.len()); }
+            }
         }
+        Ok(())
+    }
+}
+
+// Saves just the contents, for blocks whose owner keeps them out of the memory map at times
+impl snapshot::Snapshot for SharedMemoryBlock {
+    fn save(&self, w: &mut snapshot::Writer) {
+        let buf = w.alloc_bytes(self.get_bytes() as usize);
+        unsafe { self.read_to_ptr(0, buf.as_mut_ptr(), buf.len()); }
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        let buf = r.get_slice(self.get_bytes() as usize)?;
+        unsafe { self.write_from_ptr(0, buf.as_ptr(), buf.len()); }
+        Ok(())
     }
 }
 
@@ -236,6 +542,72 @@ mod test {
     use super::*;
     use std::io::Write;
 
+    #[test]
+    fn snapshot_roundtrip() {
+        use snapshot::{Reader, Snapshot, Writer};
+
+        let itcm = SharedMemoryBlock::new(1);
+        let mut controller = MemController::new();
+        controller.map_region(0x0000, AddressBlock::SharedRam(itcm.clone()));
+        controller.map_region(0x8000, AddressBlock::SharedRam(itcm.clone()));
+        controller.map_region(0x10000, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+
+        controller.write::<u32>(0x10, 0x12345678);
+        controller.write::<u32>(0x10010, 0x9ABCDEF0);
+        let mut w = Writer::new();
+        controller.save(&mut w);
+        let data = w.into_inner();
+
+        controller.write::<u32>(0x10, 0);
+        controller.write::<u32>(0x10010, 0);
+        controller.load(&mut Reader::new(&data).unwrap()).unwrap();
+        assert_eq!(controller.read::<u32>(0x8010), 0x12345678);
+        assert_eq!(controller.read::<u32>(0x10010), 0x9ABCDEF0);
+    }
+
+    #[test]
+    fn overlays() {
+        let mut controller = MemController::new();
+        controller.map_region_timed(0x1000, AddressBlock::SharedRam(SharedMemoryBlock::new(4)), 2);
+        let high = controller.add_overlay(SharedMemoryBlock::new(1));
+        let low = controller.add_overlay(SharedMemoryBlock::new(1));
+        assert!(controller.is_free(0x0C00, 0x400));
+
+        // Mirrored across the area, over the region underneath
+        controller.map_overlay(low, 0x0C00, 0x1000, false);
+        assert!(!controller.is_free(0x0C00, 0x400));
+        controller.write::<u32>(0x1010, 0x12345678);
+        assert_eq!(controller.read::<u32>(0x0C10), 0x12345678);
+        assert_eq!(controller.wait_states(0x1010), 0);
+
+        // The overlay added first wins where they overlap
+        controller.map_overlay(high, 0x1000, 0x400, true);
+        controller.write::<u32>(0x1010, 0x9ABCDEF0);
+        assert_eq!(controller.read::<u32>(0x1010), 0x12345678);
+
+        controller.unmap_overlay(low);
+        controller.unmap_overlay(high);
+        assert_eq!(controller.read::<u32>(0x1010), 0);
+        assert_eq!(controller.wait_states(0x1010), 2);
+        controller.map_overlay(high, 0x3000, 0, false);
+        assert_eq!(controller.read::<u32>(0x3010), 0x9ABCDEF0);
+    }
+
+    #[test]
+    fn unmapped_accesses() {
+        let mut controller = MemController::new();
+        controller.map_region(0x1000, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+
+        controller.write::<u32>(0x2000, 0x12345678);
+        assert_eq!(controller.read::<u32>(0x2000), 0);
+        assert_eq!(controller.take_bus_error(), Some(BusError { addr: 0x2000, is_write: true }));
+        assert_eq!(controller.take_bus_error(), None);
+
+        controller.set_unmapped_policy(UnmappedPolicy::ReadZero);
+        assert_eq!(controller.read::<u32>(0x0FFC), 0);
+        assert_eq!(controller.take_bus_error(), None);
+    }
+
     #[test]
     fn write_intra_block() {
         let block = SharedMemoryBlock::new(1);
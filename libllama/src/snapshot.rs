@@ -0,0 +1,219 @@
+use std::mem;
+
+use utils::bytes;
+
+error_chain! {
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
+    errors {
+        BadMagic {
+            description("file is not a llama snapshot")
+        }
+        BadVersion(version: u32) {
+            description("unsupported snapshot version")
+            display("unsupported snapshot version {}", version)
+        }
+        Truncated {
+            description("snapshot ended unexpectedly")
+        }
+        Mismatch(what: String) {
+            description("snapshot does not match the emulated system")
+            display("snapshot does not match the emulated system: {}", what)
+        }
+    }
+}
+
+const MAGIC: &'static [u8; 8] = b"LLAMASNP";
+const VERSION: u32 = 8;
+
+/// Implemented by every piece of hardware that must survive a save/load cycle.
+///
+/// `load` must consume exactly the bytes written by `save`, in the same order.
+pub trait Snapshot {
+    fn save(&self, w: &mut Writer);
+    fn load(&mut self, r: &mut Reader) -> Result<()>;
+}
+
+pub struct Writer {
+    buf: Vec<u8>
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        let mut w = Writer { buf: Vec::new() };
+        w.put_bytes(MAGIC);
+        w.put(VERSION);
+        w
+    }
+
+    /// Writes a plain-old-data value in host byte order
+    pub fn put<T: Copy>(&mut self, val: T) {
+        let data = unsafe { bytes::from_val(&val) };
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn put_bool(&mut self, val: bool) {
+        self.put(val as u8);
+    }
+
+    pub fn put_bytes(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Appends `len` zeroed bytes and returns them to be filled in place
+    pub fn alloc_bytes(&mut self, len: usize) -> &mut [u8] {
+        let start = self.buf.len();
+        self.buf.resize(start + len, 0);
+        &mut self.buf[start..]
+    }
+
+    /// Writes a length-prefixed byte buffer
+    pub fn put_vec(&mut self, data: &[u8]) {
+        self.put(data.len() as u64);
+        self.put_bytes(data);
+    }
+
+    /// Writes a tag, so loading a misordered or foreign snapshot fails early
+    pub fn section(&mut self, name: &str) {
+        self.put_vec(name.as_bytes());
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Result<Reader<'a>> {
+        let mut r = Reader { buf: buf, pos: 0 };
+        let mut magic = [0u8; 8];
+        r.get_bytes(&mut magic).map_err(|_| Error::from(ErrorKind::BadMagic))?;
+        if &magic != MAGIC {
+            bail!(ErrorKind::BadMagic);
+        }
+        let version = r.get::<u32>()?;
+        if version != VERSION {
+            bail!(ErrorKind::BadVersion(version));
+        }
+        Ok(r)
+    }
+
+    // Fails unless `len` more bytes are left to read
+    fn check_left(&self, len: usize) -> Result<()> {
+        match self.pos.checked_add(len) {
+            Some(end) if end <= self.buf.len() => Ok(()),
+            _ => bail!(ErrorKind::Truncated)
+        }
+    }
+
+    pub fn get<T: Copy>(&mut self) -> Result<T> {
+        let size = mem::size_of::<T>();
+        self.check_left(size)?;
+        let val = unsafe { bytes::to_val(&self.buf[self.pos .. self.pos + size]) };
+        self.pos += size;
+        Ok(val)
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool> {
+        Ok(self.get::<u8>()? != 0)
+    }
+
+    pub fn get_bytes(&mut self, out: &mut [u8]) -> Result<()> {
+        self.check_left(out.len())?;
+        out.copy_from_slice(&self.buf[self.pos .. self.pos + out.len()]);
+        self.pos += out.len();
+        Ok(())
+    }
+
+    /// Borrows the next `len` bytes without copying them
+    pub fn get_slice(&mut self, len: usize) -> Result<&'a [u8]> {
+        self.check_left(len)?;
+        let buf = self.buf;
+        let slice = &buf[self.pos .. self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn get_vec(&mut self) -> Result<Vec<u8>> {
+        let len = self.get::<u64>()? as usize;
+        self.check_left(len)?;
+        let mut out = vec![0u8; len];
+        self.get_bytes(&mut out)?;
+        Ok(out)
+    }
+
+    pub fn section(&mut self, name: &str) -> Result<()> {
+        let found = self.get_vec()?;
+        if found.as_slice() != name.as_bytes() {
+            bail!(ErrorKind::Mismatch(format!("expected section `{}`, found `{}`",
+                                              name, String::from_utf8_lossy(&found))));
+        }
+        Ok(())
+    }
+
+    /// Reads a value that must equal what the running system already has
+    pub fn expect<T: Copy + PartialEq + ::std::fmt::Debug>(&mut self, what: &str, val: T) -> Result<()> {
+        let found = self.get::<T>()?;
+        if found != val {
+            bail!(ErrorKind::Mismatch(format!("{}: expected {:?}, found {:?}", what, val, found)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut w = Writer::new();
+        w.section("test");
+        w.put(0xDEADBEEFu32);
+        w.put_bool(true);
+        w.put_vec(&[1, 2, 3]);
+        let data = w.into_inner();
+
+        let mut r = Reader::new(&data).unwrap();
+        r.section("test").unwrap();
+        assert_eq!(r.get::<u32>().unwrap(), 0xDEADBEEF);
+        assert_eq!(r.get_bool().unwrap(), true);
+        assert_eq!(r.get_vec().unwrap(), vec![1, 2, 3]);
+        assert!(r.get::<u8>().is_err());
+    }
+
+    #[test]
+    fn bad_section() {
+        let mut w = Writer::new();
+        w.section("cpu");
+        let data = w.into_inner();
+
+        let mut r = Reader::new(&data).unwrap();
+        assert!(r.section("io").is_err());
+    }
+
+    #[test]
+    fn bad_length() {
+        let mut w = Writer::new();
+        w.put(u64::max_value());
+        let data = w.into_inner();
+
+        let mut r = Reader::new(&data).unwrap();
+        match *r.get_vec().unwrap_err().kind() {
+            ErrorKind::Truncated => {}
+            ref kind => panic!("Unexpected error {:?}", kind)
+        }
+    }
+
+    #[test]
+    fn bad_magic() {
+        assert!(Reader::new(b"NOTASNAPSHOT").is_err());
+    }
+}
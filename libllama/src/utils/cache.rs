@@ -1,5 +1,7 @@
 use std::mem;
 
+use snapshot;
+
 pub struct TinyCache<T: Copy> {
     plru_set: u64,
     lru_index: usize,
@@ -58,4 +60,24 @@ impl<T: Copy> TinyCache<T> {
             updater(key as u32, &mut self.map_vals[pos]);
         }
     }
-}
\ No newline at end of file
+}
+impl<T: Copy> snapshot::Snapshot for TinyCache<T> {
+    fn save(&self, w: &mut snapshot::Writer) {
+        w.put(self.plru_set);
+        w.put(self.lru_index as u64);
+        w.put(self.map_keys);
+        for val in self.map_vals.iter() {
+            w.put(*val);
+        }
+    }
+
+    fn load(&mut self, r: &mut snapshot::Reader) -> snapshot::Result<()> {
+        self.plru_set = r.get()?;
+        self.lru_index = r.get::<u64>()? as usize;
+        self.map_keys = r.get()?;
+        for val in self.map_vals.iter_mut() {
+            *val = r.get()?;
+        }
+        Ok(())
+    }
+}
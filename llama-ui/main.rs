@@ -1,6 +1,5 @@
 #[macro_use]
 extern crate log;
-extern crate capstone;
 extern crate lgl;
 extern crate libllama;
 
@@ -8,6 +7,7 @@ mod commands;
 mod uilog;
 
 use std::env;
+use std::process::exit;
 
 use libllama::{dbgcore, gdbstub, hwcore, ldr, msgs};
 
@@ -18,6 +18,7 @@ mod c {
 
 struct Backend<'a> {
     loader: &'a ldr::Loader,
+    gdb_addr: gdbstub::BindAddr,
     debugger: dbgcore::DbgCore,
     gdb: gdbstub::GdbStub,
     fbs: hwcore::Framebuffers,
@@ -120,7 +121,7 @@ mod cbs {
         let backend = Backend::from_c(backend);
         backend.msg_client.send(Message::Quit).unwrap();
         backend.gdb.wait(); // Need to wait because the GDB thread owns the port
-        *backend = super::load_game(backend.loader);
+        *backend = super::load_game(backend.loader, backend.gdb_addr.clone());
     }
 
     pub unsafe extern fn log(buf: c::LogBufferView) {
@@ -141,14 +142,14 @@ mod cbs {
     }
 }
 
-fn load_game<'a>(loader: &'a ldr::Loader) -> Backend<'a> {
+fn load_game<'a>(loader: &'a ldr::Loader, gdb_addr: gdbstub::BindAddr) -> Backend<'a> {
     let fbs = hwcore::Framebuffers {
         top_screen: Vec::new(), bot_screen: Vec::new(),
         top_screen_size: (240, 400, 3), bot_screen_size: (240, 320, 3),
     };
 
     let mut pump = msgs::Pump::new();
-    let client_gdb = pump.add_client(&["quit", "arm9halted"]);
+    let client_gdb = pump.add_client(&["quit", "arm9halted", "arm11halted"]);
     let client_user = pump.add_client(&[]);
 
     let hwcore = hwcore::HwCore::new(pump, loader);
@@ -156,8 +157,9 @@ fn load_game<'a>(loader: &'a ldr::Loader) -> Backend<'a> {
 
     let backend = Backend {
         loader: loader,
+        gdb_addr: gdb_addr.clone(),
         debugger: debugger.clone(),
-        gdb: gdbstub::GdbStub::new(client_gdb, debugger),
+        gdb: gdbstub::GdbStub::new(client_gdb, debugger, gdb_addr),
         fbs: fbs,
         msg_client: client_user,
     };
@@ -165,11 +167,30 @@ fn load_game<'a>(loader: &'a ldr::Loader) -> Backend<'a> {
     backend
 }
 
+fn usage() -> ! {
+    println!("Usage: llama-ui [--gdb <addr>] <program | --boot9 <bootrom dump>>");
+    exit(1)
+}
+
 fn main() {
     let _logger = uilog::init().unwrap();
 
-    let path = env::args().nth(1).unwrap();
-    let loader = ldr::Ctr9Loader::from_folder(&path).unwrap();
+    let mut args: Vec<String> = env::args().collect();
+
+    // `--gdb <addr>` can go anywhere, and sets where the GDB stub listens
+    let gdb_addr = match args.iter().position(|arg| arg == "--gdb") {
+        Some(i) if i + 1 < args.len() => {
+            let addr = args.remove(i + 1);
+            args.remove(i);
+            addr.parse().unwrap()
+        }
+        _ => gdbstub::BindAddr::default()
+    };
+    let loader: Box<ldr::Loader> = match (args.get(1).map(|s| s.as_str()), args.get(2)) {
+        (Some("--boot9"), Some(path)) => Box::new(ldr::Boot9Loader::from_file(path).unwrap()),
+        (Some("--boot9"), None) | (None, _) => usage(),
+        (Some(path), _) => ldr::from_path(path).unwrap()
+    };
 
     let callbacks = c::FrontendCallbacks {
         set_running: Some(cbs::set_running),
@@ -187,6 +208,6 @@ fn main() {
         buffer_size: Some(cbs::buffer_size),
     };
 
-    let mut backend = load_game(&loader);
+    let mut backend = load_game(&*loader, gdb_addr);
     unsafe { c::llama_open_gui(backend.to_c(), &callbacks) };
 }
\ No newline at end of file